@@ -0,0 +1,117 @@
+// The huck runtime, linked into every native executable by `huck build`.
+//
+// Compiled huck code starts at `huck_main`; we provide the real `main`,
+// report failed arithmetic checks the way the interpreter does, and use
+// the program's value as the exit code. A float can't be an exit
+// code, so programs that produce one print it instead.
+
+#include <math.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+extern int64_t huck_main(void);
+
+// Only defined (as another name for huck_main) when main returns an f64
+extern double huckrt_main_f64(void) __attribute__((weak));
+
+// Distinct from anything a test is likely to pick as its exit code
+#define PANIC_STATUS 101
+
+// Called by checked arithmetic that failed, with where it was in the
+// source
+void huckrt_panic(const char *what, int64_t line, int64_t col) {
+    fprintf(stderr, "Runtime error: %s at %lld:%lld\n", what, (long long) line, (long long) col);
+    exit(PANIC_STATUS);
+}
+
+// `x as T` for an integer type T of the given width: round toward zero,
+// saturate at the ends of the type, and turn NaN into 0. Narrower types
+// come back extended to 64 bits, the way huck code keeps them.
+int64_t huckrt_f64_to_int(double x, int bits, int is_signed) {
+    if (isnan(x)) {
+        return 0;
+    }
+    if (is_signed) {
+        uint64_t limit = (uint64_t) 1 << (bits - 1);
+        if (x <= -(double) limit) {
+            return (int64_t) -limit;
+        }
+        if (x >= (double) limit) {
+            return (int64_t) (limit - 1);
+        }
+        return (int64_t) x;
+    }
+    uint64_t max = bits == 64 ? UINT64_MAX : ((uint64_t) 1 << bits) - 1;
+    if (x <= 0) {
+        return 0;
+    }
+    // 2^64 as a double, since UINT64_MAX isn't one
+    if (x >= (bits == 64 ? 18446744073709551616.0 : (double) max + 1)) {
+        return (int64_t) max;
+    }
+    return (int64_t) (uint64_t) x;
+}
+
+// Print a float the way the interpreter shows it: the fewest digits that
+// read back as the same number, in plain notation unless it's huge or
+// tiny, and always with a decimal point or exponent so it looks like a
+// float.
+static void print_f64(double x) {
+    if (isnan(x)) {
+        puts("NaN");
+        return;
+    }
+    if (isinf(x)) {
+        puts(x > 0 ? "inf" : "-inf");
+        return;
+    }
+
+    char digits[64];
+    int precision = 1;
+    for (; precision < 17; precision++) {
+        snprintf(digits, sizeof digits, "%.*e", precision - 1, x);
+        if (strtod(digits, NULL) == x) {
+            break;
+        }
+    }
+    snprintf(digits, sizeof digits, "%.*e", precision - 1, x);
+    char *e = strchr(digits, 'e');
+    int exponent = atoi(e + 1);
+    if (exponent < -4 || exponent >= 17) {
+        *e = '\0';
+        printf("%se%d\n", digits, exponent);
+        return;
+    }
+
+    char plain[400];
+    int decimals = precision - 1 - exponent;
+    snprintf(plain, sizeof plain, "%.*f", decimals > 0 ? decimals : 0, x);
+    printf(strchr(plain, '.') ? "%s\n" : "%s.0\n", plain);
+}
+
+// `print(x)`, which writes each value on a line of its own
+void huckrt_print_i64(int64_t x) {
+    printf("%lld\n", (long long) x);
+}
+
+void huckrt_print_u64(uint64_t x) {
+    printf("%llu\n", (unsigned long long) x);
+}
+
+void huckrt_print_f64(double x) {
+    print_f64(x);
+}
+
+void huckrt_print_bool(int64_t b) {
+    puts(b ? "true" : "false");
+}
+
+int main(void) {
+    if (huckrt_main_f64) {
+        print_f64(huckrt_main_f64());
+        return 0;
+    }
+    return (int) huck_main();
+}
@@ -0,0 +1,798 @@
+// Compiles the checked AST to bytecode for the VM.
+//
+// Function scoping mirrors `lower`: functions are hoisted to the top
+// of the block that declares them and get their index in the program
+// before any bodies are compiled, so calls can refer to functions
+// declared later in the block. Generic functions are left out, since
+// `mono` has already made a copy of each for every type it's used with.
+//
+// A `match` stores the scrutinee in a slot and walks its decision tree
+// with compare-and-jump chains. Each arm's body is compiled once, after
+// the tree, and every leaf for the arm jumps there once it's stored the
+// arm's bindings in their slots.
+
+use crate::bytecode::{Function, Op, Program};
+use crate::interp::{EnumShape, StructShape};
+use crate::matching::{self, pattern_bindings, Case, Decision, Path, Step};
+use crate::mono::{impl_method, is_generic};
+use crate::parser::{Builtin, HuckAst, MatchArm};
+use crate::typecheck::{negated_literal, CheckOutput, EnumType, IntType, StructType, TraitType, TypeInfo, Typed};
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+type CompileInput = CheckOutput;
+
+pub fn compile(ast: &CompileInput) -> Program {
+    let mut compiler = Compiler::new();
+    compiler.functions.push(None);
+    compiler.function(0, "main", &[], ast);
+
+    let functions = compiler.functions.into_iter()
+        .map(|f| f.expect("Function declared but never compiled"))
+        .collect();
+    Program { structs: compiler.structs, enums: compiler.enums, vtables: compiler.vtables, functions }
+}
+
+struct FunctionBuilder {
+    locals: u16,
+    code: Vec<Op>,
+    scopes: Vec<HashMap<String, u16>>,
+    // How many values the expressions around the one being compiled have
+    // left on the stack for later, which a `break` or `continue` drops
+    depth: u16,
+    loops: Vec<LoopTarget>,
+}
+
+// A loop being compiled
+struct LoopTarget {
+    label: Option<String>,
+    depth: u16,
+    // Where a `loop` keeps the value it breaks with
+    result: Option<u16>,
+    // Jumps to patch once the end of the loop, and where `continue`
+    // goes, are known
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+impl FunctionBuilder {
+    fn new() -> Self {
+        Self {
+            locals: 0,
+            code: vec![],
+            scopes: vec![HashMap::new()],
+            depth: 0,
+            loops: vec![],
+        }
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.code.push(op);
+        self.code.len() - 1
+    }
+
+    fn here(&self) -> u32 {
+        self.code.len() as u32
+    }
+
+    // Point a previously emitted jump at the next instruction
+    fn patch(&mut self, jump: usize) {
+        self.patch_to(jump, self.here());
+    }
+
+    fn patch_to(&mut self, jump: usize, target: u32) {
+        match &mut self.code[jump] {
+            Op::Jump(t) | Op::JumpIfFalse(t) => *t = target,
+            op => unreachable!("Patching non-jump instruction {}", op),
+        }
+    }
+
+    fn bind(&mut self, ident: &str) -> u16 {
+        let slot = self.locals;
+        self.locals += 1;
+        self.scopes.last_mut().unwrap().insert(ident.to_string(), slot);
+        slot
+    }
+
+    // A slot no variable is bound to, for intermediate values
+    fn temp(&mut self) -> u16 {
+        let slot = self.locals;
+        self.locals += 1;
+        slot
+    }
+
+    fn lookup(&self, ident: &str) -> u16 {
+        self.scopes.iter().rev()
+            .find_map(|scope| scope.get(ident).copied())
+            .unwrap_or_else(|| panic!("Unbound variable {:?} survived type checking", ident))
+    }
+
+    // The innermost loop, or the one with the label
+    fn loop_target(&mut self, label: &Option<String>) -> &mut LoopTarget {
+        self.loops.iter_mut().rev()
+            .find(|target| label.is_none() || target.label == *label)
+            .unwrap_or_else(|| panic!("Jump to missing loop {:?} survived type checking", label))
+    }
+}
+
+struct Compiler {
+    // Slots are reserved when a function is declared and filled in once
+    // its body has been compiled
+    functions: Vec<Option<Function>>,
+    builder: FunctionBuilder,
+    fn_scopes: Vec<HashMap<String, u16>>,
+    structs: Vec<Rc<StructShape>>,
+    // Index in `structs` of each struct type, by id
+    struct_indices: HashMap<usize, u16>,
+    enums: Vec<Rc<EnumShape>>,
+    enum_indices: HashMap<usize, u16>,
+    vtables: Vec<Vec<u16>>,
+    // Index in `vtables` of each type cast to a `dyn`, by trait id and type
+    vtable_indices: HashMap<(usize, TypeInfo), u16>,
+}
+
+// A match partway through being compiled
+struct MatchCompilation<'a> {
+    arms: &'a [MatchArm<Typed>],
+    // Where the scrutinee is kept
+    root: u16,
+    // The slot for each name each arm binds
+    slots: Vec<HashMap<String, u16>>,
+    // Jumps to each arm's body, patched once the bodies are compiled
+    entries: Vec<Vec<usize>>,
+}
+
+impl Compiler {
+    fn new() -> Self {
+        Self {
+            functions: vec![],
+            builder: FunctionBuilder::new(),
+            fn_scopes: vec![HashMap::new()],
+            structs: vec![],
+            struct_indices: HashMap::new(),
+            enums: vec![],
+            enum_indices: HashMap::new(),
+            vtables: vec![],
+            vtable_indices: HashMap::new(),
+        }
+    }
+
+    // The impl's functions for the methods are declared at the top level,
+    // so they're in scope wherever the cast is
+    fn vtable_index(&mut self, trait_type: &TraitType, ty: &TypeInfo) -> u16 {
+        let key = (trait_type.id, ty.clone());
+        if let Some(index) = self.vtable_indices.get(&key) {
+            return *index;
+        }
+        let methods = trait_type.methods.iter()
+            .map(|(method, _)| self.fn_index(&impl_method(&trait_type.name, ty, method)))
+            .collect();
+        self.vtables.push(methods);
+        let index = self.vtables.len() as u16 - 1;
+        self.vtable_indices.insert(key, index);
+        index
+    }
+
+    fn enum_index(&mut self, enum_type: &EnumType) -> u16 {
+        let enums = &mut self.enums;
+        *self.enum_indices.entry(enum_type.id).or_insert_with(|| {
+            enums.push(Rc::new(EnumShape::new(enum_type)));
+            enums.len() as u16 - 1
+        })
+    }
+
+    fn struct_index(&mut self, struct_type: &StructType) -> u16 {
+        let structs = &mut self.structs;
+        *self.struct_indices.entry(struct_type.id).or_insert_with(|| {
+            structs.push(Rc::new(StructShape::new(struct_type)));
+            structs.len() as u16 - 1
+        })
+    }
+
+    // Fields are evaluated in the order they're written, but go on the
+    // stack in the order they're declared; if those differ, or some come
+    // from a base struct, everything goes through temporaries first
+    fn struct_literal(&mut self, struct_type: &StructType, fields: &[(String, CompileInput)], base: Option<&CompileInput>) {
+        let in_order = base.is_none()
+            && fields.iter().zip(&struct_type.fields).all(|((given, _), (declared, _))| given == declared);
+        if in_order {
+            self.operands(fields.iter().map(|(_, value)| value));
+        } else {
+            let mut slots = vec![None; struct_type.fields.len()];
+            for (name, value) in fields {
+                self.expr(value);
+                let slot = self.builder.temp();
+                self.builder.emit(Op::Store(slot));
+                self.builder.emit(Op::Pop);
+                let (index, _) = struct_type.field(name).expect("Unknown field survived type checking");
+                slots[index] = Some(slot);
+            }
+            let base_slot = base.map(|base| {
+                self.expr(base);
+                let slot = self.builder.temp();
+                self.builder.emit(Op::Store(slot));
+                self.builder.emit(Op::Pop);
+                slot
+            });
+            for (index, slot) in slots.into_iter().enumerate() {
+                match (slot, base_slot) {
+                    (Some(slot), _) => self.builder.emit(Op::Load(slot)),
+                    (None, Some(base_slot)) => {
+                        self.builder.emit(Op::Load(base_slot));
+                        self.builder.emit(Op::Field(index as u16))
+                    },
+                    (None, None) => unreachable!("Missing field survived type checking"),
+                };
+            }
+        }
+        let index = self.struct_index(struct_type);
+        self.builder.emit(Op::Struct(index));
+    }
+
+    fn match_expr(&mut self, scrutinee: &CompileInput, arms: &[MatchArm<Typed>], tail: bool) {
+        self.expr(scrutinee);
+        let root = self.builder.temp();
+        self.builder.emit(Op::Store(root));
+        self.builder.emit(Op::Pop);
+        let slots = arms.iter()
+            .map(|arm| pattern_bindings(&arm.pattern).into_iter()
+                .map(|(name, _)| (name.to_string(), self.builder.temp()))
+                .collect())
+            .collect();
+        let mut m = MatchCompilation { arms, root, slots, entries: vec![vec![]; arms.len()] };
+        self.decision(&matching::compile(arms), &mut m);
+
+        let mut ends = vec![];
+        for (arm, entries) in m.entries.iter().enumerate() {
+            // Arms no leaf reaches are never compiled
+            if entries.is_empty() {
+                continue;
+            }
+            for jump in entries {
+                self.builder.patch(*jump);
+            }
+            self.builder.scopes.push(m.slots[arm].clone());
+            if tail {
+                self.tail(&arms[arm].body);
+            } else {
+                self.expr(&arms[arm].body);
+                ends.push(self.builder.emit(Op::Jump(0)));
+            }
+            self.builder.scopes.pop();
+        }
+        for end in ends {
+            self.builder.patch(end);
+        }
+    }
+
+    fn decision(&mut self, decision: &Decision, m: &mut MatchCompilation) {
+        match decision {
+            Decision::Fail => {
+                self.builder.emit(Op::NoMatch);
+            },
+            Decision::Leaf { arm, bindings, otherwise } => {
+                for (name, path) in bindings {
+                    self.path_value(m.root, path);
+                    self.builder.emit(Op::Store(m.slots[*arm][name]));
+                    self.builder.emit(Op::Pop);
+                }
+                match (&m.arms[*arm].guard, otherwise) {
+                    (Some(guard), Some(otherwise)) => {
+                        self.builder.scopes.push(m.slots[*arm].clone());
+                        self.expr(guard);
+                        self.builder.scopes.pop();
+                        let to_otherwise = self.builder.emit(Op::JumpIfFalse(0));
+                        m.entries[*arm].push(self.builder.emit(Op::Jump(0)));
+                        self.builder.patch(to_otherwise);
+                        self.decision(otherwise, m);
+                    },
+                    _ => m.entries[*arm].push(self.builder.emit(Op::Jump(0))),
+                }
+            },
+            Decision::Switch { path, cases, default } => {
+                for (i, (case, next)) in cases.iter().enumerate() {
+                    // Every case is covered, so the last one needn't be checked
+                    if default.is_none() && i == cases.len() - 1 {
+                        self.decision(next, m);
+                        break;
+                    }
+                    self.path_value(m.root, path);
+                    match case {
+                        Case::Variant(variant) => {
+                            self.builder.emit(Op::Tag);
+                            self.builder.emit(Op::Int(*variant as i64, IntType::U32))
+                        },
+                        Case::Int(n, t) => self.builder.emit(Op::Int(*n, *t)),
+                        Case::Bool(b) => self.builder.emit(Op::Bool(*b)),
+                        Case::Tuple => unreachable!("A tuple's only case is its last"),
+                    };
+                    self.builder.emit(Op::Eq);
+                    let to_next = self.builder.emit(Op::JumpIfFalse(0));
+                    self.decision(next, m);
+                    self.builder.patch(to_next);
+                }
+                if let Some(default) = default {
+                    self.decision(default, m);
+                }
+            },
+        }
+    }
+
+    // Push the value at `path` in the scrutinee
+    fn path_value(&mut self, root: u16, path: &Path) {
+        self.builder.emit(Op::Load(root));
+        for step in path {
+            self.builder.emit(match step {
+                Step::Payload(_, index) => Op::Payload(*index as u16),
+                Step::Element(index) => Op::Field(*index as u16),
+            });
+        }
+    }
+
+    fn function(&mut self, index: u16, name: &str, params: &[String], body: &CompileInput) {
+        let outer = std::mem::replace(&mut self.builder, FunctionBuilder::new());
+
+        for param in params {
+            self.builder.bind(param);
+        }
+        self.tail(body);
+
+        let finished = std::mem::replace(&mut self.builder, outer);
+        self.functions[index as usize] = Some(Function {
+            name: name.to_string(),
+            arity: params.len() as u8,
+            locals: finished.locals,
+            code: finished.code,
+        });
+    }
+
+    fn declare_fn(&mut self, ident: &str) -> u16 {
+        let index = self.functions.len() as u16;
+        self.functions.push(None);
+        self.fn_scopes.last_mut().unwrap().insert(ident.to_string(), index);
+        index
+    }
+
+    fn fn_index(&self, ident: &str) -> u16 {
+        self.fn_scopes.iter().rev()
+            .find_map(|scope| scope.get(ident).copied())
+            .expect("Call to undeclared function")
+    }
+
+    fn begin_block(&mut self, exprs: &[CompileInput]) {
+        self.builder.scopes.push(HashMap::new());
+        self.fn_scopes.push(HashMap::new());
+
+        for expr in exprs {
+            if let HuckAst::Let(ident, _, init_expr, _) = expr {
+                if let HuckAst::Fn(..) = init_expr.as_ref() {
+                    if is_generic(init_expr) {
+                        continue;
+                    }
+                    self.declare_fn(ident);
+                }
+            }
+        }
+    }
+
+    fn end_block(&mut self) {
+        self.builder.scopes.pop();
+        self.fn_scopes.pop();
+    }
+
+    fn fn_decl(&mut self, ident: &str, init_expr: &CompileInput) {
+        let HuckAst::Fn(_, params, _, body, _) = init_expr else {
+            unreachable!("fn_decl called on a non-function")
+        };
+        // Only a function at the very top of the program isn't hoisted
+        let index = match self.fn_scopes.last().unwrap().get(ident) {
+            Some(index) => *index,
+            None => self.declare_fn(ident),
+        };
+        let params = params.iter().map(|(name, _)| name.to_string()).collect::<Vec<_>>();
+        self.function(index, ident, &params, body);
+    }
+
+    fn binary(&mut self, op: Op, lhs: &CompileInput, rhs: &CompileInput) {
+        self.operands([lhs, rhs]);
+        self.builder.emit(op);
+    }
+
+    fn args(&mut self, args: &[CompileInput]) {
+        self.operands(args);
+    }
+
+    // Push a value for each expression, for whatever comes next to use
+    fn operands<'e>(&mut self, exprs: impl IntoIterator<Item = &'e CompileInput>) {
+        let depth = self.builder.depth;
+        for expr in exprs {
+            self.expr(expr);
+            self.builder.depth += 1;
+        }
+        self.builder.depth = depth;
+    }
+
+    // Compile a loop's body, which is run for its effects, with where its
+    // `break`s and `continue`s go still to be filled in
+    fn loop_body(&mut self, label: &Option<String>, result: Option<u16>, body: &CompileInput) -> LoopTarget {
+        self.builder.loops.push(LoopTarget {
+            label: label.clone(),
+            depth: self.builder.depth,
+            result,
+            breaks: vec![],
+            continues: vec![],
+        });
+        self.expr(body);
+        self.builder.emit(Op::Pop);
+        self.builder.loops.pop().unwrap()
+    }
+
+    // Jump out of the loop or back round it, dropping anything the
+    // expressions it's in left on the stack
+    fn jump(&mut self, label: &Option<String>, to_end: bool) {
+        let depth = self.builder.depth;
+        let target = self.builder.loop_target(label);
+        let pops = depth - target.depth;
+        for _ in 0..pops {
+            self.builder.emit(Op::Pop);
+        }
+        let jump = self.builder.emit(Op::Jump(0));
+        let target = self.builder.loop_target(label);
+        if to_end {
+            target.breaks.push(jump);
+        } else {
+            target.continues.push(jump);
+        }
+        // Nothing runs after the jump, but everything around it still
+        // expects a value
+        self.builder.emit(Op::Unit);
+    }
+
+    // Compile an expression whose value is the function's result
+    fn tail(&mut self, ast: &CompileInput) {
+        match ast {
+            HuckAst::Call(ident, _, args, _) => {
+                self.args(args);
+                let func = self.fn_index(ident);
+                self.builder.emit(Op::TailCall(func));
+            },
+            HuckAst::If(test_expr, then_expr, else_expr, _) => {
+                self.expr(test_expr);
+                let to_else = self.builder.emit(Op::JumpIfFalse(0));
+                self.tail(then_expr);
+                self.builder.patch(to_else);
+                self.tail(else_expr);
+            },
+            HuckAst::Block(exprs, _) if !exprs.is_empty() => {
+                self.begin_block(exprs);
+                let (last, init) = exprs.split_last().unwrap();
+                for expr in init {
+                    self.expr(expr);
+                    self.builder.emit(Op::Pop);
+                }
+                self.tail(last);
+                self.end_block();
+            },
+            HuckAst::Match(scrutinee, arms, _) => self.match_expr(scrutinee, arms, true),
+            _ => {
+                self.expr(ast);
+                self.builder.emit(Op::Return);
+            },
+        }
+    }
+
+    // Compile an expression that leaves exactly one value on the stack
+    fn expr(&mut self, ast: &CompileInput) {
+        match ast {
+            HuckAst::Num(n, _, Typed { ty: t, .. }) => {
+                let TypeInfo::Int(t) = t else { unreachable!("Number of type {}", t) };
+                self.builder.emit(Op::Int(t.wrap(*n as i64), *t));
+            },
+            HuckAst::Float(x, _) => {
+                self.builder.emit(Op::Float(*x));
+            },
+            HuckAst::Neg(operand, _) if negated_literal(operand).is_some() => {
+                let (n, t) = negated_literal(operand).unwrap();
+                self.builder.emit(Op::Int(n, t));
+            },
+            HuckAst::Neg(operand, Typed { ty: t, .. }) => {
+                match t {
+                    TypeInfo::Int(t) => self.builder.emit(Op::Int(0, *t)),
+                    // Subtracting from -0.0 flips the sign of zero too
+                    TypeInfo::F64 => self.builder.emit(Op::Float(-0.0)),
+                    t => unreachable!("Negating a {}", t),
+                };
+                self.builder.depth += 1;
+                self.expr(operand);
+                self.builder.depth -= 1;
+                self.builder.emit(Op::Sub);
+            },
+            // Already a `dyn` of the same trait
+            HuckAst::Cast(operand, _, Typed { ty: t, .. }) if operand.ty() == t => self.expr(operand),
+            HuckAst::Cast(operand, _, Typed { ty: TypeInfo::Dyn(trait_type), .. }) => {
+                self.expr(operand);
+                let index = self.vtable_index(trait_type, operand.ty());
+                self.builder.emit(Op::Dyn(index));
+            },
+            HuckAst::Cast(operand, _, Typed { ty: t, .. }) => {
+                self.expr(operand);
+                match t {
+                    TypeInfo::Int(t) => self.builder.emit(Op::Cast(*t)),
+                    TypeInfo::F64 => self.builder.emit(Op::CastFloat),
+                    t => unreachable!("Cast to {}", t),
+                };
+            },
+            HuckAst::BoolLit(b, _) => {
+                self.builder.emit(Op::Bool(*b));
+            },
+            HuckAst::Plus(lhs, rhs, _) => self.binary(Op::Add, lhs, rhs),
+            HuckAst::Minus(lhs, rhs, _) => self.binary(Op::Sub, lhs, rhs),
+            HuckAst::Times(lhs, rhs, _) => self.binary(Op::Mul, lhs, rhs),
+            HuckAst::Div(lhs, rhs, _) => self.binary(Op::Div, lhs, rhs),
+            HuckAst::Rem(lhs, rhs, _) => self.binary(Op::Rem, lhs, rhs),
+            HuckAst::Equals(lhs, rhs, _) => self.binary(Op::Eq, lhs, rhs),
+            HuckAst::NotEquals(lhs, rhs, _) => self.binary(Op::Ne, lhs, rhs),
+            HuckAst::Less(lhs, rhs, _) => self.binary(Op::Lt, lhs, rhs),
+            HuckAst::LessEq(lhs, rhs, _) => self.binary(Op::Le, lhs, rhs),
+            HuckAst::Greater(lhs, rhs, _) => self.binary(Op::Gt, lhs, rhs),
+            HuckAst::GreaterEq(lhs, rhs, _) => self.binary(Op::Ge, lhs, rhs),
+            // Only the instances monomorphization made of it are called
+            HuckAst::Let(_, _, init_expr, _) if is_generic(init_expr) => {
+                self.builder.emit(Op::Unit);
+            },
+            HuckAst::Let(ident, _, init_expr, _) if matches!(init_expr.as_ref(), HuckAst::Fn(..)) => {
+                self.fn_decl(ident, init_expr);
+                self.builder.emit(Op::Unit);
+            },
+            HuckAst::Let(ident, _, init_expr, _) => {
+                self.expr(init_expr);
+                let slot = self.builder.bind(ident);
+                self.builder.emit(Op::Store(slot));
+            },
+            HuckAst::VarRef(ident, _) => {
+                let slot = self.builder.lookup(ident);
+                self.builder.emit(Op::Load(slot));
+            },
+            HuckAst::Assign(ident, _, value, _) => {
+                self.expr(value);
+                let slot = self.builder.lookup(ident);
+                self.builder.emit(Op::Store(slot));
+                self.builder.emit(Op::Pop);
+                self.builder.emit(Op::Unit);
+            },
+            HuckAst::Block(exprs, _) if exprs.is_empty() => {
+                self.builder.emit(Op::Unit);
+            },
+            HuckAst::Block(exprs, _) => {
+                self.begin_block(exprs);
+                let (last, init) = exprs.split_last().unwrap();
+                for expr in init {
+                    self.expr(expr);
+                    self.builder.emit(Op::Pop);
+                }
+                self.expr(last);
+                self.end_block();
+            },
+            HuckAst::If(test_expr, then_expr, else_expr, _) => {
+                self.expr(test_expr);
+                let to_else = self.builder.emit(Op::JumpIfFalse(0));
+                self.expr(then_expr);
+                let to_end = self.builder.emit(Op::Jump(0));
+                self.builder.patch(to_else);
+                self.expr(else_expr);
+                self.builder.patch(to_end);
+            },
+            HuckAst::Fn(..) => unreachable!("Bare function expression survived type checking"),
+            HuckAst::Call(ident, _, args, _) => {
+                self.args(args);
+                let func = self.fn_index(ident);
+                self.builder.emit(Op::Call(func));
+            },
+            HuckAst::Builtin(builtin, args, _) => {
+                self.args(args);
+                self.builder.emit(match builtin {
+                    Builtin::WrappingAdd => Op::WrappingAdd,
+                    Builtin::WrappingSub => Op::WrappingSub,
+                    Builtin::WrappingMul => Op::WrappingMul,
+                    Builtin::WrappingRem => Op::WrappingRem,
+                    Builtin::Print => Op::Print,
+                });
+            },
+            HuckAst::Struct(..) => {
+                self.builder.emit(Op::Unit);
+            },
+            HuckAst::StructLit(_, _, fields, base, Typed { ty, .. }) => {
+                let TypeInfo::Struct(struct_type) = ty else { unreachable!("Struct literal of type {}", ty) };
+                self.struct_literal(struct_type, fields, base.as_deref());
+            },
+            HuckAst::Tuple(elements, Typed { ty, .. }) => {
+                let TypeInfo::Struct(tuple) = ty else { unreachable!("Tuple of type {}", ty) };
+                self.operands(elements.iter());
+                let index = self.struct_index(tuple);
+                self.builder.emit(Op::Struct(index));
+            },
+            HuckAst::Field(operand, field, _) => {
+                let TypeInfo::Struct(struct_type) = operand.ty() else { unreachable!("Field of a {}", operand.ty()) };
+                let (index, _) = struct_type.field(field).expect("Unknown field survived type checking");
+                self.expr(operand);
+                self.builder.emit(Op::Field(index as u16));
+            },
+            HuckAst::Enum(..) | HuckAst::Trait(..) | HuckAst::Impl(..) => {
+                self.builder.emit(Op::Unit);
+            },
+            // The only method calls monomorphization leaves are on a `dyn`
+            HuckAst::MethodCall(receiver, method, args, _, _) => {
+                let TypeInfo::Dyn(trait_type) = receiver.ty() else {
+                    unreachable!("Method call on {} survived monomorphization", receiver.ty())
+                };
+                let method = trait_type.methods.iter().position(|(name, _)| name == method).expect("Method from another trait");
+                self.operands([receiver.as_ref()].into_iter().chain(args));
+                self.builder.emit(Op::DynCall(method as u16, args.len() as u8));
+            },
+            HuckAst::Variant(_, _, variant, args, Typed { ty, .. }) => {
+                let TypeInfo::Enum(enum_type) = ty else { unreachable!("Variant of type {}", ty) };
+                let (variant, _) = enum_type.variant(variant).expect("Unknown variant survived type checking");
+                self.args(args);
+                let index = self.enum_index(enum_type);
+                self.builder.emit(Op::Variant(index, variant as u16));
+            },
+            HuckAst::Match(scrutinee, arms, _) => self.match_expr(scrutinee, arms, false),
+            HuckAst::While(label, cond, body, _) => {
+                let start = self.builder.here();
+                self.expr(cond);
+                let to_end = self.builder.emit(Op::JumpIfFalse(0));
+                let target = self.loop_body(label, None, body);
+                self.builder.emit(Op::Jump(start));
+                for jump in target.continues {
+                    self.builder.patch_to(jump, start);
+                }
+                for jump in [to_end].into_iter().chain(target.breaks) {
+                    self.builder.patch(jump);
+                }
+                self.builder.emit(Op::Unit);
+            },
+            HuckAst::Loop(label, body, _) => {
+                let result = self.builder.temp();
+                let start = self.builder.here();
+                let target = self.loop_body(label, Some(result), body);
+                self.builder.emit(Op::Jump(start));
+                for jump in target.continues {
+                    self.builder.patch_to(jump, start);
+                }
+                for jump in target.breaks {
+                    self.builder.patch(jump);
+                }
+                self.builder.emit(Op::Load(result));
+            },
+            // The range is worked out once, before the first time round
+            HuckAst::For(label, var, from, to, body, _) => {
+                let TypeInfo::Int(t) = from.ty() else { unreachable!("Range of {}", from.ty()) };
+                let (i, end) = (self.builder.temp(), self.builder.temp());
+                for (bound, slot) in [(from, i), (to, end)] {
+                    self.expr(bound);
+                    self.builder.emit(Op::Store(slot));
+                    self.builder.emit(Op::Pop);
+                }
+                let start = self.builder.here();
+                self.builder.emit(Op::Load(i));
+                self.builder.emit(Op::Load(end));
+                self.builder.emit(Op::Lt);
+                let to_end = self.builder.emit(Op::JumpIfFalse(0));
+                self.builder.scopes.push(HashMap::from([(var.to_string(), i)]));
+                let target = self.loop_body(label, None, body);
+                self.builder.scopes.pop();
+                for jump in target.continues {
+                    self.builder.patch(jump);
+                }
+                // `i` is less than `end`, so this can't overflow
+                self.builder.emit(Op::Load(i));
+                self.builder.emit(Op::Int(1, *t));
+                self.builder.emit(Op::WrappingAdd);
+                self.builder.emit(Op::Store(i));
+                self.builder.emit(Op::Pop);
+                self.builder.emit(Op::Jump(start));
+                for jump in [to_end].into_iter().chain(target.breaks) {
+                    self.builder.patch(jump);
+                }
+                self.builder.emit(Op::Unit);
+            },
+            HuckAst::Break(label, value, _) => {
+                // Only a `loop` has a value to break with
+                if let Some(result) = self.builder.loop_target(label).result {
+                    match value {
+                        Some(value) => self.expr(value),
+                        None => {
+                            self.builder.emit(Op::Unit);
+                        },
+                    }
+                    self.builder.emit(Op::Store(result));
+                    self.builder.emit(Op::Pop);
+                }
+                self.jump(label, true);
+            },
+            HuckAst::Continue(label, _) => self.jump(label, false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use crate::typecheck::{Checker, IntType};
+
+    fn compile_str(s: &str) -> Program {
+        let ast = Parser::new(Scanner::new(s)).parse().unwrap();
+        let checked = Checker::new().check(&ast).unwrap();
+        compile(&checked)
+    }
+
+    #[test]
+    fn locals() {
+        let program = compile_str("{let x = 3; x * 2}");
+        assert_eq!(program.functions[0].locals, 1);
+        assert_eq!(program.functions[0].code, vec![
+            Op::Int(3, IntType::I64),
+            Op::Store(0),
+            Op::Pop,
+            Op::Load(0),
+            Op::Int(2, IntType::I64),
+            Op::Mul,
+            Op::Return,
+        ]);
+    }
+
+    #[test]
+    fn conditional() {
+        let program = compile_str("1 + if true { 2 } else { 3 }");
+        assert_eq!(program.functions[0].code, vec![
+            Op::Int(1, IntType::I64),
+            Op::Bool(true),
+            Op::JumpIfFalse(5),
+            Op::Int(2, IntType::I64),
+            Op::Jump(6),
+            Op::Int(3, IntType::I64),
+            Op::Add,
+            Op::Return,
+        ]);
+    }
+
+    #[test]
+    fn struct_literals() {
+        // Written in declaration order, so straight onto the stack
+        let program = compile_str("{struct P { x: i64, y: bool }; P { x: 1, y: true }.y}");
+        assert_eq!(program.structs.len(), 1);
+        assert_eq!(program.functions[0].code[1..], [
+            Op::Pop,
+            Op::Int(1, IntType::I64),
+            Op::Bool(true),
+            Op::Struct(0),
+            Op::Field(1),
+            Op::Return,
+        ]);
+        // Out of order, so through temporaries
+        let program = compile_str("{struct P { x: i64, y: bool }; P { y: true, x: 1 }}");
+        assert_eq!(program.functions[0].locals, 2);
+        assert_eq!(program.functions[0].code[2..], [
+            Op::Bool(true), Op::Store(0), Op::Pop,
+            Op::Int(1, IntType::I64), Op::Store(1), Op::Pop,
+            Op::Load(1), Op::Load(0), Op::Struct(0),
+            Op::Return,
+        ]);
+    }
+
+    #[test]
+    fn functions() {
+        let program = compile_str("{
+            let f = fn (x: i64): i64 { g(x) };
+            let g = fn (y: i64): i64 { y + 1 };
+            f(1)
+        }");
+        let names = program.functions.iter().map(|f| f.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["main", "f", "g"]);
+        assert_eq!(program.functions[1].code, vec![Op::Load(0), Op::TailCall(2)]);
+        assert_eq!(program.functions[2].arity, 1);
+    }
+}
@@ -0,0 +1,607 @@
+// Bytecode for the stack VM.
+//
+// Every function gets a fixed number of local slots: parameters come
+// first, then one slot per `let` in the body. Expressions push their
+// value onto the operand stack and operators pop their operands. Jump
+// targets are instruction indices within the function, and calls name
+// their target by its index in `Program::functions`; function 0 is the
+// entry point.
+//
+// The `.hbc` file format is a direct serialization of a `Program`: the
+// magic bytes `HBC` and a format version, then each struct as its name
+// and field names, then each enum as its name and its variants' names
+// and payload sizes, then each vtable as the indices of its functions,
+// then each function as its name, arity, local count and code. Instructions are one opcode byte followed by their operands
+// in little-endian order; an integer type is one byte, its index in
+// `IntType::ALL`.
+
+use crate::interp::{EnumShape, StructShape};
+use crate::typecheck::IntType;
+
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Op {
+    Unit,
+    Bool(bool),
+    Int(i64, IntType),
+    Load(u16),
+    // Stores without popping, since `let` is an expression too
+    Store(u16),
+    Pop,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Jump(u32),
+    JumpIfFalse(u32),
+    Call(u16),
+    TailCall(u16),
+    Return,
+    // Convert the integer or bool on top of the stack
+    Cast(IntType),
+    Float(f64),
+    // Convert the number on top of the stack to an f64
+    CastFloat,
+    // Integer arithmetic that wraps instead of failing on overflow
+    WrappingAdd,
+    WrappingSub,
+    WrappingMul,
+    WrappingRem,
+    // Pop as many values as the struct at this index in
+    // `Program::structs` has fields, last field on top, and push the
+    // struct made of them
+    Struct(u16),
+    // Replace the struct on top of the stack with one of its fields
+    Field(u16),
+    // Pop the payload of the variant at index `.1` of the enum at index
+    // `.0` in `Program::enums`, and push the variant
+    Variant(u16, u16),
+    // Replace the enum on top of the stack with the index of its variant,
+    // as a u32
+    Tag,
+    // Replace the enum on top of the stack with a value from its payload
+    Payload(u16),
+    // Fail because no arm of a match matched
+    NoMatch,
+    // Pop a number or bool, write it to the output on a line of its own,
+    // and push ()
+    Print,
+    // Replace the value on top of the stack with a `dyn` of it, using the
+    // vtable at this index in `Program::vtables`
+    Dyn(u16),
+    // Call method `.0` of the `dyn` below the `.1` arguments on top of the
+    // stack, with the value it holds in its place as `self`
+    DynCall(u16, u8),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Function {
+    pub name: String,
+    pub arity: u8,
+    pub locals: u16,
+    pub code: Vec<Op>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Program {
+    pub structs: Vec<Rc<StructShape>>,
+    pub enums: Vec<Rc<EnumShape>>,
+    // The function for each method of the trait, by index
+    pub vtables: Vec<Vec<u16>>,
+    pub functions: Vec<Function>,
+}
+
+const MAGIC: &[u8] = b"HBC";
+const VERSION: u8 = 7;
+
+impl Op {
+    fn opcode(&self) -> u8 {
+        match self {
+            Self::Unit => 0,
+            Self::Bool(_) => 1,
+            Self::Int(..) => 2,
+            Self::Load(_) => 3,
+            Self::Store(_) => 4,
+            Self::Pop => 5,
+            Self::Add => 6,
+            Self::Sub => 7,
+            Self::Mul => 8,
+            Self::Div => 9,
+            Self::Eq => 10,
+            Self::Ne => 11,
+            Self::Lt => 12,
+            Self::Le => 13,
+            Self::Gt => 14,
+            Self::Ge => 15,
+            Self::Jump(_) => 16,
+            Self::JumpIfFalse(_) => 17,
+            Self::Call(_) => 18,
+            Self::TailCall(_) => 19,
+            Self::Return => 20,
+            Self::Cast(_) => 21,
+            Self::Float(_) => 22,
+            Self::CastFloat => 23,
+            Self::WrappingAdd => 24,
+            Self::WrappingSub => 25,
+            Self::WrappingMul => 26,
+            Self::Struct(_) => 27,
+            Self::Field(_) => 28,
+            Self::Variant(..) => 29,
+            Self::Tag => 30,
+            Self::Payload(_) => 31,
+            Self::NoMatch => 32,
+            Self::Print => 33,
+            Self::Rem => 34,
+            Self::WrappingRem => 35,
+            Self::Dyn(_) => 36,
+            Self::DynCall(..) => 37,
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(self.opcode());
+        match self {
+            Self::Bool(b) => out.push(*b as u8),
+            Self::Int(n, t) => {
+                out.push(int_type_index(*t));
+                out.extend(n.to_le_bytes());
+            },
+            Self::Cast(t) => out.push(int_type_index(*t)),
+            Self::Float(x) => out.extend(x.to_bits().to_le_bytes()),
+            Self::Load(slot) | Self::Store(slot) => out.extend(slot.to_le_bytes()),
+            Self::Jump(target) | Self::JumpIfFalse(target) => out.extend(target.to_le_bytes()),
+            Self::Call(func) | Self::TailCall(func) => out.extend(func.to_le_bytes()),
+            Self::Struct(index) | Self::Field(index) | Self::Payload(index) | Self::Dyn(index) => out.extend(index.to_le_bytes()),
+            Self::Variant(index, variant) => {
+                out.extend(index.to_le_bytes());
+                out.extend(variant.to_le_bytes());
+            },
+            Self::DynCall(method, args) => {
+                out.extend(method.to_le_bytes());
+                out.push(*args);
+            },
+            _ => (),
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, String> {
+        let op = match reader.u8()? {
+            0 => Self::Unit,
+            1 => Self::Bool(reader.u8()? != 0),
+            2 => {
+                let t = reader.int_type()?;
+                Self::Int(i64::from_le_bytes(reader.array()?), t)
+            },
+            3 => Self::Load(reader.u16()?),
+            4 => Self::Store(reader.u16()?),
+            5 => Self::Pop,
+            6 => Self::Add,
+            7 => Self::Sub,
+            8 => Self::Mul,
+            9 => Self::Div,
+            10 => Self::Eq,
+            11 => Self::Ne,
+            12 => Self::Lt,
+            13 => Self::Le,
+            14 => Self::Gt,
+            15 => Self::Ge,
+            16 => Self::Jump(reader.u32()?),
+            17 => Self::JumpIfFalse(reader.u32()?),
+            18 => Self::Call(reader.u16()?),
+            19 => Self::TailCall(reader.u16()?),
+            20 => Self::Return,
+            21 => Self::Cast(reader.int_type()?),
+            22 => Self::Float(f64::from_bits(u64::from_le_bytes(reader.array()?))),
+            23 => Self::CastFloat,
+            24 => Self::WrappingAdd,
+            25 => Self::WrappingSub,
+            26 => Self::WrappingMul,
+            27 => Self::Struct(reader.u16()?),
+            28 => Self::Field(reader.u16()?),
+            29 => Self::Variant(reader.u16()?, reader.u16()?),
+            30 => Self::Tag,
+            31 => Self::Payload(reader.u16()?),
+            32 => Self::NoMatch,
+            33 => Self::Print,
+            34 => Self::Rem,
+            35 => Self::WrappingRem,
+            36 => Self::Dyn(reader.u16()?),
+            37 => Self::DynCall(reader.u16()?, reader.u8()?),
+            opcode => return Err(format!("Unknown opcode {}", opcode)),
+        };
+        Ok(op)
+    }
+}
+
+fn int_type_index(t: IntType) -> u8 {
+    IntType::ALL.iter().position(|&u| u == t).unwrap() as u8
+}
+
+fn encode_name(name: &str, out: &mut Vec<u8>) {
+    out.extend((name.len() as u16).to_le_bytes());
+    out.extend(name.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < n {
+            return Err(String::from("Unexpected end of bytecode"));
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn int_type(&mut self) -> Result<IntType, String> {
+        let index = self.u8()?;
+        IntType::ALL.get(index as usize).copied().ok_or_else(|| format!("Unknown integer type {}", index))
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn name(&mut self, what: &str) -> Result<String, String> {
+        let len = self.u16()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| format!("{} name isn't valid UTF-8", what))
+    }
+}
+
+impl Program {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        out.extend((self.structs.len() as u16).to_le_bytes());
+        for shape in &self.structs {
+            encode_name(&shape.name, &mut out);
+            out.extend((shape.fields.len() as u16).to_le_bytes());
+            for field in &shape.fields {
+                encode_name(field, &mut out);
+            }
+        }
+        out.extend((self.enums.len() as u16).to_le_bytes());
+        for shape in &self.enums {
+            encode_name(&shape.name, &mut out);
+            out.extend((shape.variants.len() as u16).to_le_bytes());
+            for (variant, payload) in &shape.variants {
+                encode_name(variant, &mut out);
+                out.extend((*payload as u16).to_le_bytes());
+            }
+        }
+        out.extend((self.vtables.len() as u16).to_le_bytes());
+        for methods in &self.vtables {
+            out.extend((methods.len() as u16).to_le_bytes());
+            for func in methods {
+                out.extend(func.to_le_bytes());
+            }
+        }
+        out.extend((self.functions.len() as u16).to_le_bytes());
+        for function in &self.functions {
+            encode_name(&function.name, &mut out);
+            out.push(function.arity);
+            out.extend(function.locals.to_le_bytes());
+            out.extend((function.code.len() as u32).to_le_bytes());
+            for op in &function.code {
+                op.encode(&mut out);
+            }
+        }
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len()).ok() != Some(MAGIC) {
+            return Err(String::from("Not a huck bytecode file"));
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(format!("Unsupported bytecode version {}", version));
+        }
+
+        let count = reader.u16()?;
+        let mut structs = vec![];
+        for _ in 0..count {
+            let name = reader.name("Struct")?;
+            let field_count = reader.u16()?;
+            let fields = (0..field_count)
+                .map(|_| reader.name("Field"))
+                .collect::<Result<Vec<_>, _>>()?;
+            structs.push(Rc::new(StructShape { name, fields }));
+        }
+
+        let count = reader.u16()?;
+        let mut enums = vec![];
+        for _ in 0..count {
+            let name = reader.name("Enum")?;
+            let variant_count = reader.u16()?;
+            let variants = (0..variant_count)
+                .map(|_| Ok((reader.name("Variant")?, reader.u16()? as usize)))
+                .collect::<Result<Vec<_>, String>>()?;
+            enums.push(Rc::new(EnumShape { name, variants }));
+        }
+
+        let count = reader.u16()?;
+        let mut vtables = vec![];
+        for _ in 0..count {
+            let method_count = reader.u16()?;
+            let methods = (0..method_count).map(|_| reader.u16()).collect::<Result<Vec<_>, _>>()?;
+            vtables.push(methods);
+        }
+
+        let count = reader.u16()?;
+        let mut functions = vec![];
+        for _ in 0..count {
+            let name = reader.name("Function")?;
+            let arity = reader.u8()?;
+            let locals = reader.u16()?;
+            let code_len = reader.u32()?;
+            let code = (0..code_len)
+                .map(|_| Op::decode(&mut reader))
+                .collect::<Result<Vec<_>, _>>()?;
+            functions.push(Function { name, arity, locals, code });
+        }
+        if !reader.bytes.is_empty() {
+            return Err(String::from("Trailing bytes after bytecode"));
+        }
+        Ok(Self { structs, enums, vtables, functions })
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unit => write!(f, "unit"),
+            Self::Bool(b) => write!(f, "bool {}", b),
+            Self::Int(n, t) => write!(f, "int {} {}", t, t.value(*n)),
+            Self::Load(slot) => write!(f, "load {}", slot),
+            Self::Store(slot) => write!(f, "store {}", slot),
+            Self::Pop => write!(f, "pop"),
+            Self::Add => write!(f, "add"),
+            Self::Sub => write!(f, "sub"),
+            Self::Mul => write!(f, "mul"),
+            Self::Div => write!(f, "div"),
+            Self::Eq => write!(f, "eq"),
+            Self::Ne => write!(f, "ne"),
+            Self::Lt => write!(f, "lt"),
+            Self::Le => write!(f, "le"),
+            Self::Gt => write!(f, "gt"),
+            Self::Ge => write!(f, "ge"),
+            Self::Jump(target) => write!(f, "jmp {}", target),
+            Self::JumpIfFalse(target) => write!(f, "jmpf {}", target),
+            Self::Call(func) => write!(f, "call {}", func),
+            Self::TailCall(func) => write!(f, "tailcall {}", func),
+            Self::Return => write!(f, "ret"),
+            Self::Cast(t) => write!(f, "cast {}", t),
+            Self::Float(x) => write!(f, "float {:?}", x),
+            Self::CastFloat => write!(f, "cast f64"),
+            Self::WrappingAdd => write!(f, "wadd"),
+            Self::WrappingSub => write!(f, "wsub"),
+            Self::WrappingMul => write!(f, "wmul"),
+            Self::Struct(index) => write!(f, "struct {}", index),
+            Self::Field(index) => write!(f, "field {}", index),
+            Self::Variant(index, variant) => write!(f, "variant {} {}", index, variant),
+            Self::Tag => write!(f, "tag"),
+            Self::Payload(index) => write!(f, "payload {}", index),
+            Self::NoMatch => write!(f, "nomatch"),
+            Self::Print => write!(f, "print"),
+            Self::Rem => write!(f, "rem"),
+            Self::WrappingRem => write!(f, "wrem"),
+            Self::Dyn(index) => write!(f, "dyn {}", index),
+            Self::DynCall(method, args) => write!(f, "dyncall {} {}", method, args),
+        }
+    }
+}
+
+// The disassembly listing used by `huck disasm`
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, shape) in self.structs.iter().enumerate() {
+            writeln!(f, "struct {} {} {{ {} }}", i, shape.name, shape.fields.join(", "))?;
+        }
+        for (i, shape) in self.enums.iter().enumerate() {
+            let variants = shape.variants.iter().map(|(name, payload)| format!("{}/{}", name, payload)).collect::<Vec<_>>();
+            writeln!(f, "enum {} {} {{ {} }}", i, shape.name, variants.join(", "))?;
+        }
+        for (i, methods) in self.vtables.iter().enumerate() {
+            let names = methods.iter()
+                .map(|func| self.functions.get(*func as usize).map_or("?", |f| &f.name))
+                .collect::<Vec<_>>();
+            writeln!(f, "vtable {} {{ {} }}", i, names.join(", "))?;
+        }
+        if !self.structs.is_empty() || !self.enums.is_empty() || !self.vtables.is_empty() {
+            writeln!(f)?;
+        }
+        for (i, function) in self.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(
+                f,
+                "fn {} {} (arity {}, locals {}):",
+                i,
+                function.name,
+                function.arity,
+                function.locals
+            )?;
+            for (ip, op) in function.code.iter().enumerate() {
+                match op {
+                    Op::Call(func) | Op::TailCall(func) => {
+                        let name = self.functions.get(*func as usize).map_or("?", |f| &f.name);
+                        writeln!(f, "{:>6}  {:<16}; {}", ip, op.to_string(), name)?
+                    },
+                    Op::Struct(index) => {
+                        let name = self.structs.get(*index as usize).map_or("?", |s| &s.name);
+                        writeln!(f, "{:>6}  {:<16}; {}", ip, op.to_string(), name)?
+                    },
+                    Op::Variant(index, variant) => {
+                        let name = self.enums.get(*index as usize)
+                            .and_then(|e| Some(format!("{}::{}", e.name, e.variants.get(*variant as usize)?.0)))
+                            .unwrap_or_else(|| String::from("?"));
+                        writeln!(f, "{:>6}  {:<16}; {}", ip, op.to_string(), name)?
+                    },
+                    _ => writeln!(f, "{:>6}  {}", ip, op)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn program() -> Program {
+        Program {
+            structs: vec![],
+            enums: vec![],
+            vtables: vec![],
+            functions: vec![
+                Function {
+                    name: "main".to_string(),
+                    arity: 0,
+                    locals: 1,
+                    code: vec![Op::Int(-7, IntType::I64), Op::Cast(IntType::U8), Op::Store(0), Op::Call(1), Op::Return],
+                },
+                Function {
+                    name: "f".to_string(),
+                    arity: 0,
+                    locals: 0,
+                    code: vec![Op::Bool(true), Op::JumpIfFalse(3), Op::Unit, Op::Return],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let program = program();
+        assert_eq!(Program::decode(&program.encode()), Ok(program));
+    }
+
+    #[test]
+    fn bad_files() {
+        let bytes = program().encode();
+        assert!(Program::decode(b"ELF").is_err());
+        assert!(Program::decode(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn structs() {
+        let program = Program {
+            structs: vec![Rc::new(StructShape { name: "P".to_string(), fields: vec!["x".to_string(), "y".to_string()] })],
+            enums: vec![],
+            vtables: vec![],
+            functions: vec![Function {
+                name: "main".to_string(),
+                arity: 0,
+                locals: 0,
+                code: vec![Op::Unit, Op::Bool(true), Op::Struct(0), Op::Field(1), Op::Return],
+            }],
+        };
+        assert_eq!(Program::decode(&program.encode()).as_ref(), Ok(&program));
+        assert_eq!(program.to_string(), "\
+struct 0 P { x, y }
+
+fn 0 main (arity 0, locals 0):
+     0  unit
+     1  bool true
+     2  struct 0        ; P
+     3  field 1
+     4  ret
+");
+    }
+
+    #[test]
+    fn enums() {
+        let program = Program {
+            structs: vec![],
+            enums: vec![Rc::new(EnumShape { name: "O".to_string(), variants: vec![("S".to_string(), 1), ("N".to_string(), 0)] })],
+            vtables: vec![],
+            functions: vec![Function {
+                name: "main".to_string(),
+                arity: 0,
+                locals: 0,
+                code: vec![Op::Bool(true), Op::Variant(0, 0), Op::Payload(0), Op::NoMatch],
+            }],
+        };
+        assert_eq!(Program::decode(&program.encode()).as_ref(), Ok(&program));
+        assert_eq!(program.to_string(), "\
+enum 0 O { S/1, N/0 }
+
+fn 0 main (arity 0, locals 0):
+     0  bool true
+     1  variant 0 0     ; O::S
+     2  payload 0
+     3  nomatch
+");
+    }
+
+    #[test]
+    fn vtables() {
+        let function = |name: &str, code| Function { name: name.to_string(), arity: 1, locals: 1, code };
+        let program = Program {
+            structs: vec![],
+            enums: vec![],
+            vtables: vec![vec![1, 2]],
+            functions: vec![
+                Function { name: "main".to_string(), arity: 0, locals: 0, code: vec![Op::Unit, Op::Dyn(0), Op::DynCall(1, 0), Op::Return] },
+                function("a", vec![Op::Load(0), Op::Return]),
+                function("b", vec![Op::Load(0), Op::Return]),
+            ],
+        };
+        assert_eq!(Program::decode(&program.encode()).as_ref(), Ok(&program));
+        assert!(program.to_string().starts_with("\
+vtable 0 { a, b }
+
+fn 0 main (arity 0, locals 0):
+     0  unit
+     1  dyn 0
+     2  dyncall 1 0
+     3  ret
+"));
+    }
+
+    #[test]
+    fn disassembly() {
+        assert_eq!(program().to_string(), "\
+fn 0 main (arity 0, locals 1):
+     0  int i64 -7
+     1  cast u8
+     2  store 0
+     3  call 1          ; f
+     4  ret
+
+fn 1 f (arity 0, locals 0):
+     0  bool true
+     1  jmpf 3
+     2  unit
+     3  ret
+");
+    }
+}
@@ -0,0 +1,316 @@
+// Command-line parsing for the `huck` driver, and the ways a run of it
+// can fail. Each kind of failure gets its own exit code so scripts can
+// tell a bad program from a bad invocation.
+
+use huck::opt::OptLevel;
+use huck::{Diagnostic, Stage};
+
+use std::fmt;
+
+pub const USAGE: &str = "\
+Usage: huck <command> [options] <file>
+
+Commands:
+    build     Compile a program to a native executable (or whatever --emit says)
+    check     Parse and type check a program without compiling it
+    run       Run a program (huck source or a compiled .hbc file)
+    disasm    Print the bytecode for a program
+    repl      Start an interactive session
+    lsp       Run a language server on stdin and stdout
+    fmt       Reformat a program in place (or to stdout, if it came from stdin)
+
+Options:
+    -o <path>         Write output to <path>; executables default to the input's
+                      name, everything else goes to stdout
+    --emit=<kind>     What build produces: exe (the default), tokens, ast,
+                      typed-ast, ir, asm, llvm or hbc
+    -O0, -O1, -O2     Optimization level for build (default -O0)
+    --linker <cc>     C compiler used to assemble and link executables
+                      (default $HUCK_CC, or cc). It has to be a compiler
+                      driver, not a bare linker like ld
+    --keep-temps      Keep the intermediate files from linking
+    --error-format=<format>
+                      How errors in the program are reported: human (the
+                      default) or json, one object per line on stderr
+    --interp          Run with the tree-walking interpreter instead of the VM
+    --check           With fmt, change nothing and fail if the file isn't
+                      formatted
+    --width <n>       Line width fmt aims for (default 80)
+    -h, --help        Print this message
+    -V, --version     Print the huck version
+
+Use - as the file to read the program from stdin.
+
+Exit codes:
+    0  success
+    1  runtime error
+    2  usage error
+    3  syntax error
+    4  type error
+    5  I/O error
+    6  assembling or linking failed
+    7  fmt --check found an unformatted file
+";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Command {
+    Build,
+    Check,
+    Run,
+    Disasm,
+    Repl,
+    Lsp,
+    Fmt,
+    Help,
+    Version,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Emit {
+    Tokens,
+    Ast,
+    TypedAst,
+    Ir,
+    Asm,
+    Llvm,
+    Hbc,
+    Exe,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ErrorFormat {
+    Human,
+    Json,
+}
+
+impl Emit {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "tokens" => Some(Self::Tokens),
+            "ast" => Some(Self::Ast),
+            "typed-ast" => Some(Self::TypedAst),
+            "ir" => Some(Self::Ir),
+            "asm" => Some(Self::Asm),
+            "llvm" => Some(Self::Llvm),
+            "hbc" => Some(Self::Hbc),
+            "exe" => Some(Self::Exe),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub command: Command,
+    // `-` means stdin
+    pub input: Option<String>,
+    pub output: Option<String>,
+    pub emit: Emit,
+    pub level: OptLevel,
+    pub interp: bool,
+    pub linker: Option<String>,
+    pub keep_temps: bool,
+    pub error_format: ErrorFormat,
+    pub check: bool,
+    pub width: Option<usize>,
+}
+
+pub fn parse_args(args: &[String]) -> Result<Options, Failure> {
+    let mut options = Options {
+        command: Command::Help,
+        input: None,
+        output: None,
+        emit: Emit::Exe,
+        level: OptLevel::O0,
+        interp: false,
+        linker: None,
+        keep_temps: false,
+        error_format: ErrorFormat::Human,
+        check: false,
+        width: None,
+    };
+    let mut command = None;
+    let mut emit = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Options { command: Command::Help, ..options }),
+            "-V" | "--version" => return Ok(Options { command: Command::Version, ..options }),
+            "-o" => {
+                let path = args.next().ok_or_else(|| Failure::Usage(String::from("-o needs a path")))?;
+                options.output = Some(path.to_string());
+            },
+            "--linker" => {
+                let cc = args.next().ok_or_else(|| Failure::Usage(String::from("--linker needs a command")))?;
+                options.linker = Some(cc.to_string());
+            },
+            "--interp" => options.interp = true,
+            "--keep-temps" => options.keep_temps = true,
+            "--check" => options.check = true,
+            "--width" => {
+                let width = args.next().ok_or_else(|| Failure::Usage(String::from("--width needs a number")))?;
+                let width = width.parse().map_err(|_| Failure::Usage(format!("Bad --width {:?}", width)))?;
+                options.width = Some(width);
+            },
+            _ => {
+                if let Some(level) = OptLevel::from_flag(arg) {
+                    options.level = level;
+                } else if let Some(name) = arg.strip_prefix("--emit=") {
+                    emit = Some(Emit::from_name(name)
+                        .ok_or_else(|| Failure::Usage(format!("Unknown --emit kind {:?}", name)))?);
+                } else if let Some(format) = arg.strip_prefix("--error-format=") {
+                    options.error_format = match format {
+                        "human" => ErrorFormat::Human,
+                        "json" => ErrorFormat::Json,
+                        _ => return Err(Failure::Usage(format!("Unknown --error-format {:?}", format))),
+                    };
+                } else if arg.starts_with('-') && arg != "-" {
+                    return Err(Failure::Usage(format!("Unknown option {:?}", arg)));
+                } else if command.is_none() {
+                    command = Some(match arg.as_str() {
+                        "build" => Command::Build,
+                        "check" => Command::Check,
+                        "run" => Command::Run,
+                        "disasm" => Command::Disasm,
+                        "repl" => Command::Repl,
+                        "lsp" => Command::Lsp,
+                        "fmt" => Command::Fmt,
+                        "help" => Command::Help,
+                        _ => return Err(Failure::Usage(format!("Unknown command {:?}", arg))),
+                    });
+                } else if options.input.is_none() {
+                    options.input = Some(arg.to_string());
+                } else {
+                    return Err(Failure::Usage(format!("Unexpected argument {:?}", arg)));
+                }
+            },
+        }
+    }
+
+    options.command = command.ok_or(Failure::NoCommand)?;
+    match options.command {
+        Command::Build | Command::Check | Command::Run | Command::Disasm | Command::Fmt if options.input.is_none() => {
+            return Err(Failure::Usage(String::from("No input file given")))
+        },
+        Command::Repl | Command::Lsp | Command::Help | Command::Version if options.input.is_some() => {
+            return Err(Failure::Usage(String::from("This command doesn't take a file")))
+        },
+        _ => (),
+    }
+    if (options.check || options.width.is_some()) && options.command != Command::Fmt {
+        return Err(Failure::Usage(String::from("--check and --width only make sense with fmt")));
+    }
+    if let Some(emit) = emit {
+        if options.command != Command::Build {
+            return Err(Failure::Usage(String::from("--emit only makes sense with build")));
+        }
+        options.emit = emit;
+    }
+    Ok(options)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Failure {
+    Usage(String),
+    // Run without a command; the whole usage text is shown on stderr
+    NoCommand,
+    // The program itself was bad; the message is shown as is
+    Rejected(Stage, String),
+    Io(String),
+    Runtime(String),
+    Link(String),
+    // `fmt --check` would have changed the named file
+    Unformatted(String),
+}
+
+impl Failure {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Runtime(_) => 1,
+            Self::Usage(_) | Self::NoCommand => 2,
+            Self::Rejected(Stage::Syntax, _) => 3,
+            Self::Rejected(Stage::Type, _) => 4,
+            Self::Rejected(Stage::Runtime, _) => 1,
+            Self::Io(_) => 5,
+            Self::Link(_) => 6,
+            Self::Unformatted(_) => 7,
+        }
+    }
+}
+
+impl From<Diagnostic> for Failure {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Self::Rejected(diagnostic.stage, diagnostic.to_string())
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Usage(msg) => write!(f, "{}\n\nRun huck --help for usage", msg),
+            Self::NoCommand => write!(f, "{}", USAGE.trim_end()),
+            Self::Rejected(_, msg) => write!(f, "{}", msg.trim_end()),
+            Self::Io(msg) => write!(f, "I/O error: {}", msg),
+            Self::Runtime(msg) => write!(f, "Runtime error: {}", msg),
+            Self::Link(msg) => write!(f, "Link error: {}", msg),
+            Self::Unformatted(path) => write!(f, "{} isn't formatted; run huck fmt on it", path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, Failure> {
+        parse_args(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn build() {
+        let options = parse(&["build", "-O2", "prog.huck", "-o", "prog.s", "--emit=llvm"]).unwrap();
+        assert_eq!(options.command, Command::Build);
+        assert_eq!(options.input, Some("prog.huck".to_string()));
+        assert_eq!(options.output, Some("prog.s".to_string()));
+        assert_eq!(options.emit, Emit::Llvm);
+        assert_eq!(options.level, OptLevel::O2);
+    }
+
+    #[test]
+    fn stdin_and_flags() {
+        let options = parse(&["run", "-", "--interp", "--error-format=json"]).unwrap();
+        assert_eq!(options.input, Some("-".to_string()));
+        assert!(options.interp);
+        assert_eq!(options.error_format, ErrorFormat::Json);
+        let options = parse(&["fmt", "--check", "--width", "100", "x.huck"]).unwrap();
+        assert!(options.check);
+        assert_eq!(options.width, Some(100));
+        assert_eq!(parse(&["help"]).unwrap().command, Command::Help);
+        assert_eq!(parse(&["--help"]).unwrap().command, Command::Help);
+        assert_eq!(parse(&["check", "--version"]).unwrap().command, Command::Version);
+    }
+
+    #[test]
+    fn usage_errors() {
+        assert_eq!(parse(&[]), Err(Failure::NoCommand));
+        for args in [
+            &[][..],
+            &["-O2"],
+            &["build"],
+            &["frobnicate", "x.huck"],
+            &["build", "x.huck", "--emit=elf"],
+            &["run", "x.huck", "--emit=ir"],
+            &["build", "x.huck", "-o"],
+            &["build", "x.huck", "--linker"],
+            &["repl", "x.huck"],
+            &["check", "x.huck", "y.huck"],
+            &["check", "--bogus", "x.huck"],
+            &["check", "x.huck", "--error-format=xml"],
+            &["fmt", "x.huck", "--width", "wide"],
+            &["check", "x.huck", "--check"],
+        ] {
+            assert_eq!(parse(args).map_err(|f| f.exit_code()), Err(2), "{:?}", args);
+        }
+    }
+}
@@ -1,26 +1,1080 @@
-use crate::parser::HuckAst::Num;
-use crate::typecheck::CheckOutput;
+// x86-64 assembly backend (AT&T syntax, System V).
+//
+// Every virtual register gets its own 8-byte stack slot; instructions
+// load their operands into %rax/%rcx, do the work, and store the result
+// back. Dumb, but obviously correct, and the optimizer gets to work on
+// the IR before we ever see it. It also means a mutable variable's
+// register is just its slot, which every assignment stores to.
+//
+// Functions use the System V calling convention, so tail calls can jump
+// straight into the callee as long as its stack arguments fit into the
+// space our own caller set aside for ours.
+//
+// Floats travel between huck functions as their bits in the integer
+// registers, and are only moved into %xmm registers to do arithmetic on
+// them. A float result is also left in %xmm0, where C expects it.
+//
+// Structs are laid out like C's: each field aligned to its size, in the
+// order they're declared. A struct register's slot goes unused, and the
+// struct itself lives in memory further down the frame. They're passed
+// to functions by address, and the callee copies them in; a function
+// returning one is passed the address to write it to as a hidden first
+// argument, and hands it back in %rax. That's how System V returns big
+// structs, though we do it for small ones too. Passing a struct to a tail
+// call points the callee into our frame, so those stay real calls.
+//
+// Enums are a u32 tag followed by the payload of whichever variant they
+// hold, laid out like a struct of its own; every variant's payload starts
+// in the same place. Otherwise they're treated just like structs.
+//
+// A `dyn` is treated like a struct of two pointers: one to a copy of its
+// value from `malloc`, one to its vtable in `.data.rel.ro`. Calling a
+// method through it passes the first pointer as `self`. A struct or enum
+// is passed by address anyway, so its methods go in the vtable as they
+// are; for anything else the vtable has a shim that loads `self` from the
+// pointer and jumps to the method.
+//
+// Checked arithmetic jumps to a stub at the end of its function when it
+// fails, which calls the runtime's `huckrt_panic` with the message and
+// where in the source it happened. So does a match where no arm matches.
+//
+// The output doesn't define `main`: the runtime in `runtime/huck_rt.c`
+// does, and calls `huck_main`. See `link` for putting the two together.
+// The runtime's own helpers are prefixed with `huckrt_` so they can't
+// collide with huck functions.
 
-use std::io::Write;
+use crate::ir::{
+    BinOp, Block, BlockId, Const, EnumId, Function, Inst, Loc, Module, Operand, Reg, StructId, Terminator, Ty, VTableDef, VTableId,
+    DIVISION_BY_ZERO, NO_MATCH,
+};
+use crate::typecheck::IntType;
 
-type CompileInput = CheckOutput;
+use std::collections::HashMap;
+use std::io::{self, Write};
 
-// type CompileResult<T> = Result<T, String>;
-type CompileResult<T> = T;
+type CompileInput = Module;
 
-pub fn compile<T>(code: CompileInput, output: &mut T) -> CompileResult<()>
+type CompileResult<T> = io::Result<T>;
+
+pub fn compile<T>(code: &CompileInput, output: &mut T) -> CompileResult<()>
+where T: Write
+{
+    write_header(output)?;
+    let mut traps = Traps::default();
+    for function in &code.functions {
+        compile_function(code, function, &mut traps, output)?;
+    }
+    for (i, vtable) in code.vtables.iter().enumerate() {
+        write_vtable(code, VTableId(i), vtable, output)?;
+    }
+    traps.write_messages(output)?;
+    // Tell the runtime to print main's result rather than exit with it
+    if code.functions.iter().any(|f| f.name == "main" && f.ret == Ty::F64) {
+        writeln!(output, ".global huckrt_main_f64")?;
+        writeln!(output, ".set huckrt_main_f64, {}", symbol("main"))?;
+    }
+    writeln!(output, "  .section .note.GNU-stack,\"\",@progbits")
+}
+
+fn write_header<T>(output: &mut T) -> CompileResult<()> where T: Write {
+    writeln!(output, "  .text")?;
+    writeln!(output, ".global {}", symbol("main"))
+}
+
+const ARG_REGISTERS: [&str; 6] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+
+// Huck functions get a prefix so they can't collide with libc
+fn symbol(name: &str) -> String {
+    format!("huck_{}", name)
+}
+
+fn vtable_label(id: VTableId) -> String {
+    format!(".Lvtable{}", id.0)
+}
+
+fn write_vtable<T>(module: &Module, id: VTableId, vtable: &VTableDef, output: &mut T) -> CompileResult<()> where T: Write {
+    let mut entries = vec![];
+    for (i, method) in vtable.methods.iter().enumerate() {
+        if in_memory(vtable.ty) {
+            entries.push(symbol(method));
+            continue;
+        }
+        let function = module.functions.iter().find(|f| f.name == *method).expect("Functions in vtables are kept");
+        // `self` comes after the address for the result, if there is one
+        let reg = ARG_REGISTERS[in_memory(function.ret) as usize];
+        let shim = format!("{}_{}", vtable_label(id), i);
+        writeln!(output, "{}:", shim)?;
+        writeln!(output, "  movq ({}), {}", reg, reg)?;
+        writeln!(output, "  jmp {}", symbol(method))?;
+        entries.push(shim);
+    }
+    writeln!(output, "  .section .data.rel.ro")?;
+    writeln!(output, "  .p2align 3")?;
+    writeln!(output, "{}:", vtable_label(id))?;
+    for entry in entries {
+        writeln!(output, "  .quad {}", entry)?;
+    }
+    writeln!(output, "  .text")
+}
+
+fn stack_arg_count(arg_count: usize) -> usize {
+    arg_count.saturating_sub(ARG_REGISTERS.len())
+}
+
+// Incoming stack arguments sit above the saved %rbp and return address
+fn incoming_arg(index: usize) -> String {
+    format!("{}(%rbp)", 16 + 8 * index)
+}
+
+// Where each field of a struct goes, in bytes from its start
+struct Layout {
+    offsets: Vec<usize>,
+    size: usize,
+    align: usize,
+}
+
+fn layout(module: &Module, id: StructId) -> Layout {
+    let fields = module.structs[id.0].fields.iter().map(|(_, ty)| *ty).collect::<Vec<_>>();
+    layout_fields(module, &fields, 0)
+}
+
+// Offsets are from the start of the enum, tag included
+fn variant_layout(module: &Module, id: EnumId, variant: usize) -> Layout {
+    layout_fields(module, &module.enums[id.0].variants[variant].1, TAG_SIZE)
+}
+
+const TAG_SIZE: usize = 4;
+
+// Fields one after the other, starting `start` bytes in
+fn layout_fields(module: &Module, fields: &[Ty], start: usize) -> Layout {
+    let mut offsets = vec![];
+    let mut size = start;
+    let mut align = 1;
+    for ty in fields {
+        let (field_size, field_align) = size_align(module, *ty);
+        size = size.next_multiple_of(field_align);
+        offsets.push(size);
+        size += field_size;
+        align = align.max(field_align);
+    }
+    Layout { offsets, size: size.next_multiple_of(align), align }
+}
+
+pub(crate) fn size_align(module: &Module, ty: Ty) -> (usize, usize) {
+    match ty {
+        Ty::Unit => (0, 1),
+        Ty::Bool => (1, 1),
+        Ty::Int(t) => (t.bits() as usize / 8, t.bits() as usize / 8),
+        Ty::F64 => (8, 8),
+        Ty::Struct(id) => {
+            let layout = layout(module, id);
+            (layout.size, layout.align)
+        },
+        Ty::Enum(id) => {
+            let variants = (0..module.enums[id.0].variants.len()).map(|variant| variant_layout(module, id, variant));
+            let (size, align) = variants.fold((TAG_SIZE, TAG_SIZE), |(size, align), layout| {
+                (size.max(layout.size), align.max(layout.align))
+            });
+            (size.next_multiple_of(align), align)
+        },
+        Ty::Dyn => (16, 8),
+    }
+}
+
+// Structs, enums and `dyn`s live in memory rather than in a register's
+// slot
+fn in_memory(ty: Ty) -> bool {
+    matches!(ty, Ty::Struct(_) | Ty::Enum(_) | Ty::Dyn)
+}
+
+// Where a function keeps its registers: an 8-byte slot each, then the
+// address to return a struct to if there is one, then the memory of
+// every struct or enum register
+struct Frame {
+    // How far below %rbp each struct or enum register's memory starts
+    structs: HashMap<Reg, usize>,
+    size: usize,
+}
+
+impl Frame {
+    fn new(module: &Module, function: &Function) -> Self {
+        let mut top = sret_offset(function);
+        let mut structs = HashMap::new();
+        for (i, ty) in function.regs.iter().enumerate() {
+            if in_memory(*ty) {
+                top += size_align(module, *ty).0.next_multiple_of(8);
+                structs.insert(Reg(i), top);
+            }
+        }
+        // Keep %rsp 16-byte aligned
+        Self { structs, size: top.next_multiple_of(16) }
+    }
+
+    // Byte `offset` of a struct register
+    fn at(&self, reg: Reg, offset: usize) -> String {
+        format!("-{}(%rbp)", self.structs[&reg] - offset)
+    }
+}
+
+// Where the caller's address for our struct result is kept
+fn sret_offset(function: &Function) -> usize {
+    let regs = function.regs.len() * 8;
+    if in_memory(function.ret) { regs + 8 } else { regs }
+}
+
+fn sret_slot(function: &Function) -> String {
+    format!("-{}(%rbp)", sret_offset(function))
+}
+
+// Arguments as the callee sees them
+enum Arg {
+    Value(Operand),
+    // A struct, or where to put one
+    Address(String),
+    // The address our own caller gave us for our result, passed on by a
+    // tail call
+    Saved(String),
+    // The pointer to a `dyn`'s value, to pass as `self`
+    DynValue(String),
+}
+
+fn load_arg<T>(arg: &Arg, dst: &str, output: &mut T) -> CompileResult<()> where T: Write {
+    match arg {
+        Arg::Value(operand) => load(operand, dst, output),
+        Arg::Address(address) => writeln!(output, "  leaq {}, {}", address, dst),
+        Arg::Saved(slot) | Arg::DynValue(slot) => writeln!(output, "  movq {}, {}", slot, dst),
+    }
+}
+
+// `ret` is where the callee should put its result, if it's a struct
+fn call_args(frame: &Frame, ret: Option<Arg>, args: &[Operand]) -> Vec<Arg> {
+    let args = args.iter().map(|arg| match arg {
+        Operand::Reg(reg) if frame.structs.contains_key(reg) => Arg::Address(frame.at(*reg, 0)),
+        _ => Arg::Value(*arg),
+    });
+    ret.into_iter().chain(args).collect()
+}
+
+// Copy `size` bytes from (%rsi) to (%rdi)
+fn copy_bytes<T>(size: usize, output: &mut T) -> CompileResult<()> where T: Write {
+    if size > 0 {
+        writeln!(output, "  movq ${}, %rcx", size)?;
+        writeln!(output, "  rep movsb")?;
+    }
+    Ok(())
+}
+
+fn copy_struct<T>(module: &Module, ty: Ty, src: &str, dst: &str, output: &mut T) -> CompileResult<()> where T: Write {
+    writeln!(output, "  leaq {}, %rsi", src)?;
+    writeln!(output, "  leaq {}, %rdi", dst)?;
+    copy_bytes(size_align(module, ty).0, output)
+}
+
+// Store %rax into a field of type `ty`
+fn store_field<T>(ty: Ty, address: &str, output: &mut T) -> CompileResult<()> where T: Write {
+    match ty {
+        Ty::Unit => Ok(()),
+        Ty::Bool | Ty::Int(IntType::I8 | IntType::U8) => writeln!(output, "  movb %al, {}", address),
+        Ty::Int(IntType::I16 | IntType::U16) => writeln!(output, "  movw %ax, {}", address),
+        Ty::Int(IntType::I32 | IntType::U32) => writeln!(output, "  movl %eax, {}", address),
+        Ty::Int(IntType::I64 | IntType::U64) | Ty::F64 => writeln!(output, "  movq %rax, {}", address),
+        Ty::Struct(_) | Ty::Enum(_) | Ty::Dyn => unreachable!("Storing a {} from a register", ty),
+    }
+}
+
+// Load a field of type `ty` into %rax, extended the way registers keep it
+fn load_field<T>(ty: Ty, address: &str, output: &mut T) -> CompileResult<()> where T: Write {
+    match ty {
+        Ty::Unit => writeln!(output, "  xorl %eax, %eax"),
+        Ty::Bool | Ty::Int(IntType::U8) => writeln!(output, "  movzbq {}, %rax", address),
+        Ty::Int(IntType::I8) => writeln!(output, "  movsbq {}, %rax", address),
+        Ty::Int(IntType::I16) => writeln!(output, "  movswq {}, %rax", address),
+        Ty::Int(IntType::U16) => writeln!(output, "  movzwq {}, %rax", address),
+        Ty::Int(IntType::I32) => writeln!(output, "  movslq {}, %rax", address),
+        Ty::Int(IntType::U32) => writeln!(output, "  movl {}, %eax", address),
+        Ty::Int(IntType::I64 | IntType::U64) | Ty::F64 => writeln!(output, "  movq {}, %rax", address),
+        Ty::Struct(_) | Ty::Enum(_) | Ty::Dyn => unreachable!("Loading a {} into a register", ty),
+    }
+}
+
+// The failure paths of checked arithmetic
+#[derive(Default)]
+struct Traps {
+    messages: Vec<String>,
+    // Label, index into `messages`, and where it happened
+    stubs: Vec<(String, usize, Loc)>,
+    count: usize,
+}
+
+impl Traps {
+    // Returns the label to jump to
+    fn add(&mut self, function: &Function, message: String, loc: Loc) -> String {
+        let index = match self.messages.iter().position(|m| *m == message) {
+            Some(index) => index,
+            None => {
+                self.messages.push(message);
+                self.messages.len() - 1
+            },
+        };
+        let label = format!(".L{}_trap{}", function.name, self.count);
+        self.count += 1;
+        self.stubs.push((label.clone(), index, loc));
+        label
+    }
+
+    fn write_stubs<T>(&mut self, output: &mut T) -> CompileResult<()> where T: Write {
+        for (label, index, loc) in self.stubs.drain(..) {
+            writeln!(output, "{}:", label)?;
+            writeln!(output, "  leaq .Lpanic_message{}(%rip), %rdi", index)?;
+            writeln!(output, "  movq ${}, %rsi", loc.line)?;
+            writeln!(output, "  movq ${}, %rdx", loc.col)?;
+            writeln!(output, "  call huckrt_panic")?;
+        }
+        Ok(())
+    }
+
+    fn write_messages<T>(&self, output: &mut T) -> CompileResult<()> where T: Write {
+        if self.messages.is_empty() {
+            return Ok(());
+        }
+        writeln!(output, "  .section .rodata")?;
+        for (i, message) in self.messages.iter().enumerate() {
+            writeln!(output, ".Lpanic_message{}:", i)?;
+            writeln!(output, "  .asciz \"{}\"", message)?;
+        }
+        writeln!(output, "  .text")
+    }
+}
+
+fn compile_function<T>(module: &Module, function: &Function, traps: &mut Traps, output: &mut T) -> CompileResult<()>
+where T: Write
+{
+    let frame = Frame::new(module, function);
+
+    writeln!(output, "{}:", symbol(&function.name))?;
+    writeln!(output, "  pushq %rbp")?;
+    writeln!(output, "  movq %rsp, %rbp")?;
+    if frame.size > 0 {
+        writeln!(output, "  subq ${}, %rsp", frame.size)?;
+    }
+
+    // Spill parameters into their slots, after the address for our
+    // result if we return a struct
+    let mut slots = function.params.iter().map(|param| slot(*param)).collect::<Vec<_>>();
+    if in_memory(function.ret) {
+        slots.insert(0, sret_slot(function));
+    }
+    for (i, slot) in slots.iter().enumerate() {
+        match ARG_REGISTERS.get(i) {
+            Some(reg) => writeln!(output, "  movq {}, {}", reg, slot)?,
+            None => {
+                writeln!(output, "  movq {}, %rax", incoming_arg(i - ARG_REGISTERS.len()))?;
+                writeln!(output, "  movq %rax, {}", slot)?;
+            },
+        }
+    }
+    // Struct parameters arrived as addresses, and we want our own copy
+    for param in &function.params {
+        if frame.structs.contains_key(param) {
+            writeln!(output, "  movq {}, %rsi", slot(*param))?;
+            writeln!(output, "  leaq {}, %rdi", frame.at(*param, 0))?;
+            copy_bytes(size_align(module, function.reg_type(*param)).0, output)?;
+        }
+    }
+
+    for (i, block) in function.blocks.iter().enumerate() {
+        compile_block(module, function, &frame, BlockId(i), block, traps, output)?;
+    }
+    traps.write_stubs(output)
+}
+
+fn compile_block<T>(
+    module: &Module,
+    function: &Function,
+    frame: &Frame,
+    id: BlockId,
+    block: &Block,
+    traps: &mut Traps,
+    output: &mut T,
+) -> CompileResult<()>
 where T: Write
 {
-    match code {
-        Num(n, _) => {
-            write_header(output);
-            output.write(
-                format!("  movl ${}, %eax\n  ret\n", n).as_bytes()
-            );
+    writeln!(output, "{}:", block_label(function, id))?;
+
+    for inst in &block.insts {
+        match inst {
+            Inst::Copy { dst, src: Operand::Reg(src) } if frame.structs.contains_key(dst) => {
+                copy_struct(module, function.reg_type(*dst), &frame.at(*src, 0), &frame.at(*dst, 0), output)?;
+            },
+            Inst::Copy { dst, src } => {
+                load(src, "%rax", output)?;
+                store(*dst, output)?;
+            },
+            Inst::Binary { dst, op, lhs, rhs } if function.operand_type(lhs) == Ty::F64 => {
+                load(lhs, "%rax", output)?;
+                load(rhs, "%rcx", output)?;
+                writeln!(output, "  movq %rax, %xmm0")?;
+                writeln!(output, "  movq %rcx, %xmm1")?;
+                match op {
+                    BinOp::Add => float_arithmetic("addsd", output)?,
+                    BinOp::Sub => float_arithmetic("subsd", output)?,
+                    BinOp::Mul => float_arithmetic("mulsd", output)?,
+                    BinOp::Div => float_arithmetic("divsd", output)?,
+                    op => float_compare(*op, output)?,
+                }
+                store(*dst, output)?;
+            },
+            Inst::Binary { dst, op, lhs, rhs } => {
+                load(lhs, "%rax", output)?;
+                load(rhs, "%rcx", output)?;
+                let signed = match function.operand_type(lhs) {
+                    Ty::Int(t) => t.is_signed(),
+                    Ty::Unit | Ty::Bool | Ty::F64 | Ty::Struct(_) | Ty::Enum(_) | Ty::Dyn => true,
+                };
+                match op {
+                    BinOp::Add => writeln!(output, "  addq %rcx, %rax")?,
+                    BinOp::Sub => writeln!(output, "  subq %rcx, %rax")?,
+                    BinOp::Mul => writeln!(output, "  imulq %rcx, %rax")?,
+                    BinOp::Div if signed => {
+                        writeln!(output, "  cqto")?;
+                        writeln!(output, "  idivq %rcx")?;
+                    },
+                    BinOp::Div => {
+                        writeln!(output, "  xorl %edx, %edx")?;
+                        writeln!(output, "  divq %rcx")?;
+                    },
+                    BinOp::Rem if signed => {
+                        writeln!(output, "  cqto")?;
+                        writeln!(output, "  idivq %rcx")?;
+                        writeln!(output, "  movq %rdx, %rax")?;
+                    },
+                    BinOp::Rem => {
+                        writeln!(output, "  xorl %edx, %edx")?;
+                        writeln!(output, "  divq %rcx")?;
+                        writeln!(output, "  movq %rdx, %rax")?;
+                    },
+                    BinOp::Eq => compare("sete", output)?,
+                    BinOp::Ne => compare("setne", output)?,
+                    BinOp::Lt => compare(if signed { "setl" } else { "setb" }, output)?,
+                    BinOp::Le => compare(if signed { "setle" } else { "setbe" }, output)?,
+                    BinOp::Gt => compare(if signed { "setg" } else { "seta" }, output)?,
+                    BinOp::Ge => compare(if signed { "setge" } else { "setae" }, output)?,
+                }
+                if let Ty::Int(t) = function.reg_type(*dst) {
+                    extend(t, output)?;
+                }
+                store(*dst, output)?;
+            },
+            Inst::Cast { dst, src } => {
+                load(src, "%rax", output)?;
+                cast(function.operand_type(src), function.reg_type(*dst), output)?;
+                store(*dst, output)?;
+            },
+            Inst::Call { dst, func, args } if frame.structs.contains_key(dst) => {
+                let ret = Arg::Address(frame.at(*dst, 0));
+                call(&symbol(func), &call_args(frame, Some(ret), args), output)?;
+            },
+            Inst::Call { dst, func, args } => {
+                call(&symbol(func), &call_args(frame, None, args), output)?;
+                store(*dst, output)?;
+            },
+            Inst::Checked { dst, op, lhs, rhs, loc } => {
+                let Ty::Int(t) = function.reg_type(*dst) else { unreachable!("Checked {} on {}", op, function.reg_type(*dst)) };
+                load(lhs, "%rax", output)?;
+                load(rhs, "%rcx", output)?;
+                let zero = op.divides().then(|| traps.add(function, DIVISION_BY_ZERO.to_string(), *loc));
+                let overflow = traps.add(function, op.overflow_message(), *loc);
+                checked(*op, t, zero.as_deref(), &overflow, output)?;
+                store(*dst, output)?;
+            },
+            Inst::Struct { dst, fields } => {
+                let Ty::Struct(id) = function.reg_type(*dst) else { unreachable!("Struct of type {}", function.reg_type(*dst)) };
+                store_fields(module, function, frame, *dst, fields, &layout(module, id), output)?;
+            },
+            Inst::Field { dst, src, index } => {
+                let Operand::Reg(src) = src else { unreachable!("Field of constant {}", src) };
+                let Ty::Struct(id) = function.reg_type(*src) else { unreachable!("Field of a {}", function.reg_type(*src)) };
+                let address = frame.at(*src, layout(module, id).offsets[*index]);
+                load_from(module, function, frame, *dst, &address, output)?;
+            },
+            Inst::Variant { dst, variant, fields } => {
+                let Ty::Enum(id) = function.reg_type(*dst) else { unreachable!("Variant of type {}", function.reg_type(*dst)) };
+                writeln!(output, "  movl ${}, {}", variant, frame.at(*dst, 0))?;
+                store_fields(module, function, frame, *dst, fields, &variant_layout(module, id, *variant), output)?;
+            },
+            Inst::Tag { dst, src } => {
+                let Operand::Reg(src) = src else { unreachable!("Tag of constant {}", src) };
+                writeln!(output, "  movl {}, %eax", frame.at(*src, 0))?;
+                store(*dst, output)?;
+            },
+            Inst::Payload { dst, src, variant, index } => {
+                let Operand::Reg(src) = src else { unreachable!("Payload of constant {}", src) };
+                let Ty::Enum(id) = function.reg_type(*src) else { unreachable!("Payload of a {}", function.reg_type(*src)) };
+                let address = frame.at(*src, variant_layout(module, id, *variant).offsets[*index]);
+                load_from(module, function, frame, *dst, &address, output)?;
+            },
+            Inst::Print { dst, src } => {
+                load(src, "%rax", output)?;
+                match function.operand_type(src) {
+                    Ty::F64 => {
+                        writeln!(output, "  movq %rax, %xmm0")?;
+                        writeln!(output, "  call huckrt_print_f64")?;
+                    },
+                    ty => {
+                        let print = match ty {
+                            Ty::Bool => "huckrt_print_bool",
+                            Ty::Int(t) if !t.is_signed() => "huckrt_print_u64",
+                            _ => "huckrt_print_i64",
+                        };
+                        writeln!(output, "  movq %rax, %rdi")?;
+                        writeln!(output, "  call {}", print)?;
+                    },
+                }
+                writeln!(output, "  xorl %eax, %eax")?;
+                store(*dst, output)?;
+            },
+            // A scalar is copied from its whole slot, which the shims in
+            // the vtable load back
+            Inst::Dyn { dst, src, vtable } => {
+                let size = size_align(module, function.operand_type(src)).0;
+                writeln!(output, "  movq ${}, %rdi", size.max(8))?;
+                writeln!(output, "  call malloc")?;
+                match src {
+                    Operand::Reg(src) if frame.structs.contains_key(src) => {
+                        writeln!(output, "  leaq {}, %rsi", frame.at(*src, 0))?;
+                        writeln!(output, "  movq %rax, %rdi")?;
+                        copy_bytes(size, output)?;
+                    },
+                    _ => {
+                        load(src, "%rcx", output)?;
+                        writeln!(output, "  movq %rcx, (%rax)")?;
+                    },
+                }
+                writeln!(output, "  movq %rax, {}", frame.at(*dst, 0))?;
+                writeln!(output, "  leaq {}(%rip), %rax", vtable_label(*vtable))?;
+                writeln!(output, "  movq %rax, {}", frame.at(*dst, 8))?;
+            },
+            // Argument loads leave %r11 alone
+            Inst::DynCall { dst, method, args } => {
+                let Operand::Reg(receiver) = args[0] else { unreachable!("Method call on constant {}", args[0]) };
+                let ret = frame.structs.contains_key(dst).then(|| Arg::Address(frame.at(*dst, 0)));
+                let mut call_args = call_args(frame, ret, &args[1..]);
+                let self_index = frame.structs.contains_key(dst) as usize;
+                call_args.insert(self_index, Arg::DynValue(frame.at(receiver, 0)));
+                writeln!(output, "  movq {}, %r11", frame.at(receiver, 8))?;
+                call(&format!("*{}(%r11)", method * 8), &call_args, output)?;
+                if !frame.structs.contains_key(dst) {
+                    store(*dst, output)?;
+                }
+            },
+        }
+    }
+
+    match &block.term {
+        Terminator::Jump(target) => {
+            writeln!(output, "  jmp {}", block_label(function, *target))?;
+        },
+        Terminator::Branch { cond, then_block, else_block } => {
+            load(cond, "%rax", output)?;
+            writeln!(output, "  testq %rax, %rax")?;
+            writeln!(output, "  jne {}", block_label(function, *then_block))?;
+            writeln!(output, "  jmp {}", block_label(function, *else_block))?;
+        },
+        // A compare for each case; LLVM can do better, but the optimizer
+        // here doesn't know about jump tables
+        Terminator::Switch { value, cases, default } => {
+            load(value, "%rax", output)?;
+            for (case, block) in cases {
+                load(&Operand::Const(*case), "%rcx", output)?;
+                writeln!(output, "  cmpq %rcx, %rax")?;
+                writeln!(output, "  je {}", block_label(function, *block))?;
+            }
+            writeln!(output, "  jmp {}", block_label(function, *default))?;
+        },
+        Terminator::NoMatch(loc) => {
+            let trap = traps.add(function, NO_MATCH.to_string(), *loc);
+            writeln!(output, "  jmp {}", trap)?;
+        },
+        Terminator::Return(Operand::Reg(value)) if frame.structs.contains_key(value) => {
+            writeln!(output, "  leaq {}, %rsi", frame.at(*value, 0))?;
+            writeln!(output, "  movq {}, %rdi", sret_slot(function))?;
+            copy_bytes(size_align(module, function.ret).0, output)?;
+            writeln!(output, "  movq {}, %rax", sret_slot(function))?;
+            writeln!(output, "  leave")?;
+            writeln!(output, "  ret")?;
+        },
+        Terminator::Return(value) => {
+            load(value, "%rax", output)?;
+            if function.ret == Ty::F64 {
+                writeln!(output, "  movq %rax, %xmm0")?;
+            }
+            writeln!(output, "  leave")?;
+            writeln!(output, "  ret")?;
+        },
+        Terminator::TailCall { func, args } => {
+            // The callee returns whatever we do, so a struct goes straight
+            // to where our caller wanted ours
+            let ret = in_memory(function.ret).then(|| Arg::Saved(sret_slot(function)));
+            let param_count = function.params.len() + ret.is_some() as usize;
+            let args = call_args(frame, ret, args);
+            // Struct arguments point into our frame, so it has to stay
+            let borrows_frame = args.iter().any(|arg| matches!(arg, Arg::Address(_)));
+            if stack_arg_count(args.len()) <= stack_arg_count(param_count) && !borrows_frame {
+                // Overwrite our own incoming arguments, then hand our
+                // frame's return address to the callee
+                for (i, arg) in args.iter().enumerate().skip(ARG_REGISTERS.len()) {
+                    load_arg(arg, "%rax", output)?;
+                    writeln!(output, "  movq %rax, {}", incoming_arg(i - ARG_REGISTERS.len()))?;
+                }
+                for (arg, reg) in args.iter().zip(ARG_REGISTERS) {
+                    load_arg(arg, reg, output)?;
+                }
+                writeln!(output, "  leave")?;
+                writeln!(output, "  jmp {}", symbol(func))?;
+            } else {
+                call(&symbol(func), &args, output)?;
+                writeln!(output, "  leave")?;
+                writeln!(output, "  ret")?;
+            }
         },
     }
+    Ok(())
 }
 
-fn write_header<T>(output: &mut T) where T: Write {
-    output.write(".global main\nmain:\n".as_bytes());
+// Fill in the fields of a struct or variant register at the offsets in
+// `layout`
+fn store_fields<T>(
+    module: &Module,
+    function: &Function,
+    frame: &Frame,
+    dst: Reg,
+    fields: &[Operand],
+    layout: &Layout,
+    output: &mut T,
+) -> CompileResult<()>
+where T: Write
+{
+    for (field, offset) in fields.iter().zip(&layout.offsets) {
+        let address = frame.at(dst, *offset);
+        let ty = function.operand_type(field);
+        match field {
+            Operand::Reg(src) if frame.structs.contains_key(src) => {
+                copy_struct(module, ty, &frame.at(*src, 0), &address, output)?;
+            },
+            _ => {
+                load(field, "%rax", output)?;
+                store_field(ty, &address, output)?;
+            },
+        }
+    }
+    Ok(())
+}
+
+// Read a field of some struct or enum in memory into `dst`
+fn load_from<T>(module: &Module, function: &Function, frame: &Frame, dst: Reg, address: &str, output: &mut T) -> CompileResult<()>
+where T: Write {
+    let ty = function.reg_type(dst);
+    if frame.structs.contains_key(&dst) {
+        copy_struct(module, ty, address, &frame.at(dst, 0), output)
+    } else {
+        load_field(ty, address, output)?;
+        store(dst, output)
+    }
+}
+
+fn block_label(function: &Function, id: BlockId) -> String {
+    format!(".L{}_{}", function.name, id)
+}
+
+// Compare %rax with %rcx, leaving 0 or 1 in %rax
+fn compare<T>(set_instr: &str, output: &mut T) -> CompileResult<()> where T: Write {
+    writeln!(output, "  cmpq %rcx, %rax")?;
+    writeln!(output, "  {} %al", set_instr)?;
+    writeln!(output, "  movzbq %al, %rax")
+}
+
+// Do %xmm0 op %xmm1, leaving the bits of the result in %rax
+fn float_arithmetic<T>(instr: &str, output: &mut T) -> CompileResult<()> where T: Write {
+    writeln!(output, "  {} %xmm1, %xmm0", instr)?;
+    writeln!(output, "  movq %xmm0, %rax")
+}
+
+// Compare %xmm0 with %xmm1, leaving 0 or 1 in %rax. Any comparison with
+// NaN is unordered, which sets the parity flag; only != is true then.
+fn float_compare<T>(op: BinOp, output: &mut T) -> CompileResult<()> where T: Write {
+    match op {
+        // a < b is b > a, which is false when unordered
+        BinOp::Lt | BinOp::Le => writeln!(output, "  ucomisd %xmm0, %xmm1")?,
+        _ => writeln!(output, "  ucomisd %xmm1, %xmm0")?,
+    }
+    match op {
+        BinOp::Lt | BinOp::Gt => writeln!(output, "  seta %al")?,
+        BinOp::Le | BinOp::Ge => writeln!(output, "  setae %al")?,
+        BinOp::Eq => {
+            writeln!(output, "  sete %al")?;
+            writeln!(output, "  setnp %cl")?;
+            writeln!(output, "  andb %cl, %al")?;
+        },
+        BinOp::Ne => {
+            writeln!(output, "  setne %al")?;
+            writeln!(output, "  setp %cl")?;
+            writeln!(output, "  orb %cl, %al")?;
+        },
+        BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Rem => unreachable!("{} isn't a comparison", op),
+    }
+    writeln!(output, "  movzbq %al, %rax")
+}
+
+// Convert %rax from one type to another, as `as` does
+fn cast<T>(from: Ty, to: Ty, output: &mut T) -> CompileResult<()> where T: Write {
+    match (from, to) {
+        (_, Ty::Int(t)) if from != Ty::F64 => extend(t, output),
+        // Rounding toward zero and saturating is fiddly, so the runtime
+        // does it
+        (Ty::F64, Ty::Int(t)) => {
+            writeln!(output, "  movq %rax, %xmm0")?;
+            writeln!(output, "  movl ${}, %edi", t.bits())?;
+            writeln!(output, "  movl ${}, %esi", t.is_signed() as u8)?;
+            writeln!(output, "  call huckrt_f64_to_int")
+        },
+        // cvtsi2sdq only takes signed numbers, so halve big u64s
+        // (keeping the low bit for rounding) and double them afterwards
+        (Ty::Int(IntType::U64), Ty::F64) => {
+            writeln!(output, "  testq %rax, %rax")?;
+            writeln!(output, "  js 1f")?;
+            writeln!(output, "  cvtsi2sdq %rax, %xmm0")?;
+            writeln!(output, "  jmp 2f")?;
+            writeln!(output, "1:")?;
+            writeln!(output, "  movq %rax, %rcx")?;
+            writeln!(output, "  shrq %rcx")?;
+            writeln!(output, "  andl $1, %eax")?;
+            writeln!(output, "  orq %rax, %rcx")?;
+            writeln!(output, "  cvtsi2sdq %rcx, %xmm0")?;
+            writeln!(output, "  addsd %xmm0, %xmm0")?;
+            writeln!(output, "2:")?;
+            writeln!(output, "  movq %xmm0, %rax")
+        },
+        // Narrower integers are already extended to 64 bits
+        (Ty::Int(_), Ty::F64) => {
+            writeln!(output, "  cvtsi2sdq %rax, %xmm0")?;
+            writeln!(output, "  movq %xmm0, %rax")
+        },
+        (Ty::F64, Ty::F64) => Ok(()),
+        _ => unreachable!("Cast from {} to {}", from, to),
+    }
+}
+
+// %rax op %rcx into %rax, jumping to `overflow` if the result doesn't
+// fit in `t` and to `zero` on division by zero
+fn checked<T>(op: BinOp, t: IntType, zero: Option<&str>, overflow: &str, output: &mut T) -> CompileResult<()>
+where T: Write {
+    if let Some(zero) = zero {
+        writeln!(output, "  testq %rcx, %rcx")?;
+        writeln!(output, "  je {}", zero)?;
+    }
+    match (op, t) {
+        // The 64-bit types find out from the flags
+        (BinOp::Add, IntType::I64) | (BinOp::Sub, IntType::I64) | (BinOp::Mul, IntType::I64) => {
+            let instr = match op { BinOp::Add => "addq", BinOp::Sub => "subq", _ => "imulq" };
+            writeln!(output, "  {} %rcx, %rax", instr)?;
+            writeln!(output, "  jo {}", overflow)?;
+        },
+        (BinOp::Add, IntType::U64) | (BinOp::Sub, IntType::U64) => {
+            let instr = if op == BinOp::Add { "addq" } else { "subq" };
+            writeln!(output, "  {} %rcx, %rax", instr)?;
+            writeln!(output, "  jc {}", overflow)?;
+        },
+        (BinOp::Mul, IntType::U64) => {
+            writeln!(output, "  mulq %rcx")?;
+            writeln!(output, "  jc {}", overflow)?;
+        },
+        // MIN / -1 is the only quotient too big for an i64, and idiv
+        // faults on it rather than setting a flag
+        (BinOp::Div, IntType::I64) => {
+            writeln!(output, "  cmpq $-1, %rcx")?;
+            writeln!(output, "  jne 1f")?;
+            writeln!(output, "  movabsq ${}, %rdx", i64::MIN)?;
+            writeln!(output, "  cmpq %rdx, %rax")?;
+            writeln!(output, "  je {}", overflow)?;
+            writeln!(output, "1:")?;
+            writeln!(output, "  cqto")?;
+            writeln!(output, "  idivq %rcx")?;
+        },
+        (BinOp::Div, IntType::U64) => {
+            writeln!(output, "  xorl %edx, %edx")?;
+            writeln!(output, "  divq %rcx")?;
+        },
+        // A remainder always fits, but `MIN % -1` fails anyway, like the
+        // division it comes from (which idiv faults on for i64)
+        (BinOp::Rem, t) if t.is_signed() => {
+            writeln!(output, "  cmpq $-1, %rcx")?;
+            writeln!(output, "  jne 1f")?;
+            writeln!(output, "  movabsq ${}, %rdx", t.min())?;
+            writeln!(output, "  cmpq %rdx, %rax")?;
+            writeln!(output, "  je {}", overflow)?;
+            writeln!(output, "1:")?;
+            writeln!(output, "  cqto")?;
+            writeln!(output, "  idivq %rcx")?;
+            writeln!(output, "  movq %rdx, %rax")?;
+        },
+        (BinOp::Rem, _) => {
+            writeln!(output, "  xorl %edx, %edx")?;
+            writeln!(output, "  divq %rcx")?;
+            writeln!(output, "  movq %rdx, %rax")?;
+        },
+        // The narrow types can't overflow 64 bits, so do the whole thing
+        // and see whether the answer survives being cut down to size
+        (op, t) => {
+            match op {
+                BinOp::Add => writeln!(output, "  addq %rcx, %rax")?,
+                BinOp::Sub => writeln!(output, "  subq %rcx, %rax")?,
+                BinOp::Mul => writeln!(output, "  imulq %rcx, %rax")?,
+                BinOp::Div if t.is_signed() => {
+                    writeln!(output, "  cqto")?;
+                    writeln!(output, "  idivq %rcx")?;
+                },
+                BinOp::Div => {
+                    writeln!(output, "  xorl %edx, %edx")?;
+                    writeln!(output, "  divq %rcx")?;
+                },
+                _ => unreachable!("Checked {}", op),
+            }
+            writeln!(output, "  movq %rax, %rdx")?;
+            extend(t, output)?;
+            writeln!(output, "  cmpq %rax, %rdx")?;
+            writeln!(output, "  jne {}", overflow)?;
+        },
+    }
+    Ok(())
+}
+
+// Cut %rax down to an integer type and extend it back out to 64 bits,
+// the way values of that type are stored
+fn extend<T>(t: IntType, output: &mut T) -> CompileResult<()> where T: Write {
+    match t {
+        IntType::I8 => writeln!(output, "  movsbq %al, %rax"),
+        IntType::U8 => writeln!(output, "  movzbq %al, %rax"),
+        IntType::I16 => writeln!(output, "  movswq %ax, %rax"),
+        IntType::U16 => writeln!(output, "  movzwq %ax, %rax"),
+        IntType::I32 => writeln!(output, "  movslq %eax, %rax"),
+        // Writing a 32-bit register clears the top half
+        IntType::U32 => writeln!(output, "  movl %eax, %eax"),
+        IntType::I64 | IntType::U64 => Ok(()),
+    }
+}
+
+// Call a function, leaving its result in %rax. `target` is the operand of
+// the call instruction.
+fn call<T>(target: &str, args: &[Arg], output: &mut T) -> CompileResult<()> where T: Write {
+    let stack_args = stack_arg_count(args.len());
+    // The stack must be 16-byte aligned at the call instruction
+    let padding = if stack_args % 2 == 1 { 8 } else { 0 };
+    if padding > 0 {
+        writeln!(output, "  subq ${}, %rsp", padding)?;
+    }
+    for arg in args.iter().skip(ARG_REGISTERS.len()).rev() {
+        load_arg(arg, "%rax", output)?;
+        writeln!(output, "  pushq %rax")?;
+    }
+    for (arg, reg) in args.iter().zip(ARG_REGISTERS) {
+        load_arg(arg, reg, output)?;
+    }
+    writeln!(output, "  call {}", target)?;
+    if stack_args > 0 {
+        writeln!(output, "  addq ${}, %rsp", stack_args * 8 + padding)?;
+    }
+    Ok(())
+}
+
+fn slot(reg: Reg) -> String {
+    format!("-{}(%rbp)", (reg.0 + 1) * 8)
+}
+
+fn load<T>(operand: &Operand, dst: &str, output: &mut T) -> CompileResult<()>
+where T: Write
+{
+    match operand {
+        Operand::Reg(reg) => writeln!(output, "  movq {}, {}", slot(*reg), dst),
+        Operand::Const(c) => {
+            let n = match c {
+                Const::Unit => 0,
+                Const::Bool(b) => *b as i64,
+                Const::Int(n, _) => *n,
+                Const::Float(x) => x.to_bits() as i64,
+            };
+            // movq only takes a sign-extended 32-bit immediate
+            if i32::try_from(n).is_ok() {
+                writeln!(output, "  movq ${}, {}", n, dst)
+            } else {
+                writeln!(output, "  movabsq ${}, {}", n, dst)
+            }
+        },
+    }
+}
+
+fn store<T>(reg: Reg, output: &mut T) -> CompileResult<()> where T: Write {
+    writeln!(output, "  movq %rax, {}", slot(reg))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::link::Linker;
+    use crate::lower::lower;
+    use crate::opt::{optimize, OptLevel};
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use crate::typecheck::Checker;
+
+    use std::fs;
+    use std::process::Command;
+
+    // Assemble, link and run a program, returning its exit code
+    fn run(name: &str, source: &str, level: OptLevel) -> Option<i32> {
+        let ast = Parser::new(Scanner::new(source)).parse().unwrap();
+        let checked = Checker::new().check(&ast).unwrap();
+        let mut module = lower(&checked, source);
+        optimize(&mut module, level);
+
+        let mut asm = vec![];
+        compile(&module, &mut asm).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("huck-codegen-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let exe_path = dir.join("out");
+
+        Linker::new(Some("cc"), false).link(&asm, &exe_path).ok()?;
+        let code = Command::new(&exe_path).status().unwrap().code();
+        fs::remove_dir_all(&dir).unwrap();
+        code
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(run("arithmetic", "{let x = 1; let y = 2; 50 + y * x / 2 - 1}", OptLevel::O0), Some(50));
+    }
+
+    #[test]
+    fn deep_mutual_recursion() {
+        let source = "{
+            let odd = fn (x: i64): bool { if (x == 0) { false } else { even(x - 1) } };
+            let even = fn (x: i64): bool { if (x == 0) { true } else { odd(x - 1) } };
+            even(10000000)
+        }";
+        // Ten million frames would be far more than the default 8MB stack
+        assert_eq!(run("mutual", source, OptLevel::O0), Some(1));
+    }
+
+    #[test]
+    fn deep_recursion_with_stack_arguments() {
+        let source = "{
+            let go = fn (n: i64, a: i64, b: i64, c: i64, d: i64, e: i64, f: i64, g: i64): i64 {
+                if n == 0 { a + b + c + d + e + f + g } else { go(n - 1, b, c, d, e, f, g, a + 1) }
+            };
+            go(10000000, 0, 0, 0, 0, 0, 0, 0) - 9999990
+        }";
+        assert_eq!(run("stack-args", source, OptLevel::O2), Some(10));
+    }
+
+    #[test]
+    fn integer_widths() {
+        let source = "{
+            let small = wrapping_add(200u8, 100u8);
+            let big = 4000000000u32 / 3u32;
+            let low = wrapping_sub(-100i8, 100i8);
+            if 255u8 > 1u8 { small as i64 + big as i64 - 1333333333 + low as i64 } else { 0 }
+        }";
+        // 44 + 0 + 56
+        assert_eq!(run("widths", source, OptLevel::O0), Some(100));
+        assert_eq!(run("widths-opt", source, OptLevel::O2), Some(100));
+    }
+
+    #[test]
+    fn floats() {
+        let source = "{
+            let half = fn (x: f64): f64 { x / 2.0 };
+            let zero = 0.0;
+            let nan = zero / zero;
+            let unordered = if nan != nan { if nan < 1.0 { 0 } else { 10 } } else { 0 };
+            let top = 18446744073709551615u64;
+            let round_trip = if top as f64 as u64 == top { 20 } else { 0 };
+            -half(7.0) as i64 + 1000000000000.0 as u8 as i64 + unordered + round_trip
+        }";
+        // -3 (toward zero) + 255 (saturated) + 10 + 20
+        assert_eq!(run("floats", source, OptLevel::O0), Some(282 & 0xff));
+        assert_eq!(run("floats-opt", source, OptLevel::O2), Some(282 & 0xff));
+    }
+
+    #[test]
+    fn structs() {
+        let source = "{
+            struct Small { flag: bool, n: i16, m: u8 };
+            struct Pair { small: Small, x: f64, count: i64 };
+            let bump = fn (p: Pair, by: i64): Pair { Pair { count: p.count + by, ..p } };
+            let build = fn (a: i64, b: i64, c: i64, d: i64, e: i64, f: i64, g: i64): Pair {
+                Pair { small: Small { flag: g > 0, n: -300i16, m: 200u8 }, x: 0.5, count: a + b + c + d + e + f + g }
+            };
+            let count_up = fn (p: Pair, n: i64): Pair { if n == 0 { p } else { count_up(bump(p, 1), n - 1) } };
+            let p = count_up(build(1, 2, 3, 4, 5, 6, 7), 1000);
+            let s = p.small;
+            if s.flag { p.count - 1000 + s.n as i64 + s.m as i64 + (p.x * 4.0) as i64 } else { 0 }
+        }";
+        // 28 - 300 + 200 + 2
+        assert_eq!(run("structs", source, OptLevel::O0), Some(-70 & 0xff));
+        assert_eq!(run("structs-opt", source, OptLevel::O2), Some(-70 & 0xff));
+    }
+
+    #[test]
+    fn enums() {
+        let source = "{
+            struct P { x: i64, y: u8 };
+            enum Shape { Dot(P), Line(P, P, bool), Empty };
+            let length = fn (s: Shape): i64 {
+                match s { Shape::Line(a, b, true) => b.x - a.x + (b.y - a.y) as i64, Shape::Dot(_) => 1, _ => 0 }
+            };
+            let grow = fn (s: Shape, n: i64): Shape {
+                match s {
+                    Shape::Line(a, b, v) if n > 0 => grow(Shape::Line(a, P { x: b.x + 1, ..b }, v), n - 1),
+                    _ => s,
+                }
+            };
+            let empty = Shape::Empty;
+            length(grow(Shape::Line(P { x: 1, y: 2 }, P { x: 3, y: 5 }, true), 1000)) + length(Shape::Dot(P { x: 0, y: 0 })) + length(empty)
+        }";
+        // 1002 + 3 + 1
+        assert_eq!(run("enums", source, OptLevel::O0), Some(1006 & 0xff));
+        assert_eq!(run("enums-opt", source, OptLevel::O2), Some(1006 & 0xff));
+        assert_eq!(run("binding-arm", "match 7u8 { 0u8 => 1u8, n => n }", OptLevel::O0), Some(7));
+    }
+
+    #[test]
+    fn arithmetic_traps() {
+        let traps = [
+            "9223372036854775807 + 1",
+            "0u64 - 1u64",
+            "4294967296 * 4294967296",
+            "4294967296u64 * 4294967296u64",
+            "{let x = 0; 1 / x}",
+            "{let x = -1; -9223372036854775808 / x}",
+            "{let x = -1i8; -128i8 / x}",
+            "100i8 + 100i8",
+            "1u16 - 2u16",
+            "65536i32 * 65536i32",
+            "-(-2147483648i32)",
+            "{let x = 0u8; 7u8 % x}",
+            "{let x = -1; -9223372036854775808 % x}",
+            "{let x = -1i16; -32768i16 % x}",
+            "{let x = 0i32; wrapping_rem(1i32, x)}",
+        ];
+        for (i, source) in traps.iter().enumerate() {
+            assert_eq!(run(&format!("trap{}", i), source, OptLevel::O0), Some(101), "{}", source);
+            assert_eq!(run(&format!("trap{}-opt", i), source, OptLevel::O2), Some(101), "{}", source);
+        }
+        let source = "{let x = -1i8; 127i8 * x + wrapping_mul(16i8, 16i8) + (4294967295u32 / 4294967295u32) as i8}";
+        assert_eq!(run("no-trap", source, OptLevel::O0), Some(-126 & 0xff));
+        let source = "{let x = -1i8; let m = -128i8; wrapping_rem(m, x) + -7i8 % 3i8 + 255u8 as i8 % 2i8 + wrapping_rem(m, 3i8)}";
+        // 0 - 1 - 1 - 2
+        assert_eq!(run("remainders", source, OptLevel::O0), Some(-4 & 0xff));
+        assert_eq!(run("remainders-opt", source, OptLevel::O2), Some(-4 & 0xff));
+    }
 }
@@ -0,0 +1,566 @@
+//! Errors in huck programs, and rendering them for people.
+//!
+//! A [`Diagnostic`] points at the code it's about with byte-offset
+//! [`Span`]s: a primary one for the problem itself and any number of
+//! secondary labels for context. [`Diagnostic::render`] lays these out
+//! rustc-style, under the source lines they refer to.
+
+use crate::json::Json;
+
+use std::fmt;
+
+/// A byte range `start..end` in the source.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// The smallest span covering both.
+    pub fn to(self, other: Span) -> Self {
+        Self::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+/// Identifies a kind of error, so tools can match on it without parsing
+/// messages. Shown as `E0001` and so on.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Code(pub u16);
+
+// Syntax errors are E00xx, type errors E01xx
+impl Code {
+    pub const UNEXPECTED_CHARACTER: Code = Code(1);
+    pub const UNEXPECTED_TOKEN: Code = Code(2);
+    pub const UNEXPECTED_EOF: Code = Code(3);
+    pub const NUMBER_TOO_BIG: Code = Code(4);
+    pub const EMPTY_BLOCK: Code = Code(5);
+    pub const BAD_CALLEE: Code = Code(6);
+    pub const BAD_FLOAT_SUFFIX: Code = Code(7);
+    pub const EMPTY_MATCH: Code = Code(8);
+    pub const BAD_PATTERN: Code = Code(9);
+    pub const MISSING_SELF: Code = Code(10);
+    pub const FIELD_ASSIGNMENT: Code = Code(11);
+
+    pub const UNBOUND_VARIABLE: Code = Code(100);
+    pub const CAPTURED_LOCAL: Code = Code(101);
+    pub const UNKNOWN_TYPE: Code = Code(102);
+    pub const NON_BOOL_CONDITION: Code = Code(103);
+    pub const BRANCH_MISMATCH: Code = Code(104);
+    pub const BAD_OPERANDS: Code = Code(105);
+    pub const ARGUMENT_MISMATCH: Code = Code(106);
+    pub const WRONG_ARGUMENT_COUNT: Code = Code(107);
+    pub const RETURN_MISMATCH: Code = Code(108);
+    pub const FUNCTION_AS_VALUE: Code = Code(109);
+    pub const ANONYMOUS_FUNCTION: Code = Code(110);
+    pub const NOT_CALLABLE: Code = Code(111);
+    pub const UNEXPECTED_TYPE: Code = Code(112);
+    pub const LITERAL_OUT_OF_RANGE: Code = Code(113);
+    pub const BAD_CAST: Code = Code(114);
+    pub const MISSING_FIELDS: Code = Code(115);
+    pub const UNKNOWN_FIELD: Code = Code(116);
+    pub const DUPLICATE_FIELD: Code = Code(117);
+    pub const FIELD_MISMATCH: Code = Code(118);
+    pub const NOT_A_STRUCT: Code = Code(119);
+    pub const RECURSIVE_TYPE: Code = Code(120);
+    pub const DUPLICATE_TYPE: Code = Code(121);
+    pub const NOT_AN_ENUM: Code = Code(122);
+    pub const UNKNOWN_VARIANT: Code = Code(123);
+    pub const PAYLOAD_COUNT: Code = Code(124);
+    pub const PATTERN_MISMATCH: Code = Code(125);
+    pub const DUPLICATE_BINDING: Code = Code(126);
+    pub const ARM_MISMATCH: Code = Code(127);
+    pub const DUPLICATE_VARIANT: Code = Code(128);
+    pub const NON_EXHAUSTIVE: Code = Code(129);
+    pub const UNREACHABLE_ARM: Code = Code(130);
+    pub const TYPE_ARGUMENT_COUNT: Code = Code(131);
+    pub const CANNOT_INFER: Code = Code(132);
+    pub const UNUSED_TYPE_PARAMETER: Code = Code(133);
+    pub const INFINITE_INSTANTIATION: Code = Code(134);
+    pub const UNKNOWN_TRAIT: Code = Code(135);
+    pub const NOT_IMPLEMENTED: Code = Code(136);
+    pub const UNKNOWN_METHOD: Code = Code(137);
+    pub const AMBIGUOUS_METHOD: Code = Code(138);
+    pub const IMPL_MISMATCH: Code = Code(139);
+    pub const DUPLICATE_IMPL: Code = Code(140);
+    pub const BAD_IMPL: Code = Code(141);
+    pub const NOT_OBJECT_SAFE: Code = Code(142);
+    pub const NOT_TOP_LEVEL: Code = Code(144);
+    pub const IMMUTABLE_ASSIGN: Code = Code(145);
+    pub const ASSIGN_MISMATCH: Code = Code(146);
+    pub const MUTABLE_FUNCTION: Code = Code(147);
+    pub const OUTSIDE_LOOP: Code = Code(148);
+    pub const UNKNOWN_LABEL: Code = Code(149);
+    pub const BREAK_VALUE: Code = Code(150);
+    pub const BREAK_MISMATCH: Code = Code(151);
+    pub const BAD_RANGE: Code = Code(152);
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "E{:04}", self.0)
+    }
+}
+
+/// Which stage of the pipeline rejected a program.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Stage {
+    Syntax,
+    Type,
+    Runtime,
+}
+
+/// Whether a diagnostic stops the program from compiling.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Severity {
+    #[default]
+    Error,
+    Warning,
+}
+
+/// Some text attached to a span of the source.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Label {
+    fn non_empty_message(&self) -> Option<&str> {
+        Some(self.message.as_str()).filter(|m| !m.is_empty())
+    }
+}
+
+/// Extra text shown under the snippet.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Note {
+    Note(String),
+    // How to fix it
+    Help(String),
+    // A fix that can be applied mechanically: replace the span with
+    // `replacement`
+    Suggestion { message: String, span: Span, replacement: String },
+}
+
+/// An error in a huck program, or a warning about one.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub stage: Stage,
+    pub severity: Severity,
+    pub code: Option<Code>,
+    pub message: String,
+    /// What the error is about, with an optional (possibly empty) label.
+    pub primary: Option<Label>,
+    pub secondary: Vec<Label>,
+    pub notes: Vec<Note>,
+}
+
+impl Diagnostic {
+    pub fn new(stage: Stage, message: impl Into<String>) -> Self {
+        Self {
+            stage,
+            severity: Severity::Error,
+            code: None,
+            message: message.into(),
+            primary: None,
+            secondary: vec![],
+            notes: vec![],
+        }
+    }
+
+    pub fn syntax(code: Code, message: impl Into<String>, span: Span) -> Self {
+        Self { code: Some(code), ..Self::new(Stage::Syntax, message).with_span(span) }
+    }
+
+    pub fn type_error(code: Code, message: impl Into<String>, span: Span) -> Self {
+        Self { code: Some(code), ..Self::new(Stage::Type, message).with_span(span) }
+    }
+
+    /// A problem that doesn't stop the program from compiling.
+    pub fn warning(code: Code, message: impl Into<String>, span: Span) -> Self {
+        Self { severity: Severity::Warning, ..Self::type_error(code, message, span) }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub fn with_span(self, span: Span) -> Self {
+        self.with_label(span, "")
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.primary = Some(Label { span, message: message.into() });
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.secondary.push(Label { span, message: message.into() });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(Note::Note(note.into()));
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.notes.push(Note::Help(help.into()));
+        self
+    }
+
+    pub fn with_suggestion(mut self, message: impl Into<String>, span: Span, replacement: impl Into<String>) -> Self {
+        self.notes.push(Note::Suggestion { message: message.into(), span, replacement: replacement.into() });
+        self
+    }
+}
+
+// The one-line version, for when there's no source to show
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.stage {
+            _ if !self.is_error() => write!(f, "Warning: {}", self.message),
+            Stage::Syntax => write!(f, "Syntax error: {}", self.message),
+            Stage::Type => write!(f, "Type error: {}", self.message),
+            Stage::Runtime => write!(f, "Runtime error: {}", self.message),
+        }
+    }
+}
+
+/// 1-based line and column (in characters) of a byte offset.
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let before = &source[..floor_char_boundary(source, offset)];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+fn floor_char_boundary(source: &str, mut offset: usize) -> usize {
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+struct Style {
+    color: bool,
+    warning: bool,
+}
+
+impl Style {
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.color && !text.is_empty() {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    // Errors are red and warnings yellow
+    fn primary(&self, text: &str) -> String {
+        self.paint(if self.warning { "1;33" } else { "1;31" }, text)
+    }
+
+    fn secondary(&self, text: &str) -> String {
+        self.paint("1;34", text)
+    }
+
+    fn bold(&self, text: &str) -> String {
+        self.paint("1", text)
+    }
+}
+
+// A label resolved to a single source line; spans running over several
+// lines get underlined to the end of their first line
+struct LineLabel<'a> {
+    start_col: usize,
+    end_col: usize,
+    message: &'a str,
+    primary: bool,
+}
+
+const TAB: &str = "    ";
+
+impl Diagnostic {
+    /// Render the diagnostic with the lines of `source` it refers to.
+    /// `file` is only used for the location in the header.
+    pub fn render(&self, file: &str, source: &str, color: bool) -> String {
+        let style = Style { color, warning: !self.is_error() };
+        let level = match self.code {
+            Some(code) => format!("{}[{}]", self.level(), code),
+            None => String::from(self.level()),
+        };
+        let mut out = format!("{}{}\n", style.primary(&level), style.bold(&format!(": {}", self.message)));
+
+        let labels = self.primary.iter().map(|l| (l, true))
+            .chain(self.secondary.iter().map(|l| (l, false)))
+            .collect::<Vec<_>>();
+
+        // Group labels by line, in order
+        let lines = source.split('\n').collect::<Vec<_>>();
+        let mut by_line: Vec<(usize, Vec<LineLabel>)> = vec![];
+        for (label, primary) in &labels {
+            let (line, _) = line_col(source, label.span.start);
+            let text = lines.get(line - 1).copied().unwrap_or("");
+            let line_start = source.split('\n').take(line - 1).map(|l| l.len() + 1).sum::<usize>();
+            let start = label.span.start.saturating_sub(line_start).min(text.len());
+            let end = label.span.end.saturating_sub(line_start).clamp(start, text.len());
+            let line_label = LineLabel {
+                start_col: display_width(&text[..floor_char_boundary(text, start)]),
+                end_col: display_width(&text[..floor_char_boundary(text, end)]),
+                message: &label.message,
+                primary: *primary,
+            };
+            match by_line.iter_mut().find(|(l, _)| *l == line) {
+                Some((_, group)) => group.push(line_label),
+                None => by_line.push((line, vec![line_label])),
+            }
+        }
+        by_line.sort_by_key(|(line, _)| *line);
+
+        let gutter_width = by_line.last().map_or(1, |(line, _)| line.to_string().len());
+        let pad = " ".repeat(gutter_width);
+        let bar = style.secondary("|");
+
+        let location = match &self.primary {
+            Some(label) => {
+                let (line, col) = line_col(source, label.span.start);
+                format!("{}:{}:{}", file, line, col)
+            },
+            None => file.to_string(),
+        };
+        out.push_str(&format!("{}{} {}\n", pad, style.secondary("-->"), location));
+
+        if !by_line.is_empty() {
+            out.push_str(&format!("{} {}\n", pad, bar));
+        }
+        let mut previous_line = None;
+        for (line, group) in &mut by_line {
+            if previous_line.is_some_and(|previous| *line > previous + 1) {
+                out.push_str(&format!("{}\n", style.secondary("...")));
+            }
+            previous_line = Some(*line);
+
+            let text = lines.get(*line - 1).copied().unwrap_or("").replace('\t', TAB);
+            let number = format!("{:>width$}", line, width = gutter_width);
+            out.push_str(&format!("{} {} {}\n", style.secondary(&number), bar, text));
+            render_labels(&mut out, &style, &pad, group);
+        }
+
+        if !self.notes.is_empty() {
+            out.push_str(&format!("{} {}\n", pad, bar));
+        }
+        for note in &self.notes {
+            let (kind, text) = match note {
+                Note::Note(text) => ("note", text.to_string()),
+                Note::Help(text) => ("help", text.to_string()),
+                Note::Suggestion { message, replacement, .. } => ("help", format!("{}: `{}`", message, replacement)),
+            };
+            out.push_str(&format!("{} {} {}: {}\n", pad, style.secondary("="), style.bold(kind), text));
+        }
+        out
+    }
+}
+
+// JSON, for tools. The layout follows rustc's `--error-format=json`, so
+// anything that already reads that can read ours.
+impl Diagnostic {
+    /// The diagnostic as a single line of JSON. `file` and `source` are
+    /// used to turn spans into lines and columns.
+    pub fn to_json(&self, file: &str, source: &str) -> String {
+        let span = |span: Span, primary: bool, label: Option<&str>, replacement: Option<&str>| {
+            let (line_start, column_start) = line_col(source, span.start);
+            let (line_end, column_end) = line_col(source, span.end);
+            Json::object(vec![
+                ("file_name", file.into()),
+                ("byte_start", span.start.into()),
+                ("byte_end", span.end.into()),
+                ("line_start", line_start.into()),
+                ("line_end", line_end.into()),
+                ("column_start", column_start.into()),
+                ("column_end", column_end.into()),
+                ("is_primary", primary.into()),
+                ("label", label.into()),
+                ("suggested_replacement", replacement.into()),
+            ])
+        };
+
+        let spans = self.primary.iter().map(|l| span(l.span, true, l.non_empty_message(), None))
+            .chain(self.secondary.iter().map(|l| span(l.span, false, l.non_empty_message(), None)))
+            .collect::<Vec<_>>();
+        let children = self.notes.iter().map(|note| {
+            let (level, message, spans) = match note {
+                Note::Note(text) => ("note", text, vec![]),
+                Note::Help(text) => ("help", text, vec![]),
+                Note::Suggestion { message, span: at, replacement } => {
+                    ("help", message, vec![span(*at, true, None, Some(replacement))])
+                },
+            };
+            Json::object(vec![
+                ("level", level.into()),
+                ("message", message.as_str().into()),
+                ("spans", spans.into()),
+            ])
+        }).collect::<Vec<_>>();
+
+        let stage = match self.stage {
+            Stage::Syntax => "syntax",
+            Stage::Type => "type",
+            Stage::Runtime => "runtime",
+        };
+        Json::object(vec![
+            ("code", self.code.map(|c| c.to_string()).into()),
+            ("level", self.level().into()),
+            ("stage", stage.into()),
+            ("message", self.message.as_str().into()),
+            ("spans", spans.into()),
+            ("children", children.into()),
+            ("rendered", self.render(file, source, false).into()),
+        ]).to_string()
+    }
+
+    fn level(&self) -> &'static str {
+        match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+fn display_width(text: &str) -> usize {
+    text.chars().map(|c| if c == '\t' { TAB.len() } else { 1 }).sum()
+}
+
+// Underline every label on the line in one row, with the rightmost
+// label's message on the end of it. Other messages hang below their
+// labels, connected by `|`s.
+fn render_labels(out: &mut String, style: &Style, pad: &str, labels: &mut [LineLabel]) {
+    labels.sort_by_key(|l| l.start_col);
+    let paint = |label: &LineLabel, text: &str| {
+        if label.primary { style.primary(text) } else { style.secondary(text) }
+    };
+    let bar = style.secondary("|");
+
+    let mut row = String::new();
+    let mut col = 0;
+    for label in labels.iter() {
+        if label.start_col < col {
+            continue;
+        }
+        let marker = if label.primary { "^" } else { "-" };
+        let width = (label.end_col - label.start_col).max(1);
+        row.push_str(&" ".repeat(label.start_col - col));
+        row.push_str(&paint(label, &marker.repeat(width)));
+        col = label.start_col + width;
+    }
+    let (last, hanging) = labels.split_last().unwrap();
+    if !last.message.is_empty() {
+        row.push(' ');
+        row.push_str(&paint(last, last.message));
+    }
+    out.push_str(&format!("{} {} {}\n", pad, bar, row.trim_end()));
+
+    let hanging = hanging.iter().filter(|l| !l.message.is_empty()).collect::<Vec<_>>();
+    for i in (0..hanging.len()).rev() {
+        let connectors = |n: usize| {
+            let mut row = String::new();
+            let mut col = 0;
+            for label in &hanging[..n] {
+                row.push_str(&" ".repeat(label.start_col.saturating_sub(col)));
+                row.push_str(&paint(label, "|"));
+                col = label.start_col + 1;
+            }
+            (row, col)
+        };
+        let (row, _) = connectors(i + 1);
+        out.push_str(&format!("{} {} {}\n", pad, bar, row));
+        let (mut row, col) = connectors(i);
+        row.push_str(&" ".repeat(hanging[i].start_col.saturating_sub(col)));
+        row.push_str(&paint(hanging[i], hanging[i].message));
+        out.push_str(&format!("{} {} {}\n", pad, bar, row));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn positions() {
+        let source = "ab\n\tcé\nd";
+        assert_eq!(line_col(source, 0), (1, 1));
+        assert_eq!(line_col(source, 3), (2, 1));
+        assert_eq!(line_col(source, 7), (2, 4));
+        assert_eq!(line_col(source, 8), (3, 1));
+    }
+
+    #[test]
+    fn render() {
+        let source = "{\n  let x = if c { 1 } else { true };\n  x\n}";
+        let diagnostic = Diagnostic::type_error(Code::BRANCH_MISMATCH, "Conditional branch types i64 and bool do not match", Span::new(28, 36))
+            .with_label(Span::new(28, 36), "expected i64, found bool")
+            .with_secondary(Span::new(17, 22), "this branch has type i64")
+            .with_note("both branches of an if need the same type");
+        assert_eq!(diagnostic.render("test.huck", source, false), "\
+error[E0104]: Conditional branch types i64 and bool do not match
+ --> test.huck:2:27
+  |
+2 |   let x = if c { 1 } else { true };
+  |                -----      ^^^^^^^^ expected i64, found bool
+  |                |
+  |                this branch has type i64
+  |
+  = note: both branches of an if need the same type
+");
+    }
+
+    #[test]
+    fn labels_on_different_lines() {
+        let source = "let f = fn (): i64 {\n  true\n}";
+        let diagnostic = Diagnostic::type_error(Code::RETURN_MISMATCH, "Mismatched return", Span::new(23, 27))
+            .with_label(Span::new(23, 27), "this is a bool")
+            .with_secondary(Span::new(15, 18), "expected because of this");
+        let rendered = diagnostic.render("f.huck", source, false);
+        assert_eq!(rendered, "\
+error[E0108]: Mismatched return
+ --> f.huck:2:3
+  |
+1 | let f = fn (): i64 {
+  |                --- expected because of this
+2 |   true
+  |   ^^^^ this is a bool
+");
+        assert!(diagnostic.render("f.huck", source, true).contains("\x1b[1;31m^^^^"));
+    }
+
+    #[test]
+    fn json() {
+        let source = "fn (): i64 { 1 }";
+        let diagnostic = Diagnostic::type_error(Code::ANONYMOUS_FUNCTION, "Functions must be \"declared\"", Span::new(0, 16))
+            .with_suggestion("give it a name", Span::new(0, 0), "let f = ");
+        let json = diagnostic.to_json("a\\b.huck", source);
+        assert!(!json.contains('\n'));
+        assert!(json.starts_with("{\"code\":\"E0110\",\"level\":\"error\",\"stage\":\"type\",\
+                                  \"message\":\"Functions must be \\\"declared\\\"\",\"spans\":[{\"file_name\":\"a\\\\b.huck\","));
+        assert!(json.contains("\"line_start\":1,\"line_end\":1,\"column_start\":1,\"column_end\":17,\"is_primary\":true,\"label\":null"));
+        assert!(json.contains("\"children\":[{\"level\":\"help\",\"message\":\"give it a name\",\"spans\":[{"));
+        assert!(json.contains("\"suggested_replacement\":\"let f = \"}]}]"));
+    }
+
+    #[test]
+    fn warnings() {
+        let source = "match b { _ => 1, true => 2 }";
+        let diagnostic = Diagnostic::warning(Code::UNREACHABLE_ARM, "Unreachable match arm", Span::new(18, 22))
+            .with_label(Span::new(18, 22), "this arm never matches");
+        assert!(!diagnostic.is_error());
+        assert!(diagnostic.render("w.huck", source, false).starts_with("warning[E0130]: Unreachable match arm\n"));
+        assert!(diagnostic.render("w.huck", source, true).contains("\x1b[1;33m^^^^"));
+        assert!(diagnostic.to_json("w.huck", source).starts_with("{\"code\":\"E0130\",\"level\":\"warning\","));
+        assert_eq!(diagnostic.to_string(), "Warning: Unreachable match arm");
+    }
+}
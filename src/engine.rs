@@ -0,0 +1,310 @@
+//! Running huck inside a Rust program.
+//!
+//! An [`Engine`] evaluates huck source with the tree-walking
+//! interpreter. Top-level `let`s persist from one call to the next, and
+//! the host can register Rust closures as huck functions. Their
+//! signatures come from the closure's argument and return types, so the
+//! checker rejects bad calls before anything runs.
+//!
+//! ```
+//! use huck::engine::Engine;
+//!
+//! let mut engine = Engine::new();
+//! engine.register_fn("clamp", |x: i64, lo: i64, hi: i64| x.max(lo).min(hi));
+//! engine.run("let limit = 100").unwrap();
+//! let n: i64 = engine.eval("clamp(250, 0, limit)").unwrap();
+//! assert_eq!(n, 100);
+//! ```
+
+use crate::interp::{HostFunction, Interpreter, Value};
+use crate::parser::ParseOutput;
+use crate::typecheck::{Checker, IntType, TypeInfo};
+use crate::{parse_str, Code, Diagnostic, Stage};
+
+use std::rc::Rc;
+
+/// A Rust type that can cross into and out of huck. Only the integer
+/// types, `f64`, `bool` and `()` implement it; huck structs, enums and
+/// tuples have no Rust counterpart, so they can't be passed to or
+/// returned from host functions, or read back with [`Engine::eval`].
+pub trait HuckValue: Sized {
+    /// The huck type this corresponds to.
+    fn type_info() -> TypeInfo;
+
+    fn into_value(self) -> Value;
+
+    /// `None` if the value has some other type.
+    fn from_value(value: Value) -> Option<Self>;
+}
+
+// Integers are stored as i64s, extended the way `IntType` describes, so
+// `as` gets them in and out
+macro_rules! int_value {
+    ($($rust:ty => $huck:ident),*) => {$(
+        impl HuckValue for $rust {
+            fn type_info() -> TypeInfo {
+                TypeInfo::Int(IntType::$huck)
+            }
+
+            fn into_value(self) -> Value {
+                Value::Int(self as i64, IntType::$huck)
+            }
+
+            fn from_value(value: Value) -> Option<Self> {
+                match value {
+                    Value::Int(n, IntType::$huck) => Some(n as $rust),
+                    _ => None,
+                }
+            }
+        }
+    )*};
+}
+
+int_value!(i8 => I8, i16 => I16, i32 => I32, i64 => I64, u8 => U8, u16 => U16, u32 => U32, u64 => U64);
+
+impl HuckValue for f64 {
+    fn type_info() -> TypeInfo {
+        TypeInfo::F64
+    }
+
+    fn into_value(self) -> Value {
+        Value::Float(self)
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Float(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+impl HuckValue for bool {
+    fn type_info() -> TypeInfo {
+        TypeInfo::Bool
+    }
+
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+impl HuckValue for () {
+    fn type_info() -> TypeInfo {
+        TypeInfo::Unit
+    }
+
+    fn into_value(self) -> Value {
+        Value::Unit
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Unit => Some(()),
+            _ => None,
+        }
+    }
+}
+
+/// A Rust closure that can be registered as a huck function. This is
+/// implemented for closures of up to six [`HuckValue`] arguments that
+/// return a `HuckValue`; `Args` is the tuple of argument types.
+pub trait HostFn<Args> {
+    /// The huck type of the function.
+    fn signature() -> TypeInfo;
+
+    fn into_host_function(self) -> HostFunction;
+}
+
+macro_rules! host_fn {
+    ($($arg:ident),*) => {
+        impl<Func, R, $($arg),*> HostFn<($($arg,)*)> for Func
+        where
+            Func: Fn($($arg),*) -> R + 'static,
+            R: HuckValue,
+            $($arg: HuckValue),*
+        {
+            fn signature() -> TypeInfo {
+                TypeInfo::Fn(vec![$(<$arg as HuckValue>::type_info()),*], Box::new(R::type_info()))
+            }
+
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn into_host_function(self) -> HostFunction {
+                Rc::new(move |args: &[Value]| {
+                    let mut args = args.iter().cloned();
+                    $(
+                        let $arg = args.next()
+                            .and_then(<$arg as HuckValue>::from_value)
+                            .ok_or_else(|| String::from("Bad argument to host function"))?;
+                    )*
+                    Ok(self($($arg),*).into_value())
+                })
+            }
+        }
+    };
+}
+
+host_fn!();
+host_fn!(A);
+host_fn!(A, B);
+host_fn!(A, B, C);
+host_fn!(A, B, C, D);
+host_fn!(A, B, C, D, E);
+host_fn!(A, B, C, D, E, F);
+
+/// A huck interpreter session that can be driven from Rust.
+pub struct Engine {
+    checker: Checker,
+    interpreter: Interpreter,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self {
+            checker: Checker::new(),
+            interpreter: Interpreter::new(),
+        }
+    }
+
+    /// Let huck code use up to `bytes` of the calling thread's stack for
+    /// recursion; past that, evaluation fails with a stack overflow
+    /// rather than crashing the process. The default,
+    /// [`DEFAULT_STACK_LIMIT`](crate::interp::DEFAULT_STACK_LIMIT), suits
+    /// a thread with Rust's default stack size.
+    pub fn with_stack_limit(mut self, bytes: usize) -> Self {
+        self.interpreter = self.interpreter.with_stack_limit(bytes);
+        self
+    }
+
+    /// Make `f` callable from huck as `name`. Registering a name again
+    /// replaces the earlier function for code evaluated afterwards.
+    ///
+    /// Arguments and results are limited to the scalar types that
+    /// implement [`HuckValue`]: integers, floats, `bool` and `()`. A host
+    /// function can't take or return a huck struct or enum; pass its
+    /// fields separately instead.
+    pub fn register_fn<Args, F>(&mut self, name: &str, f: F)
+    where F: HostFn<Args>
+    {
+        self.checker.declare_fn(name, F::signature());
+        self.interpreter.define_host_fn(name, f.into_host_function());
+    }
+
+    /// Evaluate some huck source, which has to produce a `T`. If it's
+    /// rejected or fails at runtime, any bindings it made are dropped.
+    pub fn eval<T>(&mut self, source: &str) -> Result<T, Diagnostic>
+    where T: HuckValue
+    {
+        let value = self.eval_checked(source, Some(T::type_info()))?;
+        Ok(T::from_value(value).expect("Checked value should have the expected type"))
+    }
+
+    /// Evaluate some huck source for its effects, whatever its type.
+    pub fn run(&mut self, source: &str) -> Result<Value, Diagnostic> {
+        self.eval_checked(source, None)
+    }
+
+    fn eval_checked(&mut self, source: &str, expected: Option<TypeInfo>) -> Result<Value, Diagnostic> {
+        let ast = parse_str(source)?;
+
+        let saved_checker = self.checker.clone();
+        let result = self.check_and_run(&ast, expected);
+        if result.is_err() {
+            self.checker = saved_checker;
+        }
+        result
+    }
+
+    fn check_and_run(&mut self, ast: &ParseOutput, expected: Option<TypeInfo>) -> Result<Value, Diagnostic> {
+        let checked = self.checker.check(ast)?;
+        // Embedders only hear about errors
+        self.checker.take_warnings();
+
+        let actual = checked.ty();
+        match expected {
+            Some(expected) if *actual != expected => {
+                let span = *ast.get_metadata();
+                return Err(Diagnostic::type_error(Code::UNEXPECTED_TYPE, format!("Expected a value of type {} but found {}", expected, actual), span)
+                    .with_label(span, format!("this has type {}", actual)))
+            },
+            _ => (),
+        }
+
+        self.interpreter.eval(&checked).map_err(|message| Diagnostic::new(Stage::Runtime, message))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    #[test]
+    fn host_functions() {
+        let mut engine = Engine::new();
+        let log = Rc::new(RefCell::new(vec![]));
+        let sink = log.clone();
+        engine.register_fn("record", move |n: i64| sink.borrow_mut().push(n));
+        engine.register_fn("is_even", |n: i64| n % 2 == 0);
+        engine.register_fn("answer", || 42i64);
+
+        let source = "{
+            let go = fn (n: i64): i64 { if n == 0 { 0 } else { { record(n); go(n - 1) } } };
+            go(3);
+            is_even(answer())
+        }";
+        assert_eq!(engine.eval::<bool>(source), Ok(true));
+        assert_eq!(*log.borrow(), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn host_signatures_are_checked() {
+        let mut engine = Engine::new();
+        engine.register_fn("double", |n: i64| n * 2);
+        assert_eq!(engine.eval::<i64>("double(double(3))"), Ok(12));
+        engine.register_fn("sqrt", f64::sqrt);
+        assert_eq!(engine.eval::<f64>("sqrt(2.25) * 2.0"), Ok(3.0));
+
+        let err = engine.eval::<i64>("double(true)").unwrap_err();
+        assert_eq!(err.stage, Stage::Type);
+        assert!(engine.eval::<i64>("double(1, 2)").is_err());
+        assert!(engine.eval::<bool>("double(1)").is_err());
+    }
+
+    #[test]
+    fn state_persists() {
+        let mut engine = Engine::new();
+        engine.eval::<i64>("let x = 20").unwrap();
+        engine.run("let add = fn (a: i64, b: i64): i64 { a + b }").unwrap();
+        assert_eq!(engine.eval::<i64>("add(x, 22)"), Ok(42));
+
+        let err = engine.eval::<i64>("let y = 1 / 0").unwrap_err();
+        assert_eq!(err.stage, Stage::Runtime);
+        assert!(engine.eval::<i64>("y").is_err());
+    }
+
+    #[test]
+    fn deep_recursion_fails_cleanly() {
+        let mut engine = Engine::new();
+        engine.run("let sum = fn (n: i64): i64 { if n == 0 { 0 } else { n + sum(n - 1) } }").unwrap();
+        let err = engine.eval::<i64>("sum(1000000)").unwrap_err();
+        assert_eq!(err.stage, Stage::Runtime);
+        assert_eq!(err.message, "Stack overflow");
+        // The engine is still usable afterwards
+        assert_eq!(engine.eval::<i64>("sum(10)"), Ok(55));
+    }
+}
@@ -0,0 +1,264 @@
+// Checking that a `match` covers every value, and that each of its arms
+// can match something.
+//
+// Both questions come down to whether a pattern is "useful" after some
+// others: whether there's a value it matches that none of them do. This
+// is the algorithm from Maranget's "Warnings for pattern matching". An
+// arm is unreachable if its pattern isn't useful after the arms above it,
+// and the match is exhaustive if `_` isn't useful after all of them.
+// Arms with guards might not match, so they don't count towards covering
+// anything. Rather than only answering yes or no for `_`, `witness` finds
+// a value nothing matches, so the error can show what's missing.
+//
+// Enum payloads and tuple elements are matched as the columns of a row,
+// so nested patterns like `O::S(true, _)` and `(true, _)` are handled the
+// same way. A tuple has a single constructor, with its elements as fields.
+
+use crate::matching::{case, Case};
+use crate::parser::{MatchArm, Pattern};
+use crate::typecheck::{TypeInfo, Typed};
+
+use std::slice;
+
+// A pattern with only the parts that matter here: bindings are wildcards,
+// and literals are constructors with no fields
+#[derive(Debug, Clone)]
+enum Pat {
+    Wild,
+    Ctor(Case, Vec<Pat>),
+}
+
+impl Pat {
+    fn new(pattern: &Pattern<Typed>) -> Self {
+        match pattern {
+            Pattern::Wildcard(_) | Pattern::Binding(..) => Pat::Wild,
+            Pattern::Variant(_, _, fields, _) | Pattern::Tuple(fields, _) => Pat::Ctor(case(pattern), fields.iter().map(Pat::new).collect()),
+            Pattern::Literal(_) => Pat::Ctor(case(pattern), vec![]),
+        }
+    }
+}
+
+pub struct Report {
+    /// A value no arm matches, if there is one.
+    pub missing: Option<String>,
+    /// Arms that can never match, because the ones above them match
+    /// everything they do.
+    pub unreachable: Vec<usize>,
+}
+
+pub fn check(arms: &[MatchArm<Typed>], ty: &TypeInfo) -> Report {
+    let tys = slice::from_ref(ty);
+    let mut rows = vec![];
+    let mut unreachable = vec![];
+    for (i, arm) in arms.iter().enumerate() {
+        let row = vec![Pat::new(&arm.pattern)];
+        if !useful(&rows, &row, tys) {
+            unreachable.push(i);
+        }
+        if arm.guard.is_none() {
+            rows.push(row);
+        }
+    }
+    let missing = witness(&rows, tys).map(|mut values| values.remove(0));
+    Report { missing, unreachable }
+}
+
+// Whether some value matches `row` but none of `rows`. `tys` are the
+// types of the columns.
+fn useful(rows: &[Vec<Pat>], row: &[Pat], tys: &[TypeInfo]) -> bool {
+    let Some((head, rest)) = row.split_first() else {
+        return rows.is_empty();
+    };
+    match head {
+        Pat::Ctor(case, _) => {
+            let (rows, tys) = specialize(rows, &tys[0], *case, tys);
+            specialize_row(row, *case, tys.len() - rest.len())
+                .is_some_and(|row| useful(&rows, &row, &tys))
+        },
+        // `_` is useful if it is for any of the constructors, but when
+        // some aren't mentioned at all, those are enough on their own
+        Pat::Wild => {
+            let used = used(rows);
+            if complete(&tys[0], &used) {
+                used.into_iter().any(|case| {
+                    let (rows, tys) = specialize(rows, &tys[0], case, tys);
+                    let row = specialize_row(row, case, tys.len() - rest.len()).unwrap();
+                    useful(&rows, &row, &tys)
+                })
+            } else {
+                useful(&defaults(rows), rest, &tys[1..])
+            }
+        },
+    }
+}
+
+// Values for each column that none of `rows` match, if there are any
+fn witness(rows: &[Vec<Pat>], tys: &[TypeInfo]) -> Option<Vec<String>> {
+    let Some((ty, rest)) = tys.split_first() else {
+        return rows.is_empty().then(Vec::new);
+    };
+    let used = used(rows);
+    if complete(ty, &used) {
+        return used.into_iter().find_map(|case| {
+            let (rows, tys) = specialize(rows, ty, case, tys);
+            let mut fields = witness(&rows, &tys)?;
+            let rest = fields.split_off(tys.len() - rest.len());
+            let mut values = vec![show(ty, case, &fields)];
+            values.extend(rest);
+            Some(values)
+        });
+    }
+
+    let mut values = witness(&defaults(rows), rest)?;
+    let value = match unused(ty, &used) {
+        Some(case) => show(ty, case, &vec![String::from("_"); payload(ty, case).len()]),
+        None => String::from("_"),
+    };
+    values.insert(0, value);
+    Some(values)
+}
+
+// The rows that can match once the first column is known to be `case`,
+// with that column replaced by what's inside it. The column types change
+// the same way.
+fn specialize(rows: &[Vec<Pat>], ty: &TypeInfo, case: Case, tys: &[TypeInfo]) -> (Vec<Vec<Pat>>, Vec<TypeInfo>) {
+    let fields = payload(ty, case);
+    let rows = rows.iter().filter_map(|row| specialize_row(row, case, fields.len())).collect();
+    let tys = fields.iter().chain(&tys[1..]).cloned().collect();
+    (rows, tys)
+}
+
+fn specialize_row(row: &[Pat], case: Case, arity: usize) -> Option<Vec<Pat>> {
+    let (head, rest) = row.split_first().unwrap();
+    let mut row = match head {
+        Pat::Wild => vec![Pat::Wild; arity],
+        Pat::Ctor(c, fields) if *c == case => fields.clone(),
+        Pat::Ctor(..) => return None,
+    };
+    row.extend_from_slice(rest);
+    Some(row)
+}
+
+// The rows that match whatever's in the first column, without it
+fn defaults(rows: &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+    rows.iter()
+        .filter(|row| matches!(row[0], Pat::Wild))
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+// The constructors in the first column, in the order they come up
+fn used(rows: &[Vec<Pat>]) -> Vec<Case> {
+    let mut used = vec![];
+    for row in rows {
+        if let Pat::Ctor(case, _) = row[0] {
+            if !used.contains(&case) {
+                used.push(case);
+            }
+        }
+    }
+    used
+}
+
+fn payload(ty: &TypeInfo, case: Case) -> Vec<TypeInfo> {
+    match (ty, case) {
+        (TypeInfo::Enum(enum_type), Case::Variant(i)) => enum_type.variants[i].1.clone(),
+        (TypeInfo::Struct(tuple), Case::Tuple) => tuple.args.clone(),
+        _ => vec![],
+    }
+}
+
+// Whether `used` has every value of the type
+fn complete(ty: &TypeInfo, used: &[Case]) -> bool {
+    match ty {
+        TypeInfo::Enum(enum_type) => used.len() == enum_type.variants.len(),
+        TypeInfo::Bool => used.len() == 2,
+        TypeInfo::Struct(tuple) if tuple.is_tuple() => used.len() == 1,
+        TypeInfo::Int(t) => t.bits() < 64 && used.len() as u64 == 1 << t.bits(),
+        _ => false,
+    }
+}
+
+// A constructor that isn't in `used`, if the type has any to choose from
+fn unused(ty: &TypeInfo, used: &[Case]) -> Option<Case> {
+    let missing = match ty {
+        TypeInfo::Enum(enum_type) => (0..enum_type.variants.len()).map(Case::Variant).find(|case| !used.contains(case)),
+        TypeInfo::Bool => [false, true].into_iter().map(Case::Bool).find(|case| !used.contains(case)),
+        // The smallest that isn't matched, which is always small
+        TypeInfo::Int(t) => (0..).map(|n| Case::Int(t.wrap(n), *t)).find(|case| !used.contains(case)),
+        _ => None,
+    };
+    missing.filter(|_| !used.is_empty())
+}
+
+fn show(ty: &TypeInfo, case: Case, fields: &[String]) -> String {
+    match (ty, case) {
+        (TypeInfo::Enum(enum_type), Case::Variant(i)) => {
+            let name = format!("{}::{}", enum_type.name, enum_type.variants[i].0);
+            if fields.is_empty() { name } else { format!("{}({})", name, fields.join(", ")) }
+        },
+        (_, Case::Int(n, t)) => t.value(n).to_string(),
+        (_, Case::Bool(b)) => b.to_string(),
+        (_, Case::Tuple) if fields.len() == 1 => format!("({},)", fields[0]),
+        (_, Case::Tuple) => format!("({})", fields.join(", ")),
+        _ => unreachable!("Variant of a {}", ty),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{HuckAst, Parser};
+    use crate::scanner::Scanner;
+    use crate::typecheck::Checker;
+
+    // Checks the match at the end of `source` by hand, since the checker
+    // would reject it if it weren't exhaustive
+    fn report(source: &str) -> Report {
+        let ast = Parser::new(Scanner::new(source)).parse().unwrap();
+        let HuckAst::Block(mut exprs, span) = ast else { panic!() };
+        let Some(HuckAst::Match(scrutinee, mut arms, _)) = exprs.pop() else { panic!() };
+        arms.push(MatchArm { pattern: Pattern::Wildcard(span), guard: None, body: HuckAst::Num(0, None, span) });
+        exprs.push(HuckAst::Match(scrutinee, arms, span));
+
+        let checked = Checker::new().check(&HuckAst::Block(exprs, span)).unwrap();
+        let HuckAst::Block(exprs, _) = checked else { panic!() };
+        let Some(HuckAst::Match(scrutinee, arms, _)) = exprs.last() else { panic!() };
+        check(&arms[..arms.len() - 1], scrutinee.ty())
+    }
+
+    #[test]
+    fn missing_values() {
+        let missing = |source: &str| report(source).missing;
+        assert_eq!(missing("{ match true { true => 1 } }"), Some(String::from("false")));
+        assert_eq!(missing("{ match true { true => 1, false => 2 } }"), None);
+        assert_eq!(missing("{ match 3 { 0 => 1, 1 => 2, n if n > 1 => 3 } }"), Some(String::from("2")));
+        assert_eq!(missing("{ match 3u8 { n => n } }"), None);
+        assert_eq!(missing("{ let x = 3; match x { y => y } }"), None);
+
+        let enums = "enum O { S(bool, i64), N };";
+        assert_eq!(missing(&format!("{{ {} match O::N {{ O::S(true, _) => 1, O::N => 2 }} }}", enums)), Some(String::from("O::S(false, _)")));
+        assert_eq!(missing(&format!("{{ {} match O::N {{ O::S(_, 1) => 1, O::S(true, _) => 2 }} }}", enums)), Some(String::from("O::N")));
+        assert_eq!(missing(&format!("{{ {} match O::N {{ O::N => 1 }} }}", enums)), Some(String::from("O::S(_, _)")));
+        assert_eq!(missing(&format!("{{ {} match O::N {{ O::S(false, _) => 1, O::S(true, n) => n, O::N => 2 }} }}", enums)), None);
+
+        assert_eq!(missing("{ match (true, 1) { (true, _) => 1 } }"), Some(String::from("(false, _)")));
+        assert_eq!(missing("{ match (true, false) { (true, _) => 1, (_, true) => 2 } }"), Some(String::from("(false, false)")));
+        assert_eq!(missing("{ match (true, false) { (true, _) => 1, (false, b) => 2 } }"), None);
+        assert_eq!(missing("{ match (1, (true, 2)) { (_, (false, _)) => 1, (0, _) => 2 } }"), Some(String::from("(1, (true, _))")));
+        assert_eq!(missing(&format!("{{ {} match (O::N, true) {{ (O::N, _) => 1, (O::S(_, _), true) => 2 }} }}", enums)), Some(String::from("(O::S(_, _), false)")));
+        let nested = "enum P { S((bool, i64), i64), N };";
+        assert_eq!(missing(&format!("{{ {} match P::N {{ P::S((true, _), _) => 1, P::N => 2 }} }}", nested)), Some(String::from("P::S((false, _), _)")));
+    }
+
+    #[test]
+    fn unreachable_arms() {
+        let unreachable = |source: &str| report(source).unreachable;
+        assert_eq!(unreachable("{ match true { _ => 1, true => 2 } }"), vec![1]);
+        assert_eq!(unreachable("{ match 1 { 1 => 1, 2 => 2, 1 => 3 } }"), vec![2]);
+        assert_eq!(unreachable("{ match true { true if false => 1, true => 2, false => 3 } }"), Vec::<usize>::new());
+        assert_eq!(unreachable("{ enum O { S(bool), N }; match O::N { O::S(true) => 1, O::S(false) => 2, O::S(_) => 3, O::N => 4 } }"), vec![2]);
+        assert_eq!(unreachable("{ match (true, false) { (true, _) => 1, (_, false) => 2, (true, true) => 3, (false, true) => 4 } }"), vec![2]);
+        assert_eq!(unreachable("{ match (true, 1) { (a, b) => b, (true, 2) => 2 } }"), vec![1]);
+    }
+}
@@ -0,0 +1,855 @@
+//! The huck code formatter.
+//!
+//! Programs are parsed as usual, and the comments (which the parser
+//! never sees) are found in the gaps between tokens and woven back in as
+//! the tree is turned into a [`Doc`]. A `Doc` is laid out Wadler-style:
+//! each group goes on one line if it fits in the width, and is broken
+//! over several otherwise.
+//!
+//! Formatting keeps comments where they were relative to the statements
+//! of a block; a comment in the middle of an expression moves to just
+//! before the next statement. Single blank lines between statements are
+//! kept. The output is always re-parsed and compared with the input, so
+//! the formatter can't change what a program means.
+
+use crate::diagnostic::{Diagnostic, Span};
+use crate::parser::{HuckAst, MatchArm, ParseOutput, Pattern, TypeAnn, TypeArgList};
+use crate::scanner::Scanner;
+use crate::parse_str;
+
+const INDENT: usize = 2;
+
+pub const DEFAULT_WIDTH: usize = 80;
+
+#[derive(Debug, PartialEq)]
+pub enum FormatError {
+    // The input doesn't parse
+    Syntax(Diagnostic),
+    // The formatted program would mean something else; this is a bug
+    ChangedMeaning,
+}
+
+/// Format a huck program to fit in `width` columns where possible.
+pub fn format(source: &str, width: usize) -> Result<String, FormatError> {
+    let ast = parse_str(source).map_err(FormatError::Syntax)?;
+    let comments = comments(source);
+
+    let mut formatter = Formatter { source, comments: &comments, next_comment: 0 };
+    let doc = formatter.program(&ast);
+    let formatted = layout(&doc, width);
+
+    // Comments aren't in the AST, so count them separately
+    let reparsed = parse_str(&formatted).map_err(|_| FormatError::ChangedMeaning)?;
+    if reparsed.map_metadata(&mut |_| ()) != ast.map_metadata(&mut |_| ())
+        || self::comments(&formatted).len() != comments.len()
+    {
+        return Err(FormatError::ChangedMeaning);
+    }
+    Ok(formatted)
+}
+
+// Comments can only be in the gaps between tokens, where everything else
+// is whitespace
+fn comments(source: &str) -> Vec<Span> {
+    let mut gaps = vec![];
+    let mut end = 0;
+    for (_, span) in Scanner::new(source).spanned() {
+        gaps.push(Span::new(end, span.start));
+        end = span.end;
+    }
+    gaps.push(Span::new(end, source.len()));
+
+    let mut comments = vec![];
+    for gap in gaps {
+        let mut start = gap.start;
+        while let Some(i) = source[start..gap.end].find("//") {
+            let comment_start = start + i;
+            let comment_end = source[comment_start..gap.end].find('\n').map_or(gap.end, |j| comment_start + j);
+            comments.push(Span::new(comment_start, comment_end));
+            start = comment_end;
+        }
+    }
+    comments
+}
+
+/// A layout for some code, before it's known which lines need breaking.
+#[derive(Debug, Clone)]
+pub enum Doc {
+    Text(String),
+    // A space, or a newline if the enclosing group is broken
+    Line,
+    // Nothing, or a newline if the enclosing group is broken
+    SoftLine,
+    // Always a newline, so every group around it breaks
+    HardLine,
+    // Forces the enclosing group to break without printing anything;
+    // used after line comments
+    BreakParent,
+    Nest(Box<Doc>),
+    Group(Box<Doc>),
+    Concat(Vec<Doc>),
+}
+
+fn text(s: impl Into<String>) -> Doc {
+    Doc::Text(s.into())
+}
+
+fn nest(doc: Doc) -> Doc {
+    Doc::Nest(Box::new(doc))
+}
+
+fn group(doc: Doc) -> Doc {
+    Doc::Group(Box::new(doc))
+}
+
+fn join(docs: Vec<Doc>, separator: Doc) -> Vec<Doc> {
+    let mut joined = vec![];
+    for (i, doc) in docs.into_iter().enumerate() {
+        if i > 0 {
+            joined.push(separator.clone());
+        }
+        joined.push(doc);
+    }
+    joined
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+/// Print a doc, breaking groups that don't fit in `width`.
+pub fn layout(doc: &Doc, width: usize) -> String {
+    let mut out = String::new();
+    let mut column = 0;
+    let mut stack = vec![(0, Mode::Break, doc)];
+
+    while let Some((indent, mode, doc)) = stack.pop() {
+        match doc {
+            Doc::Text(s) => {
+                out.push_str(s);
+                column += s.chars().count();
+            },
+            Doc::Line | Doc::SoftLine if mode == Mode::Flat => {
+                if let Doc::Line = doc {
+                    out.push(' ');
+                    column += 1;
+                }
+            },
+            Doc::Line | Doc::SoftLine | Doc::HardLine => {
+                out.push('\n');
+                out.push_str(&" ".repeat(indent));
+                column = indent;
+            },
+            Doc::BreakParent => (),
+            Doc::Nest(doc) => stack.push((indent + INDENT, mode, doc)),
+            Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc))),
+            Doc::Group(doc) => {
+                let flat = mode == Mode::Flat || fits(width.saturating_sub(column), doc, &stack);
+                stack.push((indent, if flat { Mode::Flat } else { Mode::Break }, doc));
+            },
+        }
+    }
+
+    // Blank lines get indented like everything else
+    let mut lines = out.lines().map(str::trim_end).collect::<Vec<_>>().join("\n");
+    lines.push('\n');
+    lines
+}
+
+// Whether `doc` fits on the rest of the line when it's flat, along with
+// whatever follows it up to the next line break
+fn fits(mut width: usize, doc: &Doc, rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut stack = vec![(Mode::Flat, doc)];
+    let mut rest = rest.iter().rev();
+    loop {
+        let (mode, doc) = match stack.pop() {
+            Some(next) => next,
+            None => match rest.next() {
+                Some((_, mode, doc)) => (*mode, *doc),
+                None => return true,
+            },
+        };
+        match doc {
+            Doc::Text(s) => match width.checked_sub(s.chars().count()) {
+                Some(left) => width = left,
+                None => return false,
+            },
+            Doc::Line | Doc::SoftLine if mode == Mode::Break => return true,
+            Doc::Line => match width.checked_sub(1) {
+                Some(left) => width = left,
+                None => return false,
+            },
+            Doc::SoftLine => (),
+            Doc::HardLine | Doc::BreakParent => return mode == Mode::Break,
+            Doc::Nest(doc) | Doc::Group(doc) => stack.push((mode, doc)),
+            Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (mode, doc))),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+enum Prec {
+    Compare,
+    AddSub,
+    MultDiv,
+    Cast,
+    Unary,
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    comments: &'a [Span],
+    // Comments before this one have been printed
+    next_comment: usize,
+}
+
+impl Formatter<'_> {
+    fn comment(&self, span: Span) -> Doc {
+        text(self.source[span.start..span.end].trim_end())
+    }
+
+    // Every comment not yet printed that starts before `offset`
+    fn comments_before(&mut self, offset: usize) -> Vec<Doc> {
+        let mut docs = vec![];
+        while let Some(span) = self.comments.get(self.next_comment).filter(|span| span.start < offset) {
+            docs.push(self.comment(*span));
+            self.next_comment += 1;
+        }
+        docs
+    }
+
+    // A comment on the same line as `end`, that comes before `limit`
+    fn trailing_comment(&mut self, end: usize, limit: usize) -> Option<Doc> {
+        let span = *self.comments.get(self.next_comment)?;
+        if span.start < end || span.start >= limit || self.source[end..span.start].contains('\n') {
+            return None;
+        }
+        self.next_comment += 1;
+        Some(Doc::Concat(vec![text(" "), self.comment(span), Doc::BreakParent]))
+    }
+
+    fn blank_line_between(&self, end: usize, start: usize) -> bool {
+        self.source.get(end..start).is_some_and(|gap| gap.matches('\n').count() > 1)
+    }
+
+    fn program(&mut self, ast: &ParseOutput) -> Doc {
+        let span = *ast.get_metadata();
+        let mut lines = self.comments_before(span.start);
+        let mut program = vec![self.expr(ast)];
+        program.extend(self.trailing_comment(span.end, usize::MAX));
+        lines.push(Doc::Concat(program));
+        lines.extend(self.comments_before(usize::MAX));
+        Doc::Concat(join(lines, Doc::HardLine))
+    }
+
+    fn block(&mut self, exprs: &[ParseOutput], span: Span) -> Doc {
+        let mut lines = vec![];
+        let mut previous_end = span.start + 1;
+        let first_start = exprs.first().map_or(span.end, |expr| expr.get_metadata().start);
+        let opening = self.trailing_comment(previous_end, first_start);
+        for (i, expr) in exprs.iter().enumerate() {
+            let expr_span = *expr.get_metadata();
+            let leading = self.comments_before(expr_span.start);
+            let first_start = self.comments.get(self.next_comment - leading.len())
+                .filter(|_| !leading.is_empty())
+                .map_or(expr_span.start, |c| c.start);
+            if i > 0 && self.blank_line_between(previous_end, first_start) {
+                lines.push(text(""));
+            }
+            lines.extend(leading);
+
+            // Comments inside the expression that nothing nested took go
+            // before it, since there's nowhere for them in the middle
+            let doc = self.expr(expr);
+            lines.extend(self.comments_before(expr_span.end));
+            let mut line = vec![doc];
+            let limit = match exprs.get(i + 1) {
+                Some(next) => next.get_metadata().start,
+                None => span.end,
+            };
+            if i + 1 < exprs.len() {
+                line.push(text(";"));
+            }
+            line.extend(self.trailing_comment(expr_span.end, limit));
+            lines.push(Doc::Concat(line));
+            previous_end = expr_span.end;
+        }
+        lines.extend(self.comments_before(span.end));
+
+        // Blocks of one expression can share a line with what's around them
+        let separator = if lines.len() > 1 { Doc::HardLine } else { Doc::Line };
+        group(Doc::Concat(vec![
+            text("{"),
+            Doc::Concat(opening.into_iter().collect()),
+            nest(Doc::Concat(vec![separator.clone(), Doc::Concat(join(lines, separator.clone()))])),
+            separator,
+            text("}"),
+        ]))
+    }
+
+    fn expr(&mut self, ast: &ParseOutput) -> Doc {
+        match ast {
+            HuckAst::Num(n, suffix, _) => text(format!("{}{}", n, suffix.as_ref().map_or(String::new(), type_ann))),
+            HuckAst::Float(x, _) => {
+                // Keep the point so it still reads back as a float
+                let digits = x.to_string();
+                text(if digits.contains('.') { digits } else { digits + ".0" })
+            },
+            HuckAst::Neg(operand, _) => {
+                let operand = self.operand(operand, |p| p < Prec::Unary);
+                Doc::Concat(vec![text("-"), operand])
+            },
+            HuckAst::Cast(operand, ann, _) => {
+                let operand = self.operand(operand, |p| p < Prec::Cast);
+                Doc::Concat(vec![operand, text(format!(" as {}", type_ann(ann)))])
+            },
+            HuckAst::BoolLit(b, _) => text(b.to_string()),
+            HuckAst::VarRef(name, _) => text(name),
+            HuckAst::Plus(l, r, _) => self.binary(l, "+", r, Prec::AddSub),
+            HuckAst::Minus(l, r, _) => self.binary(l, "-", r, Prec::AddSub),
+            HuckAst::Times(l, r, _) => self.binary(l, "*", r, Prec::MultDiv),
+            HuckAst::Div(l, r, _) => self.binary(l, "/", r, Prec::MultDiv),
+            HuckAst::Rem(l, r, _) => self.binary(l, "%", r, Prec::MultDiv),
+            HuckAst::Equals(l, r, _) => self.binary(l, "==", r, Prec::Compare),
+            HuckAst::NotEquals(l, r, _) => self.binary(l, "!=", r, Prec::Compare),
+            HuckAst::Less(l, r, _) => self.binary(l, "<", r, Prec::Compare),
+            HuckAst::LessEq(l, r, _) => self.binary(l, "<=", r, Prec::Compare),
+            HuckAst::Greater(l, r, _) => self.binary(l, ">", r, Prec::Compare),
+            HuckAst::GreaterEq(l, r, _) => self.binary(l, ">=", r, Prec::Compare),
+            HuckAst::Let(name, false, init, _) => Doc::Concat(vec![text(format!("let {} = ", name)), self.expr(init)]),
+            HuckAst::Let(name, true, init, _) => Doc::Concat(vec![text(format!("let mut {} = ", name)), self.expr(init)]),
+            HuckAst::Assign(name, op, value, _) => {
+                let op = op.map_or("=", |op| op.symbol());
+                Doc::Concat(vec![text(format!("{} {} ", name, op)), self.expr(value)])
+            },
+            HuckAst::Block(exprs, span) => self.block(exprs, *span),
+            HuckAst::If(test, then_branch, else_branch, _) => Doc::Concat(vec![
+                text("if "),
+                self.before_brace(test),
+                text(" "),
+                self.expr(then_branch),
+                text(" else "),
+                self.expr(else_branch),
+            ]),
+            HuckAst::Fn(type_params, params, ret, body, _) => {
+                let params = params.iter()
+                    .map(|(name, ann)| text(format!("{}: {}", name, type_ann(ann))))
+                    .collect();
+                let type_params = match type_params.as_slice() {
+                    [] => String::new(),
+                    type_params => {
+                        let type_params = type_params.iter().map(|(name, bounds)| match bounds.as_slice() {
+                            [] => name.clone(),
+                            bounds => format!("{}: {}", name, bounds.join(" + ")),
+                        });
+                        format!("<{}>", type_params.collect::<Vec<_>>().join(", "))
+                    },
+                };
+                Doc::Concat(vec![
+                    text(format!("fn {}", type_params)),
+                    Self::list(params),
+                    text(format!(": {} ", type_ann(ret))),
+                    self.expr(body),
+                ])
+            },
+            HuckAst::Call(name, type_args, args, _) => {
+                let args = args.iter().map(|arg| self.expr(arg)).collect();
+                Doc::Concat(vec![text(format!("{}{}", name, turbofish(type_args))), Self::list(args)])
+            },
+            HuckAst::Builtin(builtin, args, _) => {
+                let args = args.iter().map(|arg| self.expr(arg)).collect();
+                Doc::Concat(vec![text(builtin.name()), Self::list(args)])
+            },
+            HuckAst::Struct(name, type_params, fields, _) => {
+                let fields = fields.iter()
+                    .map(|(name, ann)| text(format!("{}: {}", name, type_ann(ann))))
+                    .collect();
+                Doc::Concat(vec![text(format!("struct {} ", generic_name(name, type_params))), Self::fields(fields)])
+            },
+            HuckAst::StructLit(name, type_args, fields, base, _) => {
+                let mut fields = fields.iter()
+                    .map(|(name, value)| Doc::Concat(vec![text(format!("{}: ", name)), self.expr(value)]))
+                    .collect::<Vec<_>>();
+                if let Some(base) = base {
+                    fields.push(Doc::Concat(vec![text(".."), self.expr(base)]));
+                }
+                Doc::Concat(vec![text(format!("{}{} ", name, turbofish(type_args))), Self::fields(fields)])
+            },
+            HuckAst::Tuple(elements, _) => {
+                let elements = elements.iter().map(|element| self.expr(element)).collect();
+                Self::tuple(elements)
+            },
+            HuckAst::Field(operand, field, _) => {
+                let operand = self.operand(operand, |_| true);
+                Doc::Concat(vec![operand, text(format!(".{}", field))])
+            },
+            HuckAst::Enum(name, type_params, variants, _) => {
+                let variants = variants.iter()
+                    .map(|(name, payload)| match payload.as_slice() {
+                        [] => text(name),
+                        payload => Doc::Concat(vec![text(name), Self::list(payload.iter().map(|ann| text(type_ann(ann))).collect())]),
+                    })
+                    .collect();
+                Doc::Concat(vec![text(format!("enum {} ", generic_name(name, type_params))), Self::fields(variants)])
+            },
+            HuckAst::Variant(name, type_args, variant, args, _) => {
+                let args = args.iter().map(|arg| self.expr(arg)).collect::<Vec<_>>();
+                let path = format!("{}{}::{}", name, turbofish(type_args), variant);
+                if args.is_empty() {
+                    text(path)
+                } else {
+                    Doc::Concat(vec![text(path), Self::list(args)])
+                }
+            },
+            // Arms always go on lines of their own
+            HuckAst::Match(scrutinee, arms, _) => {
+                let scrutinee = self.before_brace(scrutinee);
+                let arms = arms.iter().map(|arm| self.arm(arm)).collect();
+                Doc::Concat(vec![
+                    text("match "),
+                    scrutinee,
+                    text(" {"),
+                    nest(Doc::Concat(vec![Doc::HardLine, Doc::Concat(join(arms, Doc::HardLine))])),
+                    Doc::HardLine,
+                    text("}"),
+                ])
+            },
+            HuckAst::Trait(name, methods, _) => {
+                let methods = methods.iter()
+                    .map(|method| Doc::Concat(vec![Self::method_head(&method.name, &method.params), text(format!(": {}", type_ann(&method.ret)))]))
+                    .collect();
+                Doc::Concat(vec![text(format!("trait {} ", name)), Self::members(methods)])
+            },
+            HuckAst::Impl(name, target, methods, _) => {
+                let methods = methods.iter()
+                    .map(|(name, method)| match method {
+                        HuckAst::Fn(_, params, ret, body, _) => Doc::Concat(vec![
+                            Self::method_head(name, params),
+                            text(format!(": {} ", type_ann(ret))),
+                            self.expr(body),
+                        ]),
+                        _ => unreachable!("Impl methods are always functions"),
+                    })
+                    .collect();
+                Doc::Concat(vec![text(format!("impl {} for {} ", name, type_ann(target))), Self::members(methods)])
+            },
+            HuckAst::MethodCall(receiver, method, args, _, _) => {
+                let receiver = self.operand(receiver, |_| true);
+                let args = args.iter().map(|arg| self.expr(arg)).collect();
+                Doc::Concat(vec![receiver, text(format!(".{}", method)), Self::list(args)])
+            },
+            HuckAst::While(label, cond, body, _) => Doc::Concat(vec![
+                text(format!("{}while ", loop_label(label))),
+                self.before_brace(cond),
+                text(" "),
+                self.expr(body),
+            ]),
+            HuckAst::Loop(label, body, _) => Doc::Concat(vec![text(format!("{}loop ", loop_label(label))), self.expr(body)]),
+            HuckAst::For(label, var, from, to, body, _) => Doc::Concat(vec![
+                text(format!("{}for {} in ", loop_label(label), var)),
+                self.before_brace(from),
+                text(".."),
+                self.before_brace(to),
+                text(" "),
+                self.expr(body),
+            ]),
+            HuckAst::Break(label, value, _) => {
+                let label = label.as_ref().map_or(String::new(), |label| format!(" '{}", label));
+                match value {
+                    Some(value) => Doc::Concat(vec![text(format!("break{} ", label)), self.expr(value)]),
+                    None => text(format!("break{}", label)),
+                }
+            },
+            HuckAst::Continue(label, _) => text(format!("continue{}", label.as_ref().map_or(String::new(), |label| format!(" '{}", label)))),
+        }
+    }
+
+    // What comes before the brace of an `if`, `match` or loop, where a
+    // struct literal's brace would be taken for that one
+    fn before_brace(&mut self, ast: &ParseOutput) -> Doc {
+        if bare_struct_literal(ast) {
+            Doc::Concat(vec![text("("), self.expr(ast), text(")")])
+        } else {
+            self.expr(ast)
+        }
+    }
+
+    // `fn name(self, x: i64)`, where `self` doesn't get its type written
+    fn method_head(name: &str, params: &[(String, TypeAnn)]) -> Doc {
+        let params = params.iter()
+            .map(|(name, ann)| match name.as_str() {
+                "self" => text("self"),
+                _ => text(format!("{}: {}", name, type_ann(ann))),
+            })
+            .collect();
+        Doc::Concat(vec![text(format!("fn {}", name)), Self::list(params)])
+    }
+
+    // The methods of a trait or impl, one per line and separated by
+    // semicolons
+    fn members(members: Vec<Doc>) -> Doc {
+        if members.is_empty() {
+            return text("{}");
+        }
+        Doc::Concat(vec![
+            text("{"),
+            nest(Doc::Concat(vec![Doc::HardLine, Doc::Concat(join(members, Doc::Concat(vec![text(";"), Doc::HardLine])))])),
+            Doc::HardLine,
+            text("}"),
+        ])
+    }
+
+    fn arm(&mut self, arm: &MatchArm<Span>) -> Doc {
+        let mut doc = vec![self.pattern(&arm.pattern)];
+        if let Some(guard) = &arm.guard {
+            doc.push(text(" if "));
+            doc.push(self.expr(guard));
+        }
+        doc.push(text(" => "));
+        doc.push(self.expr(&arm.body));
+        doc.push(text(","));
+        Doc::Concat(doc)
+    }
+
+    fn pattern(&mut self, pattern: &Pattern<Span>) -> Doc {
+        match pattern {
+            Pattern::Wildcard(_) => text("_"),
+            Pattern::Binding(name, _) => text(name),
+            Pattern::Literal(literal) => self.expr(literal),
+            Pattern::Variant(name, variant, fields, _) if fields.is_empty() => text(format!("{}::{}", name, variant)),
+            Pattern::Variant(name, variant, fields, _) => {
+                let fields = fields.iter().map(|field| self.pattern(field)).collect();
+                Doc::Concat(vec![text(format!("{}::{}", name, variant)), Self::list(fields)])
+            },
+            Pattern::Tuple(elements, _) => {
+                let elements = elements.iter().map(|element| self.pattern(element)).collect();
+                Self::tuple(elements)
+            },
+        }
+    }
+
+    // Struct fields, in braces and laid out like a `list`
+    fn fields(fields: Vec<Doc>) -> Doc {
+        if fields.is_empty() {
+            return text("{}");
+        }
+        group(Doc::Concat(vec![
+            text("{"),
+            nest(Doc::Concat(vec![Doc::Line, Doc::Concat(join(fields, Doc::Concat(vec![text(","), Doc::Line])))])),
+            Doc::Line,
+            text("}"),
+        ]))
+    }
+
+    // A parenthesized, comma-separated list that puts one item on each
+    // line if it's too long
+    fn list(items: Vec<Doc>) -> Doc {
+        if items.is_empty() {
+            return text("()");
+        }
+        group(Doc::Concat(vec![
+            text("("),
+            nest(Doc::Concat(vec![Doc::SoftLine, Doc::Concat(join(items, Doc::Concat(vec![text(","), Doc::Line])))])),
+            Doc::SoftLine,
+            text(")"),
+        ]))
+    }
+
+    // A tuple with one element needs its comma, or it's just parentheses
+    fn tuple(mut elements: Vec<Doc>) -> Doc {
+        match elements.len() {
+            1 => Doc::Concat(vec![text("("), elements.remove(0), text(",)")]),
+            _ => Self::list(elements),
+        }
+    }
+
+    fn binary(&mut self, lhs: &ParseOutput, op: &str, rhs: &ParseOutput, prec: Prec) -> Doc {
+        // Operators are left-associative, so a right operand at the same
+        // precedence needs parentheses
+        let lhs = self.operand(lhs, |p| p < prec);
+        let rhs = self.operand(rhs, |p| p <= prec);
+        group(Doc::Concat(vec![lhs, text(format!(" {}", op)), nest(Doc::Concat(vec![Doc::Line, rhs]))]))
+    }
+
+    fn operand(&mut self, ast: &ParseOutput, needs_parens: impl Fn(Prec) -> bool) -> Doc {
+        let parens = match ast {
+            // These would swallow the rest of the expression
+            HuckAst::Let(..) | HuckAst::Assign(..) | HuckAst::Fn(..) | HuckAst::Break(_, Some(_), _) => true,
+            _ => binary_prec(ast).is_some_and(needs_parens),
+        };
+        let doc = self.expr(ast);
+        if parens {
+            Doc::Concat(vec![text("("), doc, text(")")])
+        } else {
+            doc
+        }
+    }
+}
+
+fn binary_prec<T>(ast: &HuckAst<T>) -> Option<Prec> {
+    match ast {
+        HuckAst::Plus(..) | HuckAst::Minus(..) => Some(Prec::AddSub),
+        HuckAst::Times(..) | HuckAst::Div(..) | HuckAst::Rem(..) => Some(Prec::MultDiv),
+        HuckAst::Equals(..)
+        | HuckAst::NotEquals(..)
+        | HuckAst::Less(..)
+        | HuckAst::LessEq(..)
+        | HuckAst::Greater(..)
+        | HuckAst::GreaterEq(..) => Some(Prec::Compare),
+        HuckAst::Cast(..) => Some(Prec::Cast),
+        HuckAst::Neg(..) => Some(Prec::Unary),
+        _ => None,
+    }
+}
+
+// Whether there's a struct literal in `ast` that isn't inside some kind of
+// brackets already
+fn bare_struct_literal<T>(ast: &HuckAst<T>) -> bool {
+    match ast {
+        HuckAst::StructLit(..) => true,
+        HuckAst::Plus(l, r, _)
+        | HuckAst::Minus(l, r, _)
+        | HuckAst::Times(l, r, _)
+        | HuckAst::Div(l, r, _)
+        | HuckAst::Rem(l, r, _)
+        | HuckAst::Equals(l, r, _)
+        | HuckAst::NotEquals(l, r, _)
+        | HuckAst::Less(l, r, _)
+        | HuckAst::LessEq(l, r, _)
+        | HuckAst::Greater(l, r, _)
+        | HuckAst::GreaterEq(l, r, _) => bare_struct_literal(l) || bare_struct_literal(r),
+        HuckAst::Neg(operand, _)
+        | HuckAst::Cast(operand, _, _)
+        | HuckAst::Field(operand, _, _)
+        | HuckAst::Let(_, _, operand, _)
+        | HuckAst::Assign(_, _, operand, _)
+        | HuckAst::If(operand, _, _, _)
+        | HuckAst::While(_, operand, _, _) => bare_struct_literal(operand),
+        HuckAst::Break(_, value, _) => value.as_deref().is_some_and(bare_struct_literal),
+        HuckAst::For(_, _, from, to, _, _) => bare_struct_literal(from) || bare_struct_literal(to),
+        _ => false,
+    }
+}
+
+// `'outer: `, before a loop's keyword
+fn loop_label(label: &Option<String>) -> String {
+    label.as_ref().map_or(String::new(), |label| format!("'{}: ", label))
+}
+
+fn type_ann(ann: &TypeAnn) -> String {
+    match ann {
+        TypeAnn::Unit => String::from("()"),
+        TypeAnn::Named(name) => name.clone(),
+        TypeAnn::Applied(name, args) => {
+            let args = args.args.iter().map(type_ann).collect::<Vec<_>>();
+            format!("{}<{}>", name, args.join(", "))
+        },
+        TypeAnn::Dyn(name) => format!("dyn {}", name),
+        TypeAnn::Tuple(elements) if elements.len() == 1 => format!("({},)", type_ann(&elements[0])),
+        TypeAnn::Tuple(elements) => format!("({})", elements.iter().map(type_ann).collect::<Vec<_>>().join(", ")),
+    }
+}
+
+// Explicit type arguments in an expression, like the `::<i64>` in
+// `f::<i64>(1)`
+fn turbofish(type_args: &TypeArgList) -> String {
+    match &type_args.args[..] {
+        [] => String::new(),
+        args => format!("::<{}>", args.iter().map(type_ann).collect::<Vec<_>>().join(", ")),
+    }
+}
+
+fn generic_name(name: &str, type_params: &[String]) -> String {
+    match type_params {
+        [] => name.to_string(),
+        type_params => format!("{}<{}>", name, type_params.join(", ")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fmt(source: &str) -> String {
+        let formatted = format(source, DEFAULT_WIDTH).unwrap();
+        assert_eq!(format(&formatted, DEFAULT_WIDTH).unwrap(), formatted, "not idempotent");
+        formatted
+    }
+
+    #[test]
+    fn blocks_and_conditionals() {
+        assert_eq!(fmt("{let x=1;let y=2;let test=false;if test{x}else{y}}"), "\
+{
+  let x = 1;
+  let y = 2;
+  let test = false;
+  if test { x } else { y }
+}
+");
+        assert_eq!(fmt("  69  "), "69\n");
+    }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        let source = "// expect: 3
+{ // the start
+  let x = 1; // one
+
+
+  // two
+  let y = 2;
+  x + // in the middle
+    y
+  // the end
+}
+";
+        assert_eq!(fmt(source), "\
+// expect: 3
+{ // the start
+  let x = 1; // one
+
+  // two
+  let y = 2;
+  // in the middle
+  x + y
+  // the end
+}
+");
+    }
+
+    #[test]
+    fn parentheses() {
+        assert_eq!(fmt("(1 - (2 - 3)) - 4 * (5 + 6)"), "1 - (2 - 3) - 4 * (5 + 6)\n");
+        assert_eq!(fmt("(1 < 2) == (3 < 4)"), "1 < 2 == (3 < 4)\n");
+        assert_eq!(fmt("(7 % 3) * 2 % (5 / 2)"), "7 % 3 * 2 % (5 / 2)\n");
+        assert_eq!(fmt("{(let x = 1) + 2}"), "{ (let x = 1) + 2 }\n");
+        assert_eq!(fmt("-(1 + 2) as u8 * (3u8 as i64 as u8) - -(-4)"), "-(1 + 2) as u8 * 3u8 as i64 as u8 - --4\n");
+    }
+
+    #[test]
+    fn structs() {
+        let source = "{struct P{x:i64,y:bool,}; let p=P{y:true,x:1}; if (P{..p}).y {P{x:p.x+1,..p}} else {-(p).x; P{x:0,y:false}}}";
+        assert_eq!(fmt(source), "\
+{
+  struct P { x: i64, y: bool };
+  let p = P { y: true, x: 1 };
+  if (P { ..p }.y) { P { x: p.x + 1, ..p } } else {
+    -p.x;
+    P { x: 0, y: false }
+  }
+}
+");
+        assert_eq!(fmt("{ struct E {}; (E {}) }"), "{\n  struct E {};\n  E {}\n}\n");
+    }
+
+    #[test]
+    fn matches() {
+        let source = "{enum E{A(i64,bool,),B}; match E::A(1,true) {E::A(n,true) if n>0=>-1,E::A(_,false)=>n,E::B=>E::B,}}";
+        assert_eq!(fmt(source), "\
+{
+  enum E { A(i64, bool), B };
+  match E::A(1, true) {
+    E::A(n, true) if n > 0 => -1,
+    E::A(_, false) => n,
+    E::B => E::B,
+  }
+}
+");
+    }
+
+    #[test]
+    fn tuples() {
+        let source = "{let f=fn(p:(i64,(bool,))):i64{match p{(n,(true,))=>n,(_,(false,))=>0}}; f((1,(true,))) + (2,3).1}";
+        assert_eq!(fmt(source), "\
+{
+  let f = fn (p: (i64, (bool,))): i64 {
+    match p {
+      (n, (true,)) => n,
+      (_, (false,)) => 0,
+    }
+  };
+  f((1, (true,))) + (2, 3).1
+}
+");
+    }
+
+    #[test]
+    fn generics() {
+        let source = "{enum O<T>{S(T),N}; struct P<A,B>{a:A,b:B}; let f=fn<T>(x:O<P<T,u8>>):T{g::<T,i64>(x)}; O::<i32>::N; P::<bool,u8>{a:true,b:1u8}}";
+        assert_eq!(fmt(source), "\
+{
+  enum O<T> { S(T), N };
+  struct P<A, B> { a: A, b: B };
+  let f = fn <T>(x: O<P<T, u8>>): T { g::<T, i64>(x) };
+  O::<i32>::N;
+  P::<bool, u8> { a: true, b: 1u8 }
+}
+");
+    }
+
+    #[test]
+    fn assignment() {
+        assert_eq!(fmt("{let mut x=1;x=x+1;x-=2;x%=3;x}"), "{\n  let mut x = 1;\n  x = x + 1;\n  x -= 2;\n  x %= 3;\n  x\n}\n");
+    }
+
+    #[test]
+    fn loops() {
+        assert_eq!(fmt("{let mut i=0;'outer:while i<10{i+=1;for j in 0..i{if j==3{continue 'outer}else{break}}};loop{break 'inner (P{x:1})}}"), "\
+{
+  let mut i = 0;
+  'outer: while i < 10 {
+    i += 1;
+    for j in 0..i { if j == 3 { continue 'outer } else { break } }
+  };
+  loop { break 'inner P { x: 1 } }
+}
+");
+        assert_eq!(fmt("for i in (P{x:0}).x..(n) {i}"), "for i in (P { x: 0 }.x)..n { i }\n");
+        assert_eq!(fmt("1+(break 2)"), "1 + (break 2)\n");
+    }
+
+    #[test]
+    fn traits() {
+        let source = "{trait S{fn area(self):f64;fn scale(self,by:f64):Self;}; impl S for i64{fn area(self):f64{self as f64};fn scale(self,by:f64):i64{self}}; let f=fn<T:S+Eq,U>(x:T,y:dyn S):f64{x.scale(2.0).area()+y.area()}; f(1,2 as dyn S)}";
+        assert_eq!(fmt(source), "\
+{
+  trait S {
+    fn area(self): f64;
+    fn scale(self, by: f64): Self
+  };
+  impl S for i64 {
+    fn area(self): f64 { self as f64 };
+    fn scale(self, by: f64): i64 { self }
+  };
+  let f = fn <T: S + Eq, U>(x: T, y: dyn S): f64 {
+    x.scale(2.0).area() + y.area()
+  };
+  f(1, 2 as dyn S)
+}
+");
+    }
+
+    #[test]
+    fn width() {
+        let source = "{let f = fn (first: i64, second: i64, third: bool): i64 { first };
+                       f(100000000000, 200000000000, 300000000000 + 400000000000 * 500000000000 == 600)}";
+        assert_eq!(fmt(source), "\
+{
+  let f = fn (first: i64, second: i64, third: bool): i64 { first };
+  f(
+    100000000000,
+    200000000000,
+    300000000000 + 400000000000 * 500000000000 == 600
+  )
+}
+");
+        assert_eq!(format("{ 1 + 2 }", 6).unwrap(), "{\n  1 +\n    2\n}\n");
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert!(matches!(format("{ 1 +", DEFAULT_WIDTH), Err(FormatError::Syntax(_))));
+    }
+}
@@ -0,0 +1,233 @@
+// Inlining of small, non-recursive functions.
+//
+// Recursive functions (including mutually recursive groups) are left
+// alone: they rely on tail calls to run in constant stack, and inlining
+// them would never terminate anyway.
+
+use crate::ir::{Block, BlockId, Function, Inst, Module, Operand, Reg, Terminator};
+
+use std::collections::{HashMap, HashSet};
+
+// Maximum callee size, in instructions plus blocks
+const INLINE_THRESHOLD: usize = 24;
+
+pub fn inline_functions(module: &mut Module) -> bool {
+    let callees: HashMap<String, Vec<String>> = module.functions.iter()
+        .map(|f| (f.name.clone(), callees(f)))
+        .collect();
+
+    let inlinable: HashMap<String, Function> = module.functions.iter()
+        .filter(|f| f.name != "main" && size(f) <= INLINE_THRESHOLD && !is_recursive(&f.name, &callees))
+        .map(|f| (f.name.clone(), f.clone()))
+        .collect();
+
+    let mut changed = false;
+    for function in &mut module.functions {
+        while let Some((block, site)) = find_call_site(function, &inlinable) {
+            inline_call(function, block, site, &inlinable);
+            changed = true;
+        }
+    }
+
+    changed | remove_unused_functions(module)
+}
+
+fn callees(function: &Function) -> Vec<String> {
+    let mut names = vec![];
+    for block in &function.blocks {
+        for inst in &block.insts {
+            if let Inst::Call { func, .. } = inst {
+                names.push(func.clone());
+            }
+        }
+        if let Terminator::TailCall { func, .. } = &block.term {
+            names.push(func.clone());
+        }
+    }
+    names
+}
+
+fn size(function: &Function) -> usize {
+    function.blocks.iter().map(|b| b.insts.len() + 1).sum()
+}
+
+// Can `name` reach itself through the call graph?
+fn is_recursive(name: &str, callees: &HashMap<String, Vec<String>>) -> bool {
+    let mut seen = HashSet::new();
+    let mut stack: Vec<&str> = callees[name].iter().map(String::as_str).collect();
+    while let Some(next) = stack.pop() {
+        if next == name {
+            return true;
+        }
+        if seen.insert(next) {
+            stack.extend(callees[next].iter().map(String::as_str));
+        }
+    }
+    false
+}
+
+// Where a call lives: before instruction `n` of a block, or in its terminator
+#[derive(Clone, Copy)]
+enum CallSite {
+    Inst(usize),
+    Tail,
+}
+
+fn find_call_site(function: &Function, inlinable: &HashMap<String, Function>) -> Option<(BlockId, CallSite)> {
+    let can_inline = |func: &String| func != &function.name && inlinable.contains_key(func);
+
+    for (i, block) in function.blocks.iter().enumerate() {
+        for (n, inst) in block.insts.iter().enumerate() {
+            if let Inst::Call { func, .. } = inst {
+                if can_inline(func) {
+                    return Some((BlockId(i), CallSite::Inst(n)));
+                }
+            }
+        }
+        if let Terminator::TailCall { func, .. } = &block.term {
+            if can_inline(func) {
+                return Some((BlockId(i), CallSite::Tail));
+            }
+        }
+    }
+    None
+}
+
+fn inline_call(caller: &mut Function, block: BlockId, site: CallSite, inlinable: &HashMap<String, Function>) {
+    let (func, args, rest) = match site {
+        CallSite::Inst(n) => {
+            let insts = &mut caller.blocks[block.0].insts;
+            let rest = insts.split_off(n + 1);
+            let Some(Inst::Call { dst, func, args }) = insts.pop() else { unreachable!() };
+            (func, args, Some((dst, rest)))
+        },
+        CallSite::Tail => {
+            let Terminator::TailCall { func, args } = caller.blocks[block.0].term.clone() else { unreachable!() };
+            (func, args, None)
+        },
+    };
+    let callee = &inlinable[&func];
+
+    let reg_offset = caller.regs.len();
+    let block_offset = caller.blocks.len();
+    let continuation = BlockId(block_offset + callee.blocks.len());
+    caller.regs.extend(&callee.regs);
+
+    // Arguments become copies into the callee's (renamed) parameters
+    for (param, arg) in callee.params.iter().zip(args) {
+        let dst = Reg(param.0 + reg_offset);
+        caller.blocks[block.0].insts.push(Inst::Copy { dst, src: arg });
+    }
+    let old_term = std::mem::replace(&mut caller.blocks[block.0].term, Terminator::Jump(BlockId(block_offset)));
+
+    let shift = |operand: &mut Operand| {
+        if let Operand::Reg(reg) = operand {
+            reg.0 += reg_offset;
+        }
+    };
+
+    for callee_block in &callee.blocks {
+        let mut block = callee_block.clone();
+        for inst in &mut block.insts {
+            inst.dst_mut().0 += reg_offset;
+            inst.operands_mut().into_iter().for_each(shift);
+        }
+        block.term.operands_mut().into_iter().for_each(shift);
+        for target in block.term.successors_mut() {
+            target.0 += block_offset;
+        }
+
+        // Returning from the callee means resuming the caller; inlined
+        // into a tail position, the callee's returns are ours
+        if let Some((dst, _)) = &rest {
+            match block.term.clone() {
+                Terminator::Return(value) => {
+                    block.insts.push(Inst::Copy { dst: *dst, src: value });
+                    block.term = Terminator::Jump(continuation);
+                },
+                Terminator::TailCall { func, args } => {
+                    block.insts.push(Inst::Call { dst: *dst, func, args });
+                    block.term = Terminator::Jump(continuation);
+                },
+                _ => (),
+            }
+        }
+        caller.blocks.push(block);
+    }
+
+    if let Some((_, rest)) = rest {
+        caller.blocks.push(Block { insts: rest, term: old_term });
+    }
+}
+
+// Drop functions that can't be reached from main any more. Whatever a
+// vtable lists can be called through a `dyn`, so it stays too.
+fn remove_unused_functions(module: &mut Module) -> bool {
+    let callees: HashMap<&str, Vec<String>> = module.functions.iter()
+        .map(|f| (f.name.as_str(), callees(f)))
+        .collect();
+
+    let mut live: HashSet<String> = HashSet::new();
+    let mut stack = vec!["main".to_string()];
+    stack.extend(module.vtables.iter().flat_map(|vtable| vtable.methods.iter().cloned()));
+    while let Some(name) = stack.pop() {
+        if live.insert(name.clone()) {
+            stack.extend(callees.get(name.as_str()).into_iter().flatten().cloned());
+        }
+    }
+
+    let before = module.functions.len();
+    module.functions.retain(|f| live.contains(&f.name));
+    module.functions.len() != before
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lower::lower;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use crate::typecheck::Checker;
+
+    fn lower_str(s: &str) -> Module {
+        let ast = Parser::new(Scanner::new(s)).parse().unwrap();
+        let checked = Checker::new().check(&ast).unwrap();
+        lower(&checked, s)
+    }
+
+    fn names(module: &Module) -> Vec<&str> {
+        module.functions.iter().map(|f| f.name.as_str()).collect()
+    }
+
+    #[test]
+    fn inlines_small_functions() {
+        let mut module = lower_str("{
+            let double = fn (x: i64): i64 { x * 2 };
+            let y = double(3);
+            double(y)
+        }");
+        assert!(inline_functions(&mut module));
+        assert_eq!(names(&module), vec!["main"]);
+        assert!(callees(&module.functions[0]).is_empty());
+    }
+
+    #[test]
+    fn leaves_recursion_alone() {
+        let mut module = lower_str("{
+            let odd = fn (x: i64): bool { if x == 0 { false } else { even(x - 1) } };
+            let even = fn (x: i64): bool { if x == 0 { true } else { odd(x - 1) } };
+            let fact = fn (n: i64): i64 { if n == 0 { 1 } else { n * fact(n - 1) } };
+            if even(4) { fact(5) } else { 0 }
+        }");
+        inline_functions(&mut module);
+        assert_eq!(names(&module), vec!["main", "odd", "even", "fact"]);
+    }
+
+    #[test]
+    fn respects_size_limit() {
+        let body = (0..INLINE_THRESHOLD).map(|_| "x + 1").collect::<Vec<_>>().join(" + ");
+        let mut module = lower_str(&format!("{{ let big = fn (x: i64): i64 {{ {} }}; big(1) }}", body));
+        assert!(!inline_functions(&mut module));
+        assert_eq!(names(&module), vec!["main", "big"]);
+    }
+}
@@ -0,0 +1,998 @@
+// Tree-walking interpreter over the checked AST.
+//
+// This is the reference semantics for the native backends: integer
+// arithmetic that overflows its type is an error, as is division by
+// zero, unless it goes through the `wrapping_*` builtins; and calls in
+// tail position don't grow the stack, so deeply recursive programs run
+// here exactly when they run natively. Other calls recurse, and a
+// program that recurses past the stack limit fails with "Stack overflow"
+// instead of taking the host process down. A `match` just tries each arm in
+// turn, which is slow but obviously right.
+//
+// Programs don't have to be monomorphized first, since values know their
+// own types: a method call finds the impl for whatever its receiver turns
+// out to be, and a `dyn` is just the value it was cast from.
+
+use crate::ir::{BinOp, DIVISION_BY_ZERO, NO_MATCH};
+use crate::mono::impl_method;
+use crate::parser::{Builtin, HuckAst, MatchArm, Pattern};
+use crate::typecheck::{builtin_impl, negated_literal, CheckOutput, EnumType, IntType, StructType, TypeInfo, Typed};
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Unit,
+    Bool(bool),
+    // Stored the way `IntType` describes
+    Int(i64, IntType),
+    Float(f64),
+    // Fields in the order the struct declares them
+    Struct(Rc<StructShape>, Rc<[Value]>),
+    // The variant's index, and its payload
+    Enum(Rc<EnumShape>, usize, Rc<[Value]>),
+    // A `dyn` in the VM: the index of its vtable, and the value it holds.
+    // The interpreter finds methods from the value itself, so it leaves
+    // values cast to a `dyn` as they are.
+    Dyn(usize, Rc<Value>),
+}
+
+/// What a struct value needs to know about its type to be shown.
+#[derive(Debug, PartialEq)]
+pub struct StructShape {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+impl StructShape {
+    pub fn new(struct_type: &StructType) -> Self {
+        let fields = struct_type.fields.iter().map(|(name, _)| name.clone()).collect();
+        Self { name: struct_type.name.clone(), fields }
+    }
+}
+
+/// What an enum value needs to know about its type to be shown or
+/// built: each variant's name and how many values its payload has.
+#[derive(Debug, PartialEq)]
+pub struct EnumShape {
+    pub name: String,
+    pub variants: Vec<(String, usize)>,
+}
+
+impl EnumShape {
+    pub fn new(enum_type: &EnumType) -> Self {
+        let variants = enum_type.variants.iter().map(|(name, payload)| (name.clone(), payload.len())).collect();
+        Self { name: enum_type.name.clone(), variants }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unit => write!(f, "()"),
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::Int(n, t) => write!(f, "{}", t.value(*n)),
+            Self::Float(x) => write!(f, "{}", format_float(*x)),
+            Self::Struct(shape, values) if shape.name.is_empty() => match &values[..] {
+                [value] => write!(f, "({},)", value),
+                values => write!(f, "({})", values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ")),
+            },
+            Self::Struct(shape, values) if values.is_empty() => write!(f, "{} {{}}", shape.name),
+            Self::Struct(shape, values) => {
+                let fields = shape.fields.iter().zip(values.iter())
+                    .map(|(name, value)| format!("{}: {}", name, value))
+                    .collect::<Vec<_>>();
+                write!(f, "{} {{ {} }}", shape.name, fields.join(", "))
+            },
+            Self::Enum(shape, variant, values) => {
+                write!(f, "{}::{}", shape.name, shape.variants[*variant].0)?;
+                if !values.is_empty() {
+                    let values = values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+                    write!(f, "({})", values.join(", "))?;
+                }
+                Ok(())
+            },
+            Self::Dyn(_, value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Show a float with the fewest digits that read back as the same
+/// number: in plain notation unless it's huge or tiny, and always with a
+/// decimal point or exponent. The native runtime prints them the same way.
+pub fn format_float(x: f64) -> String {
+    if x.is_nan() {
+        return String::from("NaN");
+    }
+    if x.is_infinite() {
+        return String::from(if x > 0.0 { "inf" } else { "-inf" });
+    }
+
+    let precision = (1..17)
+        .find(|precision| format!("{:.*e}", precision - 1, x).parse() == Ok(x))
+        .unwrap_or(17);
+    let digits = format!("{:.*e}", precision - 1, x);
+    let (mantissa, exponent) = digits.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    if !(-4..17).contains(&exponent) {
+        return format!("{}e{}", mantissa, exponent);
+    }
+
+    let plain = format!("{:.*}", (precision as i32 - 1 - exponent).max(0) as usize, x);
+    if plain.contains('.') { plain } else { plain + ".0" }
+}
+
+// Integer operations shared with the VM
+pub(crate) type IntOp = fn(IntType, i64, i64) -> Result<i64, String>;
+
+pub(crate) type FloatOp = fn(f64, f64) -> f64;
+
+pub(crate) fn add(t: IntType, a: i64, b: i64) -> Result<i64, String> {
+    t.add(a, b).ok_or_else(|| BinOp::Add.overflow_message())
+}
+
+pub(crate) fn sub(t: IntType, a: i64, b: i64) -> Result<i64, String> {
+    t.sub(a, b).ok_or_else(|| BinOp::Sub.overflow_message())
+}
+
+pub(crate) fn mul(t: IntType, a: i64, b: i64) -> Result<i64, String> {
+    t.mul(a, b).ok_or_else(|| BinOp::Mul.overflow_message())
+}
+
+pub(crate) fn div(t: IntType, a: i64, b: i64) -> Result<i64, String> {
+    t.divide(a, b).ok_or_else(|| match b {
+        0 => String::from(DIVISION_BY_ZERO),
+        _ => BinOp::Div.overflow_message(),
+    })
+}
+
+pub(crate) fn rem(t: IntType, a: i64, b: i64) -> Result<i64, String> {
+    t.remainder(a, b).ok_or_else(|| match b {
+        0 => String::from(DIVISION_BY_ZERO),
+        _ => BinOp::Rem.overflow_message(),
+    })
+}
+
+// What the `wrapping_*` builtins do
+pub(crate) fn wrapping(builtin: Builtin) -> IntOp {
+    match builtin {
+        Builtin::WrappingAdd => wrapping_add,
+        Builtin::WrappingSub => wrapping_sub,
+        Builtin::WrappingMul => wrapping_mul,
+        Builtin::WrappingRem => wrapping_rem,
+        Builtin::Print => unreachable!("`print` isn't arithmetic"),
+    }
+}
+
+// What `print` does with a number or bool, shared with the VM
+pub(crate) fn print(out: &mut dyn Write, value: &Value) -> EvalResult {
+    writeln!(out, "{}", value).map_err(|err| format!("Can't print: {}", err))?;
+    Ok(Value::Unit)
+}
+
+pub(crate) fn wrapping_add(t: IntType, a: i64, b: i64) -> Result<i64, String> {
+    Ok(t.wrap(a.wrapping_add(b)))
+}
+
+pub(crate) fn wrapping_sub(t: IntType, a: i64, b: i64) -> Result<i64, String> {
+    Ok(t.wrap(a.wrapping_sub(b)))
+}
+
+pub(crate) fn wrapping_mul(t: IntType, a: i64, b: i64) -> Result<i64, String> {
+    Ok(t.wrap(a.wrapping_mul(b)))
+}
+
+// Anything divided by -1 leaves nothing over, `MIN` included. A zero
+// divisor still fails.
+pub(crate) fn wrapping_rem(t: IntType, a: i64, b: i64) -> Result<i64, String> {
+    match b {
+        -1 if t.is_signed() => Ok(0),
+        _ => rem(t, a, b),
+    }
+}
+
+pub(crate) fn cast(value: Value, t: IntType) -> EvalResult {
+    match value {
+        Value::Int(n, _) => Ok(Value::Int(t.wrap(n), t)),
+        Value::Bool(b) => Ok(Value::Int(b as i64, t)),
+        Value::Float(x) => Ok(Value::Int(t.from_f64(x), t)),
+        Value::Unit | Value::Struct(..) | Value::Enum(..) | Value::Dyn(..) => Err(format!("Cannot cast {} to {}", value, t)),
+    }
+}
+
+pub(crate) fn cast_to_float(value: Value) -> EvalResult {
+    match value {
+        Value::Int(n, t) => Ok(Value::Float(t.to_f64(n))),
+        Value::Float(x) => Ok(Value::Float(x)),
+        _ => Err(format!("Cannot cast {} to f64", value)),
+    }
+}
+
+pub(crate) fn arithmetic(lhs: Value, rhs: Value, int_op: IntOp, float_op: FloatOp) -> EvalResult {
+    match (lhs, rhs) {
+        (Value::Float(a), Value::Float(b)) => Ok(Value::Float(float_op(a, b))),
+        (l, r) => int_arithmetic(l, r, int_op),
+    }
+}
+
+pub(crate) fn int_arithmetic(lhs: Value, rhs: Value, int_op: IntOp) -> EvalResult {
+    match (lhs, rhs) {
+        (Value::Int(a, t), Value::Int(b, _)) => int_op(t, a, b).map(|n| Value::Int(n, t)),
+        (l, r) => Err(format!("Cannot do arithmetic on {} and {}", l, r)),
+    }
+}
+
+// Anything compared with NaN is unordered, which no ordering accepts
+pub(crate) fn comparison(lhs: Value, rhs: Value, f: fn(Ordering) -> bool) -> EvalResult {
+    match (lhs, rhs) {
+        (Value::Int(a, t), Value::Int(b, _)) => Ok(Value::Bool(f(t.compare(a, b)))),
+        (Value::Float(a), Value::Float(b)) => Ok(Value::Bool(a.partial_cmp(&b).is_some_and(f))),
+        (l, r) => Err(format!("Cannot compare {} and {}", l, r)),
+    }
+}
+
+type EvalInput = CheckOutput;
+
+pub type EvalResult = Result<Value, String>;
+
+/// A function implemented by the program embedding huck; see `engine`.
+/// Arguments have already been type checked against its signature.
+pub type HostFunction = Rc<dyn Fn(&[Value]) -> EvalResult>;
+
+enum FnDef {
+    Huck { params: Vec<String>, body: Rc<EvalInput> },
+    Host(HostFunction),
+}
+
+// Functions visible at some point in the program: one frame per block
+// that declares any. A function runs in the frame it was declared in,
+// which includes itself and its siblings, so recursion needs no cycles.
+struct FnEnv {
+    fns: HashMap<String, Rc<FnDef>>,
+    parent: Option<Rc<FnEnv>>,
+}
+
+impl FnEnv {
+    fn lookup(env: &Rc<FnEnv>, ident: &str) -> Option<(Rc<FnDef>, Rc<FnEnv>)> {
+        let mut current = Some(env);
+        while let Some(frame) = current {
+            if let Some(def) = frame.fns.get(ident) {
+                return Some((def.clone(), frame.clone()));
+            }
+            current = frame.parent.as_ref();
+        }
+        None
+    }
+}
+
+// What evaluating an expression in tail position produced: either a
+// value, or a call for the trampoline in `call` to make
+enum Tail {
+    Done(Value),
+    Call(Rc<FnDef>, Rc<FnEnv>, Vec<Value>),
+}
+
+// Where a `break` or `continue` is going. They unwind to their loop as
+// errors, so everything in between stops the way it would for a real
+// one, with this saying it isn't.
+enum Jump {
+    Break(Option<String>, Value),
+    Continue(Option<String>),
+}
+
+// Only ever seen if a jump gets out of its function, which the checker
+// rules out
+const JUMP: &str = "break or continue outside a loop";
+
+// Calls that aren't tail calls recurse on the Rust stack, and how much
+// each one takes depends on the build, so recursion is bounded by the
+// bytes used rather than a count of calls. This much fits in the 2 MiB
+// Rust gives a spawned thread by default.
+pub const DEFAULT_STACK_LIMIT: usize = 1 << 20;
+
+pub struct Interpreter {
+    vars: Vec<HashMap<String, Value>>,
+    fn_env: Rc<FnEnv>,
+    jump: Option<Jump>,
+    // Shared by every value of each struct or enum type, by id
+    shapes: HashMap<usize, Rc<StructShape>>,
+    enum_shapes: HashMap<usize, Rc<EnumShape>>,
+    // Where `print` writes
+    out: Box<dyn Write>,
+    // The address of a local in the outermost active call, and how far
+    // below it calls may go
+    stack_base: Option<usize>,
+    stack_limit: usize,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self {
+            vars: vec![HashMap::new()],
+            fn_env: Rc::new(FnEnv { fns: HashMap::new(), parent: None }),
+            jump: None,
+            shapes: HashMap::new(),
+            enum_shapes: HashMap::new(),
+            out: Box::new(io::stdout()),
+            stack_base: None,
+            stack_limit: DEFAULT_STACK_LIMIT,
+        }
+    }
+
+    /// Send what the program prints somewhere other than stdout.
+    pub fn with_output(mut self, out: impl Write + 'static) -> Self {
+        self.out = Box::new(out);
+        self
+    }
+
+    /// Let calls use up to `bytes` of the stack before a program fails
+    /// with a stack overflow. Whatever thread runs the interpreter needs
+    /// a stack comfortably bigger than this.
+    pub fn with_stack_limit(mut self, bytes: usize) -> Self {
+        self.stack_limit = bytes;
+        self
+    }
+
+    fn get_var(&self, ident: &str) -> EvalResult {
+        self.vars.iter().rev()
+            .find_map(|scope| scope.get(ident).cloned())
+            .ok_or_else(|| format!("Unbound variable {:?}", ident))
+    }
+
+    fn set_var(&mut self, ident: &str, value: Value) -> EvalResult {
+        let var = self.vars.iter_mut().rev()
+            .find_map(|scope| scope.get_mut(ident))
+            .ok_or_else(|| format!("Unbound variable {:?}", ident))?;
+        *var = value;
+        Ok(Value::Unit)
+    }
+
+    fn fn_def(init_expr: &EvalInput) -> Option<FnDef> {
+        match init_expr {
+            HuckAst::Fn(_, params, _, body, _) => Some(FnDef::Huck {
+                params: params.iter().map(|(name, _)| name.to_string()).collect(),
+                body: body.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    // An impl's methods, by the names `method_call` looks them up by
+    fn impl_fns(trait_name: &str, methods: &[(String, EvalInput)]) -> Vec<(String, FnDef)> {
+        methods.iter().map(|(method, decl)| {
+            let TypeInfo::Fn(params, _) = decl.ty() else { unreachable!("Methods are functions") };
+            (impl_method(trait_name, &params[0], method), Self::fn_def(decl).unwrap())
+        }).collect()
+    }
+
+    // Push a function frame with every function the block declares,
+    // including the methods of its impls
+    fn begin_block(&mut self, exprs: &[EvalInput]) -> Option<Rc<FnEnv>> {
+        self.vars.push(HashMap::new());
+
+        let fns: HashMap<String, Rc<FnDef>> = exprs.iter()
+            .flat_map(|expr| match expr {
+                HuckAst::Let(ident, _, init_expr, _) => Self::fn_def(init_expr).map(|def| (ident.to_string(), def)).into_iter().collect(),
+                HuckAst::Impl(trait_name, _, methods, _) => Self::impl_fns(trait_name, methods),
+                _ => vec![],
+            })
+            .map(|(ident, def)| (ident, Rc::new(def)))
+            .collect();
+        if fns.is_empty() {
+            return None;
+        }
+
+        let frame = Rc::new(FnEnv { fns, parent: Some(self.fn_env.clone()) });
+        Some(std::mem::replace(&mut self.fn_env, frame))
+    }
+
+    fn end_block(&mut self, outer_fn_env: Option<Rc<FnEnv>>) {
+        self.vars.pop();
+        if let Some(env) = outer_fn_env {
+            self.fn_env = env;
+        }
+    }
+
+    pub fn eval(&mut self, ast: &EvalInput) -> EvalResult {
+        match ast {
+            HuckAst::Num(n, _, Typed { ty: TypeInfo::Int(t), .. }) => Ok(Value::Int(t.wrap(*n as i64), *t)),
+            HuckAst::Num(_, _, t) => Err(format!("Number of type {}", t.ty)),
+            HuckAst::BoolLit(b, _) => Ok(Value::Bool(*b)),
+            HuckAst::Float(x, _) => Ok(Value::Float(*x)),
+            HuckAst::Neg(operand, _) if negated_literal(operand).is_some() => {
+                let (n, t) = negated_literal(operand).unwrap();
+                Ok(Value::Int(n, t))
+            },
+            HuckAst::Neg(operand, _) => match self.eval(operand)? {
+                Value::Int(n, t) => sub(t, 0, n).map(|n| Value::Int(n, t)),
+                Value::Float(x) => Ok(Value::Float(-x)),
+                v => Err(format!("Cannot negate {}", v)),
+            },
+            HuckAst::Cast(operand, _, Typed { ty: TypeInfo::Int(t), .. }) => cast(self.eval(operand)?, *t),
+            HuckAst::Cast(operand, _, Typed { ty: TypeInfo::F64, .. }) => cast_to_float(self.eval(operand)?),
+            HuckAst::Cast(operand, _, Typed { ty: TypeInfo::Dyn(_), .. }) => self.eval(operand),
+            HuckAst::Cast(_, _, t) => Err(format!("Cannot cast to {}", t.ty)),
+            HuckAst::Plus(lhs, rhs, _) => self.arithmetic(lhs, rhs, add, |a, b| a + b),
+            HuckAst::Minus(lhs, rhs, _) => self.arithmetic(lhs, rhs, sub, |a, b| a - b),
+            HuckAst::Times(lhs, rhs, _) => self.arithmetic(lhs, rhs, mul, |a, b| a * b),
+            HuckAst::Div(lhs, rhs, _) => self.arithmetic(lhs, rhs, div, |a, b| a / b),
+            HuckAst::Rem(lhs, rhs, _) => self.arithmetic(lhs, rhs, rem, |a, b| a % b),
+            HuckAst::Equals(lhs, rhs, _) => {
+                let (l, r) = (self.eval(lhs)?, self.eval(rhs)?);
+                Ok(Value::Bool(l == r))
+            },
+            HuckAst::NotEquals(lhs, rhs, _) => {
+                let (l, r) = (self.eval(lhs)?, self.eval(rhs)?);
+                Ok(Value::Bool(l != r))
+            },
+            HuckAst::Less(lhs, rhs, _) => self.comparison(lhs, rhs, Ordering::is_lt),
+            HuckAst::LessEq(lhs, rhs, _) => self.comparison(lhs, rhs, Ordering::is_le),
+            HuckAst::Greater(lhs, rhs, _) => self.comparison(lhs, rhs, Ordering::is_gt),
+            HuckAst::GreaterEq(lhs, rhs, _) => self.comparison(lhs, rhs, Ordering::is_ge),
+            HuckAst::Let(ident, _, init_expr, _) => {
+                if let Some(def) = Self::fn_def(init_expr) {
+                    self.declare_fn(ident, def);
+                    return Ok(Value::Unit);
+                }
+                let value = self.eval(init_expr)?;
+                self.vars.last_mut().unwrap().insert(ident.to_string(), value.clone());
+                Ok(value)
+            },
+            HuckAst::VarRef(ident, _) => self.get_var(ident),
+            HuckAst::Assign(ident, _, value, _) => {
+                let value = self.eval(value)?;
+                self.set_var(ident, value)
+            },
+            HuckAst::Block(exprs, _) => {
+                let outer_fn_env = self.begin_block(exprs);
+                let mut last = Ok(Value::Unit);
+                for expr in exprs {
+                    last = self.eval(expr);
+                    if last.is_err() {
+                        break;
+                    }
+                }
+                self.end_block(outer_fn_env);
+                last
+            },
+            HuckAst::If(test_expr, then_expr, else_expr, _) => {
+                if self.eval(test_expr)? == Value::Bool(true) {
+                    self.eval(then_expr)
+                } else {
+                    self.eval(else_expr)
+                }
+            },
+            HuckAst::Fn(..) => Err(String::from("Functions must be declared with let")),
+            HuckAst::Call(ident, _, args, _) => {
+                let (def, env) = FnEnv::lookup(&self.fn_env, ident)
+                    .ok_or_else(|| format!("Unbound function {:?}", ident))?;
+                let args = self.args(args)?;
+                self.call(def, env, args)
+            },
+            HuckAst::Builtin(Builtin::Print, args, _) => {
+                let value = self.eval(&args[0])?;
+                print(self.out.as_mut(), &value)
+            },
+            HuckAst::Builtin(builtin, args, _) => {
+                let [lhs, rhs] = args.as_slice() else { unreachable!("{} takes two arguments", builtin.name()) };
+                let (l, r) = (self.eval(lhs)?, self.eval(rhs)?);
+                int_arithmetic(l, r, wrapping(*builtin))
+            },
+            HuckAst::Struct(..) => Ok(Value::Unit),
+            HuckAst::StructLit(_, _, fields, base, Typed { ty: TypeInfo::Struct(struct_type), .. }) => {
+                let given = fields.iter()
+                    .map(|(name, value)| Ok((name, self.eval(value)?)))
+                    .collect::<Result<Vec<_>, String>>()?;
+                let mut values = match base {
+                    Some(base) => match self.eval(base)? {
+                        Value::Struct(_, values) => values.to_vec(),
+                        v => return Err(format!("Cannot take fields from {}", v)),
+                    },
+                    None => vec![Value::Unit; struct_type.fields.len()],
+                };
+                for (name, value) in given {
+                    let (index, _) = struct_type.field(name).ok_or_else(|| format!("No field {:?}", name))?;
+                    values[index] = value;
+                }
+                let shape = self.shapes.entry(struct_type.id).or_insert_with(|| Rc::new(StructShape::new(struct_type)));
+                Ok(Value::Struct(shape.clone(), values.into()))
+            },
+            HuckAst::StructLit(_, _, _, _, t) => Err(format!("Struct literal of type {}", t.ty)),
+            HuckAst::Tuple(elements, Typed { ty: TypeInfo::Struct(tuple), .. }) => {
+                let values = elements.iter().map(|element| self.eval(element)).collect::<Result<Vec<_>, _>>()?;
+                let shape = self.shapes.entry(tuple.id).or_insert_with(|| Rc::new(StructShape::new(tuple)));
+                Ok(Value::Struct(shape.clone(), values.into()))
+            },
+            HuckAst::Tuple(_, t) => Err(format!("Tuple of type {}", t.ty)),
+            HuckAst::Field(operand, field, _) => match self.eval(operand)? {
+                Value::Struct(shape, values) => shape.fields.iter()
+                    .position(|name| name == field)
+                    .map(|index| values[index].clone())
+                    .ok_or_else(|| format!("No field {:?} in {}", field, shape.name)),
+                v => Err(format!("Cannot get field {:?} of {}", field, v)),
+            },
+            HuckAst::Enum(..) => Ok(Value::Unit),
+            HuckAst::Variant(_, _, variant, args, Typed { ty: TypeInfo::Enum(enum_type), .. }) => {
+                let (index, _) = enum_type.variant(variant).ok_or_else(|| format!("No variant {:?}", variant))?;
+                let values = self.args(args)?;
+                let shape = self.enum_shapes.entry(enum_type.id).or_insert_with(|| Rc::new(EnumShape::new(enum_type)));
+                Ok(Value::Enum(shape.clone(), index, values.into()))
+            },
+            HuckAst::Variant(_, _, _, _, t) => Err(format!("Variant of type {}", t.ty)),
+            HuckAst::Match(scrutinee, arms, _) => {
+                let body = self.choose_arm(scrutinee, arms)?;
+                let result = self.eval(body);
+                self.vars.pop();
+                result
+            },
+            HuckAst::Trait(..) => Ok(Value::Unit),
+            HuckAst::Impl(trait_name, _, methods, _) => {
+                for (ident, def) in Self::impl_fns(trait_name, methods) {
+                    self.declare_fn(&ident, def);
+                }
+                Ok(Value::Unit)
+            },
+            HuckAst::MethodCall(receiver, method, args, trait_name, _) => {
+                let trait_name = trait_name.as_deref().ok_or_else(|| format!("Method {:?} without a trait", method))?;
+                let receiver = self.eval(receiver)?;
+                let args = self.args(args)?;
+                self.method_call(trait_name, method, receiver, args)
+            },
+            HuckAst::While(label, cond, body, _) => {
+                while self.eval(cond)? == Value::Bool(true) {
+                    if let Some(Jump::Break(..)) = self.loop_body(label, body)? {
+                        break;
+                    }
+                }
+                Ok(Value::Unit)
+            },
+            HuckAst::Loop(label, body, _) => loop {
+                if let Some(Jump::Break(_, value)) = self.loop_body(label, body)? {
+                    break Ok(value);
+                }
+            },
+            HuckAst::For(label, var, from, to, body, _) => {
+                let (Value::Int(from, t), Value::Int(to, _)) = (self.eval(from)?, self.eval(to)?) else {
+                    return Err(String::from("Range bounds must be integers"));
+                };
+                let mut i = from;
+                while t.compare(i, to).is_lt() {
+                    self.vars.push(HashMap::from([(var.to_string(), Value::Int(i, t))]));
+                    let jump = self.loop_body(label, body);
+                    self.vars.pop();
+                    if let Some(Jump::Break(..)) = jump? {
+                        break;
+                    }
+                    i = t.wrap(i.wrapping_add(1));
+                }
+                Ok(Value::Unit)
+            },
+            HuckAst::Break(label, value, _) => {
+                let value = match value {
+                    Some(value) => self.eval(value)?,
+                    None => Value::Unit,
+                };
+                self.jump = Some(Jump::Break(label.clone(), value));
+                Err(String::from(JUMP))
+            },
+            HuckAst::Continue(label, _) => {
+                self.jump = Some(Jump::Continue(label.clone()));
+                Err(String::from(JUMP))
+            },
+        }
+    }
+
+    // The built-in traits on built-in types do what the operators do;
+    // anything else calls the method from the impl for the receiver's type
+    fn method_call(&mut self, trait_name: &str, method: &str, receiver: Value, args: Vec<Value>) -> EvalResult {
+        let ty = match &receiver {
+            Value::Int(_, t) => TypeInfo::Int(*t),
+            Value::Float(_) => TypeInfo::F64,
+            Value::Bool(_) => TypeInfo::Bool,
+            _ => TypeInfo::Unit,
+        };
+        if builtin_impl(trait_name, &ty) {
+            return match (trait_name, &receiver, args.first()) {
+                ("Eq", _, Some(other)) => Ok(Value::Bool(receiver == *other)),
+                // NaN isn't less than or equal to anything, so it's greater
+                ("Ord", Value::Int(a, t), Some(Value::Int(b, _))) => Ok(Value::Int(t.compare(*a, *b) as i64, IntType::I64)),
+                ("Ord", Value::Float(a), Some(Value::Float(b))) => {
+                    Ok(Value::Int(a.partial_cmp(b).unwrap_or(Ordering::Greater) as i64, IntType::I64))
+                },
+                ("Display", _, None) => print(self.out.as_mut(), &receiver),
+                _ => Err(format!("Bad call to {}.{}", trait_name, method)),
+            }
+        }
+        let key = match &receiver {
+            Value::Struct(shape, _) => shape.name.clone(),
+            Value::Enum(shape, _, _) => shape.name.clone(),
+            _ => ty.to_string(),
+        };
+        let ident = format!("impl.{}.{}.{}", trait_name, key, method);
+        let (def, env) = FnEnv::lookup(&self.fn_env, &ident).ok_or_else(|| format!("{} doesn't implement {}", receiver, trait_name))?;
+        self.call(def, env, [receiver].into_iter().chain(args).collect())
+    }
+
+    // Find the first arm whose pattern matches and whose guard holds,
+    // leaving a scope with its bindings pushed
+    fn choose_arm<'a>(&mut self, scrutinee: &EvalInput, arms: &'a [MatchArm<Typed>]) -> Result<&'a EvalInput, String> {
+        let value = self.eval(scrutinee)?;
+        for arm in arms {
+            let mut bindings = HashMap::new();
+            if !self.matches(&arm.pattern, &value, &mut bindings)? {
+                continue;
+            }
+            self.vars.push(bindings);
+            let guard = match &arm.guard {
+                Some(guard) => self.eval(guard),
+                None => Ok(Value::Bool(true)),
+            };
+            match guard {
+                Ok(Value::Bool(true)) => return Ok(&arm.body),
+                Ok(_) => { self.vars.pop(); },
+                Err(err) => {
+                    self.vars.pop();
+                    return Err(err);
+                },
+            }
+        }
+        Err(String::from(NO_MATCH))
+    }
+
+    fn matches(&mut self, pattern: &Pattern<Typed>, value: &Value, bindings: &mut HashMap<String, Value>) -> Result<bool, String> {
+        match (pattern, value) {
+            (Pattern::Wildcard(_), _) => Ok(true),
+            (Pattern::Binding(name, _), _) => {
+                bindings.insert(name.clone(), value.clone());
+                Ok(true)
+            },
+            (Pattern::Literal(literal), _) => Ok(self.eval(literal)? == *value),
+            (Pattern::Variant(_, variant, fields, Typed { ty: TypeInfo::Enum(enum_type), .. }), Value::Enum(_, index, values)) => {
+                if enum_type.variant(variant).map(|(i, _)| i) != Some(*index) {
+                    return Ok(false);
+                }
+                for (field, value) in fields.iter().zip(values.iter()) {
+                    if !self.matches(field, value, bindings)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            },
+            (Pattern::Tuple(elements, _), Value::Struct(_, values)) => {
+                for (element, value) in elements.iter().zip(values.iter()) {
+                    if !self.matches(element, value, bindings)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            },
+            (pattern, _) => Err(format!("Cannot match {} against {:?}", value, pattern)),
+        }
+    }
+
+    // Functions declared outside a block (at the top level of a program,
+    // or a REPL line) get a frame of their own, unless the enclosing
+    // block already hoisted this very declaration
+    fn declare_fn(&mut self, ident: &str, def: FnDef) {
+        if let Some((existing, _)) = FnEnv::lookup(&self.fn_env, ident) {
+            if let (FnDef::Huck { body: existing, .. }, FnDef::Huck { body, .. }) = (existing.as_ref(), &def) {
+                if Rc::ptr_eq(existing, body) {
+                    return;
+                }
+            }
+        }
+        let fns = HashMap::from([(ident.to_string(), Rc::new(def))]);
+        let parent = Some(self.fn_env.clone());
+        self.fn_env = Rc::new(FnEnv { fns, parent });
+    }
+
+    /// Make a host function callable from huck code evaluated later on.
+    pub fn define_host_fn(&mut self, ident: &str, f: HostFunction) {
+        self.declare_fn(ident, FnDef::Host(f));
+    }
+
+    // Run a loop's body once, catching any `break` or `continue` meant
+    // for it. Whatever the jump skipped past is cleaned up here.
+    fn loop_body(&mut self, label: &Option<String>, body: &EvalInput) -> Result<Option<Jump>, String> {
+        let (vars, fn_env) = (self.vars.len(), self.fn_env.clone());
+        let err = match self.eval(body) {
+            Ok(_) => return Ok(None),
+            Err(err) => err,
+        };
+        let target = match &self.jump {
+            Some(Jump::Break(target, _) | Jump::Continue(target)) => target,
+            None => return Err(err),
+        };
+        if target.is_some() && target != label {
+            return Err(err);
+        }
+        self.vars.truncate(vars);
+        self.fn_env = fn_env;
+        Ok(self.jump.take())
+    }
+
+    fn args(&mut self, args: &[EvalInput]) -> Result<Vec<Value>, String> {
+        args.iter().map(|arg| self.eval(arg)).collect()
+    }
+
+    fn call(&mut self, mut def: Rc<FnDef>, mut env: Rc<FnEnv>, mut args: Vec<Value>) -> EvalResult {
+        let here = &def as *const _ as usize;
+        let outermost = self.stack_base.is_none();
+        let base = *self.stack_base.get_or_insert(here);
+        if base.saturating_sub(here) > self.stack_limit {
+            return Err(String::from("Stack overflow"));
+        }
+        let outer_vars = std::mem::take(&mut self.vars);
+        let outer_fn_env = self.fn_env.clone();
+
+        // Trampoline: tail calls loop here instead of recursing
+        let result = loop {
+            let (params, body) = match def.as_ref() {
+                FnDef::Huck { params, body } => (params, body.clone()),
+                FnDef::Host(f) => break f(&args),
+            };
+            self.vars = vec![params.iter().cloned().zip(args).collect()];
+            self.fn_env = env;
+            match self.eval_tail(&body) {
+                Ok(Tail::Done(value)) => break Ok(value),
+                Ok(Tail::Call(next_def, next_env, next_args)) => {
+                    def = next_def;
+                    env = next_env;
+                    args = next_args;
+                },
+                Err(err) => break Err(err),
+            }
+        };
+
+        self.vars = outer_vars;
+        self.fn_env = outer_fn_env;
+        if outermost {
+            self.stack_base = None;
+        }
+        result
+    }
+
+    fn eval_tail(&mut self, ast: &EvalInput) -> Result<Tail, String> {
+        match ast {
+            HuckAst::Call(ident, _, args, _) => {
+                let (def, env) = FnEnv::lookup(&self.fn_env, ident)
+                    .ok_or_else(|| format!("Unbound function {:?}", ident))?;
+                Ok(Tail::Call(def, env, self.args(args)?))
+            },
+            HuckAst::If(test_expr, then_expr, else_expr, _) => {
+                if self.eval(test_expr)? == Value::Bool(true) {
+                    self.eval_tail(then_expr)
+                } else {
+                    self.eval_tail(else_expr)
+                }
+            },
+            HuckAst::Match(scrutinee, arms, _) => {
+                let body = self.choose_arm(scrutinee, arms)?;
+                let result = self.eval_tail(body);
+                self.vars.pop();
+                result
+            },
+            HuckAst::Block(exprs, _) if !exprs.is_empty() => {
+                let outer_fn_env = self.begin_block(exprs);
+                let (last, init) = exprs.split_last().unwrap();
+                let mut result = Ok(Tail::Done(Value::Unit));
+                for expr in init {
+                    if let Err(err) = self.eval(expr) {
+                        result = Err(err);
+                        break;
+                    }
+                }
+                if result.is_ok() {
+                    result = self.eval_tail(last);
+                }
+                self.end_block(outer_fn_env);
+                result
+            },
+            _ => self.eval(ast).map(Tail::Done),
+        }
+    }
+
+    fn arithmetic(&mut self, lhs: &EvalInput, rhs: &EvalInput, int_op: IntOp, float_op: FloatOp) -> EvalResult {
+        let (l, r) = (self.eval(lhs)?, self.eval(rhs)?);
+        arithmetic(l, r, int_op, float_op)
+    }
+
+    fn comparison(&mut self, lhs: &EvalInput, rhs: &EvalInput, f: fn(Ordering) -> bool) -> EvalResult {
+        let (l, r) = (self.eval(lhs)?, self.eval(rhs)?);
+        comparison(l, r, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use crate::typecheck::Checker;
+
+    fn eval_str(s: &str) -> EvalResult {
+        let ast = Parser::new(Scanner::new(s)).parse().unwrap();
+        let checked = Checker::new().check(&ast).unwrap();
+        Interpreter::new().eval(&checked)
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(eval_str("{let x = 1; let y = 2; 50 + y * x / 2 - 1}"), Ok(Value::Int(50, IntType::I64)));
+        assert_eq!(eval_str("9223372036854775807 + 1"), Err(String::from("Overflow in `+`")));
+        assert_eq!(eval_str("{let x = 3; 0 - 9223372036854775807 - x}"), Err(String::from("Overflow in `-`")));
+        assert_eq!(eval_str("wrapping_add(9223372036854775807, 1)"), Ok(Value::Int(i64::MIN, IntType::I64)));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert!(eval_str("{let x = 0; 1 / x}").is_err());
+        assert!(eval_str("{let x = -1; -9223372036854775808 / x}").is_err());
+        assert_eq!(eval_str("{let x = -1i8; -128i8 / x}"), Err(String::from("Overflow in `/`")));
+        assert_eq!(eval_str("{let x = 0u8; 1u8 % x}"), Err(String::from("Division by zero")));
+        assert_eq!(eval_str("{let x = -1i8; -128i8 % x}"), Err(String::from("Overflow in `%`")));
+        assert_eq!(eval_str("{let x = -1i8; wrapping_rem(-128i8, x)}"), Ok(Value::Int(0, IntType::I8)));
+        assert_eq!(eval_str("wrapping_rem(1, 0)"), Err(String::from("Division by zero")));
+        assert_eq!(eval_str("-7 % 3 + (18446744073709551615u64 % 10u64) as i64"), Ok(Value::Int(4, IntType::I64)));
+    }
+
+    #[test]
+    fn integer_widths() {
+        assert_eq!(eval_str("200u8 + 100u8"), Err(String::from("Overflow in `+`")));
+        assert_eq!(eval_str("wrapping_add(200u8, 100u8)"), Ok(Value::Int(44, IntType::U8)));
+        assert_eq!(eval_str("wrapping_sub(1u32, 2u32)"), Ok(Value::Int(4294967295, IntType::U32)));
+        assert_eq!(eval_str("wrapping_mul(-128i8, -1i8)"), Ok(Value::Int(-128, IntType::I8)));
+        assert_eq!(eval_str("-(-128i8)"), Err(String::from("Overflow in `-`")));
+        assert_eq!(eval_str("-1 as u64 / 2u64").map(|v| v.to_string()), Ok(String::from("9223372036854775807")));
+        assert_eq!(eval_str("255u8 as i8"), Ok(Value::Int(-1, IntType::I8)));
+        assert_eq!(eval_str("-1i32 as u32 > 1"), Ok(Value::Bool(true)));
+        assert_eq!(eval_str("true as u16 + 1"), Ok(Value::Int(2, IntType::U16)));
+    }
+
+    #[test]
+    fn floats() {
+        assert_eq!(eval_str("{let x = 0.1; x + 0.2}").map(|v| v.to_string()), Ok(String::from("0.30000000000000004")));
+        assert_eq!(eval_str("7 as f64 / 2.0 < 3.5"), Ok(Value::Bool(false)));
+        assert_eq!(eval_str("1000000000000.5 as i8"), Ok(Value::Int(127, IntType::I8)));
+        assert_eq!(eval_str("-2.9 as u32"), Ok(Value::Int(0, IntType::U32)));
+    }
+
+    #[test]
+    fn float_formatting() {
+        let cases = [
+            (1.0, "1.0"), (-0.0, "-0.0"), (0.1, "0.1"), (1.5e-7, "1.5e-7"), (123456.75, "123456.75"),
+            (1e20, "1e20"), (f64::MAX, "1.7976931348623157e308"), (f64::NAN, "NaN"), (f64::NEG_INFINITY, "-inf"),
+        ];
+        for (x, shown) in cases {
+            assert_eq!(format_float(x), shown);
+        }
+    }
+
+    #[test]
+    fn conditional() {
+        assert_eq!(eval_str("{let test = false; if test { 1 } else { 2 }}"), Ok(Value::Int(2, IntType::I64)));
+        assert_eq!(eval_str("if 1 < 2 { true == true } else { false }"), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn structs() {
+        let source = "{
+            struct Point { x: i64, y: u8 };
+            let flip = fn (p: Point): Point { Point { y: p.x as u8, x: p.y as i64 } };
+            let p = flip(Point { x: 1, y: 2 });
+            Point { y: 3, ..p }
+        }";
+        assert_eq!(eval_str(source).map(|v| v.to_string()), Ok(String::from("Point { x: 2, y: 3 }")));
+        assert_eq!(eval_str("{struct E {}; E {}}").map(|v| v.to_string()), Ok(String::from("E {}")));
+    }
+
+    #[test]
+    fn matches() {
+        let source = "{
+            enum Shape { Circle(f64), Square(f64, bool), Empty };
+            let area = fn (s: Shape): f64 {
+                match s { Shape::Circle(r) => 3.0 * r * r, Shape::Square(x, true) => x * x, _ => 0.0 }
+            };
+            area(Shape::Circle(1.0)) + area(Shape::Square(2.0, true)) + area(Shape::Square(2.0, false))
+        }";
+        assert_eq!(eval_str(source), Ok(Value::Float(7.0)));
+        let source = "{enum E { A(i64, bool), B }; let b = E::B; E::A(match b { E::A(n, _) => n, E::B => 2 }, true)}";
+        assert_eq!(eval_str(source).map(|v| v.to_string()), Ok(String::from("E::A(2, true)")));
+        // The guard fails, so the next arm gets a look
+        assert_eq!(eval_str("match 5 { n if n > 9 => n, 5 => 0, _ => 1 }"), Ok(Value::Int(0, IntType::I64)));
+        assert_eq!(eval_str("match -1 { 0 => 1, -2 => 2, _ => 3 }"), Ok(Value::Int(3, IntType::I64)));
+    }
+
+    #[test]
+    fn traits() {
+        // Without monomorphization, so method calls and `dyn` are the
+        // interpreter's to deal with
+        let source = "{
+            trait Shape { fn area(self): f64 };
+            struct Square { side: f64 };
+            impl Shape for Square { fn area(self): f64 { self.side * self.side } };
+            impl Shape for i64 { fn area(self): f64 { self as f64 } };
+            let both = fn <T: Shape>(a: T, b: dyn Shape): f64 { a.area() + b.area() };
+            both(Square { side: 2.0 }, 3 as dyn Shape) + both(1, Square { side: 1.0 } as dyn Shape)
+        }";
+        assert_eq!(eval_str(source), Ok(Value::Float(9.0)));
+        let source = "{
+            struct P { x: i64 };
+            impl Eq for P { fn eq(self, other: P): bool { self.x == other.x } };
+            impl Ord for P { fn cmp(self, other: P): i64 { self.x - other.x } };
+            let max = fn <T: Ord>(a: T, b: T): T { if a < b { b } else { a } };
+            if (P { x: 1 } == P { x: 1 }) { max(P { x: 3 }, P { x: 4 }).x + max(2.5, 1.0) as i64 } else { 0 }
+        }";
+        assert_eq!(eval_str(source), Ok(Value::Int(6, IntType::I64)));
+    }
+
+    #[test]
+    fn mutation() {
+        let source = "{
+            let mut x = 1;
+            let y = x;
+            x += 10;
+            { let mut x = 0; x = 5 };
+            if y == 1 { x = x * 2 } else { x = 0 };
+            x
+        }";
+        assert_eq!(eval_str(source), Ok(Value::Int(22, IntType::I64)));
+        assert_eq!(eval_str("{let mut x = 1; x = 2}"), Ok(Value::Unit));
+    }
+
+    #[test]
+    fn loops() {
+        assert_eq!(eval_str("{let mut n = 0; let mut i = 0; while i < 5 { i += 1; n += i }; n}"), Ok(Value::Int(15, IntType::I64)));
+        assert_eq!(eval_str("{let mut i = 0u8; let mut sq = 0u8; loop { i += 1; if i * i > 50 { break i } else { sq = i * i } }}"), Ok(Value::Int(8, IntType::U8)));
+        assert_eq!(eval_str("{
+            let mut n = 0;
+            'rows: for i in 0..10 {
+                for j in 0..10 {
+                    if j == 3 { continue 'rows } else { if i == 4 { break 'rows } else { n += 1 } }
+                }
+            };
+            n
+        }"), Ok(Value::Int(12, IntType::I64)));
+        // Nothing is left in scope by a jump out of the middle of a block
+        assert_eq!(eval_str("{let x = 1; for i in 0..3 { let mut x = i; if x > 0 { continue } else { x = 5 } }; x}"), Ok(Value::Int(1, IntType::I64)));
+    }
+
+    #[test]
+    fn deep_tail_recursion() {
+        let source = "{
+            let odd = fn (x: i64): bool { if (x == 0) { false } else { even(x - 1) } };
+            let even = fn (x: i64): bool { if (x == 0) { true } else { odd(x - 1) } };
+            even(1000001)
+        }";
+        assert_eq!(eval_str(source), Ok(Value::Bool(false)));
+    }
+
+    #[test]
+    fn non_tail_recursion() {
+        let source = "{
+            let fact = fn (n: i64): i64 { if n == 0 { 1 } else { n * fact(n - 1) } };
+            fact(10)
+        }";
+        assert_eq!(eval_str(source), Ok(Value::Int(3628800, IntType::I64)));
+    }
+
+    #[test]
+    fn lexical_function_scope() {
+        let source = "{
+            let f = fn (): i64 { 1 };
+            let g = fn (): i64 { f() };
+            { let f = fn (): i64 { 2 }; g() + f() * 10 }
+        }";
+        assert_eq!(eval_str(source), Ok(Value::Int(21, IntType::I64)));
+    }
+}
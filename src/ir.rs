@@ -0,0 +1,523 @@
+// Mid-level IR sitting between the type checker and the backends.
+//
+// Code is three-address style: every instruction reads operands
+// (constants or virtual registers) and writes at most one register.
+// Registers are typed and may be assigned in more than one block (the
+// two arms of an `if` both write the result register, and a `let mut`
+// variable is one register written by each assignment), so this isn't
+// strict SSA. Control flow is explicit: a function is a list of basic
+// blocks, each ending in exactly one terminator, and `blocks[0]` is
+// the entry block.
+//
+// Integers are stored the way `IntType` describes: narrower types are
+// sign- or zero-extended to 64 bits, and every operation leaves its
+// result that way. Integer arithmetic written in the source is
+// `Checked`, stopping the program if the result doesn't fit; `Binary`
+// arithmetic on integers wraps.
+//
+// Structs are values like any other: a register can hold a whole one,
+// and they're passed to and returned from functions by value. How
+// they're laid out in memory is up to each backend. Enums are the same,
+// holding one of their variants: `Tag` says which, as a u32 counting
+// from 0 in the order they're declared.
+//
+// A `dyn` is two pointers: one to a copy of the value it was made from,
+// which is never freed, and one to the vtable for that value's type,
+// holding the functions implementing each of the trait's methods.
+// `DynCall` calls one of them with the pointer to the value as `self`,
+// so the functions a vtable lists take `self` by value and each backend
+// goes through a shim of its own that loads it.
+
+use crate::typecheck::IntType;
+
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Ty {
+    Unit,
+    Bool,
+    Int(IntType),
+    F64,
+    Struct(StructId),
+    Enum(EnumId),
+    Dyn,
+}
+
+// Index into `Module::structs`
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub struct StructId(pub usize);
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct StructDef {
+    pub name: String,
+    pub fields: Vec<(String, Ty)>,
+}
+
+// Index into `Module::enums`
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub struct EnumId(pub usize);
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct EnumDef {
+    pub name: String,
+    // Each variant's name and payload
+    pub variants: Vec<(String, Vec<Ty>)>,
+}
+
+// Index into `Module::vtables`
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub struct VTableId(pub usize);
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct VTableDef {
+    pub name: String,
+    // The type of the values a `dyn` using it points to
+    pub ty: Ty,
+    // The function for each of the trait's methods, in order
+    pub methods: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub struct Reg(pub usize);
+
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub struct BlockId(pub usize);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Const {
+    Unit,
+    Bool(bool),
+    Int(i64, IntType),
+    Float(f64),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Operand {
+    Reg(Reg),
+    Const(Const),
+}
+
+// Where a check came from in the source, for the error it reports
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Loc {
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    // The remainder has the sign of the dividend, as in Rust
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Inst {
+    Copy { dst: Reg, src: Operand },
+    // Whether division and comparisons are signed, or on floats, depends
+    // on the type of the operands
+    Binary { dst: Reg, op: BinOp, lhs: Operand, rhs: Operand },
+    // Convert a number or bool to the type of `dst`, as `as` does
+    Cast { dst: Reg, src: Operand },
+    Call { dst: Reg, func: String, args: Vec<Operand> },
+    // Integer `Add`, `Sub`, `Mul`, `Div` or `Rem` that fails at runtime
+    // on overflow or a zero divisor
+    Checked { dst: Reg, op: BinOp, lhs: Operand, rhs: Operand, loc: Loc },
+    // Build a struct of the type of `dst` from all its fields, in order
+    Struct { dst: Reg, fields: Vec<Operand> },
+    Field { dst: Reg, src: Operand, index: usize },
+    // Build an enum of the type of `dst` holding `variant` and its payload
+    Variant { dst: Reg, variant: usize, fields: Vec<Operand> },
+    // Which variant an enum holds, as a u32
+    Tag { dst: Reg, src: Operand },
+    // Field `index` of the payload of an enum known to hold `variant`
+    Payload { dst: Reg, src: Operand, variant: usize, index: usize },
+    // Write a number or bool to stdout on a line of its own. `dst` is ().
+    Print { dst: Reg, src: Operand },
+    // Make a `dyn` pointing to a copy of `src`, with `vtable` for its type
+    Dyn { dst: Reg, src: Operand, vtable: VTableId },
+    // Call method `method` of the `dyn` in `args[0]`, which gets a pointer
+    // to its value as `self`, with the rest of `args`
+    DynCall { dst: Reg, method: usize, args: Vec<Operand> },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Terminator {
+    Jump(BlockId),
+    Branch { cond: Operand, then_block: BlockId, else_block: BlockId },
+    Return(Operand),
+    // Call in tail position: the callee's result is our result, so the
+    // backends can reuse the caller's frame and jump
+    TailCall { func: String, args: Vec<Operand> },
+    // Go to the block for whichever case `value` (an integer) is equal
+    // to, or `default` if none
+    Switch { value: Operand, cases: Vec<(Const, BlockId)>, default: BlockId },
+    // A `match` where no arm matched, which stops the program
+    NoMatch(Loc),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Block {
+    pub insts: Vec<Inst>,
+    pub term: Terminator,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<Reg>,
+    pub ret: Ty,
+    // Type of every virtual register, indexed by `Reg`
+    pub regs: Vec<Ty>,
+    pub blocks: Vec<Block>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Module {
+    pub structs: Vec<StructDef>,
+    pub enums: Vec<EnumDef>,
+    pub vtables: Vec<VTableDef>,
+    pub functions: Vec<Function>,
+}
+
+impl Inst {
+    pub fn dst(&self) -> Reg {
+        match self {
+            Self::Copy { dst, .. } => *dst,
+            Self::Binary { dst, .. } => *dst,
+            Self::Cast { dst, .. } => *dst,
+            Self::Call { dst, .. } => *dst,
+            Self::Checked { dst, .. } => *dst,
+            Self::Struct { dst, .. } => *dst,
+            Self::Field { dst, .. } => *dst,
+            Self::Variant { dst, .. } => *dst,
+            Self::Tag { dst, .. } => *dst,
+            Self::Payload { dst, .. } => *dst,
+            Self::Print { dst, .. } => *dst,
+            Self::Dyn { dst, .. } => *dst,
+            Self::DynCall { dst, .. } => *dst,
+        }
+    }
+
+    pub fn dst_mut(&mut self) -> &mut Reg {
+        match self {
+            Self::Copy { dst, .. } => dst,
+            Self::Binary { dst, .. } => dst,
+            Self::Cast { dst, .. } => dst,
+            Self::Call { dst, .. } => dst,
+            Self::Checked { dst, .. } => dst,
+            Self::Struct { dst, .. } => dst,
+            Self::Field { dst, .. } => dst,
+            Self::Variant { dst, .. } => dst,
+            Self::Tag { dst, .. } => dst,
+            Self::Payload { dst, .. } => dst,
+            Self::Print { dst, .. } => dst,
+            Self::Dyn { dst, .. } => dst,
+            Self::DynCall { dst, .. } => dst,
+        }
+    }
+
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Self::Copy { src, .. } | Self::Cast { src, .. } | Self::Field { src, .. }
+            | Self::Tag { src, .. } | Self::Payload { src, .. } | Self::Print { src, .. } | Self::Dyn { src, .. } => vec![src],
+            Self::Binary { lhs, rhs, .. } | Self::Checked { lhs, rhs, .. } => vec![lhs, rhs],
+            Self::Call { args, .. } | Self::Struct { fields: args, .. } | Self::Variant { fields: args, .. }
+            | Self::DynCall { args, .. } => args.iter().collect(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Self::Copy { src, .. } | Self::Cast { src, .. } | Self::Field { src, .. }
+            | Self::Tag { src, .. } | Self::Payload { src, .. } | Self::Print { src, .. } | Self::Dyn { src, .. } => vec![src],
+            Self::Binary { lhs, rhs, .. } | Self::Checked { lhs, rhs, .. } => vec![lhs, rhs],
+            Self::Call { args, .. } | Self::Struct { fields: args, .. } | Self::Variant { fields: args, .. }
+            | Self::DynCall { args, .. } => args.iter_mut().collect(),
+        }
+    }
+}
+
+impl Terminator {
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Self::Jump(_) | Self::NoMatch(_) => vec![],
+            Self::Branch { cond, .. } => vec![cond],
+            Self::Return(value) | Self::Switch { value, .. } => vec![value],
+            Self::TailCall { args, .. } => args.iter().collect(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Self::Jump(_) | Self::NoMatch(_) => vec![],
+            Self::Branch { cond, .. } => vec![cond],
+            Self::Return(value) | Self::Switch { value, .. } => vec![value],
+            Self::TailCall { args, .. } => args.iter_mut().collect(),
+        }
+    }
+
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Self::Jump(target) => vec![*target],
+            Self::Branch { then_block, else_block, .. } => vec![*then_block, *else_block],
+            Self::Switch { cases, default, .. } => cases.iter().map(|(_, block)| *block).chain([*default]).collect(),
+            Self::Return(_) | Self::TailCall { .. } | Self::NoMatch(_) => vec![],
+        }
+    }
+
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Self::Jump(target) => vec![target],
+            Self::Branch { then_block, else_block, .. } => vec![then_block, else_block],
+            Self::Switch { cases, default, .. } => cases.iter_mut().map(|(_, block)| block).chain([default]).collect(),
+            Self::Return(_) | Self::TailCall { .. } | Self::NoMatch(_) => vec![],
+        }
+    }
+}
+
+impl Function {
+    pub fn reg_type(&self, reg: Reg) -> Ty {
+        self.regs[reg.0]
+    }
+
+    pub fn operand_type(&self, operand: &Operand) -> Ty {
+        match operand {
+            Operand::Reg(reg) => self.reg_type(*reg),
+            Operand::Const(c) => c.ty(),
+        }
+    }
+}
+
+impl Const {
+    pub fn ty(&self) -> Ty {
+        match self {
+            Self::Unit => Ty::Unit,
+            Self::Bool(_) => Ty::Bool,
+            Self::Int(_, t) => Ty::Int(*t),
+            Self::Float(_) => Ty::F64,
+        }
+    }
+}
+
+fn comma_separated<T: fmt::Display>(items: &[T]) -> String {
+    items.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unit => write!(f, "()"),
+            Self::Bool => write!(f, "bool"),
+            Self::Int(t) => write!(f, "{}", t),
+            Self::F64 => write!(f, "f64"),
+            Self::Struct(id) => write!(f, "{}", id),
+            Self::Enum(id) => write!(f, "{}", id),
+            Self::Dyn => write!(f, "dyn"),
+        }
+    }
+}
+
+// Functions don't know the names of the structs they use, so the module
+// lists them
+impl fmt::Display for StructId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "s{}", self.0)
+    }
+}
+
+impl fmt::Display for EnumId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "e{}", self.0)
+    }
+}
+
+impl fmt::Display for VTableId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "vt{}", self.0)
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+
+impl fmt::Display for Const {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unit => write!(f, "()"),
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::Int(n, t) => write!(f, "{}", t.value(*n)),
+            Self::Float(x) => write!(f, "{:?}", x),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Reg(reg) => write!(f, "{}", reg),
+            Self::Const(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+impl BinOp {
+    // How the operator is written in huck
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Rem => "%",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+        }
+    }
+
+    // Whether this can fail on a zero divisor
+    pub fn divides(self) -> bool {
+        matches!(self, Self::Div | Self::Rem)
+    }
+
+    // What a `Checked` instruction doing this reports when its result
+    // doesn't fit
+    pub fn overflow_message(self) -> String {
+        format!("Overflow in `{}`", self.symbol())
+    }
+}
+
+pub const DIVISION_BY_ZERO: &str = "Division by zero";
+
+pub const NO_MATCH: &str = "No match arm matched";
+
+impl fmt::Display for Loc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Add => "add",
+            Self::Sub => "sub",
+            Self::Mul => "mul",
+            Self::Div => "div",
+            Self::Rem => "rem",
+            Self::Eq => "eq",
+            Self::Ne => "ne",
+            Self::Lt => "lt",
+            Self::Le => "le",
+            Self::Gt => "gt",
+            Self::Ge => "ge",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Jump(target) => write!(f, "jmp {}", target),
+            Self::Branch { cond, then_block, else_block } =>
+                write!(f, "br {}, {}, {}", cond, then_block, else_block),
+            Self::Return(value) => write!(f, "ret {}", value),
+            Self::TailCall { func, args } => write!(f, "tailcall {}({})", func, comma_separated(args)),
+            Self::Switch { value, cases, default } => {
+                let cases = cases.iter().map(|(c, block)| format!("{}: {}", c, block)).collect::<Vec<_>>();
+                write!(f, "switch {}, [{}], {}", value, cases.join(", "), default)
+            },
+            Self::NoMatch(loc) => write!(f, "nomatch at {}", loc),
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let params = self.params.iter()
+            .map(|p| format!("{}: {}", p, self.reg_type(*p)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(f, "fn {}({}) -> {} {{", self.name, params, self.ret)?;
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "{}:", BlockId(i))?;
+            for inst in &block.insts {
+                let dst = inst.dst();
+                write!(f, "    {}: {} = ", dst, self.reg_type(dst))?;
+                match inst {
+                    Inst::Copy { src, .. } => writeln!(f, "copy {}", src)?,
+                    Inst::Binary { op, lhs, rhs, .. } => writeln!(f, "{} {}, {}", op, lhs, rhs)?,
+                    Inst::Cast { src, .. } => writeln!(f, "cast {}", src)?,
+                    Inst::Call { func, args, .. } => writeln!(f, "call {}({})", func, comma_separated(args))?,
+                    Inst::Checked { op, lhs, rhs, loc, .. } => writeln!(f, "checked {} {}, {} at {}", op, lhs, rhs, loc)?,
+                    Inst::Struct { fields, .. } => writeln!(f, "struct {{ {} }}", comma_separated(fields))?,
+                    Inst::Field { src, index, .. } => writeln!(f, "field {}.{}", src, index)?,
+                    Inst::Variant { variant, fields, .. } => writeln!(f, "variant {}({})", variant, comma_separated(fields))?,
+                    Inst::Tag { src, .. } => writeln!(f, "tag {}", src)?,
+                    Inst::Payload { src, variant, index, .. } => writeln!(f, "payload {}.{}.{}", src, variant, index)?,
+                    Inst::Print { src, .. } => writeln!(f, "print {}", src)?,
+                    Inst::Dyn { src, vtable, .. } => writeln!(f, "dyn {}, {}", src, vtable)?,
+                    Inst::DynCall { method, args, .. } => writeln!(f, "dyncall {}({})", method, comma_separated(args))?,
+                }
+            }
+            writeln!(f, "    {}", block.term)?;
+        }
+
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, def) in self.structs.iter().enumerate() {
+            let fields = def.fields.iter().map(|(name, ty)| format!("{}: {}", name, ty)).collect::<Vec<_>>();
+            writeln!(f, "type {} = {} {{ {} }}", StructId(i), def.name, fields.join(", "))?;
+        }
+        for (i, def) in self.enums.iter().enumerate() {
+            let variants = def.variants.iter()
+                .map(|(name, payload)| match payload.as_slice() {
+                    [] => name.clone(),
+                    _ => format!("{}({})", name, comma_separated(payload)),
+                })
+                .collect::<Vec<_>>();
+            writeln!(f, "type {} = {} {{ {} }}", EnumId(i), def.name, variants.join(", "))?;
+        }
+        for (i, def) in self.vtables.iter().enumerate() {
+            writeln!(f, "vtable {} = {}: {} {{ {} }}", VTableId(i), def.name, def.ty, def.methods.join(", "))?;
+        }
+        if !self.structs.is_empty() || !self.enums.is_empty() || !self.vtables.is_empty() {
+            writeln!(f)?;
+        }
+        for (i, function) in self.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", function)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+// Just enough JSON for talking to tools: a value type, a parser and
+// compact serialization through Display.
+
+use std::fmt;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    // Keys keep the order they were written in
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object<K: Into<String>>(fields: Vec<(K, Json)>) -> Self {
+        Self::Object(fields.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    /// Look up a key of an object; anything else has no keys.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Self::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Follow a path of keys, like `json.path(&["a", "b"])` for `json.a.b`.
+    pub fn path(&self, keys: &[&str]) -> Option<&Json> {
+        keys.iter().try_fold(self, |json, key| json.get(key))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Self::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Self::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = JsonParser { text, position: 0 };
+        let json = parser.value()?;
+        parser.skip_whitespace();
+        if parser.position != text.len() {
+            return Err(format!("Trailing characters at {}", parser.position));
+        }
+        Ok(json)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Self::Bool(b)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Self::String(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Self::String(s)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Self {
+        Self::Number(n as f64)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(items: Vec<Json>) -> Self {
+        Self::Array(items)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(option: Option<T>) -> Self {
+        option.map_or(Self::Null, Into::into)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Null => write!(f, "null"),
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Self::Number(n) => write!(f, "{}", n),
+            Self::String(s) => write_string(f, s),
+            Self::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Self::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct JsonParser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl JsonParser<'_> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.next_char() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("Expected {:?} but found {:?} at {}", expected, c, self.position - 1)),
+            None => Err(format!("Expected {:?} but the input ended", expected)),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if self.text[self.position..].starts_with(word) {
+            self.position += word.len();
+            Ok(value)
+        } else {
+            Err(format!("Bad literal at {}", self.position))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek().ok_or("Unexpected end of JSON")? {
+            'n' => self.literal("null", Json::Null),
+            't' => self.literal("true", Json::Bool(true)),
+            'f' => self.literal("false", Json::Bool(false)),
+            '"' => Ok(Json::String(self.string()?)),
+            '[' => {
+                self.next_char();
+                let mut items = vec![];
+                self.skip_whitespace();
+                if self.peek() == Some(']') {
+                    self.next_char();
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.next_char() {
+                        Some(',') => continue,
+                        Some(']') => return Ok(Json::Array(items)),
+                        _ => return Err(format!("Expected , or ] at {}", self.position)),
+                    }
+                }
+            },
+            '{' => {
+                self.next_char();
+                let mut fields = vec![];
+                self.skip_whitespace();
+                if self.peek() == Some('}') {
+                    self.next_char();
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.expect(':')?;
+                    fields.push((key, self.value()?));
+                    self.skip_whitespace();
+                    match self.next_char() {
+                        Some(',') => continue,
+                        Some('}') => return Ok(Json::Object(fields)),
+                        _ => return Err(format!("Expected , or }} at {}", self.position)),
+                    }
+                }
+            },
+            _ => self.number(),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        while matches!(self.peek(), Some('0'..='9' | '-' | '+' | '.' | 'e' | 'E')) {
+            self.next_char();
+        }
+        self.text[start..self.position].parse()
+            .map(Json::Number)
+            .map_err(|_| format!("Bad number at {}", start))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.next_char().ok_or("Unterminated string")? {
+                '"' => return Ok(s),
+                '\\' => match self.next_char().ok_or("Unterminated string")? {
+                    'n' => s.push('\n'),
+                    't' => s.push('\t'),
+                    'r' => s.push('\r'),
+                    'b' => s.push('\u{8}'),
+                    'f' => s.push('\u{c}'),
+                    'u' => {
+                        let mut code = self.hex4()?;
+                        // Characters outside the BMP come as surrogate pairs
+                        if (0xd800..0xdc00).contains(&code) && self.text[self.position..].starts_with("\\u") {
+                            self.position += 2;
+                            let low = self.hex4()?;
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                        }
+                        s.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                    },
+                    c => s.push(c),
+                },
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.text.get(self.position..self.position + 4).ok_or("Bad \\u escape")?;
+        self.position += 4;
+        u32::from_str_radix(digits, 16).map_err(|_| String::from("Bad \\u escape"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let text = r#"{"a":[1,-2.5,true,null],"b":{"c":"q\"u\\o\nteé"},"d":[]}"#;
+        let json = Json::parse(text).unwrap();
+        assert_eq!(json.path(&["b", "c"]).and_then(Json::as_str), Some("q\"u\\o\nteé"));
+        assert_eq!(json.get("a").and_then(Json::as_array).map(|a| a.len()), Some(4));
+        assert_eq!(json.to_string(), text.replace("\\u00e9", "é"));
+        assert_eq!(Json::parse(" [ 1 , { } ] ").unwrap(), Json::Array(vec![Json::Number(1.0), Json::Object(vec![])]));
+    }
+
+    #[test]
+    fn errors() {
+        for bad in ["", "[1,", "{\"a\" 1}", "tru", "\"abc", "1 2"] {
+            assert!(Json::parse(bad).is_err(), "{:?}", bad);
+        }
+    }
+}
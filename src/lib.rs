@@ -0,0 +1,193 @@
+//! The huck compiler as a library.
+//!
+//! Each stage of the pipeline is its own module, but most callers only
+//! need the functions here:
+//!
+//! ```
+//! use huck::opt::OptLevel;
+//!
+//! let ast = huck::parse_str("{let x = 2; x * 21}").unwrap();
+//! let checked = huck::check(&ast).unwrap();
+//! let asm = huck::compile_to_asm(&checked, "{let x = 2; x * 21}", OptLevel::O1);
+//! assert!(asm.contains("huck_main:"));
+//! ```
+//!
+//! A [`Session`] does the same thing for a driver: it holds the options
+//! for a compilation and collects diagnostics instead of handing back
+//! `Result`s. To run huck code from inside a Rust program, use
+//! [`engine::Engine`].
+
+pub mod diagnostic;
+pub mod scanner;
+pub mod parser;
+pub mod typecheck;
+pub mod matching;
+mod exhaustiveness;
+pub mod mono;
+pub mod ir;
+mod inline;
+pub mod lower;
+pub mod opt;
+pub mod codegen;
+pub mod llvm;
+pub mod interp;
+pub mod bytecode;
+pub mod bcgen;
+pub mod vm;
+pub mod repl;
+pub mod link;
+pub mod engine;
+pub mod json;
+pub mod lsp;
+pub mod format;
+
+use opt::OptLevel;
+use parser::ParseOutput;
+use typecheck::CheckOutput;
+
+pub use diagnostic::{Code, Diagnostic, Span, Stage};
+
+/// Parse huck source into an untyped AST.
+pub fn parse_str(source: &str) -> Result<ParseOutput, Diagnostic> {
+    parser::Parser::new(scanner::Scanner::new(source)).parse().map_err(|err| match err {
+        parser::ParseError::Eof => {
+            let end = Span::new(source.trim_end().len(), source.trim_end().len());
+            Diagnostic::syntax(Code::UNEXPECTED_EOF, "Unexpected end of input", end).with_label(end, "expected more here")
+        },
+        parser::ParseError::Fucked(diagnostic) => diagnostic,
+    })
+}
+
+/// Type check a parsed program, annotating every node with its type, and
+/// monomorphize it so that only concrete types are left.
+pub fn check(ast: &ParseOutput) -> Result<CheckOutput, Diagnostic> {
+    mono::monomorphize(&typecheck::Checker::new().check(ast)?)
+}
+
+/// Lower a checked program to IR and optimize it. `source` is the text it
+/// was parsed from, which runtime errors point into.
+pub fn compile_to_ir(checked: &CheckOutput, source: &str, level: OptLevel) -> ir::Module {
+    let mut module = lower::lower(checked, source);
+    opt::optimize(&mut module, level);
+    module
+}
+
+/// Compile a checked program to x86-64 assembly (AT&T syntax). The
+/// result still has to be linked against the runtime; see [`link`].
+pub fn compile_to_asm(checked: &CheckOutput, source: &str, level: OptLevel) -> String {
+    let mut asm = vec![];
+    codegen::compile(&compile_to_ir(checked, source, level), &mut asm).expect("Writing to a Vec can't fail");
+    String::from_utf8(asm).expect("Assembly should be ASCII")
+}
+
+/// Compile a checked program to textual LLVM IR.
+pub fn compile_to_llvm(checked: &CheckOutput, source: &str, level: OptLevel) -> String {
+    let mut llvm = vec![];
+    llvm::compile(&compile_to_ir(checked, source, level), &mut llvm).expect("Writing to a Vec can't fail");
+    String::from_utf8(llvm).expect("LLVM IR should be ASCII")
+}
+
+/// Options that affect how a [`Session`] compiles programs.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Options {
+    pub opt_level: OptLevel,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self { opt_level: OptLevel::O0 }
+    }
+}
+
+/// One run of the compiler. Each stage returns `None` when the program
+/// is rejected, after recording why in [`Session::diagnostics`].
+#[derive(Debug, Default)]
+pub struct Session {
+    pub options: Options,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Session {
+    pub fn new(options: Options) -> Self {
+        Self { options, diagnostics: vec![] }
+    }
+
+    /// Everything reported so far, oldest first.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Whether anything reported so far is an error, not just a warning.
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(Diagnostic::is_error)
+    }
+
+    fn report<T>(&mut self, result: Result<T, Diagnostic>) -> Option<T> {
+        result.map_err(|diagnostic| self.diagnostics.push(diagnostic)).ok()
+    }
+
+    pub fn parse(&mut self, source: &str) -> Option<ParseOutput> {
+        self.report(parse_str(source))
+    }
+
+    pub fn check(&mut self, source: &str) -> Option<CheckOutput> {
+        let ast = self.parse(source)?;
+        let mut checker = typecheck::Checker::new();
+        let checked = checker.check(&ast);
+        self.diagnostics.extend(checker.take_warnings());
+        self.report(checked.and_then(|checked| mono::monomorphize(&checked)))
+    }
+
+    pub fn compile_to_ir(&mut self, source: &str) -> Option<ir::Module> {
+        let checked = self.check(source)?;
+        Some(compile_to_ir(&checked, source, self.options.opt_level))
+    }
+
+    pub fn compile_to_asm(&mut self, source: &str) -> Option<String> {
+        let checked = self.check(source)?;
+        Some(compile_to_asm(&checked, source, self.options.opt_level))
+    }
+
+    pub fn compile_to_llvm(&mut self, source: &str) -> Option<String> {
+        let checked = self.check(source)?;
+        Some(compile_to_llvm(&checked, source, self.options.opt_level))
+    }
+
+    pub fn compile_to_bytecode(&mut self, source: &str) -> Option<bytecode::Program> {
+        let checked = self.check(source)?;
+        Some(bcgen::compile(&checked))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pipeline() {
+        let checked = check(&parse_str("if 1 < 2 { 3 } else { 4 }").unwrap()).unwrap();
+        assert_eq!(*checked.ty(), typecheck::TypeInfo::Int(typecheck::IntType::I64));
+        assert!(compile_to_llvm(&checked, "if 1 < 2 { 3 } else { 4 }", OptLevel::O2).contains("define i32 @main()"));
+    }
+
+    #[test]
+    fn session_diagnostics() {
+        let mut session = Session::new(Options { opt_level: OptLevel::O1 });
+        assert!(session.compile_to_asm("1 + 2").is_some());
+        assert!(!session.has_errors());
+
+        assert!(session.check("{1 +").is_none());
+        assert!(session.compile_to_asm("1 + true").is_none());
+        let stages = session.diagnostics().iter().map(|d| d.stage).collect::<Vec<_>>();
+        assert_eq!(stages, vec![Stage::Syntax, Stage::Type]);
+    }
+
+    #[test]
+    fn session_warnings() {
+        let mut session = Session::default();
+        assert!(session.check("match true { _ => 1, false => 2 }").is_some());
+        assert!(!session.has_errors());
+        let codes = session.diagnostics().iter().map(|d| d.code).collect::<Vec<_>>();
+        assert_eq!(codes, vec![Some(diagnostic::Code::UNREACHABLE_ARM)]);
+    }
+}
@@ -0,0 +1,139 @@
+// Turns assembly from `codegen` into an executable using the system C
+// compiler, which drives the assembler and linker for us and builds the
+// runtime at the same time. `HUCK_CC` picks a different compiler; the
+// driver's `--linker` flag overrides that.
+//
+// Either way it has to be a C compiler driver. The runtime is C source,
+// and the driver also knows where the crt objects and libc are, so a bare
+// linker like `ld` is turned away with a hint to pass it through the
+// driver instead, as in `clang -fuse-ld=lld`.
+
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const RUNTIME_SOURCE: &str = include_str!("../runtime/huck_rt.c");
+
+const DEFAULT_CC: &str = "cc";
+
+static NEXT_TEMP_DIR: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Linker {
+    cc: String,
+    keep_temps: bool,
+}
+
+impl Linker {
+    pub fn new(cc: Option<&str>, keep_temps: bool) -> Self {
+        let cc = cc.map(String::from)
+            .or_else(|| env::var("HUCK_CC").ok().filter(|cc| !cc.is_empty()))
+            .unwrap_or_else(|| DEFAULT_CC.to_string());
+        Self { cc, keep_temps }
+    }
+
+    // Temporaries go in a fresh directory that's removed afterwards,
+    // unless we were asked to keep it around
+    fn temp_dir() -> Result<PathBuf, String> {
+        let n = NEXT_TEMP_DIR.fetch_add(1, Ordering::Relaxed);
+        let dir = env::temp_dir().join(format!("huck-{}-{}", std::process::id(), n));
+        fs::create_dir_all(&dir)
+            .map_err(|err| format!("Can't create temporary directory {}: {}", dir.display(), err))?;
+        Ok(dir)
+    }
+
+    // Returns the temporary directory if it was kept
+    pub fn link(&self, asm: &[u8], output: &Path) -> Result<Option<PathBuf>, String> {
+        self.check_driver()?;
+        let dir = Self::temp_dir()?;
+        let result = self.link_in(&dir, asm, output);
+        if self.keep_temps {
+            return result.map(|_| Some(dir));
+        }
+        let _ = fs::remove_dir_all(&dir);
+        result.map(|_| None)
+    }
+
+    fn check_driver(&self) -> Result<(), String> {
+        let program = self.cc.split_whitespace().next().unwrap_or(DEFAULT_CC);
+        let name = Path::new(program).file_name().and_then(|name| name.to_str()).unwrap_or(program);
+        let Some(flavor) = linker_flavor(name) else {
+            return Ok(());
+        };
+        Err(format!(
+            "{:?} is a linker, not a C compiler; huck links with a C compiler driver like cc or clang, \
+             which also builds the runtime and finds the C library (try --linker \"cc -fuse-ld={}\")",
+            program,
+            flavor,
+        ))
+    }
+
+    fn link_in(&self, dir: &Path, asm: &[u8], output: &Path) -> Result<(), String> {
+        let asm_path = dir.join("out.s");
+        let runtime_path = dir.join("huck_rt.c");
+        for (path, contents) in [(&asm_path, asm), (&runtime_path, RUNTIME_SOURCE.as_bytes())] {
+            fs::write(path, contents)
+                .map_err(|err| format!("Can't write {}: {}", path.display(), err))?;
+        }
+
+        // `cc` may be something like "clang -fuse-ld=lld"
+        let mut words = self.cc.split_whitespace();
+        let program = words.next().unwrap_or(DEFAULT_CC);
+        let status = Command::new(program)
+            .args(words)
+            .arg(&asm_path)
+            .arg(&runtime_path)
+            .arg("-o")
+            .arg(output)
+            .status();
+
+        match status {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(format!("{} failed ({}) while linking {}", self.cc, status, output.display())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Err(format!(
+                "Can't find a C compiler to link with ({:?} isn't installed); set HUCK_CC or pass --linker",
+                program
+            )),
+            Err(err) => Err(format!("Can't run {}: {}", self.cc, err)),
+        }
+    }
+}
+
+// What `-fuse-ld` calls `ld` or one of its variants, none of which can
+// compile the runtime. None for anything else.
+fn linker_flavor(name: &str) -> Option<&str> {
+    match name {
+        "ld" => Some("bfd"),
+        "lld" | "ld64.lld" => Some("lld"),
+        "mold" => Some("mold"),
+        _ => name.strip_prefix("ld."),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn missing_toolchain() {
+        let linker = Linker::new(Some("huck-no-such-cc"), false);
+        let output = env::temp_dir().join("huck-missing-toolchain");
+        let err = linker.link(b"", &output).unwrap_err();
+        assert!(err.contains("Can't find a C compiler"), "{}", err);
+    }
+
+    #[test]
+    fn bare_linkers() {
+        let output = env::temp_dir().join("huck-bare-linker");
+        let err = Linker::new(Some("/usr/bin/ld"), false).link(b"", &output).unwrap_err();
+        assert!(err.contains("is a linker, not a C compiler"), "{}", err);
+        assert!(err.contains("-fuse-ld=bfd"), "{}", err);
+        let err = Linker::new(Some("ld.lld --verbose"), false).link(b"", &output).unwrap_err();
+        assert!(err.contains("-fuse-ld=lld"), "{}", err);
+        assert_eq!(linker_flavor("mold"), Some("mold"));
+        assert_eq!(linker_flavor("clang"), None);
+        assert_eq!(linker_flavor("gcc-ld-wrapper"), None);
+    }
+}
@@ -0,0 +1,838 @@
+// LLVM IR backend.
+//
+// Like the assembly backend, every virtual register lives in memory (an
+// `alloca` in the entry block), so non-SSA IR registers need no phi
+// construction; `opt -mem2reg` cleans this up if anyone cares. Tail
+// calls between functions with identical signatures and no structs are
+// `musttail`, so LLVM has to turn them into jumps.
+//
+// There's no runtime here, so modules define what they need of it
+// themselves. Checked arithmetic calls a `huckrt_panic` that reports the
+// failure the same way the native runtime does. Floats, printed or as the
+// program's value, go through a `huckrt_print_f64` that formats them the
+// same way too, so every backend prints the same thing. Everything else
+// `print` does goes straight to printf.
+//
+// An enum is an i32 tag and enough i64s to hold any of its payloads,
+// which are read and written through a pointer cast to the variant's
+// own struct type.
+//
+// A `dyn` is an `i8*` to a copy of its value from `malloc` and an `i8*`
+// to its vtable, an array of functions taking that pointer as `self`.
+// Each is a shim that loads the value and calls the method with it.
+
+use crate::codegen::size_align;
+use crate::ir::{
+    BinOp, BlockId, Const, EnumId, Function, Inst, Loc, Module, Operand, Reg, Terminator, Ty, VTableDef, VTableId, DIVISION_BY_ZERO,
+    NO_MATCH,
+};
+use crate::typecheck::IntType;
+
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+type CompileInput = Module;
+
+type CompileResult<T> = io::Result<T>;
+
+pub fn compile<T>(code: &CompileInput, output: &mut T) -> CompileResult<()>
+where T: Write
+{
+    let messages = panic_messages(code);
+    for function in &code.functions {
+        FunctionEmitter::new(code, function, &messages, output).emit()?;
+        writeln!(output)?;
+    }
+    for (i, vtable) in code.vtables.iter().enumerate() {
+        write_vtable(code, VTableId(i), vtable, output)?;
+    }
+    for declaration in intrinsics(code) {
+        writeln!(output, "{}", declaration)?;
+    }
+    if !messages.is_empty() {
+        write_panic(&messages, output)?;
+    }
+    if prints_floats(code) {
+        write_float_printer(output)?;
+    }
+    let prints = uses_print(code);
+    if prints {
+        for (name, format) in PRINT_FORMATS {
+            writeln!(output, "{}", string_constant(name, format).0)?;
+        }
+    }
+
+    // The huck entry point returns its value as the process exit code
+    if let Some(main) = code.functions.iter().find(|f| f.name == "main") {
+        writeln!(output, "define i32 @main() {{")?;
+        writeln!(output, "entry:")?;
+        writeln!(output, "  %result = call {} {}()", llvm_type(code, main.ret), symbol(&main.name))?;
+        match main.ret {
+            // Main is lowered to return () when its value is a struct or enum
+            Ty::Unit | Ty::Struct(_) | Ty::Enum(_) | Ty::Dyn => writeln!(output, "  ret i32 0")?,
+            Ty::Bool => {
+                writeln!(output, "  %code = zext i1 %result to i32")?;
+                writeln!(output, "  ret i32 %code")?;
+            },
+            Ty::F64 => {
+                writeln!(output, "  call void @huckrt_print_f64(double %result)")?;
+                writeln!(output, "  ret i32 0")?;
+            },
+            Ty::Int(t) if t.bits() == 32 => writeln!(output, "  ret i32 %result")?,
+            Ty::Int(t) => {
+                let conversion = match t.bits() {
+                    64 => "trunc",
+                    _ if t.is_signed() => "sext",
+                    _ => "zext",
+                };
+                writeln!(output, "  %code = {} {} %result to i32", conversion, llvm_type(code, main.ret))?;
+                writeln!(output, "  ret i32 %code")?;
+            },
+        }
+        writeln!(output, "}}")?;
+        if main.ret == Ty::F64 && !prints {
+            writeln!(output, "declare i32 @printf(i8*, ...)")?;
+        }
+    }
+    if prints {
+        writeln!(output, "declare i32 @printf(i8*, ...)")?;
+    }
+    Ok(())
+}
+
+// What `print` hands printf for each kind of value
+const PRINT_FORMATS: [(&str, &str); 5] = [
+    ("@print_signed", "%lld\n"),
+    ("@print_unsigned", "%llu\n"),
+    ("@print_string", "%s\n"),
+    ("@print_true", "true"),
+    ("@print_false", "false"),
+];
+
+fn print_format(name: &str) -> String {
+    let (_, format) = PRINT_FORMATS.iter().find(|(n, _)| *n == name).unwrap();
+    string_constant(name, format).1
+}
+
+fn uses_print(module: &Module) -> bool {
+    module.functions.iter()
+        .flat_map(|f| &f.blocks)
+        .flat_map(|b| &b.insts)
+        .any(|inst| matches!(inst, Inst::Print { .. }))
+}
+
+// Whether anything needs `huckrt_print_f64`
+fn prints_floats(module: &Module) -> bool {
+    module.functions.iter().any(|function| {
+        (function.name == "main" && function.ret == Ty::F64) || function.blocks.iter()
+            .flat_map(|b| &b.insts)
+            .any(|inst| matches!(inst, Inst::Print { src, .. } if function.operand_type(src) == Ty::F64))
+    })
+}
+
+// `as` from a float to an integer saturates, which LLVM has intrinsics for
+fn saturating_conversion(t: IntType) -> String {
+    let kind = if t.is_signed() { "fptosi" } else { "fptoui" };
+    format!("@llvm.{}.sat.i{}.f64", kind, t.bits())
+}
+
+// Checked addition, subtraction and multiplication return the result
+// along with whether it overflowed
+fn overflow_intrinsic(op: BinOp, t: IntType) -> String {
+    let sign = if t.is_signed() { "s" } else { "u" };
+    let name = match op {
+        BinOp::Add => "add",
+        BinOp::Sub => "sub",
+        BinOp::Mul => "mul",
+        _ => unreachable!("No overflow intrinsic for {}", op),
+    };
+    format!("@llvm.{}{}.with.overflow.i{}", sign, name, t.bits())
+}
+
+fn intrinsics(module: &Module) -> BTreeSet<String> {
+    let mut declarations = BTreeSet::new();
+    for function in &module.functions {
+        for inst in function.blocks.iter().flat_map(|b| &b.insts) {
+            match inst {
+                Inst::Cast { dst, src } => {
+                    if let (Ty::F64, Ty::Int(t)) = (function.operand_type(src), function.reg_type(*dst)) {
+                        declarations.insert(format!("declare i{} {}(double)", t.bits(), saturating_conversion(t)));
+                    }
+                },
+                Inst::Checked { dst, op, .. } if !op.divides() => {
+                    if let Ty::Int(t) = function.reg_type(*dst) {
+                        let ty = llvm_type(module, Ty::Int(t));
+                        declarations.insert(format!("declare {{{}, i1}} {}({}, {})", ty, overflow_intrinsic(*op, t), ty, ty));
+                    }
+                },
+                Inst::Dyn { .. } => {
+                    declarations.insert(String::from("declare i8* @malloc(i64)"));
+                },
+                _ => (),
+            }
+        }
+    }
+    declarations
+}
+
+// Everything checked arithmetic or a failed match in the module might
+// report
+fn panic_messages(module: &Module) -> Vec<String> {
+    let mut messages = vec![];
+    let blocks = || module.functions.iter().flat_map(|f| &f.blocks);
+    if blocks().any(|b| matches!(b.term, Terminator::NoMatch(_))) {
+        messages.push(NO_MATCH.to_string());
+    }
+    for inst in blocks().flat_map(|b| &b.insts) {
+        if let Inst::Checked { op, .. } = inst {
+            if op.divides() && !messages.iter().any(|m| m == DIVISION_BY_ZERO) {
+                messages.push(DIVISION_BY_ZERO.to_string());
+            }
+            if !messages.contains(&op.overflow_message()) {
+                messages.push(op.overflow_message());
+            }
+        }
+    }
+    messages
+}
+
+// A NUL-terminated string constant, and an `i8*` expression pointing at it
+fn string_constant(name: &str, s: &str) -> (String, String) {
+    let len = s.len() + 1;
+    let escaped = s.replace('\\', "\\5C").replace('"', "\\22").replace('\n', "\\0A");
+    (
+        format!("{} = private constant [{} x i8] c\"{}\\00\"", name, len, escaped),
+        format!("i8* getelementptr ([{} x i8], [{} x i8]* {}, i32 0, i32 0)", len, len, name),
+    )
+}
+
+fn message_name(index: usize) -> String {
+    format!("@panic_message{}", index)
+}
+
+fn write_panic<T>(messages: &[String], output: &mut T) -> CompileResult<()> where T: Write {
+    let (format, format_pointer) = string_constant("@panic_format", "Runtime error: %s at %ld:%ld\n");
+    writeln!(output, "define private void @huckrt_panic(i8* %what, i64 %line, i64 %col) noreturn {{")?;
+    writeln!(output, "entry:")?;
+    writeln!(output, "  %printed = call i32 (i32, i8*, ...) @dprintf(i32 2, {}, i8* %what, i64 %line, i64 %col)", format_pointer)?;
+    writeln!(output, "  call void @exit(i32 101)")?;
+    writeln!(output, "  unreachable")?;
+    writeln!(output, "}}")?;
+    writeln!(output, "{}", format)?;
+    for (i, message) in messages.iter().enumerate() {
+        writeln!(output, "{}", string_constant(&message_name(i), message).0)?;
+    }
+    writeln!(output, "declare i32 @dprintf(i32, i8*, ...)")?;
+    writeln!(output, "declare void @exit(i32)")
+}
+
+// The native runtime's `print_f64`, which shows a float the way the
+// interpreter does: the fewest digits that read back as the same number,
+// in plain notation unless it's huge or tiny, and always with a decimal
+// point or exponent.
+fn write_float_printer<T>(output: &mut T) -> CompileResult<()> where T: Write {
+    let strings = [
+        ("@float_nan", "NaN"),
+        ("@float_inf", "inf"),
+        ("@float_neg_inf", "-inf"),
+        ("@float_scientific", "%.*e"),
+        ("@float_fixed", "%.*f"),
+        ("@float_exponent", "%se%d\n"),
+        ("@float_point", "%s\n"),
+        ("@float_no_point", "%s.0\n"),
+    ];
+    let pointer = |name: &str| {
+        let (_, s) = strings.iter().find(|(n, _)| *n == name).unwrap();
+        string_constant(name, s).1
+    };
+    writeln!(output, "\
+define private void @huckrt_print_f64(double %x) {{
+entry:
+  %digits = alloca [64 x i8]
+  %plain = alloca [400 x i8]
+  %d = getelementptr [64 x i8], [64 x i8]* %digits, i32 0, i32 0
+  %p = getelementptr [400 x i8], [400 x i8]* %plain, i32 0, i32 0
+  %nan = fcmp uno double %x, %x
+  br i1 %nan, label %not_a_number, label %number
+not_a_number:
+  %nan_printed = call i32 @puts({nan})
+  ret void
+number:
+  %inf = fcmp oeq double %x, 0x7FF0000000000000
+  %neg_inf = fcmp oeq double %x, 0xFFF0000000000000
+  %infinite = or i1 %inf, %neg_inf
+  br i1 %infinite, label %infinity, label %search
+infinity:
+  %sign = select i1 %inf, {inf}, {neg_inf}
+  %inf_printed = call i32 @puts(i8* %sign)
+  ret void
+search:
+  %precision = phi i32 [1, %number], [%next, %keep_looking]
+  %tried = sub i32 %precision, 1
+  %written = call i32 (i8*, i64, i8*, ...) @snprintf(i8* %d, i64 64, {scientific}, i32 %tried, double %x)
+  %back = call double @strtod(i8* %d, i8** null)
+  %same = fcmp oeq double %back, %x
+  br i1 %same, label %found, label %keep_looking
+keep_looking:
+  %next = add i32 %precision, 1
+  %more = icmp slt i32 %next, 17
+  br i1 %more, label %search, label %found
+found:
+  %final = phi i32 [%precision, %search], [%next, %keep_looking]
+  %decimal_digits = sub i32 %final, 1
+  %rewritten = call i32 (i8*, i64, i8*, ...) @snprintf(i8* %d, i64 64, {scientific}, i32 %decimal_digits, double %x)
+  %e = call i8* @strchr(i8* %d, i32 101)
+  %exponent_digits = getelementptr i8, i8* %e, i64 1
+  %exponent = call i32 @atoi(i8* %exponent_digits)
+  %tiny = icmp slt i32 %exponent, -4
+  %huge = icmp sge i32 %exponent, 17
+  %far = or i1 %tiny, %huge
+  br i1 %far, label %exponential, label %fixed
+exponential:
+  store i8 0, i8* %e
+  %exp_printed = call i32 (i8*, ...) @printf({exponent}, i8* %d, i32 %exponent)
+  ret void
+fixed:
+  %decimals = sub i32 %decimal_digits, %exponent
+  %some = icmp sgt i32 %decimals, 0
+  %places = select i1 %some, i32 %decimals, i32 0
+  %fixed_written = call i32 (i8*, i64, i8*, ...) @snprintf(i8* %p, i64 400, {fixed}, i32 %places, double %x)
+  %point = call i8* @strchr(i8* %p, i32 46)
+  %has_point = icmp ne i8* %point, null
+  %format = select i1 %has_point, {point}, {no_point}
+  %fixed_printed = call i32 (i8*, ...) @printf(i8* %format, i8* %p)
+  ret void
+}}",
+        nan = pointer("@float_nan"),
+        inf = pointer("@float_inf"),
+        neg_inf = pointer("@float_neg_inf"),
+        scientific = pointer("@float_scientific"),
+        exponent = pointer("@float_exponent"),
+        fixed = pointer("@float_fixed"),
+        point = pointer("@float_point"),
+        no_point = pointer("@float_no_point"),
+    )?;
+    for (name, s) in strings {
+        writeln!(output, "{}", string_constant(name, s).0)?;
+    }
+    for declaration in ["i32 @puts(i8*)", "i32 @snprintf(i8*, i64, i8*, ...)", "double @strtod(i8*, i8**)", "i8* @strchr(i8*, i32)", "i32 @atoi(i8*)"] {
+        writeln!(output, "declare {}", declaration)?;
+    }
+    Ok(())
+}
+
+// Structs are literal struct types rather than named ones, so they need
+// no declarations
+fn llvm_type(module: &Module, ty: Ty) -> String {
+    match ty {
+        Ty::Unit => "{}".to_string(),
+        Ty::Bool => "i1".to_string(),
+        Ty::Int(t) => format!("i{}", t.bits()),
+        Ty::F64 => "double".to_string(),
+        Ty::Struct(id) => {
+            let fields = module.structs[id.0].fields.iter()
+                .map(|(_, ty)| llvm_type(module, *ty))
+                .collect::<Vec<_>>();
+            format!("{{ {} }}", fields.join(", "))
+        },
+        Ty::Enum(id) => format!("{{ i32, [{} x i64] }}", payload_words(module, id)),
+        Ty::Dyn => DYN_TYPE.to_string(),
+    }
+}
+
+const DYN_TYPE: &str = "{ i8*, i8* }";
+
+fn vtable_name(id: VTableId) -> String {
+    format!("@vtable{}", id.0)
+}
+
+fn vtable_type(vtable: &VTableDef) -> String {
+    format!("[{} x i8*]", vtable.methods.len())
+}
+
+// The type of a function taking `self` as a pointer, with `params` after
+// it, as it's called through a vtable
+fn dyn_method_type(ret: &str, params: &[String]) -> String {
+    let params = ["i8*".to_string()].into_iter().chain(params.iter().cloned()).collect::<Vec<_>>();
+    format!("{} ({})", ret, params.join(", "))
+}
+
+fn write_vtable<T>(module: &Module, id: VTableId, vtable: &VTableDef, output: &mut T) -> CompileResult<()> where T: Write {
+    let ty = llvm_type(module, vtable.ty);
+    let mut entries = vec![];
+    for (i, method) in vtable.methods.iter().enumerate() {
+        let function = module.functions.iter().find(|f| f.name == *method).expect("Functions in vtables are kept");
+        let ret = llvm_type(module, function.ret);
+        let params = function.params[1..].iter().map(|p| llvm_type(module, function.reg_type(*p))).collect::<Vec<_>>();
+        let args = params.iter().enumerate().map(|(i, param)| format!("{} %a{}", param, i)).collect::<Vec<_>>();
+        let shim = format!("{}.{}", vtable_name(id), i);
+        let shim_params = [String::from("i8* %self")].into_iter().chain(args.iter().cloned()).collect::<Vec<_>>();
+        writeln!(output, "define private {} {}({}) {{", ret, shim, shim_params.join(", "))?;
+        writeln!(output, "entry:")?;
+        writeln!(output, "  %typed = bitcast i8* %self to {}*", ty)?;
+        writeln!(output, "  %value = load {}, {}* %typed", ty, ty)?;
+        let args = [format!("{} %value", ty)].into_iter().chain(args).collect::<Vec<_>>();
+        writeln!(output, "  %result = call {} {}({})", ret, symbol(method), args.join(", "))?;
+        writeln!(output, "  ret {} %result", ret)?;
+        writeln!(output, "}}")?;
+        entries.push(format!("i8* bitcast ({}* {} to i8*)", dyn_method_type(&ret, &params), shim));
+    }
+    writeln!(output, "{} = private constant {} [{}]", vtable_name(id), vtable_type(vtable), entries.join(", "))?;
+    writeln!(output)
+}
+
+// Enough i64s for the biggest payload
+fn payload_words(module: &Module, id: EnumId) -> usize {
+    (0..module.enums[id.0].variants.len())
+        .map(|variant| payload_size(module, id, variant).div_ceil(8))
+        .max()
+        .unwrap_or(0)
+}
+
+fn payload_type(module: &Module, id: EnumId, variant: usize) -> String {
+    let fields = module.enums[id.0].variants[variant].1.iter()
+        .map(|ty| llvm_type(module, *ty))
+        .collect::<Vec<_>>();
+    format!("{{ {} }}", fields.join(", "))
+}
+
+// LLVM lays out a payload's struct type the same way the native backend
+// lays out structs
+fn payload_size(module: &Module, id: EnumId, variant: usize) -> usize {
+    let (size, align) = module.enums[id.0].variants[variant].1.iter()
+        .fold((0usize, 1), |(size, align), ty| {
+            let (field_size, field_align) = size_align(module, *ty);
+            (size.next_multiple_of(field_align) + field_size, align.max(field_align))
+        });
+    size.next_multiple_of(align)
+}
+
+// LLVM doesn't know about signedness, so constants are written as the
+// signed number with the same bits
+fn constant(n: i64, t: IntType) -> i64 {
+    let shift = 64 - t.bits();
+    (n << shift) >> shift
+}
+
+// Huck functions get a prefix so they can't collide with libc
+fn symbol(name: &str) -> String {
+    format!("@\"huck_{}\"", name)
+}
+
+struct FunctionEmitter<'a, T> {
+    module: &'a Module,
+    function: &'a Function,
+    messages: &'a [String],
+    output: &'a mut T,
+    next_temp: usize,
+}
+
+impl<'a, T> FunctionEmitter<'a, T> where T: Write {
+    fn new(module: &'a Module, function: &'a Function, messages: &'a [String], output: &'a mut T) -> Self {
+        Self { module, function, messages, output, next_temp: 0 }
+    }
+
+    fn emit(&mut self) -> CompileResult<()> {
+        let function = self.function;
+        let params = function.params.iter()
+            .map(|p| format!("{} %p{}", llvm_type(self.module, function.reg_type(*p)), p.0))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(self.output, "define {} {}({}) {{", llvm_type(self.module, function.ret), symbol(&function.name), params)?;
+        writeln!(self.output, "entry:")?;
+        for (i, ty) in function.regs.iter().enumerate() {
+            writeln!(self.output, "  %r{} = alloca {}", i, llvm_type(self.module, *ty))?;
+        }
+        for param in &function.params {
+            self.store(*param, &format!("%p{}", param.0))?;
+        }
+        writeln!(self.output, "  br label %{}", BlockId(0))?;
+
+        for (i, block) in function.blocks.iter().enumerate() {
+            writeln!(self.output, "{}:", BlockId(i))?;
+            for inst in &block.insts {
+                self.inst(inst)?;
+            }
+            self.terminator(&block.term)?;
+        }
+
+        writeln!(self.output, "}}")
+    }
+
+    fn temp(&mut self) -> String {
+        self.next_temp += 1;
+        format!("%t{}", self.next_temp - 1)
+    }
+
+    // Turn an operand into something usable as an LLVM value, loading
+    // registers out of their stack slots
+    fn value(&mut self, operand: &Operand) -> CompileResult<String> {
+        match operand {
+            Operand::Reg(reg) => {
+                let temp = self.temp();
+                let ty = llvm_type(self.module, self.function.reg_type(*reg));
+                writeln!(self.output, "  {} = load {}, {}* %r{}", temp, ty, ty, reg.0)?;
+                Ok(temp)
+            },
+            Operand::Const(Const::Unit) => Ok("zeroinitializer".to_string()),
+            Operand::Const(Const::Bool(b)) => Ok(b.to_string()),
+            Operand::Const(Const::Int(n, t)) => Ok(constant(*n, *t).to_string()),
+            // Hex is the only way to write every double exactly
+            Operand::Const(Const::Float(x)) => Ok(format!("0x{:016X}", x.to_bits())),
+        }
+    }
+
+    fn store(&mut self, dst: Reg, value: &str) -> CompileResult<()> {
+        let ty = llvm_type(self.module, self.function.reg_type(dst));
+        writeln!(self.output, "  store {} {}, {}* %r{}", ty, value, ty, dst.0)
+    }
+
+    fn inst(&mut self, inst: &Inst) -> CompileResult<()> {
+        match inst {
+            Inst::Copy { dst, src } => {
+                let value = self.value(src)?;
+                self.store(*dst, &value)
+            },
+            Inst::Binary { dst, op, lhs, rhs } => {
+                // Comparisons are typed by their operands, not their result
+                let operand_type = self.function.operand_type(lhs);
+                let ty = llvm_type(self.module, operand_type);
+                let signed = !matches!(operand_type, Ty::Int(t) if !t.is_signed());
+                let lhs = self.value(lhs)?;
+                let rhs = self.value(rhs)?;
+                // Ordered comparisons are false if either side is NaN,
+                // and != is unordered so it's true then
+                let instr = if operand_type == Ty::F64 {
+                    match op {
+                        BinOp::Add => "fadd",
+                        BinOp::Sub => "fsub",
+                        BinOp::Mul => "fmul",
+                        BinOp::Div => "fdiv",
+                        BinOp::Rem => "frem",
+                        BinOp::Eq => "fcmp oeq",
+                        BinOp::Ne => "fcmp une",
+                        BinOp::Lt => "fcmp olt",
+                        BinOp::Le => "fcmp ole",
+                        BinOp::Gt => "fcmp ogt",
+                        BinOp::Ge => "fcmp oge",
+                    }
+                } else {
+                    match (op, signed) {
+                        (BinOp::Add, _) => "add",
+                        (BinOp::Sub, _) => "sub",
+                        (BinOp::Mul, _) => "mul",
+                        (BinOp::Div, true) => "sdiv",
+                        (BinOp::Div, false) => "udiv",
+                        (BinOp::Rem, true) => "srem",
+                        (BinOp::Rem, false) => "urem",
+                        (BinOp::Eq, _) => "icmp eq",
+                        (BinOp::Ne, _) => "icmp ne",
+                        (BinOp::Lt, true) => "icmp slt",
+                        (BinOp::Le, true) => "icmp sle",
+                        (BinOp::Gt, true) => "icmp sgt",
+                        (BinOp::Ge, true) => "icmp sge",
+                        (BinOp::Lt, false) => "icmp ult",
+                        (BinOp::Le, false) => "icmp ule",
+                        (BinOp::Gt, false) => "icmp ugt",
+                        (BinOp::Ge, false) => "icmp uge",
+                    }
+                };
+                let temp = self.temp();
+                writeln!(self.output, "  {} = {} {} {}, {}", temp, instr, ty, lhs, rhs)?;
+                self.store(*dst, &temp)
+            },
+            Inst::Cast { dst, src } => {
+                let from = self.function.operand_type(src);
+                let to = self.function.reg_type(*dst);
+                let value = self.value(src)?;
+                let temp = self.temp();
+                match (from, to) {
+                    (Ty::F64, Ty::F64) => return self.store(*dst, &value),
+                    (Ty::F64, Ty::Int(t)) => {
+                        writeln!(self.output, "  {} = call {} {}(double {})", temp, llvm_type(self.module, to), saturating_conversion(t), value)?;
+                        return self.store(*dst, &temp);
+                    },
+                    (Ty::Int(t), Ty::F64) => {
+                        let conversion = if t.is_signed() { "sitofp" } else { "uitofp" };
+                        writeln!(self.output, "  {} = {} {} {} to double", temp, conversion, llvm_type(self.module, from), value)?;
+                        return self.store(*dst, &temp);
+                    },
+                    _ => (),
+                }
+                let (from_bits, extension) = match from {
+                    Ty::Int(t) => (t.bits(), if t.is_signed() { "sext" } else { "zext" }),
+                    _ => (1, "zext"),
+                };
+                let to_bits = match to {
+                    Ty::Int(t) => t.bits(),
+                    _ => unreachable!("Cast to {}", to),
+                };
+                let conversion = match from_bits.cmp(&to_bits) {
+                    std::cmp::Ordering::Equal => return self.store(*dst, &value),
+                    std::cmp::Ordering::Greater => "trunc",
+                    std::cmp::Ordering::Less => extension,
+                };
+                writeln!(self.output, "  {} = {} {} {} to {}", temp, conversion, llvm_type(self.module, from), value, llvm_type(self.module, to))?;
+                self.store(*dst, &temp)
+            },
+            Inst::Call { dst, func, args } => {
+                let temp = self.call("call", func, args)?;
+                self.store(*dst, &temp)
+            },
+            Inst::Checked { dst, op, lhs, rhs, loc } => {
+                let Ty::Int(t) = self.function.reg_type(*dst) else { unreachable!("Checked {} on {}", op, self.function.reg_type(*dst)) };
+                let ty = llvm_type(self.module, Ty::Int(t));
+                let lhs = self.value(lhs)?;
+                let rhs = self.value(rhs)?;
+                let temp = self.temp();
+                if op.divides() {
+                    let zero = self.temp();
+                    writeln!(self.output, "  {} = icmp eq {} {}, 0", zero, ty, rhs)?;
+                    self.trap_if(&zero, DIVISION_BY_ZERO, *loc)?;
+                    if t.is_signed() {
+                        let (minus_one, min, both) = (self.temp(), self.temp(), self.temp());
+                        writeln!(self.output, "  {} = icmp eq {} {}, -1", minus_one, ty, rhs)?;
+                        writeln!(self.output, "  {} = icmp eq {} {}, {}", min, ty, lhs, t.min())?;
+                        writeln!(self.output, "  {} = and i1 {}, {}", both, minus_one, min)?;
+                        self.trap_if(&both, &op.overflow_message(), *loc)?;
+                    }
+                    let instr = match (op, t.is_signed()) {
+                        (BinOp::Div, true) => "sdiv",
+                        (BinOp::Div, false) => "udiv",
+                        (_, true) => "srem",
+                        (_, false) => "urem",
+                    };
+                    writeln!(self.output, "  {} = {} {} {}, {}", temp, instr, ty, lhs, rhs)?;
+                } else {
+                    let (result, overflowed) = (self.temp(), self.temp());
+                    writeln!(self.output, "  {} = call {{{}, i1}} {}({} {}, {} {})", result, ty, overflow_intrinsic(*op, t), ty, lhs, ty, rhs)?;
+                    writeln!(self.output, "  {} = extractvalue {{{}, i1}} {}, 1", overflowed, ty, result)?;
+                    self.trap_if(&overflowed, &op.overflow_message(), *loc)?;
+                    writeln!(self.output, "  {} = extractvalue {{{}, i1}} {}, 0", temp, ty, result)?;
+                }
+                self.store(*dst, &temp)
+            },
+            Inst::Struct { dst, fields } => {
+                let ty = llvm_type(self.module, self.function.reg_type(*dst));
+                let mut aggregate = "undef".to_string();
+                for (i, field) in fields.iter().enumerate() {
+                    let field_ty = llvm_type(self.module, self.function.operand_type(field));
+                    let value = self.value(field)?;
+                    let temp = self.temp();
+                    writeln!(self.output, "  {} = insertvalue {} {}, {} {}, {}", temp, ty, aggregate, field_ty, value, i)?;
+                    aggregate = temp;
+                }
+                self.store(*dst, &aggregate)
+            },
+            Inst::Field { dst, src, index } => {
+                let ty = llvm_type(self.module, self.function.operand_type(src));
+                let value = self.value(src)?;
+                let temp = self.temp();
+                writeln!(self.output, "  {} = extractvalue {} {}, {}", temp, ty, value, index)?;
+                self.store(*dst, &temp)
+            },
+            Inst::Variant { dst, variant, fields } => {
+                let Ty::Enum(id) = self.function.reg_type(*dst) else { unreachable!("Variant of type {}", self.function.reg_type(*dst)) };
+                // Everything has to be read before `dst` gets written, in
+                // case it's one of the fields
+                let mut values = vec![];
+                for field in fields {
+                    values.push((llvm_type(self.module, self.function.operand_type(field)), self.value(field)?));
+                }
+                let ty = llvm_type(self.module, Ty::Enum(id));
+                let tag = self.temp();
+                writeln!(self.output, "  {} = getelementptr {}, {}* %r{}, i32 0, i32 0", tag, ty, ty, dst.0)?;
+                writeln!(self.output, "  store i32 {}, i32* {}", variant, tag)?;
+                let payload = self.payload(*dst, id, *variant)?;
+                let payload_ty = payload_type(self.module, id, *variant);
+                for (i, (field_ty, value)) in values.iter().enumerate() {
+                    let field = self.temp();
+                    writeln!(self.output, "  {} = getelementptr {}, {}* {}, i32 0, i32 {}", field, payload_ty, payload_ty, payload, i)?;
+                    writeln!(self.output, "  store {} {}, {}* {}", field_ty, value, field_ty, field)?;
+                }
+                Ok(())
+            },
+            Inst::Tag { dst, src } => {
+                let Operand::Reg(src) = src else { unreachable!("Tag of constant {}", src) };
+                let ty = llvm_type(self.module, self.function.reg_type(*src));
+                let (tag, temp) = (self.temp(), self.temp());
+                writeln!(self.output, "  {} = getelementptr {}, {}* %r{}, i32 0, i32 0", tag, ty, ty, src.0)?;
+                writeln!(self.output, "  {} = load i32, i32* {}", temp, tag)?;
+                self.store(*dst, &temp)
+            },
+            Inst::Payload { dst, src, variant, index } => {
+                let Operand::Reg(src) = src else { unreachable!("Payload of constant {}", src) };
+                let Ty::Enum(id) = self.function.reg_type(*src) else { unreachable!("Payload of a {}", self.function.reg_type(*src)) };
+                let payload = self.payload(*src, id, *variant)?;
+                let payload_ty = payload_type(self.module, id, *variant);
+                let field_ty = llvm_type(self.module, self.function.reg_type(*dst));
+                let (field, temp) = (self.temp(), self.temp());
+                writeln!(self.output, "  {} = getelementptr {}, {}* {}, i32 0, i32 {}", field, payload_ty, payload_ty, payload, index)?;
+                writeln!(self.output, "  {} = load {}, {}* {}", temp, field_ty, field_ty, field)?;
+                self.store(*dst, &temp)
+            },
+            Inst::Print { dst, src } => {
+                let ty = self.function.operand_type(src);
+                let value = self.value(src)?;
+                let (format, arg) = match ty {
+                    Ty::F64 => {
+                        writeln!(self.output, "  call void @huckrt_print_f64(double {})", value)?;
+                        return self.store(*dst, "zeroinitializer")
+                    },
+                    Ty::Bool => {
+                        let text = self.temp();
+                        writeln!(self.output, "  {} = select i1 {}, {}, {}", text, value, print_format("@print_true"), print_format("@print_false"))?;
+                        (print_format("@print_string"), format!("i8* {}", text))
+                    },
+                    // printf wants the whole 64 bits
+                    Ty::Int(t) => {
+                        let format = print_format(if t.is_signed() { "@print_signed" } else { "@print_unsigned" });
+                        if t.bits() == 64 {
+                            (format, format!("i64 {}", value))
+                        } else {
+                            let wide = self.temp();
+                            let conversion = if t.is_signed() { "sext" } else { "zext" };
+                            writeln!(self.output, "  {} = {} i{} {} to i64", wide, conversion, t.bits(), value)?;
+                            (format, format!("i64 {}", wide))
+                        }
+                    },
+                    _ => unreachable!("print of a {}", ty),
+                };
+                let printed = self.temp();
+                writeln!(self.output, "  {} = call i32 (i8*, ...) @printf({}, {})", printed, format, arg)?;
+                self.store(*dst, "zeroinitializer")
+            },
+            Inst::Dyn { dst, src, vtable } => {
+                let src_type = self.function.operand_type(src);
+                let ty = llvm_type(self.module, src_type);
+                let value = self.value(src)?;
+                let (memory, typed, data, both) = (self.temp(), self.temp(), self.temp(), self.temp());
+                let size = size_align(self.module, src_type).0.max(1);
+                writeln!(self.output, "  {} = call i8* @malloc(i64 {})", memory, size)?;
+                writeln!(self.output, "  {} = bitcast i8* {} to {}*", typed, memory, ty)?;
+                writeln!(self.output, "  store {} {}, {}* {}", ty, value, ty, typed)?;
+                writeln!(self.output, "  {} = insertvalue {} undef, i8* {}, 0", data, DYN_TYPE, memory)?;
+                let table_type = vtable_type(&self.module.vtables[vtable.0]);
+                let table = format!("bitcast ({}* {} to i8*)", table_type, vtable_name(*vtable));
+                writeln!(self.output, "  {} = insertvalue {} {}, i8* {}, 1", both, DYN_TYPE, data, table)?;
+                self.store(*dst, &both)
+            },
+            Inst::DynCall { dst, method, args } => {
+                let receiver = self.value(&args[0])?;
+                let mut params = vec![];
+                let mut values = vec![];
+                for arg in &args[1..] {
+                    let ty = llvm_type(self.module, self.function.operand_type(arg));
+                    values.push(format!("{} {}", ty, self.value(arg)?));
+                    params.push(ty);
+                }
+                let ret = llvm_type(self.module, self.function.reg_type(*dst));
+                let fn_type = dyn_method_type(&ret, &params);
+                let (data, table, table_ptr, slot, entry, callee, temp) =
+                    (self.temp(), self.temp(), self.temp(), self.temp(), self.temp(), self.temp(), self.temp());
+                writeln!(self.output, "  {} = extractvalue {} {}, 0", data, DYN_TYPE, receiver)?;
+                writeln!(self.output, "  {} = extractvalue {} {}, 1", table, DYN_TYPE, receiver)?;
+                writeln!(self.output, "  {} = bitcast i8* {} to i8**", table_ptr, table)?;
+                writeln!(self.output, "  {} = getelementptr i8*, i8** {}, i64 {}", slot, table_ptr, method)?;
+                writeln!(self.output, "  {} = load i8*, i8** {}", entry, slot)?;
+                writeln!(self.output, "  {} = bitcast i8* {} to {}*", callee, entry, fn_type)?;
+                let args = [format!("i8* {}", data)].into_iter().chain(values).collect::<Vec<_>>();
+                writeln!(self.output, "  {} = call {} {}({})", temp, ret, callee, args.join(", "))?;
+                self.store(*dst, &temp)
+            },
+        }
+    }
+
+    // A pointer to the payload of enum register `reg` as `variant`'s struct
+    fn payload(&mut self, reg: Reg, id: EnumId, variant: usize) -> CompileResult<String> {
+        let ty = llvm_type(self.module, Ty::Enum(id));
+        let array = format!("[{} x i64]", payload_words(self.module, id));
+        let (words, payload) = (self.temp(), self.temp());
+        writeln!(self.output, "  {} = getelementptr {}, {}* %r{}, i32 0, i32 1", words, ty, ty, reg.0)?;
+        writeln!(self.output, "  {} = bitcast {}* {} to {}*", payload, array, words, payload_type(self.module, id, variant))?;
+        Ok(payload)
+    }
+
+    // Branch off to `huckrt_panic` if `cond` is true, carrying on in a
+    // fresh block otherwise
+    fn trap_if(&mut self, cond: &str, message: &str, loc: Loc) -> CompileResult<()> {
+        let label = self.temp();
+        let label = &label[1..];
+        writeln!(self.output, "  br i1 {}, label %{}.trap, label %{}.ok", cond, label, label)?;
+        writeln!(self.output, "{}.trap:", label)?;
+        self.panic(message, loc)?;
+        writeln!(self.output, "{}.ok:", label)
+    }
+
+    fn panic(&mut self, message: &str, loc: Loc) -> CompileResult<()> {
+        let index = self.messages.iter().position(|m| m == message)
+            .unwrap_or_else(|| panic!("Unknown panic message {}", message));
+        let (_, pointer) = string_constant(&message_name(index), message);
+        writeln!(self.output, "  call void @huckrt_panic({}, i64 {}, i64 {})", pointer, loc.line, loc.col)?;
+        writeln!(self.output, "  unreachable")
+    }
+
+    fn callee(&self, func: &str) -> &'a Function {
+        self.module.functions.iter()
+            .find(|f| f.name == func)
+            .unwrap_or_else(|| panic!("Call to unknown function {}", func))
+    }
+
+    fn call(&mut self, kind: &str, func: &str, args: &[Operand]) -> CompileResult<String> {
+        let callee = self.callee(func);
+        let mut values = vec![];
+        for (arg, param) in args.iter().zip(&callee.params) {
+            let value = self.value(arg)?;
+            values.push(format!("{} {}", llvm_type(self.module, callee.reg_type(*param)), value));
+        }
+        let temp = self.temp();
+        writeln!(
+            self.output,
+            "  {} = {} {} {}({})",
+            temp,
+            kind,
+            llvm_type(self.module, callee.ret),
+            symbol(func),
+            values.join(", ")
+        )?;
+        Ok(temp)
+    }
+
+    fn terminator(&mut self, term: &Terminator) -> CompileResult<()> {
+        match term {
+            Terminator::Jump(target) => writeln!(self.output, "  br label %{}", target),
+            Terminator::Branch { cond, then_block, else_block } => {
+                let cond = self.value(cond)?;
+                writeln!(self.output, "  br i1 {}, label %{}, label %{}", cond, then_block, else_block)
+            },
+            Terminator::Switch { value, cases, default } => {
+                let ty = llvm_type(self.module, self.function.operand_type(value));
+                let value = self.value(value)?;
+                let mut targets = vec![];
+                for (case, block) in cases {
+                    targets.push(format!("{} {}, label %{}", ty, self.value(&Operand::Const(*case))?, block));
+                }
+                writeln!(self.output, "  switch {} {}, label %{} [ {} ]", ty, value, default, targets.join(" "))
+            },
+            Terminator::NoMatch(loc) => self.panic(NO_MATCH, *loc),
+            Terminator::Return(value) => {
+                let ty = llvm_type(self.module, self.function.ret);
+                let value = self.value(value)?;
+                writeln!(self.output, "  ret {} {}", ty, value)
+            },
+            Terminator::TailCall { func, args } => {
+                let callee = self.callee(func);
+                let same_signature = callee.params.iter().map(|p| callee.reg_type(*p))
+                    .eq(self.function.params.iter().map(|p| self.function.reg_type(*p)));
+                // Structs may be passed in memory, which LLVM can't always
+                // reuse for the callee
+                let has_structs = callee.params.iter().map(|p| callee.reg_type(*p))
+                    .chain([callee.ret])
+                    .any(|ty| matches!(ty, Ty::Struct(_) | Ty::Enum(_) | Ty::Dyn));
+                let kind = if same_signature && !has_structs { "musttail call" } else { "tail call" };
+                let temp = self.call(kind, func, args)?;
+                writeln!(self.output, "  ret {} {}", llvm_type(self.module, self.function.ret), temp)
+            },
+        }
+    }
+}
@@ -0,0 +1,1141 @@
+use crate::diagnostic::line_col;
+use crate::ir::{
+    BinOp, Block, BlockId, Const, EnumDef, EnumId, Function, Inst, Loc, Module, Operand, Reg, StructDef, StructId, Terminator, Ty,
+    VTableDef, VTableId,
+};
+use crate::matching::{self, pattern_bindings, Case, Decision, Path, Step};
+use crate::mono::{impl_method, is_generic};
+use crate::parser::{Builtin, HuckAst, MatchArm};
+use crate::typecheck::{negated_literal, CheckOutput, EnumType, IntType, StructType, TraitType, TypeInfo, Typed};
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+type LowerInput = CheckOutput;
+
+// `source` is what the program was checked from, so that runtime errors
+// can say where they happened
+pub fn lower(ast: &LowerInput, source: &str) -> Module {
+    let mut lowerer = Lowerer::new(source);
+    match lowerer.lower_type(ast.ty()) {
+        // A struct or enum can't be an exit code, so a program whose value
+        // is one ends as if it were ()
+        Ty::Struct(_) | Ty::Enum(_) | Ty::Dyn => {
+            lowerer.expr(ast);
+            lowerer.builder.terminate(Terminator::Return(Operand::Const(Const::Unit)));
+            let main = std::mem::replace(&mut lowerer.builder, FunctionBuilder::new()).finish("main", Ty::Unit);
+            lowerer.functions.push(main);
+        },
+        ret => lowerer.function("main", &[], ast, ret),
+    }
+
+    // Nested functions finish first; keep the entry point up front
+    let mut functions = lowerer.functions;
+    functions.rotate_right(1);
+    Module { structs: lowerer.structs, enums: lowerer.enums, vtables: lowerer.vtables, functions }
+}
+
+// A block that may still be missing its terminator
+struct PartialBlock {
+    insts: Vec<Inst>,
+    term: Option<Terminator>,
+}
+
+struct FunctionBuilder {
+    params: Vec<Reg>,
+    regs: Vec<Ty>,
+    blocks: Vec<PartialBlock>,
+    current: BlockId,
+    scopes: Vec<HashMap<String, Reg>>,
+    // The registers of `let mut` variables, which are written again by
+    // each assignment
+    mutable: HashSet<Reg>,
+    loops: Vec<LoopTarget>,
+}
+
+// Where a loop's `break`s and `continue`s go, and the register a `loop`
+// keeps the value it breaks with in
+struct LoopTarget {
+    label: Option<String>,
+    continue_block: BlockId,
+    break_block: BlockId,
+    result: Option<Reg>,
+}
+
+impl FunctionBuilder {
+    fn new() -> Self {
+        Self {
+            params: vec![],
+            regs: vec![],
+            blocks: vec![PartialBlock { insts: vec![], term: None }],
+            current: BlockId(0),
+            scopes: vec![HashMap::new()],
+            mutable: HashSet::new(),
+            loops: vec![],
+        }
+    }
+
+    fn finish(self, name: &str, ret: Ty) -> Function {
+        let blocks = self.blocks.into_iter()
+            .map(|b| Block {
+                insts: b.insts,
+                term: b.term.expect("Unterminated block in IR lowering"),
+            })
+            .collect();
+
+        Function {
+            name: name.to_string(),
+            params: self.params,
+            ret,
+            regs: self.regs,
+            blocks,
+        }
+    }
+
+    fn new_reg(&mut self, ty: Ty) -> Reg {
+        self.regs.push(ty);
+        Reg(self.regs.len() - 1)
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(PartialBlock { insts: vec![], term: None });
+        BlockId(self.blocks.len() - 1)
+    }
+
+    fn switch_to(&mut self, block: BlockId) {
+        self.current = block;
+    }
+
+    fn emit(&mut self, inst: Inst) {
+        self.blocks[self.current.0].insts.push(inst);
+    }
+
+    fn terminate(&mut self, term: Terminator) {
+        self.blocks[self.current.0].term = Some(term);
+    }
+
+    fn bind(&mut self, ident: &str, reg: Reg) {
+        self.scopes.last_mut().unwrap().insert(ident.to_string(), reg);
+    }
+
+    fn lookup(&self, ident: &str) -> Reg {
+        self.scopes.iter().rev()
+            .find_map(|scope| scope.get(ident).copied())
+            .unwrap_or_else(|| panic!("Unbound variable {:?} survived type checking", ident))
+    }
+
+    // The innermost loop, or the one with the label
+    fn loop_target(&self, label: &Option<String>) -> &LoopTarget {
+        self.loops.iter().rev()
+            .find(|target| label.is_none() || target.label == *label)
+            .unwrap_or_else(|| panic!("Jump to missing loop {:?} survived type checking", label))
+    }
+}
+
+struct Lowerer<'a> {
+    source: &'a str,
+    functions: Vec<Function>,
+    builder: FunctionBuilder,
+    // Huck function names in scope, mapped to their unique IR names
+    fn_scopes: Vec<HashMap<String, String>>,
+    symbols: HashSet<String>,
+    structs: Vec<StructDef>,
+    // The IR struct for each checker struct type, by id and type
+    // arguments, since each instance of a generic struct is its own type
+    struct_ids: HashMap<(usize, Vec<TypeInfo>), StructId>,
+    enums: Vec<EnumDef>,
+    enum_ids: HashMap<(usize, Vec<TypeInfo>), EnumId>,
+    vtables: Vec<VTableDef>,
+    // The vtable for each type cast to a `dyn`, by trait id and type
+    vtable_ids: HashMap<(usize, TypeInfo), VTableId>,
+}
+
+// Where the value of a match arm goes: into a register before carrying
+// on after the match, or back to the caller
+#[derive(Clone, Copy)]
+enum ArmExit {
+    Join(Reg, BlockId),
+    Tail,
+}
+
+// A match being lowered. Every leaf of its decision tree that picks the
+// same arm shares one copy of that arm's body, with the bindings in
+// registers of its own.
+struct MatchLowering<'m> {
+    arms: &'m [MatchArm<Typed>],
+    bindings: Vec<HashMap<String, Reg>>,
+    bodies: Vec<Option<BlockId>>,
+    exit: ArmExit,
+    loc: Loc,
+}
+
+impl<'a> Lowerer<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            functions: vec![],
+            builder: FunctionBuilder::new(),
+            fn_scopes: vec![HashMap::new()],
+            symbols: HashSet::from(["main".to_string()]),
+            structs: vec![],
+            struct_ids: HashMap::new(),
+            enums: vec![],
+            enum_ids: HashMap::new(),
+            vtables: vec![],
+            vtable_ids: HashMap::new(),
+        }
+    }
+
+    fn lower_type(&mut self, t: &TypeInfo) -> Ty {
+        match t {
+            TypeInfo::Unit | TypeInfo::Never => Ty::Unit,
+            TypeInfo::Bool => Ty::Bool,
+            TypeInfo::Int(t) => Ty::Int(*t),
+            TypeInfo::F64 => Ty::F64,
+            TypeInfo::Struct(struct_type) => Ty::Struct(self.struct_id(struct_type)),
+            TypeInfo::Enum(enum_type) => Ty::Enum(self.enum_id(enum_type)),
+            TypeInfo::Fn(..) | TypeInfo::Generic(..) => panic!("Functions aren't values and have no IR type"),
+            TypeInfo::Param(name) => panic!("Type parameter {} survived monomorphization", name),
+            TypeInfo::Dyn(_) => Ty::Dyn,
+        }
+    }
+
+    fn new_reg(&mut self, t: &TypeInfo) -> Reg {
+        let ty = self.lower_type(t);
+        self.builder.new_reg(ty)
+    }
+
+    // Structs are added to the module the first time they're used
+    fn struct_id(&mut self, struct_type: &Rc<StructType>) -> StructId {
+        let key = (struct_type.id, struct_type.args.clone());
+        if let Some(id) = self.struct_ids.get(&key) {
+            return *id;
+        }
+        let fields = struct_type.fields.iter()
+            .map(|(name, t)| (name.clone(), self.lower_type(t)))
+            .collect();
+        let id = StructId(self.structs.len());
+        let name = TypeInfo::Struct(struct_type.clone()).to_string();
+        self.structs.push(StructDef { name, fields });
+        self.struct_ids.insert(key, id);
+        id
+    }
+
+    fn enum_id(&mut self, enum_type: &Rc<EnumType>) -> EnumId {
+        let key = (enum_type.id, enum_type.args.clone());
+        if let Some(id) = self.enum_ids.get(&key) {
+            return *id;
+        }
+        let variants = enum_type.variants.iter()
+            .map(|(name, payload)| (name.clone(), payload.iter().map(|t| self.lower_type(t)).collect()))
+            .collect();
+        let id = EnumId(self.enums.len());
+        let name = TypeInfo::Enum(enum_type.clone()).to_string();
+        self.enums.push(EnumDef { name, variants });
+        self.enum_ids.insert(key, id);
+        id
+    }
+
+    // Vtables are added to the module the first time a type is cast to the
+    // trait's `dyn`. The functions for the methods are the impl's, which
+    // are declared at the top level, so they're in scope wherever that is.
+    fn vtable_id(&mut self, trait_type: &TraitType, ty: &TypeInfo) -> VTableId {
+        let key = (trait_type.id, ty.clone());
+        if let Some(id) = self.vtable_ids.get(&key) {
+            return *id;
+        }
+        let methods = trait_type.methods.iter()
+            .map(|(method, _)| {
+                let name = impl_method(&trait_type.name, ty, method);
+                self.fn_symbol(&name).unwrap_or_else(|| panic!("No function for {}", name))
+            })
+            .collect();
+        let id = VTableId(self.vtables.len());
+        let name = format!("dyn {} for {}", trait_type.name, ty);
+        let ty = self.lower_type(ty);
+        self.vtables.push(VTableDef { name, ty, methods });
+        self.vtable_ids.insert(key, id);
+        id
+    }
+
+    fn loc(&self, span: crate::diagnostic::Span) -> Loc {
+        let (line, col) = line_col(self.source, span.start);
+        Loc { line, col }
+    }
+
+    fn function(&mut self, name: &str, params: &[(String, Ty)], body: &LowerInput, ret: Ty) {
+        let outer = std::mem::replace(&mut self.builder, FunctionBuilder::new());
+
+        for (param, ty) in params {
+            let reg = self.builder.new_reg(*ty);
+            self.builder.params.push(reg);
+            self.builder.bind(param, reg);
+        }
+        self.tail(body);
+
+        let finished = std::mem::replace(&mut self.builder, outer).finish(name, ret);
+        self.functions.push(finished);
+    }
+
+    // Shadowed or repeated function names get a numeric suffix
+    fn declare_fn(&mut self, ident: &str) -> String {
+        let mut symbol = ident.to_string();
+        let mut n = 0;
+        while self.symbols.contains(&symbol) {
+            n += 1;
+            symbol = format!("{}.{}", ident, n);
+        }
+        self.symbols.insert(symbol.clone());
+        self.fn_scopes.last_mut().unwrap().insert(ident.to_string(), symbol.clone());
+        symbol
+    }
+
+    fn fn_symbol(&self, ident: &str) -> Option<String> {
+        self.fn_scopes.iter().rev().find_map(|scope| scope.get(ident).cloned())
+    }
+
+    fn begin_block(&mut self, exprs: &[LowerInput]) {
+        self.builder.scopes.push(HashMap::new());
+        self.fn_scopes.push(HashMap::new());
+
+        // Mirror the checker: functions are visible throughout their block
+        for expr in exprs {
+            if let HuckAst::Let(ident, _, init_expr, _) = expr {
+                if let HuckAst::Fn(..) = init_expr.as_ref() {
+                    if is_generic(init_expr) {
+                        continue;
+                    }
+                    self.declare_fn(ident);
+                }
+            }
+        }
+    }
+
+    fn end_block(&mut self) {
+        self.builder.scopes.pop();
+        self.fn_scopes.pop();
+    }
+
+    fn binary(&mut self, op: BinOp, lhs: &LowerInput, rhs: &LowerInput, t: &Typed) -> Operand {
+        let lhs = self.expr(lhs);
+        let rhs = self.expr(rhs);
+        let dst = self.new_reg(&t.ty);
+        self.builder.emit(Inst::Binary { dst, op, lhs, rhs });
+        Operand::Reg(dst)
+    }
+
+    // Integer arithmetic fails at runtime if it overflows
+    fn arithmetic(&mut self, op: BinOp, lhs: &LowerInput, rhs: &LowerInput, t: &Typed) -> Operand {
+        if t.ty == TypeInfo::F64 {
+            return self.binary(op, lhs, rhs, t);
+        }
+        let lhs = self.expr(lhs);
+        let rhs = self.expr(rhs);
+        self.checked(op, lhs, rhs, t)
+    }
+
+    // `wrapping_rem` is `%` except when dividing by -1, which always
+    // leaves nothing over and is the one divisor that can overflow
+    fn wrapping_rem(&mut self, lhs: &LowerInput, rhs: &LowerInput, t: &Typed) -> Operand {
+        let TypeInfo::Int(int_type) = t.ty else { unreachable!("wrapping_rem of type {}", t.ty) };
+        let lhs = self.expr(lhs);
+        let rhs = self.expr(rhs);
+        if !int_type.is_signed() {
+            return self.checked(BinOp::Rem, lhs, rhs, t);
+        }
+        let minus_one = self.builder.new_reg(Ty::Bool);
+        self.builder.emit(Inst::Binary { dst: minus_one, op: BinOp::Eq, lhs: rhs, rhs: Operand::Const(Const::Int(-1, int_type)) });
+        let zero_block = self.builder.new_block();
+        let rem_block = self.builder.new_block();
+        let join = self.builder.new_block();
+        self.builder.terminate(Terminator::Branch { cond: Operand::Reg(minus_one), then_block: zero_block, else_block: rem_block });
+
+        let dst = self.new_reg(&t.ty);
+        self.builder.switch_to(zero_block);
+        self.builder.emit(Inst::Copy { dst, src: Operand::Const(Const::Int(0, int_type)) });
+        self.builder.terminate(Terminator::Jump(join));
+        self.builder.switch_to(rem_block);
+        let loc = self.loc(t.span);
+        self.builder.emit(Inst::Checked { dst, op: BinOp::Rem, lhs, rhs, loc });
+        self.builder.terminate(Terminator::Jump(join));
+        self.builder.switch_to(join);
+        Operand::Reg(dst)
+    }
+
+    fn checked(&mut self, op: BinOp, lhs: Operand, rhs: Operand, t: &Typed) -> Operand {
+        let loc = self.loc(t.span);
+        let dst = self.new_reg(&t.ty);
+        self.builder.emit(Inst::Checked { dst, op, lhs, rhs, loc });
+        Operand::Reg(dst)
+    }
+
+    fn args(&mut self, args: &[LowerInput]) -> Vec<Operand> {
+        args.iter().map(|arg| self.expr(arg)).collect()
+    }
+
+    fn fn_decl(&mut self, ident: &str, init_expr: &LowerInput) {
+        let HuckAst::Fn(_, params, _, body, Typed { ty: TypeInfo::Fn(param_types, ret), .. }) = init_expr else {
+            unreachable!("fn_decl called on a non-function")
+        };
+        let symbol = match self.fn_symbol(ident) {
+            Some(symbol) => symbol,
+            None => self.declare_fn(ident),
+        };
+        let params = params.iter()
+            .zip(param_types)
+            .map(|((name, _), t)| (name.to_string(), self.lower_type(t)))
+            .collect::<Vec<_>>();
+        let ret = self.lower_type(ret);
+        self.function(&symbol, &params, body, ret);
+    }
+
+    // Lower an expression whose value is the function's result, ending
+    // the current block. Calls in this position become tail calls.
+    fn tail(&mut self, ast: &LowerInput) {
+        match ast {
+            HuckAst::Call(ident, _, args, _) => {
+                let args = self.args(args);
+                let func = self.fn_symbol(ident).expect("Call to undeclared function");
+                self.builder.terminate(Terminator::TailCall { func, args });
+            },
+            HuckAst::If(test_expr, then_expr, else_expr, _) => {
+                let cond = self.expr(test_expr);
+                let then_block = self.builder.new_block();
+                let else_block = self.builder.new_block();
+                self.builder.terminate(Terminator::Branch { cond, then_block, else_block });
+
+                self.builder.switch_to(then_block);
+                self.tail(then_expr);
+                self.builder.switch_to(else_block);
+                self.tail(else_expr);
+            },
+            HuckAst::Block(exprs, _) if !exprs.is_empty() => {
+                self.begin_block(exprs);
+                let (last, init) = exprs.split_last().unwrap();
+                for expr in init {
+                    self.expr(expr);
+                }
+                self.tail(last);
+                self.end_block();
+            },
+            HuckAst::Match(scrutinee, arms, t) => {
+                let value = self.expr(scrutinee);
+                self.lower_match(value, arms, t, ArmExit::Tail);
+            },
+            _ => {
+                let value = self.expr(ast);
+                self.builder.terminate(Terminator::Return(value));
+            },
+        }
+    }
+
+    // Nothing ever uses what something that never finishes gives, so
+    // rather than a register of the wrong type it's always ()
+    fn expr(&mut self, ast: &LowerInput) -> Operand {
+        let value = self.value(ast);
+        match ast.ty() {
+            TypeInfo::Never => Operand::Const(Const::Unit),
+            _ => value,
+        }
+    }
+
+    fn value(&mut self, ast: &LowerInput) -> Operand {
+        match ast {
+            HuckAst::Num(n, _, Typed { ty, .. }) => {
+                let TypeInfo::Int(t) = ty else { unreachable!("Number of type {}", ty) };
+                Operand::Const(Const::Int(t.wrap(*n as i64), *t))
+            },
+            HuckAst::Float(x, _) => Operand::Const(Const::Float(*x)),
+            HuckAst::Neg(operand, _) if negated_literal(operand).is_some() => {
+                let (n, t) = negated_literal(operand).unwrap();
+                Operand::Const(Const::Int(n, t))
+            },
+            HuckAst::Neg(operand, t @ Typed { ty: TypeInfo::Int(int_type), .. }) => {
+                let zero = Operand::Const(Const::Int(0, *int_type));
+                let rhs = self.expr(operand);
+                self.checked(BinOp::Sub, zero, rhs, t)
+            },
+            // Subtracting from -0.0 flips the sign of zero too
+            HuckAst::Neg(operand, Typed { ty: TypeInfo::F64, .. }) => {
+                let rhs = self.expr(operand);
+                let dst = self.builder.new_reg(Ty::F64);
+                self.builder.emit(Inst::Binary { dst, op: BinOp::Sub, lhs: Operand::Const(Const::Float(-0.0)), rhs });
+                Operand::Reg(dst)
+            },
+            HuckAst::Neg(_, t) => unreachable!("Negating a {}", t.ty),
+            // Already a `dyn` of the same trait
+            HuckAst::Cast(operand, _, t) if *operand.ty() == t.ty => self.expr(operand),
+            HuckAst::Cast(operand, _, t @ Typed { ty: TypeInfo::Dyn(trait_type), .. }) => {
+                let src = self.expr(operand);
+                let vtable = self.vtable_id(trait_type, operand.ty());
+                let dst = self.new_reg(&t.ty);
+                self.builder.emit(Inst::Dyn { dst, src, vtable });
+                Operand::Reg(dst)
+            },
+            HuckAst::Cast(operand, _, t) => {
+                let src = self.expr(operand);
+                let dst = self.new_reg(&t.ty);
+                self.builder.emit(Inst::Cast { dst, src });
+                Operand::Reg(dst)
+            },
+            HuckAst::BoolLit(b, _) => Operand::Const(Const::Bool(*b)),
+            HuckAst::Plus(lhs, rhs, t) => self.arithmetic(BinOp::Add, lhs, rhs, t),
+            HuckAst::Minus(lhs, rhs, t) => self.arithmetic(BinOp::Sub, lhs, rhs, t),
+            HuckAst::Times(lhs, rhs, t) => self.arithmetic(BinOp::Mul, lhs, rhs, t),
+            HuckAst::Div(lhs, rhs, t) => self.arithmetic(BinOp::Div, lhs, rhs, t),
+            HuckAst::Rem(lhs, rhs, t) => self.arithmetic(BinOp::Rem, lhs, rhs, t),
+            HuckAst::Equals(lhs, rhs, t) => self.binary(BinOp::Eq, lhs, rhs, t),
+            HuckAst::NotEquals(lhs, rhs, t) => self.binary(BinOp::Ne, lhs, rhs, t),
+            HuckAst::Less(lhs, rhs, t) => self.binary(BinOp::Lt, lhs, rhs, t),
+            HuckAst::LessEq(lhs, rhs, t) => self.binary(BinOp::Le, lhs, rhs, t),
+            HuckAst::Greater(lhs, rhs, t) => self.binary(BinOp::Gt, lhs, rhs, t),
+            HuckAst::GreaterEq(lhs, rhs, t) => self.binary(BinOp::Ge, lhs, rhs, t),
+            // Only the instances monomorphization made of it are called
+            HuckAst::Let(_, _, init_expr, _) if is_generic(init_expr) => Operand::Const(Const::Unit),
+            HuckAst::Let(ident, _, init_expr, _) if matches!(init_expr.as_ref(), HuckAst::Fn(..)) => {
+                self.fn_decl(ident, init_expr);
+                Operand::Const(Const::Unit)
+            },
+            HuckAst::Let(ident, mutable, init_expr, t) => {
+                let src = self.expr(init_expr);
+                let dst = self.new_reg(&t.ty);
+                self.builder.emit(Inst::Copy { dst, src });
+                self.builder.bind(ident, dst);
+                if *mutable {
+                    self.builder.mutable.insert(dst);
+                }
+                Operand::Reg(dst)
+            },
+            // A mutable variable is read into a register of its own, in
+            // case something later in the expression assigns to it
+            HuckAst::VarRef(ident, t) => {
+                let reg = self.builder.lookup(ident);
+                if !self.builder.mutable.contains(&reg) {
+                    return Operand::Reg(reg);
+                }
+                let dst = self.new_reg(&t.ty);
+                self.builder.emit(Inst::Copy { dst, src: Operand::Reg(reg) });
+                Operand::Reg(dst)
+            },
+            HuckAst::Assign(ident, _, value, _) => {
+                let src = self.expr(value);
+                let dst = self.builder.lookup(ident);
+                self.builder.emit(Inst::Copy { dst, src });
+                Operand::Const(Const::Unit)
+            },
+            HuckAst::Block(exprs, _) => {
+                self.begin_block(exprs);
+                let mut last = Operand::Const(Const::Unit);
+                for expr in exprs {
+                    last = self.expr(expr);
+                }
+                self.end_block();
+                last
+            },
+            HuckAst::If(test_expr, then_expr, else_expr, t) => {
+                let cond = self.expr(test_expr);
+                let result = self.new_reg(&t.ty);
+                let then_block = self.builder.new_block();
+                let else_block = self.builder.new_block();
+                let join_block = self.builder.new_block();
+                self.builder.terminate(Terminator::Branch { cond, then_block, else_block });
+
+                for (block, branch) in [(then_block, then_expr), (else_block, else_expr)] {
+                    self.builder.switch_to(block);
+                    let src = self.expr(branch);
+                    self.builder.emit(Inst::Copy { dst: result, src });
+                    self.builder.terminate(Terminator::Jump(join_block));
+                }
+
+                self.builder.switch_to(join_block);
+                Operand::Reg(result)
+            },
+            HuckAst::Fn(..) => unreachable!("Bare function expression survived type checking"),
+            HuckAst::Call(ident, _, args, t) => {
+                let args = self.args(args);
+                let func = self.fn_symbol(ident).expect("Call to undeclared function");
+                let dst = self.new_reg(&t.ty);
+                self.builder.emit(Inst::Call { dst, func, args });
+                Operand::Reg(dst)
+            },
+            HuckAst::Builtin(Builtin::Print, args, t) => {
+                let src = self.expr(&args[0]);
+                let dst = self.new_reg(&t.ty);
+                self.builder.emit(Inst::Print { dst, src });
+                Operand::Reg(dst)
+            },
+            HuckAst::Builtin(builtin, args, t) => {
+                let [lhs, rhs] = args.as_slice() else { unreachable!("{} takes two arguments", builtin.name()) };
+                let op = match builtin {
+                    Builtin::WrappingAdd => BinOp::Add,
+                    Builtin::WrappingSub => BinOp::Sub,
+                    Builtin::WrappingMul => BinOp::Mul,
+                    Builtin::WrappingRem => return self.wrapping_rem(lhs, rhs, t),
+                    Builtin::Print => unreachable!(),
+                };
+                self.binary(op, lhs, rhs, t)
+            },
+            HuckAst::Struct(..) | HuckAst::Trait(..) | HuckAst::Impl(..) => Operand::Const(Const::Unit),
+            // The only method calls monomorphization leaves are on a `dyn`
+            HuckAst::MethodCall(receiver, method, args, _, t) => {
+                let TypeInfo::Dyn(trait_type) = receiver.ty() else {
+                    unreachable!("Method call on {} survived monomorphization", receiver.ty())
+                };
+                let method = trait_type.methods.iter().position(|(name, _)| name == method).expect("Method from another trait");
+                let receiver = self.expr(receiver);
+                let args = [receiver].into_iter().chain(self.args(args)).collect();
+                let dst = self.new_reg(&t.ty);
+                self.builder.emit(Inst::DynCall { dst, method, args });
+                Operand::Reg(dst)
+            },
+            // Fields are evaluated in the order they're written
+            HuckAst::StructLit(_, _, fields, base, t) => {
+                let TypeInfo::Struct(struct_type) = &t.ty else { unreachable!("Struct literal of type {}", t.ty) };
+                let mut values = vec![None; struct_type.fields.len()];
+                for (name, value) in fields {
+                    let (index, _) = struct_type.field(name).expect("Unknown field survived type checking");
+                    values[index] = Some(self.expr(value));
+                }
+                let base = base.as_ref().map(|base| self.expr(base));
+                let fields = values.into_iter()
+                    .enumerate()
+                    .map(|(index, value)| value.unwrap_or_else(|| {
+                        let src = base.expect("Missing field survived type checking");
+                        let dst = self.new_reg(&struct_type.fields[index].1);
+                        self.builder.emit(Inst::Field { dst, src, index });
+                        Operand::Reg(dst)
+                    }))
+                    .collect();
+                let dst = self.new_reg(&t.ty);
+                self.builder.emit(Inst::Struct { dst, fields });
+                Operand::Reg(dst)
+            },
+            HuckAst::Tuple(elements, t) => {
+                let fields = self.args(elements);
+                let dst = self.new_reg(&t.ty);
+                self.builder.emit(Inst::Struct { dst, fields });
+                Operand::Reg(dst)
+            },
+            HuckAst::Field(operand, field, t) => {
+                let TypeInfo::Struct(struct_type) = operand.ty() else { unreachable!("Field of a {}", operand.ty()) };
+                let (index, _) = struct_type.field(field).expect("Unknown field survived type checking");
+                let src = self.expr(operand);
+                let dst = self.new_reg(&t.ty);
+                self.builder.emit(Inst::Field { dst, src, index });
+                Operand::Reg(dst)
+            },
+            HuckAst::Enum(..) => Operand::Const(Const::Unit),
+            HuckAst::Variant(_, _, variant, args, t) => {
+                let TypeInfo::Enum(enum_type) = &t.ty else { unreachable!("Variant of type {}", t.ty) };
+                let (variant, _) = enum_type.variant(variant).expect("Unknown variant survived type checking");
+                let fields = self.args(args);
+                let dst = self.new_reg(&t.ty);
+                self.builder.emit(Inst::Variant { dst, variant, fields });
+                Operand::Reg(dst)
+            },
+            HuckAst::Match(scrutinee, arms, t) => {
+                let value = self.expr(scrutinee);
+                let result = self.new_reg(&t.ty);
+                let join_block = self.builder.new_block();
+                self.lower_match(value, arms, t, ArmExit::Join(result, join_block));
+                self.builder.switch_to(join_block);
+                Operand::Reg(result)
+            },
+            HuckAst::While(label, cond, body, _) => {
+                let header = self.builder.new_block();
+                self.builder.terminate(Terminator::Jump(header));
+                self.builder.switch_to(header);
+                let cond = self.expr(cond);
+                let body_block = self.builder.new_block();
+                let exit = self.builder.new_block();
+                self.builder.terminate(Terminator::Branch { cond, then_block: body_block, else_block: exit });
+
+                let target = LoopTarget { label: label.clone(), continue_block: header, break_block: exit, result: None };
+                self.loop_body(body_block, target, body);
+                self.builder.switch_to(exit);
+                Operand::Const(Const::Unit)
+            },
+            HuckAst::Loop(label, body, t) => {
+                let result = self.new_reg(&t.ty);
+                let body_block = self.builder.new_block();
+                let exit = self.builder.new_block();
+                self.builder.terminate(Terminator::Jump(body_block));
+
+                let target = LoopTarget { label: label.clone(), continue_block: body_block, break_block: exit, result: Some(result) };
+                self.loop_body(body_block, target, body);
+                self.builder.switch_to(exit);
+                Operand::Reg(result)
+            },
+            // The loop variable is one register, counted up at the end of
+            // each time round. Nothing else can write it.
+            HuckAst::For(label, var, from, to, body, _) => {
+                let TypeInfo::Int(t) = from.ty() else { unreachable!("Range of {}", from.ty()) };
+                let src = self.expr(from);
+                let end = self.expr(to);
+                let i = self.builder.new_reg(Ty::Int(*t));
+                self.builder.emit(Inst::Copy { dst: i, src });
+                let header = self.builder.new_block();
+                self.builder.terminate(Terminator::Jump(header));
+
+                self.builder.switch_to(header);
+                let cond = self.builder.new_reg(Ty::Bool);
+                self.builder.emit(Inst::Binary { dst: cond, op: BinOp::Lt, lhs: Operand::Reg(i), rhs: end });
+                let body_block = self.builder.new_block();
+                let latch = self.builder.new_block();
+                let exit = self.builder.new_block();
+                self.builder.terminate(Terminator::Branch { cond: Operand::Reg(cond), then_block: body_block, else_block: exit });
+
+                self.builder.scopes.push(HashMap::from([(var.to_string(), i)]));
+                let target = LoopTarget { label: label.clone(), continue_block: latch, break_block: exit, result: None };
+                self.loop_body(body_block, target, body);
+                self.builder.scopes.pop();
+
+                // `i` is less than `end`, so this can't overflow
+                self.builder.switch_to(latch);
+                let one = Operand::Const(Const::Int(1, *t));
+                self.builder.emit(Inst::Binary { dst: i, op: BinOp::Add, lhs: Operand::Reg(i), rhs: one });
+                self.builder.terminate(Terminator::Jump(header));
+                self.builder.switch_to(exit);
+                Operand::Const(Const::Unit)
+            },
+            HuckAst::Break(label, value, _) => {
+                let src = value.as_ref().map_or(Operand::Const(Const::Unit), |value| self.expr(value));
+                let target = self.builder.loop_target(label);
+                let (exit, result) = (target.break_block, target.result);
+                // Only a `loop` has a value to break with
+                if let Some(dst) = result {
+                    self.builder.emit(Inst::Copy { dst, src });
+                }
+                self.jump(exit)
+            },
+            HuckAst::Continue(label, _) => {
+                let header = self.builder.loop_target(label).continue_block;
+                self.jump(header)
+            },
+        }
+    }
+
+    // Lower a loop's body, which is run for its effects, in `block`.
+    // Falling off the end of it goes round again.
+    fn loop_body(&mut self, block: BlockId, target: LoopTarget, body: &LowerInput) {
+        let continue_block = target.continue_block;
+        self.builder.switch_to(block);
+        self.builder.loops.push(target);
+        self.expr(body);
+        self.builder.loops.pop();
+        self.builder.terminate(Terminator::Jump(continue_block));
+    }
+
+    // Whatever comes after a `break` or `continue` is unreachable, but
+    // still has to go in a block
+    fn jump(&mut self, target: BlockId) -> Operand {
+        self.builder.terminate(Terminator::Jump(target));
+        let unreachable = self.builder.new_block();
+        self.builder.switch_to(unreachable);
+        Operand::Const(Const::Unit)
+    }
+
+    fn operand_type(&self, operand: Operand) -> Ty {
+        match operand {
+            Operand::Reg(reg) => self.builder.regs[reg.0],
+            Operand::Const(c) => c.ty(),
+        }
+    }
+
+    fn lower_match(&mut self, value: Operand, arms: &[MatchArm<Typed>], t: &Typed, exit: ArmExit) {
+        let mut bindings = vec![];
+        for arm in arms {
+            let mut regs = HashMap::new();
+            for (name, ty) in pattern_bindings(&arm.pattern) {
+                regs.insert(name.to_string(), self.new_reg(ty));
+            }
+            bindings.push(regs);
+        }
+        let mut m = MatchLowering { arms, bindings, bodies: vec![None; arms.len()], exit, loc: self.loc(t.span) };
+        let values = HashMap::from([(vec![], value)]);
+        self.decision(&matching::compile(arms), values, &mut m);
+    }
+
+    // The value at `path` in the scrutinee, getting it out of the payload
+    // that holds it if nothing has yet
+    fn path_value(&mut self, path: &Path, values: &mut HashMap<Path, Operand>) -> Operand {
+        if let Some(value) = values.get(path) {
+            return *value;
+        }
+        let (parent, step) = (&path[..path.len() - 1], path.last().expect("The scrutinee is always known"));
+        let src = self.path_value(&parent.to_vec(), values);
+        let dst = match (*step, self.operand_type(src)) {
+            (Step::Payload(variant, index), Ty::Enum(id)) => {
+                let dst = self.builder.new_reg(self.enums[id.0].variants[variant].1[index]);
+                self.builder.emit(Inst::Payload { dst, src, variant, index });
+                dst
+            },
+            (Step::Element(index), Ty::Struct(id)) => {
+                let dst = self.builder.new_reg(self.structs[id.0].fields[index].1);
+                self.builder.emit(Inst::Field { dst, src, index });
+                dst
+            },
+            (step, ty) => unreachable!("{:?} of a {:?}", step, ty),
+        };
+        values.insert(path.clone(), Operand::Reg(dst));
+        Operand::Reg(dst)
+    }
+
+    fn decision(&mut self, decision: &Decision, mut values: HashMap<Path, Operand>, m: &mut MatchLowering) {
+        match decision {
+            Decision::Fail => self.builder.terminate(Terminator::NoMatch(m.loc)),
+            Decision::Leaf { arm, bindings, otherwise } => {
+                for (name, path) in bindings {
+                    let src = self.path_value(path, &mut values);
+                    self.builder.emit(Inst::Copy { dst: m.bindings[*arm][name], src });
+                }
+                let body_block = self.arm_body(*arm, m);
+                match (&m.arms[*arm].guard, otherwise) {
+                    (Some(guard), Some(otherwise)) => {
+                        self.builder.scopes.push(m.bindings[*arm].clone());
+                        let cond = self.expr(guard);
+                        self.builder.scopes.pop();
+                        let else_block = self.builder.new_block();
+                        self.builder.terminate(Terminator::Branch { cond, then_block: body_block, else_block });
+                        self.builder.switch_to(else_block);
+                        self.decision(otherwise, values, m);
+                    },
+                    _ => self.builder.terminate(Terminator::Jump(body_block)),
+                }
+            },
+            Decision::Switch { path, cases, default } => {
+                let value = self.path_value(path, &mut values);
+                let mut blocks = cases.iter()
+                    .map(|(case, next)| (*case, self.builder.new_block(), next))
+                    .collect::<Vec<_>>();
+                let default_block = match default {
+                    Some(next) => (self.builder.new_block(), next.as_ref()),
+                    // Every case is covered, so the last one needn't be checked
+                    None => {
+                        let (_, block, next) = blocks.pop().expect("Switches have cases");
+                        (block, next)
+                    },
+                };
+
+                let term = match blocks.first().map(|(case, _, _)| *case) {
+                    Some(Case::Bool(b)) => {
+                        let (then_block, else_block) = (blocks[0].1, default_block.0);
+                        let (then_block, else_block) = if b { (then_block, else_block) } else { (else_block, then_block) };
+                        Terminator::Branch { cond: value, then_block, else_block }
+                    },
+                    Some(Case::Variant(_)) => {
+                        let tag = self.builder.new_reg(Ty::Int(IntType::U32));
+                        self.builder.emit(Inst::Tag { dst: tag, src: value });
+                        let cases = blocks.iter().map(|(case, block, _)| (case_const(*case), *block)).collect();
+                        Terminator::Switch { value: Operand::Reg(tag), cases, default: default_block.0 }
+                    },
+                    Some(Case::Int(..)) => {
+                        let cases = blocks.iter().map(|(case, block, _)| (case_const(*case), *block)).collect();
+                        Terminator::Switch { value, cases, default: default_block.0 }
+                    },
+                    Some(Case::Tuple) => unreachable!("A tuple's only case is its last"),
+                    None => Terminator::Jump(default_block.0),
+                };
+                self.builder.terminate(term);
+
+                let (default_block, default) = default_block;
+                for (block, next) in blocks.into_iter().map(|(_, block, next)| (block, next)).chain([(default_block, default)]) {
+                    self.builder.switch_to(block);
+                    self.decision(next, values.clone(), m);
+                }
+            },
+        }
+    }
+
+    // The block for an arm's body, lowered the first time it's needed
+    fn arm_body(&mut self, arm: usize, m: &mut MatchLowering) -> BlockId {
+        if let Some(block) = m.bodies[arm] {
+            return block;
+        }
+        let block = self.builder.new_block();
+        m.bodies[arm] = Some(block);
+
+        let current = self.builder.current;
+        self.builder.switch_to(block);
+        self.builder.scopes.push(m.bindings[arm].clone());
+        let body = &m.arms[arm].body;
+        match m.exit {
+            ArmExit::Join(result, join_block) => {
+                let src = self.expr(body);
+                self.builder.emit(Inst::Copy { dst: result, src });
+                self.builder.terminate(Terminator::Jump(join_block));
+            },
+            ArmExit::Tail => self.tail(body),
+        }
+        self.builder.scopes.pop();
+        self.builder.switch_to(current);
+        block
+    }
+}
+
+// Variants are compared by their tag
+fn case_const(case: Case) -> Const {
+    match case {
+        Case::Variant(variant) => Const::Int(variant as i64, IntType::U32),
+        Case::Int(n, t) => Const::Int(n, t),
+        Case::Bool(b) => Const::Bool(b),
+        Case::Tuple => unreachable!("Tuples aren't compared"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use crate::typecheck::Checker;
+
+    fn lower_str(s: &str) -> Module {
+        let ast = Parser::new(Scanner::new(s)).parse().unwrap();
+        let checked = crate::mono::monomorphize(&Checker::new().check(&ast).unwrap()).unwrap();
+        lower(&checked, s)
+    }
+
+    #[test]
+    fn constant() {
+        let module = lower_str("42");
+        assert_eq!(module.to_string(), "fn main() -> i64 {\nbb0:\n    ret 42\n}\n");
+    }
+
+    #[test]
+    fn arithmetic() {
+        let module = lower_str("{let x = 3; x * 2 + 1}");
+        assert_eq!(module.to_string(), "\
+fn main() -> i64 {
+bb0:
+    %0: i64 = copy 3
+    %1: i64 = checked mul %0, 2 at 1:13
+    %2: i64 = checked add %1, 1 at 1:13
+    ret %2
+}
+");
+    }
+
+    #[test]
+    fn mutation() {
+        // `x` is read into %1 before the assignment writes %0 again
+        let module = lower_str("{let mut x = 1; x + { x = 2; x }}");
+        assert_eq!(module.to_string(), "\
+fn main() -> i64 {
+bb0:
+    %0: i64 = copy 1
+    %1: i64 = copy %0
+    %0: i64 = copy 2
+    %2: i64 = copy %0
+    %3: i64 = checked add %1, %2 at 1:17
+    ret %3
+}
+");
+    }
+
+    #[test]
+    fn loops() {
+        // `i` is %1, written again in the latch at bb3; the `break` leaves
+        // bb8 with nothing to jump into it
+        let module = lower_str("{let mut n = 0; for i in 0..10 { if i == 5 { break } else { n += i } }; n}");
+        assert_eq!(module.to_string(), "\
+fn main() -> i64 {
+bb0:
+    %0: i64 = copy 0
+    %1: i64 = copy 0
+    jmp bb1
+bb1:
+    %2: bool = lt %1, 10
+    br %2, bb2, bb4
+bb2:
+    %3: bool = eq %1, 5
+    br %3, bb5, bb6
+bb3:
+    %1: i64 = add %1, 1
+    jmp bb1
+bb4:
+    %7: i64 = copy %0
+    ret %7
+bb5:
+    jmp bb4
+bb6:
+    %5: i64 = copy %0
+    %6: i64 = checked add %5, %1 at 1:61
+    %0: i64 = copy %6
+    %4: () = copy ()
+    jmp bb7
+bb7:
+    jmp bb3
+bb8:
+    %4: () = copy ()
+    jmp bb7
+}
+");
+    }
+
+    #[test]
+    fn conditional() {
+        let module = lower_str("1 + if true { 1 } else { 2 }");
+        let main = &module.functions[0];
+        assert_eq!(main.blocks.len(), 4);
+        assert_eq!(main.blocks[0].term, Terminator::Branch {
+            cond: Operand::Const(Const::Bool(true)),
+            then_block: BlockId(1),
+            else_block: BlockId(2),
+        });
+        assert_eq!(main.blocks[1].term, Terminator::Jump(BlockId(3)));
+        assert_eq!(main.blocks[2].term, Terminator::Jump(BlockId(3)));
+        assert_eq!(main.blocks[3].term, Terminator::Return(Operand::Reg(Reg(1))));
+    }
+
+    #[test]
+    fn tail_conditional() {
+        let module = lower_str("if true { 1 } else { 2 }");
+        assert_eq!(module.to_string(), "\
+fn main() -> i64 {
+bb0:
+    br true, bb1, bb2
+bb1:
+    ret 1
+bb2:
+    ret 2
+}
+");
+    }
+
+    #[test]
+    fn functions() {
+        let module = lower_str("{
+            let odd = fn (x: i64): bool { if x == 0 { false } else { even(x - 1) } };
+            let even = fn (x: i64): bool { if x == 0 { true } else { odd(x - 1) } };
+            let b = even(10);
+            b
+        }");
+        assert_eq!(module.to_string(), "\
+fn main() -> bool {
+bb0:
+    %0: bool = call even(10)
+    %1: bool = copy %0
+    ret %1
+}
+
+fn odd(%0: i64) -> bool {
+bb0:
+    %1: bool = eq %0, 0
+    br %1, bb1, bb2
+bb1:
+    ret false
+bb2:
+    %2: i64 = checked sub %0, 1 at 2:75
+    tailcall even(%2)
+}
+
+fn even(%0: i64) -> bool {
+bb0:
+    %1: bool = eq %0, 0
+    br %1, bb1, bb2
+bb1:
+    ret true
+bb2:
+    %2: i64 = checked sub %0, 1 at 3:74
+    tailcall odd(%2)
+}
+");
+    }
+
+    #[test]
+    fn structs() {
+        let module = lower_str("{
+            struct P { x: i64, y: bool };
+            let p = P { y: true, x: 1 };
+            P { x: 2, ..p }.y
+        }");
+        assert_eq!(module.to_string(), "\
+type s0 = P { x: i64, y: bool }
+
+fn main() -> bool {
+bb0:
+    %0: s0 = struct { 1, true }
+    %1: s0 = copy %0
+    %2: bool = field %1.1
+    %3: s0 = struct { 2, %2 }
+    %4: bool = field %3.1
+    ret %4
+}
+");
+    }
+
+    #[test]
+    fn dyn_calls() {
+        let module = lower_str("{ let d = 1 as dyn Display; let e = d as dyn Display; e.print() }");
+        assert_eq!(module.to_string(), "\
+vtable vt0 = dyn Display for i64: i64 { impl.Display.i64.print }
+
+fn main() -> () {
+bb0:
+    %0: dyn = dyn 1, vt0
+    %1: dyn = copy %0
+    %2: dyn = copy %1
+    %3: () = dyncall 0(%2)
+    ret %3
+}
+
+fn impl.Display.i64.print(%0: i64) -> () {
+bb0:
+    %1: () = print %0
+    ret %1
+}
+");
+    }
+
+    #[test]
+    fn matches() {
+        let module = lower_str("{
+            enum O { S(i64), N };
+            let o = O::S(2);
+            match o { O::S(n) if n > 1 => n, O::S(0) => 1, _ => 0 }
+        }");
+        // The guard's failure goes on to test the payload it already read
+        assert_eq!(module.to_string(), "\
+type e0 = O { S(i64), N }
+
+fn main() -> i64 {
+bb0:
+    %0: e0 = variant 0(2)
+    %1: e0 = copy %0
+    %3: u32 = tag %1
+    switch %3, [0: bb1], bb2
+bb1:
+    %4: i64 = payload %1.0.0
+    %2: i64 = copy %4
+    %5: bool = gt %2, 1
+    br %5, bb3, bb4
+bb2:
+    jmp bb8
+bb3:
+    ret %2
+bb4:
+    switch %4, [0: bb5], bb6
+bb5:
+    jmp bb7
+bb6:
+    jmp bb8
+bb7:
+    ret 1
+bb8:
+    ret 0
+}
+");
+    }
+
+    #[test]
+    fn shadowed_functions() {
+        let module = lower_str("{
+            let f = fn (): i64 { 1 };
+            let g = fn (): i64 { let f = fn (): i64 { 2 }; f() };
+            f() + g()
+        }");
+        let names = module.functions.iter().map(|f| f.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["main", "f", "f.1", "g"]);
+    }
+}
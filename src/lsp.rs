@@ -0,0 +1,739 @@
+// A language server for huck, spoken over stdio.
+//
+// Every open document is re-analysed from scratch whenever it changes:
+// parsed, checked (carrying on past errors, so they're all shown) and
+// monomorphized for diagnostics, then walked once to find its `let`
+// bindings and the names that refer to them. Hover, go to definition,
+// find references and document symbols are all answered from that
+// analysis.
+
+use crate::diagnostic::{line_col, Diagnostic, Span};
+use crate::json::Json;
+use crate::parser::{HuckAst, ParseOutput, Pattern};
+use crate::typecheck::{CheckOutput, Checker, TypeInfo, Typed};
+use crate::{mono, parse_str};
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+/// Read one message, or `None` at the end of the input.
+pub fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    let body = String::from_utf8(body).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    Json::parse(&body).map(Some).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+pub fn write_message<W: Write>(output: &mut W, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// Serve requests from `input` until the client says to exit.
+pub fn run<R: BufRead, W: Write>(mut input: R, output: &mut W) -> io::Result<()> {
+    let mut server = Server::new();
+    while let Some(message) = read_message(&mut input)? {
+        for reply in server.handle(&message) {
+            write_message(output, &reply)?;
+        }
+        if server.exited {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum BindingKind {
+    Function,
+    Variable,
+    Parameter,
+}
+
+#[derive(Debug)]
+struct Binding {
+    name: String,
+    kind: BindingKind,
+    // Just the name, where it's declared
+    name_span: Span,
+    // The whole declaration
+    span: Span,
+    type_info: Option<TypeInfo>,
+    // The function it's declared in, if any
+    parent: Option<usize>,
+    references: Vec<Span>,
+}
+
+// Everything the server knows about one version of a document
+struct Analysis {
+    diagnostics: Vec<Diagnostic>,
+    // Parsed and checked trees have the same shape, so they can be walked
+    // side by side to find the type of whatever's under the cursor
+    parsed: Option<ParseOutput>,
+    checked: Option<CheckOutput>,
+    bindings: Vec<Binding>,
+}
+
+impl Analysis {
+    fn new(source: &str) -> Self {
+        let (parsed, mut diagnostics) = match parse_str(source) {
+            Ok(parsed) => (Some(parsed), vec![]),
+            Err(diagnostic) => (None, vec![diagnostic]),
+        };
+        // Every error the checker finds, and whatever it could check
+        // around them for hovering over. Monomorphizing only has errors of
+        // its own to add if there weren't any before.
+        let checked = parsed.as_ref().and_then(|parsed| {
+            let mut checker = Checker::new();
+            let (checked, errors) = checker.check_all(parsed);
+            diagnostics.extend(checker.take_warnings());
+            if let (Some(checked), true) = (&checked, errors.is_empty()) {
+                diagnostics.extend(mono::monomorphize(checked).err());
+            }
+            diagnostics.extend(errors);
+            checked
+        });
+
+        let mut resolver = Resolver { source, bindings: vec![], scopes: vec![HashMap::new()], hoisted: HashMap::new() };
+        if let Some(parsed) = &parsed {
+            resolver.resolve(parsed, checked.as_ref(), None);
+        }
+        Self { diagnostics, parsed, checked, bindings: resolver.bindings }
+    }
+
+    // The binding whose name is at `offset`, either where it's declared or
+    // where it's used
+    fn binding_at(&self, offset: usize) -> Option<&Binding> {
+        let contains = |span: &Span| span.start <= offset && offset <= span.end;
+        self.bindings.iter()
+            .find(|b| contains(&b.name_span) || b.references.iter().any(contains))
+    }
+
+    // The type of the smallest expression around `offset`
+    fn type_at(&self, offset: usize) -> Option<&TypeInfo> {
+        let mut parsed = self.parsed.as_ref()?;
+        let mut checked = self.checked.as_ref()?;
+        if !contains(*parsed.get_metadata(), offset) {
+            return None;
+        }
+        'descend: loop {
+            for (p, c) in parsed.children().into_iter().zip(checked.children()) {
+                if contains(*p.get_metadata(), offset) {
+                    parsed = p;
+                    checked = c;
+                    continue 'descend;
+                }
+            }
+            return Some(checked.ty());
+        }
+    }
+}
+
+fn contains(span: Span, offset: usize) -> bool {
+    span.start <= offset && offset < span.end
+}
+
+// Finds the bindings in a program and what refers to them, following the
+// checker's scoping rules
+struct Resolver<'a> {
+    source: &'a str,
+    bindings: Vec<Binding>,
+    scopes: Vec<HashMap<String, usize>>,
+    // Functions declared up front by their block, by where their `let` starts
+    hoisted: HashMap<usize, usize>,
+}
+
+impl Resolver<'_> {
+    fn define(&mut self, name: &str, kind: BindingKind, name_span: Span, span: Span, parent: Option<usize>) -> usize {
+        self.bindings.push(Binding {
+            name: name.to_string(),
+            kind,
+            name_span,
+            span,
+            type_info: None,
+            parent,
+            references: vec![],
+        });
+        let index = self.bindings.len() - 1;
+        self.scopes.last_mut().unwrap().insert(name.to_string(), index);
+        index
+    }
+
+    fn refer(&mut self, name: &str, span: Span) {
+        if let Some(index) = self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            self.bindings[*index].references.push(span);
+        }
+    }
+
+    // The AST doesn't keep spans for names, so look for them in the text
+    fn find_name(&self, name: &str, from: usize, to: usize) -> Span {
+        let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let text = self.source.get(from..to.max(from)).unwrap_or("");
+        let found = text.match_indices(name).find(|(i, _)| {
+            let before = text[..*i].chars().next_back();
+            let after = text[i + name.len()..].chars().next();
+            !before.is_some_and(is_ident) && !after.is_some_and(is_ident)
+        });
+        match found {
+            Some((i, _)) => Span::new(from + i, from + i + name.len()),
+            None => Span::new(from, from),
+        }
+    }
+
+    fn let_name(&self, name: &str, span: Span, init: &ParseOutput) -> Span {
+        // Skip over the `let` keyword
+        self.find_name(name, span.start + 3, init.get_metadata().start)
+    }
+
+    fn resolve(&mut self, ast: &ParseOutput, checked: Option<&CheckOutput>, parent: Option<usize>) {
+        match ast {
+            HuckAst::VarRef(name, span) => self.refer(name, *span),
+            HuckAst::Call(name, _, _, span) => {
+                self.refer(name, Span::new(span.start, span.start + name.len()));
+                self.resolve_children(ast, checked, parent);
+            },
+            // The checker spells out `x += 1` as `x = x + 1`, so the
+            // value it checked isn't the one written
+            HuckAst::Assign(name, op, _, span) => {
+                self.refer(name, Span::new(span.start, span.start + name.len()));
+                self.resolve_children(ast, checked.filter(|_| op.is_none()), parent);
+            },
+            HuckAst::Block(exprs, _) => {
+                self.scopes.push(HashMap::new());
+                // Functions are visible throughout their block
+                for expr in exprs {
+                    if let HuckAst::Let(name, _, init, span) = expr {
+                        if let HuckAst::Fn(..) = init.as_ref() {
+                            let name_span = self.let_name(name, *span, init);
+                            let index = self.define(name, BindingKind::Function, name_span, *span, parent);
+                            self.hoisted.insert(span.start, index);
+                        }
+                    }
+                }
+                self.resolve_children(ast, checked, parent);
+                self.scopes.pop();
+            },
+            HuckAst::Let(name, _, init, span) => {
+                let checked_init = match checked {
+                    Some(HuckAst::Let(_, _, checked_init, _)) => Some(checked_init.as_ref()),
+                    _ => None,
+                };
+                if let HuckAst::Fn(..) = init.as_ref() {
+                    let index = match self.hoisted.get(&span.start) {
+                        Some(index) => *index,
+                        None => {
+                            let name_span = self.let_name(name, *span, init);
+                            self.define(name, BindingKind::Function, name_span, *span, parent)
+                        },
+                    };
+                    self.bindings[index].type_info = checked_init.map(|c| c.ty().clone());
+                    self.resolve_fn(init, checked_init, Some(index));
+                } else {
+                    self.resolve(init, checked_init, parent);
+                    // The name isn't in scope in its own initializer
+                    let name_span = self.let_name(name, *span, init);
+                    let index = self.define(name, BindingKind::Variable, name_span, *span, parent);
+                    self.bindings[index].type_info = checked.map(|c| c.ty().clone());
+                }
+            },
+            HuckAst::Fn(..) => self.resolve_fn(ast, checked, parent),
+            // The loop variable is only in scope in the body
+            HuckAst::For(label, var, from, to, body, span) => {
+                let checked_children = checked.map(HuckAst::children).unwrap_or_default();
+                self.resolve(from, checked_children.first().copied(), parent);
+                self.resolve(to, checked_children.get(1).copied(), parent);
+                self.scopes.push(HashMap::new());
+                let after_label = span.start + label.as_ref().map_or(0, |label| label.len() + 1);
+                let name_span = self.find_name(var, after_label, from.get_metadata().start);
+                let index = self.define(var, BindingKind::Variable, name_span, name_span, parent);
+                self.bindings[index].type_info = checked_children.first().map(|c| c.ty().clone());
+                self.resolve(body, checked_children.get(2).copied(), parent);
+                self.scopes.pop();
+            },
+            HuckAst::Match(scrutinee, arms, _) => {
+                let (checked_scrutinee, checked_arms) = match checked {
+                    Some(HuckAst::Match(checked_scrutinee, checked_arms, _)) => (Some(checked_scrutinee.as_ref()), checked_arms.as_slice()),
+                    _ => (None, [].as_slice()),
+                };
+                self.resolve(scrutinee, checked_scrutinee, parent);
+                for (i, arm) in arms.iter().enumerate() {
+                    let checked_arm = checked_arms.get(i);
+                    // Each arm's bindings are only in scope in that arm
+                    self.scopes.push(HashMap::new());
+                    self.define_pattern(&arm.pattern, checked_arm.map(|c| &c.pattern), parent);
+                    if let Some(guard) = &arm.guard {
+                        self.resolve(guard, checked_arm.and_then(|c| c.guard.as_ref()), parent);
+                    }
+                    self.resolve(&arm.body, checked_arm.map(|c| &c.body), parent);
+                    self.scopes.pop();
+                }
+            },
+            _ => self.resolve_children(ast, checked, parent),
+        }
+    }
+
+    fn define_pattern(&mut self, pattern: &Pattern<Span>, checked: Option<&Pattern<Typed>>, parent: Option<usize>) {
+        match pattern {
+            Pattern::Binding(name, span) => {
+                let index = self.define(name, BindingKind::Variable, *span, *span, parent);
+                self.bindings[index].type_info = checked.map(|c| c.get_metadata().ty.clone());
+            },
+            Pattern::Variant(_, _, fields, _) | Pattern::Tuple(fields, _) => {
+                let checked_fields = match checked {
+                    Some(Pattern::Variant(_, _, checked_fields, _) | Pattern::Tuple(checked_fields, _)) => checked_fields.as_slice(),
+                    _ => [].as_slice(),
+                };
+                for (i, field) in fields.iter().enumerate() {
+                    self.define_pattern(field, checked_fields.get(i), parent);
+                }
+            },
+            Pattern::Wildcard(_) | Pattern::Literal(_) => (),
+        }
+    }
+
+    fn resolve_children(&mut self, ast: &ParseOutput, checked: Option<&CheckOutput>, parent: Option<usize>) {
+        let checked_children = checked.map(HuckAst::children).unwrap_or_default();
+        for (i, child) in ast.children().into_iter().enumerate() {
+            self.resolve(child, checked_children.get(i).copied(), parent);
+        }
+    }
+
+    fn resolve_fn(&mut self, ast: &ParseOutput, checked: Option<&CheckOutput>, parent: Option<usize>) {
+        let HuckAst::Fn(_, params, _, body, span) = ast else {
+            return;
+        };
+        let (checked_body, param_types) = match checked {
+            Some(HuckAst::Fn(_, _, _, checked_body, Typed { ty, .. })) => match ty.signature() {
+                TypeInfo::Fn(param_types, _) => (Some(checked_body.as_ref()), param_types.clone()),
+                _ => (None, vec![]),
+            },
+            _ => (None, vec![]),
+        };
+
+        self.scopes.push(HashMap::new());
+        let header_end = body.get_metadata().start;
+        let mut from = span.start;
+        for (i, (name, _)) in params.iter().enumerate() {
+            let name_span = self.find_name(name, from, header_end);
+            from = name_span.end;
+            let index = self.define(name, BindingKind::Parameter, name_span, name_span, parent);
+            self.bindings[index].type_info = param_types.get(i).cloned();
+        }
+        self.resolve(body, checked_body, parent);
+        self.scopes.pop();
+    }
+}
+
+// LSP positions count lines and UTF-16 code units
+fn position(source: &str, offset: usize) -> Json {
+    let (line, _) = line_col(source, offset);
+    let line_start = source[..offset.min(source.len())].rfind('\n').map_or(0, |i| i + 1);
+    let character = source[line_start..offset.min(source.len())].encode_utf16().count();
+    Json::object(vec![("line", (line - 1).into()), ("character", character.into())])
+}
+
+fn range(source: &str, span: Span) -> Json {
+    Json::object(vec![("start", position(source, span.start)), ("end", position(source, span.end))])
+}
+
+fn offset(source: &str, position: &Json) -> Option<usize> {
+    let line = position.get("line")?.as_u64()? as usize;
+    let character = position.get("character")?.as_u64()? as usize;
+    let line_start = if line == 0 {
+        0
+    } else {
+        source.match_indices('\n').nth(line - 1)?.0 + 1
+    };
+    let text = source[line_start..].split('\n').next().unwrap_or("");
+    let mut units = 0;
+    for (i, c) in text.char_indices() {
+        if units >= character {
+            return Some(line_start + i);
+        }
+        units += c.len_utf16();
+    }
+    Some(line_start + text.len())
+}
+
+struct Document {
+    text: String,
+    analysis: Analysis,
+}
+
+pub struct Server {
+    documents: HashMap<String, Document>,
+    exited: bool,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self { documents: HashMap::new(), exited: false }
+    }
+
+    /// Handle one message from the client, returning whatever should be
+    /// sent back: a response for requests, and maybe some notifications.
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let Some(id) = message.get("id") else {
+            return self.notification(method, params);
+        };
+
+        let result = match method {
+            "initialize" => Ok(Self::capabilities()),
+            "shutdown" => Ok(Json::Null),
+            "textDocument/hover" => Ok(self.hover(params)),
+            "textDocument/definition" => Ok(self.definition(params)),
+            "textDocument/references" => Ok(self.references(params)),
+            "textDocument/documentSymbol" => Ok(self.symbols(params)),
+            _ => Err((-32601, format!("Unknown method {}", method))),
+        };
+        let reply = match result {
+            Ok(result) => ("result", result),
+            Err((code, message)) => {
+                ("error", Json::object(vec![("code", Json::Number(code as f64)), ("message", message.into())]))
+            },
+        };
+        vec![Json::object(vec![("jsonrpc", "2.0".into()), ("id", id.clone()), reply])]
+    }
+
+    fn capabilities() -> Json {
+        Json::object(vec![
+            ("capabilities", Json::object(vec![
+                // Whole documents are sent on every change
+                ("textDocumentSync", 1.into()),
+                ("hoverProvider", true.into()),
+                ("definitionProvider", true.into()),
+                ("referencesProvider", true.into()),
+                ("documentSymbolProvider", true.into()),
+            ])),
+            ("serverInfo", Json::object(vec![
+                ("name", "huck".into()),
+                ("version", env!("CARGO_PKG_VERSION").into()),
+            ])),
+        ])
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = params.path(&["textDocument", "uri"]).and_then(Json::as_str).unwrap_or("").to_string();
+        let text = match method {
+            "textDocument/didOpen" => params.path(&["textDocument", "text"]),
+            "textDocument/didChange" => {
+                params.get("contentChanges").and_then(Json::as_array).and_then(|c| c.last()).and_then(|c| c.get("text"))
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![Self::publish(&uri, vec![])];
+            },
+            "exit" => {
+                self.exited = true;
+                return vec![];
+            },
+            _ => None,
+        };
+        let Some(text) = text.and_then(Json::as_str) else {
+            return vec![];
+        };
+
+        let analysis = Analysis::new(text);
+        let diagnostics = analysis.diagnostics.iter().map(|d| Self::diagnostic(&uri, text, d)).collect();
+        self.documents.insert(uri.clone(), Document { text: text.to_string(), analysis });
+        vec![Self::publish(&uri, diagnostics)]
+    }
+
+    fn publish(uri: &str, diagnostics: Vec<Json>) -> Json {
+        Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("method", "textDocument/publishDiagnostics".into()),
+            ("params", Json::object(vec![("uri", uri.into()), ("diagnostics", diagnostics.into())])),
+        ])
+    }
+
+    fn diagnostic(uri: &str, text: &str, diagnostic: &Diagnostic) -> Json {
+        let span = diagnostic.primary.as_ref().map_or(Span::default(), |label| label.span);
+        let related = diagnostic.secondary.iter().map(|label| {
+            Json::object(vec![
+                ("location", Json::object(vec![("uri", uri.into()), ("range", range(text, label.span))])),
+                ("message", label.message.as_str().into()),
+            ])
+        }).collect::<Vec<_>>();
+        Json::object(vec![
+            ("range", range(text, span)),
+            // Errors are 1 and warnings 2
+            ("severity", if diagnostic.is_error() { 1 } else { 2 }.into()),
+            ("code", diagnostic.code.map(|c| c.to_string()).into()),
+            ("source", "huck".into()),
+            ("message", diagnostic.message.as_str().into()),
+            ("relatedInformation", related.into()),
+        ])
+    }
+
+    // The document and offset a request is about
+    fn locate<'a>(&'a self, params: &'a Json) -> Option<(&'a str, &'a Document, usize)> {
+        let uri = params.path(&["textDocument", "uri"])?.as_str()?;
+        let document = self.documents.get(uri)?;
+        let offset = offset(&document.text, params.get("position")?)?;
+        Some((uri, document, offset))
+    }
+
+    fn hover(&self, params: &Json) -> Json {
+        let Some((_, document, offset)) = self.locate(params) else {
+            return Json::Null;
+        };
+        let analysis = &document.analysis;
+        let text = match analysis.binding_at(offset) {
+            Some(Binding { name, type_info: Some(type_info), .. }) => format!("{}: {}", name, type_info),
+            Some(Binding { name, .. }) => name.to_string(),
+            None => match analysis.type_at(offset) {
+                Some(type_info) => type_info.to_string(),
+                None => return Json::Null,
+            },
+        };
+        Json::object(vec![(
+            "contents",
+            Json::object(vec![("kind", "markdown".into()), ("value", format!("```huck\n{}\n```", text).into())]),
+        )])
+    }
+
+    fn definition(&self, params: &Json) -> Json {
+        let Some((uri, document, offset)) = self.locate(params) else {
+            return Json::Null;
+        };
+        match document.analysis.binding_at(offset) {
+            Some(binding) => Self::location(uri, &document.text, binding.name_span),
+            None => Json::Null,
+        }
+    }
+
+    fn references(&self, params: &Json) -> Json {
+        let Some((uri, document, offset)) = self.locate(params) else {
+            return Json::Null;
+        };
+        let Some(binding) = document.analysis.binding_at(offset) else {
+            return Json::Array(vec![]);
+        };
+        let include_declaration = params.path(&["context", "includeDeclaration"]) == Some(&Json::Bool(true));
+        let declaration = include_declaration.then_some(binding.name_span);
+        declaration.into_iter().chain(binding.references.iter().copied())
+            .map(|span| Self::location(uri, &document.text, span))
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    fn location(uri: &str, text: &str, span: Span) -> Json {
+        Json::object(vec![("uri", uri.into()), ("range", range(text, span))])
+    }
+
+    fn symbols(&self, params: &Json) -> Json {
+        let Some(document) = params.path(&["textDocument", "uri"]).and_then(Json::as_str).and_then(|uri| self.documents.get(uri)) else {
+            return Json::Null;
+        };
+        Self::symbol_tree(document, None).into()
+    }
+
+    fn symbol_tree(document: &Document, parent: Option<usize>) -> Vec<Json> {
+        let bindings = &document.analysis.bindings;
+        bindings.iter().enumerate()
+            .filter(|(_, b)| b.parent == parent && b.kind != BindingKind::Parameter)
+            .map(|(index, binding)| {
+                // LSP's SymbolKind numbering
+                let kind = match binding.kind {
+                    BindingKind::Function => 12,
+                    _ => 13,
+                };
+                let mut fields = vec![
+                    ("name", binding.name.as_str().into()),
+                    ("kind", Json::Number(kind as f64)),
+                    ("range", range(&document.text, binding.span)),
+                    ("selectionRange", range(&document.text, binding.name_span)),
+                ];
+                if let Some(type_info) = &binding.type_info {
+                    fields.push(("detail", type_info.to_string().into()));
+                }
+                if binding.kind == BindingKind::Function {
+                    fields.push(("children", Self::symbol_tree(document, Some(index)).into()));
+                }
+                Json::object(fields)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Play a script of client messages and collect everything the server
+    // says back
+    fn session(messages: &[Json]) -> Vec<Json> {
+        let mut input = vec![];
+        for message in messages {
+            write_message(&mut input, message).unwrap();
+        }
+        let mut output = vec![];
+        run(input.as_slice(), &mut output).unwrap();
+
+        let mut output = output.as_slice();
+        let mut replies = vec![];
+        while let Some(reply) = read_message(&mut output).unwrap() {
+            replies.push(reply);
+        }
+        replies
+    }
+
+    fn request(id: usize, method: &str, params: Json) -> Json {
+        Json::object(vec![("jsonrpc", "2.0".into()), ("id", id.into()), ("method", method.into()), ("params", params)])
+    }
+
+    fn notify(method: &str, params: Json) -> Json {
+        Json::object(vec![("jsonrpc", "2.0".into()), ("method", method.into()), ("params", params)])
+    }
+
+    fn open(text: &str) -> Json {
+        notify("textDocument/didOpen", Json::object(vec![("textDocument", Json::object(vec![
+            ("uri", "file:///a.huck".into()),
+            ("languageId", "huck".into()),
+            ("version", 1.into()),
+            ("text", text.into()),
+        ]))]))
+    }
+
+    fn at(line: usize, character: usize) -> Json {
+        Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", "file:///a.huck".into())])),
+            ("position", Json::object(vec![("line", line.into()), ("character", character.into())])),
+            ("context", Json::object(vec![("includeDeclaration", true.into())])),
+        ])
+    }
+
+    fn result(replies: &[Json], id: usize) -> &Json {
+        let reply = replies.iter().find(|r| r.get("id").and_then(Json::as_u64) == Some(id as u64)).unwrap();
+        reply.get("result").unwrap()
+    }
+
+    fn start_lines(locations: &Json) -> Vec<u64> {
+        locations.as_array().unwrap().iter()
+            .map(|l| l.path(&["range", "start", "line"]).and_then(Json::as_u64).unwrap())
+            .collect()
+    }
+
+    const PROGRAM: &str = "{\n  let double = fn (n: i64): i64 { n * 2 };\n  let x = double(21);\n  x == double(x)\n}";
+
+    #[test]
+    fn diagnostics() {
+        let replies = session(&[
+            request(1, "initialize", Json::object::<&str>(vec![])),
+            open("{\n  let x = 1;\n  x + true\n}"),
+            request(2, "shutdown", Json::Null),
+            notify("exit", Json::Null),
+        ]);
+        assert_eq!(result(&replies, 1).path(&["capabilities", "hoverProvider"]), Some(&Json::Bool(true)));
+
+        let published = &replies[1];
+        assert_eq!(published.get("method").and_then(Json::as_str), Some("textDocument/publishDiagnostics"));
+        let diagnostic = &published.path(&["params", "diagnostics"]).and_then(Json::as_array).unwrap()[0];
+        assert_eq!(diagnostic.get("code").and_then(Json::as_str), Some("E0105"));
+        assert_eq!(diagnostic.get("range"), Some(&range("{\n  let x = 1;\n  x + true\n}", Span::new(21, 25))));
+        assert_eq!(*result(&replies, 2), Json::Null);
+    }
+
+    #[test]
+    fn every_error() {
+        let source = "{\n  let x = 1;\n  let y = x + true;\n  let z = y * 2;\n  print(false + 1);\n  x\n}";
+        let replies = session(&[
+            open(source),
+            request(1, "textDocument/hover", at(5, 2)),
+            notify("exit", Json::Null),
+        ]);
+        // Not one for `z`, which only has an error because `y` does
+        let diagnostics = replies[0].path(&["params", "diagnostics"]).and_then(Json::as_array).unwrap();
+        let lines = diagnostics.iter()
+            .map(|d| d.path(&["range", "start", "line"]).and_then(Json::as_u64).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec![2, 4]);
+        // and what's fine is still checked
+        let hover = result(&replies, 1).path(&["contents", "value"]).and_then(Json::as_str);
+        assert_eq!(hover, Some("```huck\nx: i64\n```"));
+
+        // Monomorphizing can find errors too
+        let replies = session(&[open("{ enum O<T> { S(T), N }; let f = fn <T>(n: i64, x: T): i64 { f(n, O::S(x)) }; f(3, 1) }")]);
+        let diagnostic = &replies[0].path(&["params", "diagnostics"]).and_then(Json::as_array).unwrap()[0];
+        assert_eq!(diagnostic.get("code").and_then(Json::as_str), Some("E0134"));
+    }
+
+    #[test]
+    fn warnings() {
+        let replies = session(&[open("match true { _ => 1, true => 2 }")]);
+        let diagnostic = &replies[0].path(&["params", "diagnostics"]).and_then(Json::as_array).unwrap()[0];
+        assert_eq!(diagnostic.get("code").and_then(Json::as_str), Some("E0130"));
+        assert_eq!(diagnostic.get("severity"), Some(&Json::from(2)));
+    }
+
+    #[test]
+    fn hover_and_navigation() {
+        let replies = session(&[
+            open(PROGRAM),
+            request(1, "textDocument/hover", at(3, 14)),
+            request(2, "textDocument/hover", at(1, 34)),
+            request(3, "textDocument/hover", at(3, 5)),
+            request(4, "textDocument/definition", at(3, 2)),
+            request(5, "textDocument/references", at(1, 7)),
+            request(6, "textDocument/hover", at(0, 0)),
+            notify("exit", Json::Null),
+        ]);
+        let hover = |id| result(&replies, id).path(&["contents", "value"]).and_then(Json::as_str).unwrap().to_string();
+        assert_eq!(hover(1), "```huck\nx: i64\n```");
+        assert_eq!(hover(2), "```huck\nn: i64\n```");
+        assert_eq!(hover(3), "```huck\nbool\n```");
+        assert_eq!(result(&replies, 4).path(&["range", "start"]), Some(&Json::object(vec![("line", 2.into()), ("character", 6.into())])));
+        assert_eq!(start_lines(result(&replies, 5)), vec![1, 2, 3]);
+        assert_eq!(hover(6), "```huck\nbool\n```");
+    }
+
+    #[test]
+    fn symbols() {
+        let replies = session(&[
+            open(PROGRAM),
+            request(1, "textDocument/documentSymbol", at(0, 0)),
+            notify("exit", Json::Null),
+        ]);
+        let symbols = result(&replies, 1).as_array().unwrap();
+        let names = symbols.iter().map(|s| s.get("name").and_then(Json::as_str).unwrap()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["double", "x"]);
+        assert_eq!(symbols[0].get("detail").and_then(Json::as_str), Some("fn (i64): i64"));
+        assert_eq!(symbols[0].path(&["selectionRange", "start", "character"]).and_then(Json::as_u64), Some(6));
+    }
+
+    #[test]
+    fn positions() {
+        let source = "a\n😀b\nc";
+        assert_eq!(position(source, 6), Json::object(vec![("line", 1.into()), ("character", 2.into())]));
+        assert_eq!(offset(source, &position(source, 6)), Some(6));
+        assert_eq!(offset(source, &Json::object(vec![("line", 2.into()), ("character", 9.into())])), Some(9));
+    }
+}
@@ -1,31 +1,253 @@
-mod scanner;
-mod parser;
-mod codegen;
-mod typecheck;
+mod cli;
+
+use huck::{bcgen, bytecode, codegen, format, interp, link, llvm, lsp, repl, scanner, typecheck, vm};
+use huck::{Diagnostic, Options as SessionOptions, Session};
+
+use cli::{Command, Emit, ErrorFormat, Failure, Options};
 
 use std::env;
 use std::fs;
-use std::io::stdout;
+use std::io::{self, stderr, stdin, stdout, IsTerminal, Read, Write};
+use std::cell::Cell;
+use std::path::Path;
+use std::process::exit;
+use std::rc::Rc;
+use std::thread;
+
+// The interpreter recurses on the Rust stack, so everything runs on a
+// thread with room for deep recursion, leaving some to spare past the
+// point where huck code is stopped
+const STACK_SIZE: usize = 512 << 20;
+const STACK_LIMIT: usize = STACK_SIZE - (16 << 20);
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let path = &args[1];
-    let data = fs::read_to_string(path);
-    match data {
-        Ok(text) => parse_file(text),
-        Err(err) => println!("Error reading source file: [{}]", err),
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || cli::parse_args(&args).and_then(|options| drive(&options)))
+        .expect("Can't start the main thread")
+        .join()
+        .unwrap_or_else(|_| exit(101));
+    if let Err(failure) = result {
+        eprintln!("{}", failure);
+        exit(failure.exit_code());
     }
 }
 
-fn parse_file(text: String) {
-    let tokens = scanner::Scanner::new(&text).peekable();
+fn drive(options: &Options) -> Result<(), Failure> {
+    match options.command {
+        Command::Help => written(write!(stdout().lock(), "{}", cli::USAGE)),
+        Command::Version => written(writeln!(stdout().lock(), "huck {}", env!("CARGO_PKG_VERSION"))),
+        Command::Repl => written(repl::run(repl::Repl::new().with_stack_limit(STACK_LIMIT), stdin().lock(), &mut stdout())),
+        Command::Lsp => written(lsp::run(stdin().lock(), &mut stdout().lock())),
+        Command::Check => {
+            check_source(options, &read_source(options)?)?;
+            Ok(())
+        },
+        Command::Build => build(options),
+        Command::Fmt => fmt(options),
+        Command::Run => {
+            let out = ProgramOutput::default();
+            let result = if options.interp {
+                let checked_ast = check_source(options, &read_source(options)?)?;
+                interp::Interpreter::new().with_stack_limit(STACK_LIMIT).with_output(out.clone()).eval(&checked_ast)
+            } else {
+                vm::Vm::new(&load_program(options)?).with_output(out.clone()).run()
+            };
+            if out.closed.get() {
+                return Ok(());
+            }
+            let value = result.map_err(Failure::Runtime)?;
+            written(writeln!(stdout().lock(), "{}", value))
+        },
+        Command::Disasm => {
+            let program = load_program(options)?;
+            written(write!(stdout().lock(), "{}", program))
+        },
+    }
+}
 
-    let mut p = parser::Parser::new(tokens);
+fn io_failure(err: io::Error) -> Failure {
+    Failure::Io(err.to_string())
+}
 
-    let ast = p.parse().expect("Error parsing AST!");
+// Output nobody's reading any more, as with `huck run x.huck | head`,
+// isn't worth complaining about, so we just stop
+fn written(result: io::Result<()>) -> Result<(), Failure> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result.map_err(io_failure),
+    }
+}
+
+// Stdout for what a program prints, which remembers whether the reader
+// went away, so the program stopping because of it isn't reported as
+// the program failing
+#[derive(Clone, Default)]
+struct ProgramOutput {
+    closed: Rc<Cell<bool>>,
+}
 
-    let mut checker = typecheck::Checker::new();
-    let checked_ast = checker.check(&ast).expect("Typechecking error!");
+impl Write for ProgramOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = stdout().lock().write(buf);
+        if result.as_ref().is_err_and(|err| err.kind() == io::ErrorKind::BrokenPipe) {
+            self.closed.set(true);
+        }
+        result
+    }
 
-    codegen::compile(checked_ast, &mut stdout());
+    fn flush(&mut self) -> io::Result<()> {
+        stdout().lock().flush()
+    }
+}
+
+fn input_path(options: &Options) -> &str {
+    options.input.as_deref().expect("Command needs an input file")
+}
+
+fn read_bytes(options: &Options) -> Result<Vec<u8>, Failure> {
+    let path = input_path(options);
+    let mut bytes = vec![];
+    let result = if path == "-" {
+        stdin().read_to_end(&mut bytes).map(|_| ())
+    } else {
+        fs::read(path).map(|read| bytes = read)
+    };
+    result.map_err(|err| Failure::Io(format!("Can't read {}: {}", path, err)))?;
+    Ok(bytes)
+}
+
+fn read_source(options: &Options) -> Result<String, Failure> {
+    String::from_utf8(read_bytes(options)?)
+        .map_err(|_| Failure::Io(format!("{} isn't valid UTF-8", input_path(options))))
+}
+
+fn session(options: &Options) -> Session {
+    Session::new(SessionOptions { opt_level: options.level })
+}
+
+// Show where in the source things went wrong: for people, in color if
+// it's going to a terminal, or as JSON for tools
+fn report(options: &Options, text: &str, diagnostics: &[Diagnostic]) -> String {
+    let file = match input_path(options) {
+        "-" => "<stdin>",
+        path => path,
+    };
+    let color = stderr().is_terminal() && env::var_os("NO_COLOR").is_none();
+    diagnostics.iter().map(|diagnostic| match options.error_format {
+        ErrorFormat::Human => diagnostic.render(file, text, color),
+        ErrorFormat::Json => diagnostic.to_json(file, text) + "\n",
+    }).collect()
+}
+
+fn rejected(options: &Options, text: &str, diagnostics: &[Diagnostic]) -> Failure {
+    let error = diagnostics.iter().find(|diagnostic| diagnostic.is_error()).expect("Rejected without an error");
+    Failure::Rejected(error.stage, report(options, text, diagnostics))
+}
+
+// Run one stage of the session, turning its complaints into a failure.
+// Warnings don't stop it, but still get shown.
+fn stage<T>(options: &Options, text: &str, f: impl FnOnce(&mut Session) -> Option<T>) -> Result<T, Failure> {
+    let mut session = session(options);
+    let result = f(&mut session).ok_or_else(|| rejected(options, text, session.diagnostics()))?;
+    eprint!("{}", report(options, text, session.diagnostics()));
+    Ok(result)
+}
+
+fn check_source(options: &Options, text: &str) -> Result<typecheck::CheckOutput, Failure> {
+    stage(options, text, |session| session.check(text))
+}
+
+// `.hbc` files are already compiled; anything else is huck source
+fn load_program(options: &Options) -> Result<bytecode::Program, Failure> {
+    if input_path(options).ends_with(".hbc") {
+        return bytecode::Program::decode(&read_bytes(options)?)
+            .map_err(|err| Failure::Io(format!("Bad bytecode file: {}", err)));
+    }
+    let text = read_source(options)?;
+    Ok(bcgen::compile(&check_source(options, &text)?))
+}
+
+// Rewrite the file in place, unless it's just being checked or came from
+// stdin, in which case the result goes to stdout
+fn fmt(options: &Options) -> Result<(), Failure> {
+    let text = read_source(options)?;
+    let formatted = match format::format(&text, options.width.unwrap_or(format::DEFAULT_WIDTH)) {
+        Ok(formatted) => formatted,
+        Err(format::FormatError::Syntax(diagnostic)) => return Err(rejected(options, &text, &[diagnostic])),
+        Err(format::FormatError::ChangedMeaning) => {
+            return Err(Failure::Io(String::from("The formatter broke this program; leaving it alone")))
+        },
+    };
+
+    let path = input_path(options);
+    if options.check {
+        if formatted != text {
+            return Err(Failure::Unformatted(path.to_string()));
+        }
+    } else if path == "-" {
+        written(write!(stdout().lock(), "{}", formatted))?;
+    } else if formatted != text {
+        fs::write(path, formatted).map_err(|err| Failure::Io(format!("Can't write {}: {}", path, err)))?;
+    }
+    Ok(())
+}
+
+fn build(options: &Options) -> Result<(), Failure> {
+    let text = read_source(options)?;
+    if options.emit == Emit::Exe {
+        return build_executable(options, &text);
+    }
+
+    let mut out: Box<dyn Write> = match &options.output {
+        Some(path) => {
+            let file = fs::File::create(path)
+                .map_err(|err| Failure::Io(format!("Can't create {}: {}", path, err)))?;
+            Box::new(io::BufWriter::new(file))
+        },
+        None => Box::new(stdout()),
+    };
+
+    let result = match options.emit {
+        Emit::Tokens => scanner::Scanner::new(&text).try_for_each(|token| writeln!(out, "{:?}", token)),
+        Emit::Ast => writeln!(out, "{:#?}", stage(options, &text, |s| s.parse(&text))?),
+        Emit::TypedAst => writeln!(out, "{:#?}", check_source(options, &text)?),
+        Emit::Hbc => out.write_all(&bcgen::compile(&check_source(options, &text)?).encode()),
+        Emit::Exe => unreachable!(),
+        Emit::Ir | Emit::Asm | Emit::Llvm => {
+            let module = stage(options, &text, |s| s.compile_to_ir(&text))?;
+            match options.emit {
+                Emit::Ir => write!(out, "{}", module),
+                Emit::Llvm => llvm::compile(&module, &mut out),
+                _ => codegen::compile(&module, &mut out),
+            }
+        },
+    };
+    written(result.and_then(|_| out.flush()))
+}
+
+// `prog.huck` builds `prog`, and a program from stdin builds `a.out`
+fn executable_path(options: &Options) -> String {
+    if let Some(path) = &options.output {
+        return path.to_string();
+    }
+    match input_path(options) {
+        "-" => String::from("a.out"),
+        path => Path::new(path).with_extension("").to_string_lossy().to_string(),
+    }
+}
+
+fn build_executable(options: &Options, text: &str) -> Result<(), Failure> {
+    let asm = stage(options, text, |s| s.compile_to_asm(text))?;
+
+    let output = executable_path(options);
+    if output == input_path(options) {
+        return Err(Failure::Usage(format!("Refusing to overwrite the input {}; pass -o", output)));
+    }
+    let linker = link::Linker::new(options.linker.as_deref(), options.keep_temps);
+    if let Some(dir) = linker.link(asm.as_bytes(), Path::new(&output)).map_err(Failure::Link)? {
+        eprintln!("Kept temporary files in {}", dir.display());
+    }
+    Ok(())
 }
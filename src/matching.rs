@@ -0,0 +1,285 @@
+// Compiling `match` expressions to decision trees.
+//
+// Arms are tried in order, but a backend shouldn't have to test each
+// pattern in turn. Instead all the arms are compiled together, as in
+// Maranget's "Compiling Pattern Matching to Good Decision Trees", into a
+// tree that looks at each part of the value at most once on any path
+// through it. Lowering and the bytecode compiler both walk the same tree.
+
+use crate::parser::{HuckAst, MatchArm, Pattern};
+use crate::typecheck::{negated_literal, IntType, TypeInfo, Typed};
+
+/// Where a value is inside the one being matched, one step at a time.
+pub type Path = Vec<Step>;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Step {
+    /// Field `.1` of the payload of variant `.0`. By the time anything
+    /// looks there, the value is known to be that variant.
+    Payload(usize, usize),
+    /// An element of a tuple.
+    Element(usize),
+}
+
+/// What a `Switch` compares the value at its path with. Integers are
+/// stored the way their `IntType` describes. Every tuple is a `Tuple`,
+/// so switching on one only gets at its elements.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Case {
+    Variant(usize),
+    Int(i64, IntType),
+    Bool(bool),
+    Tuple,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Decision {
+    /// No arm matches. The checker rejects matches where this could
+    /// happen, so it's only reached by ones that weren't checked.
+    Fail,
+    /// Arm `arm` matches, once each of `bindings` is bound to the value at
+    /// its path. If the arm has a guard and it's false, matching carries
+    /// on with `otherwise`.
+    Leaf { arm: usize, bindings: Vec<(String, Path)>, otherwise: Option<Box<Decision>> },
+    /// Look at the value at `path` and take the case it's equal to, or
+    /// `default` if there isn't one. There's no default when the cases
+    /// cover every possible value.
+    Switch { path: Path, cases: Vec<(Case, Decision)>, default: Option<Box<Decision>> },
+}
+
+// An arm partway through matching: the tests it still needs, and what
+// it's bound so far
+#[derive(Clone)]
+struct Row<'a> {
+    tests: Vec<(Path, &'a Pattern<Typed>)>,
+    bindings: Vec<(String, Path)>,
+    arm: usize,
+    guarded: bool,
+}
+
+impl Row<'_> {
+    fn test_at(&self, path: &Path) -> Option<usize> {
+        self.tests.iter().position(|(at, _)| at == path)
+    }
+
+    // Wildcards and bindings match anything, so they aren't tests
+    fn simplify(&mut self) {
+        let bindings = &mut self.bindings;
+        self.tests.retain(|(path, pattern)| match pattern {
+            Pattern::Wildcard(_) => false,
+            Pattern::Binding(name, _) => {
+                bindings.push((name.clone(), path.clone()));
+                false
+            },
+            Pattern::Literal(_) | Pattern::Variant(..) | Pattern::Tuple(..) => true,
+        });
+    }
+}
+
+pub fn compile(arms: &[MatchArm<Typed>]) -> Decision {
+    let rows = arms.iter().enumerate()
+        .map(|(arm, MatchArm { pattern, guard, .. })| Row {
+            tests: vec![(vec![], pattern)],
+            bindings: vec![],
+            arm,
+            guarded: guard.is_some(),
+        })
+        .collect();
+    decide(rows)
+}
+
+fn decide(mut rows: Vec<Row>) -> Decision {
+    rows.iter_mut().for_each(Row::simplify);
+    let Some(first) = rows.first() else {
+        return Decision::Fail;
+    };
+
+    // The first arm left matches if there's nothing left to test
+    let Some((path, pattern)) = first.tests.first() else {
+        let first = rows.remove(0);
+        let otherwise = first.guarded.then(|| Box::new(decide(rows)));
+        return Decision::Leaf { arm: first.arm, bindings: first.bindings, otherwise };
+    };
+    let path = path.clone();
+
+    // Every case any arm tests for here, in the order they come up
+    let mut cases = vec![];
+    for row in &rows {
+        if let Some(i) = row.test_at(&path) {
+            let case = case(row.tests[i].1);
+            if !cases.contains(&case) {
+                cases.push(case);
+            }
+        }
+    }
+    let complete = possibilities(pattern) == Some(cases.len());
+
+    let default = (!complete).then(|| {
+        let rest = rows.iter().filter(|row| row.test_at(&path).is_none()).cloned().collect();
+        Box::new(decide(rest))
+    });
+    let cases = cases.into_iter()
+        .map(|case| (case, decide(specialize(&rows, &path, case))))
+        .collect();
+    Decision::Switch { path, cases, default }
+}
+
+// The arms still in the running once the value at `path` is known to be
+// `case`, with tests for whatever's inside it
+fn specialize<'a>(rows: &[Row<'a>], path: &Path, case: Case) -> Vec<Row<'a>> {
+    rows.iter()
+        .filter_map(|row| {
+            let Some(i) = row.test_at(path) else {
+                return Some(row.clone());
+            };
+            if self::case(row.tests[i].1) != case {
+                return None;
+            }
+            let mut row = row.clone();
+            let (_, pattern) = row.tests.remove(i);
+            let inner = match (pattern, case) {
+                (Pattern::Variant(_, _, fields, _), Case::Variant(variant)) => inside(path, fields, |index| Step::Payload(variant, index)),
+                (Pattern::Tuple(elements, _), Case::Tuple) => inside(path, elements, Step::Element),
+                _ => vec![],
+            };
+            row.tests.splice(i..i, inner);
+            Some(row)
+        })
+        .collect()
+}
+
+// Tests for each of `fields`, one `step` further in than `path`
+fn inside<'a>(path: &Path, fields: &'a [Pattern<Typed>], step: impl Fn(usize) -> Step) -> Vec<(Path, &'a Pattern<Typed>)> {
+    fields.iter().enumerate().map(|(index, field)| {
+        let mut inner_path = path.clone();
+        inner_path.push(step(index));
+        (inner_path, field)
+    }).collect()
+}
+
+pub(crate) fn case(pattern: &Pattern<Typed>) -> Case {
+    match pattern {
+        Pattern::Literal(HuckAst::Num(n, _, Typed { ty: TypeInfo::Int(t), .. })) => Case::Int(t.wrap(*n as i64), *t),
+        Pattern::Literal(HuckAst::Neg(operand, _)) if negated_literal(operand).is_some() => {
+            let (n, t) = negated_literal(operand).unwrap();
+            Case::Int(n, t)
+        },
+        Pattern::Literal(HuckAst::BoolLit(b, _)) => Case::Bool(*b),
+        Pattern::Variant(_, variant, _, Typed { ty: TypeInfo::Enum(enum_type), .. }) => {
+            let (index, _) = enum_type.variant(variant).expect("Unknown variant survived type checking");
+            Case::Variant(index)
+        },
+        Pattern::Tuple(..) => Case::Tuple,
+        _ => unreachable!("{:?} isn't a test", pattern),
+    }
+}
+
+/// Every name a pattern binds, with its type.
+pub fn pattern_bindings(pattern: &Pattern<Typed>) -> Vec<(&str, &TypeInfo)> {
+    let mut bound = vec![];
+    collect_bindings(pattern, &mut bound);
+    bound
+}
+
+fn collect_bindings<'p>(pattern: &'p Pattern<Typed>, bound: &mut Vec<(&'p str, &'p TypeInfo)>) {
+    match pattern {
+        Pattern::Binding(name, t) => bound.push((name, &t.ty)),
+        Pattern::Variant(_, _, fields, _) | Pattern::Tuple(fields, _) => fields.iter().for_each(|field| collect_bindings(field, bound)),
+        Pattern::Wildcard(_) | Pattern::Literal(_) => (),
+    }
+}
+
+// How many different cases there are for a value like the one `pattern`
+// tests, if that's few enough to cover them all
+fn possibilities(pattern: &Pattern<Typed>) -> Option<usize> {
+    match &pattern.get_metadata().ty {
+        TypeInfo::Enum(enum_type) => Some(enum_type.variants.len()),
+        TypeInfo::Bool => Some(2),
+        TypeInfo::Struct(tuple) if tuple.is_tuple() => Some(1),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use crate::typecheck::Checker;
+
+    fn decision(source: &str) -> Decision {
+        let ast = Parser::new(Scanner::new(source)).parse().unwrap();
+        let checked = Checker::new().check(&ast).unwrap();
+        let HuckAst::Block(exprs, _) = checked else { panic!() };
+        let Some(HuckAst::Match(_, arms, _)) = exprs.last() else { panic!() };
+        compile(arms)
+    }
+
+    fn leaf(arm: usize, bindings: &[(&str, Path)]) -> Decision {
+        let bindings = bindings.iter().map(|(name, path)| (name.to_string(), path.clone())).collect();
+        Decision::Leaf { arm, bindings, otherwise: None }
+    }
+
+    #[test]
+    fn nested_variants() {
+        let tree = decision("{
+            enum O { S(bool, i64), N };
+            let o = O::S(true, 1);
+            match o { O::S(true, n) => n, O::N => 0, _ => 1 }
+        }");
+        assert_eq!(tree, Decision::Switch {
+            path: vec![],
+            cases: vec![
+                (Case::Variant(0), Decision::Switch {
+                    path: vec![Step::Payload(0, 0)],
+                    cases: vec![(Case::Bool(true), leaf(0, &[("n", vec![Step::Payload(0, 1)])]))],
+                    default: Some(Box::new(leaf(2, &[]))),
+                }),
+                (Case::Variant(1), leaf(1, &[])),
+            ],
+            default: None,
+        });
+    }
+
+    #[test]
+    fn tuple_elements() {
+        let tree = decision("{let p = (true, 1); match p { (true, n) => n, (false, 0) => 1, _ => 2 }}");
+        assert_eq!(tree, Decision::Switch {
+            path: vec![],
+            cases: vec![(Case::Tuple, Decision::Switch {
+                path: vec![Step::Element(0)],
+                cases: vec![
+                    (Case::Bool(true), leaf(0, &[("n", vec![Step::Element(1)])])),
+                    (Case::Bool(false), Decision::Switch {
+                        path: vec![Step::Element(1)],
+                        cases: vec![(Case::Int(0, IntType::I64), leaf(1, &[]))],
+                        default: Some(Box::new(leaf(2, &[]))),
+                    }),
+                ],
+                default: None,
+            })],
+            default: None,
+        });
+    }
+
+    #[test]
+    fn guards_fall_through() {
+        let tree = decision("{let x = 3; match x { n if n > 2 => 1, -1 => 2, _ => 3 }}");
+        let Decision::Leaf { arm: 0, otherwise: Some(otherwise), .. } = tree else { panic!("{:?}", tree) };
+        assert_eq!(*otherwise, Decision::Switch {
+            path: vec![],
+            cases: vec![(Case::Int(-1, IntType::I64), leaf(1, &[]))],
+            default: Some(Box::new(leaf(2, &[]))),
+        });
+    }
+
+    #[test]
+    fn covered_cases_need_no_default() {
+        let tree = decision("{let b = true; match b { true => 1, false => 2 }}");
+        assert_eq!(tree, Decision::Switch {
+            path: vec![],
+            cases: vec![(Case::Bool(true), leaf(0, &[])), (Case::Bool(false), leaf(1, &[]))],
+            default: None,
+        });
+    }
+}
@@ -0,0 +1,489 @@
+// Monomorphization: making a copy of each generic function for every
+// list of type arguments it's called with, so that nothing after this
+// needs to know about type parameters.
+//
+// Each call to a generic function is renamed to call the instance for its
+// types, like `id.i64`. Instances are declared right after the generic
+// function, in the same block, so they're in scope everywhere it is; the
+// generic function itself stays where it was, and later passes skip it.
+// An instance's body can call for more instances, so a block keeps making
+// them until there's nothing new. A function that calls itself with a
+// bigger type each time would never finish, so that's an error once it's
+// gone `MAX_DEPTH` deep.
+//
+// Generic structs and enums don't need anything done to them here: every
+// type in the program already says what its type arguments are.
+//
+// Traits go too. Each impl's methods become functions of their own, like
+// `impl.Shape.Circle.area`, and a method call on a known type calls one
+// of those, or does what the operator does for the built-in traits on
+// built-in types. That leaves calls on a `dyn`, which the backends make
+// through the vtable of the type cast to it (see ir.rs); a built-in type
+// cast to one gets functions made for its vtable here.
+
+use crate::diagnostic::{Code, Diagnostic, Span};
+use crate::parser::{Builtin, HuckAst, Pattern, TypeAnn, TypeArgList};
+use crate::typecheck::{builtin_impl, CheckOutput, TraitType, TypeInfo, Typed};
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+const MAX_DEPTH: usize = 64;
+
+/// Replace every call to a generic function with a call to a copy of it
+/// made for the call's types.
+pub fn monomorphize(ast: &CheckOutput) -> Result<CheckOutput, Diagnostic> {
+    let mut ast = ast.clone();
+    lift_impls(&mut ast);
+    Mono { scopes: vec![], depth: 0 }.expr(&mut ast)?;
+    builtin_dyn_impls(&mut ast);
+    Ok(ast)
+}
+
+/// The function an impl's method becomes.
+pub fn impl_method(trait_name: &str, ty: &TypeInfo, method: &str) -> String {
+    format!("impl.{}.{}.{}", trait_name, mangle(ty), method)
+}
+
+/// Whether `init_expr` is a generic function, which only its instances
+/// are made from.
+pub fn is_generic<T>(init_expr: &HuckAst<T>) -> bool {
+    matches!(init_expr, HuckAst::Fn(type_params, ..) if !type_params.is_empty())
+}
+
+struct Instance {
+    type_args: Vec<TypeInfo>,
+    name: String,
+    // How many instances deep the call that asked for it was
+    depth: usize,
+    built: Option<CheckOutput>,
+}
+
+struct Generic {
+    // Where it's declared in its block
+    position: usize,
+    decl: CheckOutput,
+    instances: Vec<Instance>,
+}
+
+// The functions a block declares, mapped to which of its generic
+// functions they are, if they're generic
+#[derive(Default)]
+struct Scope {
+    fns: HashMap<String, Option<usize>>,
+    generics: Vec<Generic>,
+}
+
+struct Mono {
+    scopes: Vec<Scope>,
+    depth: usize,
+}
+
+impl Mono {
+    fn expr(&mut self, ast: &mut CheckOutput) -> Result<(), Diagnostic> {
+        match ast {
+            HuckAst::Block(exprs, _) => return self.block(exprs),
+            // Its body still has type parameters in it
+            HuckAst::Let(_, _, init_expr, _) if is_generic(init_expr) => return Ok(()),
+            HuckAst::Call(ident, type_args, args, t) => {
+                if let Some(name) = self.instance(ident, args, t)? {
+                    *ident = name;
+                    *type_args = TypeArgList::default();
+                }
+            },
+            HuckAst::MethodCall(receiver, ..) if !matches!(receiver.ty(), TypeInfo::Dyn(_)) => {
+                let HuckAst::MethodCall(receiver, method, args, trait_name, t) = take(ast) else { unreachable!() };
+                let trait_name = trait_name.expect("The checker says which trait every method is from");
+                *ast = static_call(&trait_name, &method, *receiver, args, t);
+            },
+            _ => (),
+        }
+        for child in children_mut(ast) {
+            self.expr(child)?;
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, exprs: &mut Vec<CheckOutput>) -> Result<(), Diagnostic> {
+        // Functions are hoisted, as in the checker
+        let mut scope = Scope::default();
+        for (position, expr) in exprs.iter().enumerate() {
+            let HuckAst::Let(ident, _, init_expr, _) = expr else { continue };
+            if let HuckAst::Fn(..) = init_expr.as_ref() {
+                let generic = is_generic(init_expr).then(|| {
+                    scope.generics.push(Generic { position, decl: init_expr.as_ref().clone(), instances: vec![] });
+                    scope.generics.len() - 1
+                });
+                scope.fns.insert(ident.clone(), generic);
+            }
+        }
+
+        self.scopes.push(scope);
+        let result = self.block_exprs(exprs);
+        let scope = self.scopes.pop().unwrap();
+        result?;
+
+        for generic in scope.generics.into_iter().rev() {
+            let span = generic.decl.get_metadata().span;
+            let instances = generic.instances.into_iter().map(|instance| {
+                let decl = instance.built.expect("Every instance is built before its block ends");
+                HuckAst::Let(instance.name, false, Box::new(decl), Typed { ty: TypeInfo::Unit, span })
+            });
+            let after = generic.position + 1;
+            exprs.splice(after..after, instances);
+        }
+        Ok(())
+    }
+
+    fn block_exprs(&mut self, exprs: &mut [CheckOutput]) -> Result<(), Diagnostic> {
+        for (position, expr) in exprs.iter_mut().enumerate() {
+            // A function shadows any earlier one with the same name from
+            // where it's declared
+            if let HuckAst::Let(ident, _, init_expr, _) = expr {
+                if let HuckAst::Fn(..) = init_expr.as_ref() {
+                    let scope = self.scopes.last_mut().unwrap();
+                    let generic = scope.generics.iter().position(|generic| generic.position == position);
+                    scope.fns.insert(ident.clone(), generic);
+                }
+            }
+            self.expr(expr)?;
+        }
+
+        loop {
+            let scope = self.scopes.last().unwrap();
+            let pending = scope.generics.iter().enumerate().find_map(|(index, generic)| {
+                generic.instances.iter().position(|instance| instance.built.is_none()).map(|i| (index, i))
+            });
+            match pending {
+                Some((index, i)) => self.build(index, i)?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    // Make instance `i` of generic function `index` in the innermost block
+    fn build(&mut self, index: usize, i: usize) -> Result<(), Diagnostic> {
+        let generic = &self.scopes.last().unwrap().generics[index];
+        let HuckAst::Fn(type_params, params, ret, body, t) = &generic.decl else {
+            unreachable!("Generic function that isn't a function")
+        };
+        let instance = &generic.instances[i];
+        let args = type_params.iter().map(|(param, _)| param.clone()).zip(instance.type_args.iter().cloned()).collect();
+        let mut body = body.as_ref().clone();
+        subst(&mut body, &args);
+        let ty = t.ty.signature().subst(&args);
+        let mut decl = HuckAst::Fn(vec![], params.clone(), ret.clone(), Rc::new(body), Typed { ty, span: t.span });
+
+        let outer_depth = std::mem::replace(&mut self.depth, instance.depth);
+        let result = self.expr(&mut decl);
+        self.depth = outer_depth;
+        result?;
+        self.scopes.last_mut().unwrap().generics[index].instances[i].built = Some(decl);
+        Ok(())
+    }
+
+    // The instance a call to `ident` should call instead, if it's generic
+    fn instance(&mut self, ident: &str, args: &[CheckOutput], t: &Typed) -> Result<Option<String>, Diagnostic> {
+        let found = self.scopes.iter().enumerate().rev()
+            .find_map(|(depth, scope)| scope.fns.get(ident).map(|generic| generic.map(|index| (depth, index))));
+        let Some(Some((depth, index))) = found else {
+            return Ok(None)
+        };
+        let scope = &mut self.scopes[depth];
+        let generic = &scope.generics[index];
+        let HuckAst::Fn(type_params, _, _, _, decl) = &generic.decl else {
+            unreachable!("Generic function that isn't a function")
+        };
+        let type_params = type_params.iter().map(|(param, _)| param.clone()).collect::<Vec<_>>();
+        let TypeInfo::Fn(param_types, ret) = decl.ty.signature() else {
+            unreachable!("Generic function of type {}", decl.ty)
+        };
+
+        // The checker already made sure these all fit together
+        let mut bound = HashMap::new();
+        for (param_type, arg) in param_types.iter().zip(args) {
+            param_type.unify(arg.ty(), &type_params, &mut bound);
+        }
+        ret.unify(&t.ty, &type_params, &mut bound);
+        let type_args = type_params.iter().map(|param| bound[param].clone()).collect::<Vec<_>>();
+
+        if let Some(instance) = generic.instances.iter().find(|instance| instance.type_args == type_args) {
+            return Ok(Some(instance.name.clone()))
+        }
+        if self.depth >= MAX_DEPTH {
+            return Err(too_deep(ident, &type_args, t.span))
+        }
+
+        // Different types can look the same, like two structs with the
+        // same name from different blocks
+        let taken = |name: &str| scope.generics.iter().flat_map(|g| &g.instances).any(|instance| instance.name == name);
+        let mangled = type_args.iter().map(mangle).collect::<Vec<_>>();
+        let mut name = format!("{}.{}", ident, mangled.join("."));
+        let mut n = 0;
+        while taken(&name) {
+            n += 1;
+            name = format!("{}.{}.{}", ident, mangled.join("."), n);
+        }
+        let instance = Instance { type_args, name: name.clone(), depth: self.depth + 1, built: None };
+        scope.generics[index].instances.push(instance);
+        Ok(Some(name))
+    }
+}
+
+fn too_deep(ident: &str, type_args: &[TypeInfo], span: Span) -> Diagnostic {
+    let type_args = type_args.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+    Diagnostic::type_error(
+        Code::INFINITE_INSTANTIATION,
+        format!("Instantiating `{}` never finishes", ident),
+        span,
+    )
+    .with_label(span, format!("this needs `{}::<{}>`", ident, type_args.join(", ")))
+    .with_note(format!("each copy of `{}` needs another for a bigger type, more than {} deep", ident, MAX_DEPTH))
+}
+
+// Part of an instance's name. It has to work as a symbol in assembly, so
+// there's no punctuation.
+fn mangle(ty: &TypeInfo) -> String {
+    let with_args = |name: &str, args: &[TypeInfo]| {
+        args.iter().fold(name.to_string(), |name, arg| format!("{}_{}", name, mangle(arg)))
+    };
+    match ty {
+        TypeInfo::Unit => String::from("unit"),
+        TypeInfo::Struct(tuple) if tuple.is_tuple() => with_args("tuple", &tuple.args),
+        TypeInfo::Struct(struct_type) => with_args(&struct_type.name, &struct_type.args),
+        TypeInfo::Enum(enum_type) => with_args(&enum_type.name, &enum_type.args),
+        TypeInfo::Dyn(trait_type) => format!("dyn_{}", trait_type.name),
+        _ => ty.to_string(),
+    }
+}
+
+// Move a node out of the tree to rebuild it, leaving an empty block
+fn take(ast: &mut CheckOutput) -> CheckOutput {
+    let placeholder = HuckAst::Block(vec![], ast.get_metadata().clone());
+    std::mem::replace(ast, placeholder)
+}
+
+// Impls are only at the top level, so their methods are declared there
+// too, right after them
+fn lift_impls(ast: &mut CheckOutput) {
+    if let HuckAst::Impl(..) = ast {
+        let t = ast.get_metadata().clone();
+        *ast = HuckAst::Block(vec![take(ast)], t);
+    }
+    let HuckAst::Block(exprs, _) = ast else { return };
+    let mut i = 0;
+    while i < exprs.len() {
+        if let HuckAst::Impl(trait_name, _, methods, t) = &mut exprs[i] {
+            let span = t.span;
+            let trait_name = trait_name.clone();
+            let lifted = std::mem::take(methods).into_iter().map(|(method, decl)| {
+                // `self` is the type the impl is for
+                let TypeInfo::Fn(params, _) = decl.ty() else { unreachable!("Methods are functions") };
+                let name = impl_method(&trait_name, &params[0], &method);
+                HuckAst::Let(name, false, Box::new(decl), Typed { ty: TypeInfo::Unit, span })
+            }).collect::<Vec<_>>();
+            let count = lifted.len();
+            exprs.splice(i + 1..i + 1, lifted);
+            i += count;
+        }
+        i += 1;
+    }
+}
+
+// A call to `method` on a receiver whose type is known. The built-in
+// traits on built-in types do what the operators do; anything else calls
+// the method from its impl.
+fn static_call(trait_name: &str, method: &str, receiver: CheckOutput, mut args: Vec<CheckOutput>, t: Typed) -> CheckOutput {
+    let ty = receiver.ty().clone();
+    if !builtin_impl(trait_name, &ty) {
+        let args = [receiver].into_iter().chain(args).collect();
+        return HuckAst::Call(impl_method(trait_name, &ty, method), TypeArgList::default(), args, t)
+    }
+    let typed = |ty: &TypeInfo| Typed { ty: ty.clone(), span: t.span };
+    match trait_name {
+        "Eq" => HuckAst::Equals(Box::new(receiver), Box::new(args.remove(0)), t),
+        // `{ let cmp.l = a; let cmp.r = b; if cmp.l < cmp.r { -1 } else if cmp.l == cmp.r { 0 } else { 1 } }`
+        "Ord" => {
+            let var = |name: &str| Box::new(HuckAst::VarRef(name.to_string(), typed(&ty)));
+            let int = |n| Box::new(HuckAst::Num(n, None, t.clone()));
+            let less = HuckAst::Less(var("cmp.l"), var("cmp.r"), typed(&TypeInfo::Bool));
+            let equal = HuckAst::Equals(var("cmp.l"), var("cmp.r"), typed(&TypeInfo::Bool));
+            let rest = HuckAst::If(Box::new(equal), int(0), int(1), t.clone());
+            HuckAst::Block(vec![
+                HuckAst::Let(String::from("cmp.l"), false, Box::new(receiver), typed(&ty)),
+                HuckAst::Let(String::from("cmp.r"), false, Box::new(args.remove(0)), typed(&ty)),
+                HuckAst::If(Box::new(less), Box::new(HuckAst::Neg(int(1), t.clone())), Box::new(rest), t.clone()),
+            ], t)
+        },
+        _ => HuckAst::Builtin(Builtin::Print, vec![receiver], t),
+    }
+}
+
+// A built-in type cast to a `dyn` has no impl to take its vtable's
+// functions from, so they're made here, doing what the method call would:
+//
+//     let impl.Display.i64.print = fn(self: i64): () { print(self) };
+//
+// They go at the start of the top level, like functions from impls.
+fn builtin_dyn_impls(ast: &mut CheckOutput) {
+    let mut casts = vec![];
+    find_casts(ast, &mut casts);
+    let span = ast.get_metadata().span;
+    let typed = |ty: &TypeInfo| Typed { ty: ty.clone(), span };
+    let mut seen = HashSet::new();
+    let mut impls = vec![];
+    for (trait_type, ty) in casts {
+        if !builtin_impl(&trait_type.name, &ty) || !seen.insert((trait_type.id, ty.clone())) {
+            continue
+        }
+        for (method, _) in &trait_type.methods {
+            let fn_type = trait_type.method(method, &ty).expect("The trait has its own methods");
+            let TypeInfo::Fn(params, ret) = &fn_type else { unreachable!("Methods are functions") };
+            let names = (0..params.len())
+                .map(|i| if i == 0 { String::from("self") } else { format!("arg{}", i) })
+                .collect::<Vec<_>>();
+            let mut vars = names.iter().zip(params).map(|(name, t)| HuckAst::VarRef(name.clone(), typed(t))).collect::<Vec<_>>();
+            let receiver = vars.remove(0);
+            let body = static_call(&trait_type.name, method, receiver, vars, typed(ret));
+            let params = names.into_iter().zip(params).map(|(name, t)| (name, TypeAnn::Named(t.to_string()))).collect();
+            let decl = HuckAst::Fn(vec![], params, TypeAnn::Named(ret.to_string()), Rc::new(body), typed(&fn_type));
+            impls.push(HuckAst::Let(impl_method(&trait_type.name, &ty, method), false, Box::new(decl), typed(&TypeInfo::Unit)));
+        }
+    }
+    if impls.is_empty() {
+        return
+    }
+    if !matches!(ast, HuckAst::Block(..)) {
+        let t = ast.get_metadata().clone();
+        *ast = HuckAst::Block(vec![take(ast)], t);
+    }
+    let HuckAst::Block(exprs, _) = ast else { unreachable!() };
+    exprs.splice(0..0, impls);
+}
+
+// The trait and operand type of every cast to a `dyn`, outside of
+// generic functions
+fn find_casts(ast: &mut CheckOutput, casts: &mut Vec<(Rc<TraitType>, TypeInfo)>) {
+    match ast {
+        HuckAst::Let(_, _, init_expr, _) if is_generic(init_expr) => return,
+        HuckAst::Cast(operand, _, t) => {
+            if let (TypeInfo::Dyn(trait_type), false) = (&t.ty, matches!(operand.ty(), TypeInfo::Dyn(_))) {
+                casts.push((Rc::clone(trait_type), operand.ty().clone()));
+            }
+        },
+        _ => (),
+    }
+    for child in children_mut(ast) {
+        find_casts(child, casts);
+    }
+}
+
+// Replace the type parameters in every type in `ast`, except in generic
+// functions it declares, whose type parameters are their own
+fn subst(ast: &mut CheckOutput, args: &HashMap<String, TypeInfo>) {
+    let t = ast.get_metadata_mut();
+    t.ty = t.ty.subst(args);
+    match ast {
+        HuckAst::Let(_, _, init_expr, _) if is_generic(init_expr) => return,
+        HuckAst::Match(_, arms, _) => arms.iter_mut().for_each(|arm| subst_pattern(&mut arm.pattern, args)),
+        _ => (),
+    }
+    for child in children_mut(ast) {
+        subst(child, args);
+    }
+}
+
+fn subst_pattern(pattern: &mut Pattern<Typed>, args: &HashMap<String, TypeInfo>) {
+    match pattern {
+        Pattern::Literal(literal) => subst(literal, args),
+        Pattern::Variant(_, _, fields, t) | Pattern::Tuple(fields, t) => {
+            t.ty = t.ty.subst(args);
+            fields.iter_mut().for_each(|field| subst_pattern(field, args));
+        },
+        Pattern::Wildcard(t) | Pattern::Binding(_, t) => t.ty = t.ty.subst(args),
+    }
+}
+
+fn children_mut(ast: &mut CheckOutput) -> Vec<&mut CheckOutput> {
+    match ast {
+        HuckAst::Num(..)
+        | HuckAst::BoolLit(..)
+        | HuckAst::Float(..)
+        | HuckAst::VarRef(..)
+        | HuckAst::Struct(..)
+        | HuckAst::Enum(..)
+        | HuckAst::Trait(..)
+        | HuckAst::Break(_, None, _)
+        | HuckAst::Continue(..) => vec![],
+        HuckAst::Plus(l, r, _)
+        | HuckAst::Minus(l, r, _)
+        | HuckAst::Times(l, r, _)
+        | HuckAst::Div(l, r, _)
+        | HuckAst::Rem(l, r, _)
+        | HuckAst::Equals(l, r, _)
+        | HuckAst::NotEquals(l, r, _)
+        | HuckAst::Less(l, r, _)
+        | HuckAst::LessEq(l, r, _)
+        | HuckAst::Greater(l, r, _)
+        | HuckAst::GreaterEq(l, r, _) => vec![l, r],
+        HuckAst::Neg(operand, _) | HuckAst::Cast(operand, _, _) | HuckAst::Field(operand, _, _) => vec![operand],
+        HuckAst::Let(_, _, init, _) | HuckAst::Assign(_, _, init, _) | HuckAst::Break(_, Some(init), _) => vec![init],
+        HuckAst::While(_, cond, body, _) => vec![cond, body],
+        HuckAst::Loop(_, body, _) => vec![body],
+        HuckAst::For(_, _, from, to, body, _) => vec![from, to, body],
+        HuckAst::Block(exprs, _) | HuckAst::Tuple(exprs, _) => exprs.iter_mut().collect(),
+        HuckAst::If(c, a, b, _) => vec![c, a, b],
+        HuckAst::Fn(_, _, _, body, _) => vec![Rc::make_mut(body)],
+        HuckAst::Call(_, _, args, _) | HuckAst::Builtin(_, args, _) | HuckAst::Variant(_, _, _, args, _) => args.iter_mut().collect(),
+        HuckAst::StructLit(_, _, fields, base, _) => fields.iter_mut().map(|(_, value)| value).chain(base.as_deref_mut()).collect(),
+        HuckAst::Match(scrutinee, arms, _) => {
+            let arms = arms.iter_mut().flat_map(|arm| arm.guard.iter_mut().chain([&mut arm.body]));
+            [scrutinee.as_mut()].into_iter().chain(arms).collect()
+        },
+        HuckAst::Impl(_, _, methods, _) => methods.iter_mut().map(|(_, decl)| decl).collect(),
+        HuckAst::MethodCall(receiver, _, args, _, _) => [receiver.as_mut()].into_iter().chain(args.iter_mut()).collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::diagnostic::Code;
+
+    // The names of the functions `source` declares once it's been
+    // monomorphized, in order
+    fn fns(source: &str) -> Result<Vec<String>, Option<Code>> {
+        let ast = crate::parse_str(source).unwrap();
+        let HuckAst::Block(exprs, _) = crate::check(&ast).map_err(|diagnostic| diagnostic.code)? else { panic!() };
+        Ok(exprs.iter().filter_map(|expr| match expr {
+            HuckAst::Let(ident, _, init_expr, _) if matches!(init_expr.as_ref(), HuckAst::Fn(..)) => Some(ident.clone()),
+            _ => None,
+        }).collect())
+    }
+
+    #[test]
+    fn instances() {
+        assert_eq!(fns("{ let id = fn <T>(x: T): T { x }; id(1); id(true); id(2); 0 }").unwrap(), ["id", "id.i64", "id.bool"]);
+        assert_eq!(fns("{ let id = fn <T>(x: T): T { x }; let twice = fn <T>(x: T): T { id(id(x)) }; twice(1u8) }").unwrap(),
+                   ["id", "id.u8", "twice", "twice.u8"]);
+        assert_eq!(fns("{ enum O<T> { S(T), N }; let some = fn <T>(x: T): O<T> { O::S(x) }; some(some(1)); 0 }").unwrap(),
+                   ["some", "some.O_i64", "some.i64"]);
+        assert_eq!(fns("{ let id = fn <T>(x: T): T { x }; 0 }").unwrap(), ["id"]);
+    }
+
+    #[test]
+    fn impls() {
+        let source = "{ trait S { fn area(self): f64 }; struct C { r: f64 }; impl S for C { fn area(self): f64 { self.r } };
+                        let f = fn <T: S>(x: T): f64 { x.area() }; f(C { r: 1.0 }) + (C { r: 2.0 } as dyn S).area() }";
+        assert_eq!(fns(source).unwrap(), ["impl.S.C.area", "f", "f.C"]);
+        // Built-in types get functions for their vtables, once each
+        let source = "{ let a = 1 as dyn Display; let b = 2 as dyn Display; let c = true as dyn Display; a.print() }";
+        assert_eq!(fns(source).unwrap(), ["impl.Display.i64.print", "impl.Display.bool.print"]);
+    }
+
+    #[test]
+    fn infinite_instantiation() {
+        let source = "{ enum O<T> { S(T), N }; let f = fn <T>(n: i64, x: T): i64 { if n == 0 { 0 } else { f(n - 1, O::S(x)) } }; f(3, 1) }";
+        assert_eq!(fns(source), Err(Some(Code::INFINITE_INSTANTIATION)));
+    }
+}
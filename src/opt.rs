@@ -0,0 +1,464 @@
+// Optimization passes over the IR.
+//
+// Every pass reports whether it changed anything, and the pipeline runs
+// them to a fixpoint: folding a constant can make a branch constant,
+// which makes a block unreachable, which makes a binding dead, and so on.
+
+use crate::inline::inline_functions;
+use crate::ir::{BinOp, Block, BlockId, Const, Function, Inst, Module, Operand, Reg, Terminator, Ty};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum OptLevel {
+    O0,
+    O1,
+    O2,
+}
+
+impl OptLevel {
+    pub fn from_flag(flag: &str) -> Option<Self> {
+        match flag {
+            "-O0" => Some(Self::O0),
+            "-O1" => Some(Self::O1),
+            "-O2" => Some(Self::O2),
+            _ => None,
+        }
+    }
+}
+
+type Pass = fn(&mut Function) -> bool;
+
+pub fn optimize(module: &mut Module, level: OptLevel) {
+    let mut passes: Vec<Pass> = vec![];
+    if level >= OptLevel::O1 {
+        passes.extend_from_slice(&[
+            propagate_constants,
+            fold_branches,
+            remove_unreachable_blocks,
+            remove_dead_code,
+        ]);
+    }
+    if level >= OptLevel::O2 {
+        passes.extend_from_slice(&[
+            simplify_algebra,
+            propagate_copies,
+            thread_jumps,
+            merge_blocks,
+        ]);
+    }
+
+    if level >= OptLevel::O2 {
+        inline_functions(module);
+    }
+
+    for function in &mut module.functions {
+        // Deliberately not short-circuiting: run every pass each round
+        while passes.iter().fold(false, |changed, pass| pass(function) | changed) {}
+    }
+}
+
+// Fold a binary operation on constants. Integer arithmetic wraps, since
+// `Binary` only does it for the wrapping builtins.
+pub fn fold_binary(op: BinOp, lhs: Const, rhs: Const) -> Option<Const> {
+    match (op, lhs, rhs) {
+        (BinOp::Eq, _, _) => Some(Const::Bool(lhs == rhs)),
+        (BinOp::Ne, _, _) => Some(Const::Bool(lhs != rhs)),
+        (_, Const::Int(a, t), Const::Int(b, _)) => match op {
+            BinOp::Add => Some(Const::Int(t.wrap(a.wrapping_add(b)), t)),
+            BinOp::Sub => Some(Const::Int(t.wrap(a.wrapping_sub(b)), t)),
+            BinOp::Mul => Some(Const::Int(t.wrap(a.wrapping_mul(b)), t)),
+            BinOp::Div => t.divide(a, b).map(|n| Const::Int(n, t)),
+            BinOp::Rem => t.remainder(a, b).map(|n| Const::Int(n, t)),
+            BinOp::Lt => Some(Const::Bool(t.compare(a, b).is_lt())),
+            BinOp::Le => Some(Const::Bool(t.compare(a, b).is_le())),
+            BinOp::Gt => Some(Const::Bool(t.compare(a, b).is_gt())),
+            BinOp::Ge => Some(Const::Bool(t.compare(a, b).is_ge())),
+            BinOp::Eq | BinOp::Ne => unreachable!(),
+        },
+        // Float arithmetic never traps, and is the same at compile time
+        (_, Const::Float(a), Const::Float(b)) => match op {
+            BinOp::Add => Some(Const::Float(a + b)),
+            BinOp::Sub => Some(Const::Float(a - b)),
+            BinOp::Mul => Some(Const::Float(a * b)),
+            BinOp::Div => Some(Const::Float(a / b)),
+            BinOp::Rem => Some(Const::Float(a % b)),
+            BinOp::Lt => Some(Const::Bool(a < b)),
+            BinOp::Le => Some(Const::Bool(a <= b)),
+            BinOp::Gt => Some(Const::Bool(a > b)),
+            BinOp::Ge => Some(Const::Bool(a >= b)),
+            BinOp::Eq | BinOp::Ne => unreachable!(),
+        },
+        _ => None,
+    }
+}
+
+// Fold checked arithmetic on constants, unless it fails: then it's left
+// for runtime, which reports the error
+pub fn fold_checked(op: BinOp, lhs: Const, rhs: Const) -> Option<Const> {
+    let (Const::Int(a, t), Const::Int(b, _)) = (lhs, rhs) else {
+        return None;
+    };
+    let n = match op {
+        BinOp::Add => t.add(a, b),
+        BinOp::Sub => t.sub(a, b),
+        BinOp::Mul => t.mul(a, b),
+        BinOp::Div => t.divide(a, b),
+        BinOp::Rem => t.remainder(a, b),
+        _ => unreachable!("Checked {}", op),
+    };
+    n.map(|n| Const::Int(n, t))
+}
+
+// Convert a constant to another type, as `Inst::Cast` does
+pub fn fold_cast(src: Const, to: Ty) -> Option<Const> {
+    match (src, to) {
+        (Const::Int(n, _), Ty::Int(t)) => Some(Const::Int(t.wrap(n), t)),
+        (Const::Bool(b), Ty::Int(t)) => Some(Const::Int(b as i64, t)),
+        (Const::Float(x), Ty::Int(t)) => Some(Const::Int(t.from_f64(x), t)),
+        (Const::Int(n, t), Ty::F64) => Some(Const::Float(t.to_f64(n))),
+        (Const::Float(x), Ty::F64) => Some(Const::Float(x)),
+        _ => None,
+    }
+}
+
+// Instructions that can be deleted if nobody reads their result. Checked
+// arithmetic has to stay unless it can't fail.
+fn is_pure(inst: &Inst) -> bool {
+    match inst {
+        Inst::Copy { .. } | Inst::Binary { .. } | Inst::Cast { .. } | Inst::Struct { .. } | Inst::Field { .. } => true,
+        Inst::Variant { .. } | Inst::Tag { .. } | Inst::Payload { .. } | Inst::Dyn { .. } => true,
+        Inst::Checked { op: BinOp::Div | BinOp::Rem, rhs, .. } =>
+            matches!(rhs, Operand::Const(Const::Int(n, t)) if *n != 0 && (*n != -1 || !t.is_signed())),
+        Inst::Checked { .. } | Inst::Call { .. } | Inst::Print { .. } | Inst::DynCall { .. } => false,
+    }
+}
+
+// How many times each register is written, counting parameters as
+// written once on entry
+fn def_counts(function: &Function) -> Vec<usize> {
+    let mut counts = vec![0; function.regs.len()];
+    for param in &function.params {
+        counts[param.0] += 1;
+    }
+    for inst in function.blocks.iter().flat_map(|b| &b.insts) {
+        counts[inst.dst().0] += 1;
+    }
+    counts
+}
+
+fn use_counts(function: &Function) -> Vec<usize> {
+    let mut counts = vec![0; function.regs.len()];
+    for block in &function.blocks {
+        let operands = block.insts.iter()
+            .flat_map(|i| i.operands())
+            .chain(block.term.operands());
+        for operand in operands {
+            if let Operand::Reg(reg) = operand {
+                counts[reg.0] += 1;
+            }
+        }
+    }
+    counts
+}
+
+// Rewrite every operand in the function, returning whether any changed
+fn rewrite_operands(function: &mut Function, f: impl Fn(&Operand) -> Option<Operand>) -> bool {
+    let mut changed = false;
+    for block in &mut function.blocks {
+        let operands = block.insts.iter_mut()
+            .flat_map(|i| i.operands_mut())
+            .chain(block.term.operands_mut());
+        for operand in operands {
+            if let Some(new) = f(operand) {
+                *operand = new;
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+// A register written exactly once is written before any of its uses
+// (the lowering never reads a binding before its `let`), so if that
+// write is a constant, every use can see the constant directly.
+fn propagate_constants(function: &mut Function) -> bool {
+    let defs = def_counts(function);
+    let mut known: HashMap<Reg, Const> = HashMap::new();
+    let mut changed = false;
+
+    for inst in function.blocks.iter_mut().flat_map(|b| &mut b.insts) {
+        if let Inst::Binary { dst, op, lhs: Operand::Const(a), rhs: Operand::Const(b) } = inst {
+            if let Some(c) = fold_binary(*op, *a, *b) {
+                *inst = Inst::Copy { dst: *dst, src: Operand::Const(c) };
+                changed = true;
+            }
+        }
+        if let Inst::Checked { dst, op, lhs: Operand::Const(a), rhs: Operand::Const(b), .. } = inst {
+            if let Some(c) = fold_checked(*op, *a, *b) {
+                *inst = Inst::Copy { dst: *dst, src: Operand::Const(c) };
+                changed = true;
+            }
+        }
+        if let Inst::Cast { dst, src: Operand::Const(c) } = inst {
+            if let Some(c) = fold_cast(*c, function.regs[dst.0]) {
+                *inst = Inst::Copy { dst: *dst, src: Operand::Const(c) };
+                changed = true;
+            }
+        }
+        if let Inst::Copy { dst, src: Operand::Const(c) } = inst {
+            if defs[dst.0] == 1 {
+                known.insert(*dst, *c);
+            }
+        }
+    }
+
+    let substituted = rewrite_operands(function, |operand| match operand {
+        Operand::Reg(reg) => known.get(reg).map(|c| Operand::Const(*c)),
+        Operand::Const(_) => None,
+    });
+    changed | substituted
+}
+
+// Same idea for copies between single-assignment registers
+fn propagate_copies(function: &mut Function) -> bool {
+    let defs = def_counts(function);
+    let mut copies: HashMap<Reg, Reg> = HashMap::new();
+
+    for inst in function.blocks.iter().flat_map(|b| &b.insts) {
+        if let Inst::Copy { dst, src: Operand::Reg(src) } = inst {
+            if defs[dst.0] == 1 && defs[src.0] == 1 && dst != src {
+                copies.insert(*dst, *src);
+            }
+        }
+    }
+
+    let resolve = |mut reg: Reg| {
+        while let Some(src) = copies.get(&reg) {
+            reg = *src;
+        }
+        reg
+    };
+
+    rewrite_operands(function, |operand| match operand {
+        Operand::Reg(reg) if copies.contains_key(reg) => Some(Operand::Reg(resolve(*reg))),
+        _ => None,
+    })
+}
+
+fn fold_branches(function: &mut Function) -> bool {
+    let mut changed = false;
+    for block in &mut function.blocks {
+        if let Terminator::Branch { cond: Operand::Const(Const::Bool(b)), then_block, else_block } = block.term {
+            block.term = Terminator::Jump(if b { then_block } else { else_block });
+            changed = true;
+        }
+        if let Terminator::Switch { value: Operand::Const(c), cases, default } = &block.term {
+            let target = cases.iter().find(|(case, _)| case == c).map_or(*default, |(_, block)| *block);
+            block.term = Terminator::Jump(target);
+            changed = true;
+        }
+    }
+    changed
+}
+
+fn remove_unreachable_blocks(function: &mut Function) -> bool {
+    let mut reachable = HashSet::new();
+    let mut queue = VecDeque::from([BlockId(0)]);
+    while let Some(id) = queue.pop_front() {
+        if reachable.insert(id) {
+            queue.extend(function.blocks[id.0].term.successors());
+        }
+    }
+
+    if reachable.len() == function.blocks.len() {
+        return false;
+    }
+
+    // Renumber the surviving blocks, keeping their relative order
+    let mut renumbered = HashMap::new();
+    let blocks: Vec<Block> = std::mem::take(&mut function.blocks);
+    for (i, block) in blocks.into_iter().enumerate() {
+        if reachable.contains(&BlockId(i)) {
+            renumbered.insert(BlockId(i), BlockId(function.blocks.len()));
+            function.blocks.push(block);
+        }
+    }
+    for block in &mut function.blocks {
+        for target in block.term.successors_mut() {
+            *target = renumbered[target];
+        }
+    }
+    true
+}
+
+fn remove_dead_code(function: &mut Function) -> bool {
+    let uses = use_counts(function);
+    let mut changed = false;
+    for block in &mut function.blocks {
+        let before = block.insts.len();
+        block.insts.retain(|inst| uses[inst.dst().0] > 0 || !is_pure(inst));
+        changed |= block.insts.len() != before;
+    }
+    changed
+}
+
+// Identities like `x + 0` and `x * 1`, none of which can overflow
+fn simplify_algebra(function: &mut Function) -> bool {
+    let mut changed = false;
+    for inst in function.blocks.iter_mut().flat_map(|b| &mut b.insts) {
+        let (Inst::Binary { dst, op, lhs, rhs } | Inst::Checked { dst, op, lhs, rhs, .. }) = *inst else {
+            continue;
+        };
+        let int = |operand: Operand| match operand {
+            Operand::Const(Const::Int(n, _)) => Some(n),
+            _ => None,
+        };
+
+        let simplified = match (op, int(lhs), int(rhs)) {
+            (BinOp::Add, _, Some(0)) | (BinOp::Sub, _, Some(0)) => Some(lhs),
+            (BinOp::Mul, _, Some(1)) | (BinOp::Div, _, Some(1)) => Some(lhs),
+            (BinOp::Add, Some(0), _) | (BinOp::Mul, Some(1), _) => Some(rhs),
+            (BinOp::Mul, Some(0), _) => Some(lhs),
+            (BinOp::Mul, _, Some(0)) => Some(rhs),
+            _ => None,
+        };
+        if let Some(src) = simplified {
+            *inst = Inst::Copy { dst, src };
+            changed = true;
+        }
+    }
+    changed
+}
+
+// Point jumps at empty forwarding blocks straight at their destination
+fn thread_jumps(function: &mut Function) -> bool {
+    let forward = |id: BlockId, blocks: &[Block]| {
+        let block = &blocks[id.0];
+        match block.term {
+            Terminator::Jump(target) if block.insts.is_empty() && target != id => Some(target),
+            _ => None,
+        }
+    };
+
+    let mut changed = false;
+    for i in 0..function.blocks.len() {
+        let mut term = function.blocks[i].term.clone();
+        for target in term.successors_mut() {
+            if let Some(next) = forward(*target, &function.blocks) {
+                *target = next;
+                changed = true;
+            }
+        }
+        function.blocks[i].term = term;
+    }
+    changed
+}
+
+// Glue a block onto its predecessor when it's the only way in
+fn merge_blocks(function: &mut Function) -> bool {
+    let mut preds = vec![0; function.blocks.len()];
+    for block in &function.blocks {
+        for succ in block.term.successors() {
+            preds[succ.0] += 1;
+        }
+    }
+
+    for i in 0..function.blocks.len() {
+        if let Terminator::Jump(target) = function.blocks[i].term {
+            if target.0 != i && target.0 != 0 && preds[target.0] == 1 {
+                let absorbed = std::mem::replace(&mut function.blocks[target.0], Block {
+                    insts: vec![],
+                    term: Terminator::Jump(target),
+                });
+                let block = &mut function.blocks[i];
+                block.insts.extend(absorbed.insts);
+                block.term = absorbed.term;
+                // The absorbed block is now an unreachable self-loop
+                remove_unreachable_blocks(function);
+                return true;
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lower::lower;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use crate::typecheck::{Checker, IntType};
+
+    fn optimize_str(s: &str, level: OptLevel) -> Module {
+        let ast = Parser::new(Scanner::new(s)).parse().unwrap();
+        let checked = Checker::new().check(&ast).unwrap();
+        let mut module = lower(&checked, s);
+        optimize(&mut module, level);
+        module
+    }
+
+    #[test]
+    fn no_optimization() {
+        let module = optimize_str("{let y = true; 1 + 2}", OptLevel::O0);
+        assert_eq!(module.functions[0].blocks[0].insts.len(), 2);
+    }
+
+    #[test]
+    fn constant_folding() {
+        let module = optimize_str("{let x = 5 + 4 - 3 * 2 / 1; let y = true; 42}", OptLevel::O1);
+        assert_eq!(module.to_string(), "fn main() -> i64 {\nbb0:\n    ret 42\n}\n");
+        let module = optimize_str("-17 % 5 + wrapping_rem(-9223372036854775808, -1)", OptLevel::O2);
+        assert_eq!(module.to_string(), "fn main() -> i64 {\nbb0:\n    ret -2\n}\n");
+    }
+
+    #[test]
+    fn propagation_through_let() {
+        let module = optimize_str("{let x = 1; let y = 2; 50 + y * x / 2 - 1}", OptLevel::O1);
+        assert_eq!(module.to_string(), "fn main() -> i64 {\nbb0:\n    ret 50\n}\n");
+    }
+
+    #[test]
+    fn overflow_is_kept() {
+        let module = optimize_str("{let x = 9223372036854775807 + 1; 2}", OptLevel::O2);
+        assert_eq!(module.functions[0].blocks[0].insts.len(), 1);
+        let module = optimize_str("wrapping_add(9223372036854775807, 1)", OptLevel::O1);
+        assert_eq!(module.functions[0].blocks[0].term, Terminator::Return(Operand::Const(Const::Int(i64::MIN, IntType::I64))));
+    }
+
+    #[test]
+    fn division_by_zero_is_kept() {
+        let module = optimize_str("{let x = 1 / 0; 2}", OptLevel::O2);
+        assert_eq!(module.to_string(), "\
+fn main() -> i64 {
+bb0:
+    %0: i64 = checked div 1, 0 at 1:10
+    ret 2
+}
+");
+        let module = optimize_str("{let x = -9223372036854775808 % -1; 2}", OptLevel::O2);
+        assert_eq!(module.functions[0].blocks[0].insts.len(), 1);
+    }
+
+    #[test]
+    fn branch_folding() {
+        let module = optimize_str("{let test = false; if test { 1 } else { 2 }}", OptLevel::O1);
+        let main = &module.functions[0];
+        assert_eq!(main.blocks.len(), 2);
+        assert_eq!(main.blocks[0].term, Terminator::Jump(BlockId(1)));
+        assert_eq!(main.blocks[1].term, Terminator::Return(Operand::Const(Const::Int(2, IntType::I64))));
+    }
+
+    #[test]
+    fn switch_folding() {
+        let module = optimize_str("match 3 { 3 => 1, _ => 2 }", OptLevel::O2);
+        assert_eq!(module.to_string(), "fn main() -> i64 {\nbb0:\n    ret 1\n}\n");
+    }
+
+    #[test]
+    fn block_merging() {
+        let module = optimize_str("{let test = false; if test { 1 } else { 2 }}", OptLevel::O2);
+        assert_eq!(module.to_string(), "fn main() -> i64 {\nbb0:\n    ret 2\n}\n");
+    }
+}
@@ -1,421 +1,1955 @@
-use crate::scanner::{Scanner, Token};
+use crate::diagnostic::{Code, Diagnostic, Span};
+use crate::scanner::{self, Scanner, Spanned, Token};
 use std::iter::{Iterator, Peekable};
+use std::rc::Rc;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum HuckAst<T> { // Boxed to allow data recursion
-    Num(u64, T),
-    // BoolLit(bool, T),
-    // Plus(Box<HuckAst<T>>, Box<HuckAst<T>>, T),
-    // Minus(Box<HuckAst<T>>, Box<HuckAst<T>>, T),
-    // Times(Box<HuckAst<T>>, Box<HuckAst<T>>, T),
-    // Div(Box<HuckAst<T>>, Box<HuckAst<T>>, T),
-    // Let(String, Box<HuckAst<T>>, T),
-    // VarRef(String, T),
-    // Block(Vec<HuckAst<T>>, T),
-    // If(Box<HuckAst<T>>, Box<HuckAst<T>>, Box<HuckAst<T>>, T),
+    // Literals may have a type suffix, as in `255u8`
+    Num(u64, Option<TypeAnn>, T),
+    BoolLit(bool, T),
+    Plus(Box<HuckAst<T>>, Box<HuckAst<T>>, T),
+    Minus(Box<HuckAst<T>>, Box<HuckAst<T>>, T),
+    Times(Box<HuckAst<T>>, Box<HuckAst<T>>, T),
+    Div(Box<HuckAst<T>>, Box<HuckAst<T>>, T),
+    Rem(Box<HuckAst<T>>, Box<HuckAst<T>>, T),
+    // `let x = 1`, or `let mut x = 1` if the flag is set
+    Let(String, bool, Box<HuckAst<T>>, T),
+    VarRef(String, T),
+    Block(Vec<HuckAst<T>>, T),
+    If(Box<HuckAst<T>>, Box<HuckAst<T>>, Box<HuckAst<T>>, T),
+    Equals(Box<HuckAst<T>>, Box<HuckAst<T>>, T),
+    NotEquals(Box<HuckAst<T>>, Box<HuckAst<T>>, T),
+    Less(Box<HuckAst<T>>, Box<HuckAst<T>>, T),
+    LessEq(Box<HuckAst<T>>, Box<HuckAst<T>>, T),
+    Greater(Box<HuckAst<T>>, Box<HuckAst<T>>, T),
+    GreaterEq(Box<HuckAst<T>>, Box<HuckAst<T>>, T),
+    // Function bodies are shared with the interpreter's closures. Generic
+    // functions have type parameters, as in `fn <T>(x: T): T { x }`, and
+    // each can have traits it has to implement: `fn <T: Eq + Ord>`.
+    Fn(Vec<(String, Vec<String>)>, Vec<(String, TypeAnn)>, TypeAnn, Rc<HuckAst<T>>, T),
+    // `f(1)`, or `f::<i64>(1)` with explicit type arguments
+    Call(String, TypeArgList, Vec<HuckAst<T>>, T),
+    Neg(Box<HuckAst<T>>, T),
+    Cast(Box<HuckAst<T>>, TypeAnn, T),
+    // Anything with a decimal point or an `f64` suffix
+    Float(f64, T),
+    // The checker turns calls to builtins into these; the parser never
+    // makes them
+    Builtin(Builtin, Vec<HuckAst<T>>, T),
+    // `struct Point { x: i64, y: i64 }`, or `struct Pair<A, B> { ... }`
+    Struct(String, Vec<String>, Vec<(String, TypeAnn)>, T),
+    // `Point { x: 1, ..p }`, with the fields in the order they're written.
+    // Generic structs can be given type arguments, as in `Pair::<i64, bool> { ... }`.
+    StructLit(String, TypeArgList, Vec<(String, HuckAst<T>)>, Option<Box<HuckAst<T>>>, T),
+    Field(Box<HuckAst<T>>, String, T),
+    // `(1, true)`, or `(1,)` with a single element. Their elements are
+    // fields named by position, as in `t.0`.
+    Tuple(Vec<HuckAst<T>>, T),
+    // `enum Shape { Circle(f64), Empty }`, or `enum Option<T> { ... }`
+    Enum(String, Vec<String>, Vec<(String, Vec<TypeAnn>)>, T),
+    // `Shape::Circle(1.0)`, or `Shape::Empty` with no payload. Type
+    // arguments go in the middle: `Option::<i64>::None`.
+    Variant(String, TypeArgList, String, Vec<HuckAst<T>>, T),
+    Match(Box<HuckAst<T>>, Vec<MatchArm<T>>, T),
+    // `trait Shape { fn area(self): f64 }`
+    Trait(String, Vec<MethodSig>, T),
+    // `impl Shape for Circle { fn area(self): f64 { ... } }`, with each
+    // method as a `Fn`
+    Impl(String, TypeAnn, Vec<(String, HuckAst<T>)>, T),
+    // `shape.area()`. Which trait the method comes from is up to the
+    // checker, which fills it in.
+    MethodCall(Box<HuckAst<T>>, String, Vec<HuckAst<T>>, Option<String>, T),
+    // `x = 1`, or `x += 1` and friends with their operator. The checker
+    // spells those out as `x = x + 1`.
+    Assign(String, Option<AssignOp>, Box<HuckAst<T>>, T),
+    // Any loop can have a label, as in `'outer: while x < 10 { ... }`
+    While(Option<String>, Box<HuckAst<T>>, Box<HuckAst<T>>, T),
+    // `loop { ... }`, whose value is whatever a `break` gives it
+    Loop(Option<String>, Box<HuckAst<T>>, T),
+    // `for i in 0..n { ... }`, counting up from the first number to just
+    // before the second
+    For(Option<String>, String, Box<HuckAst<T>>, Box<HuckAst<T>>, Box<HuckAst<T>>, T),
+    // `break`, `break 'outer` or `break 'outer value`
+    Break(Option<String>, Option<Box<HuckAst<T>>>, T),
+    Continue(Option<String>, T),
+}
+
+// A method as a trait declares it. The first parameter is always `self`,
+// of type `Self`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MethodSig {
+    pub name: String,
+    pub params: Vec<(String, TypeAnn)>,
+    pub ret: TypeAnn,
+}
+
+// `pattern if guard => body`
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatchArm<T> {
+    pub pattern: Pattern<T>,
+    pub guard: Option<HuckAst<T>>,
+    pub body: HuckAst<T>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Pattern<T> {
+    // `_`
+    Wildcard(T),
+    Binding(String, T),
+    // An integer (maybe negated) or bool literal, kept as the expression
+    // it looks like so it's checked the same way
+    Literal(HuckAst<T>),
+    // `Shape::Rect(w, _)`
+    Variant(String, String, Vec<Pattern<T>>, T),
+    // `(x, _)`
+    Tuple(Vec<Pattern<T>>, T),
+}
+
+// Types as written in the source, resolved by the checker
+#[derive(Debug, PartialEq, Clone)]
+pub enum TypeAnn {
+    Unit,
+    Named(String),
+    // A generic type given type arguments, like `Option<i64>`
+    Applied(String, TypeArgList),
+    // `dyn Shape`: some type implementing the trait, only known at runtime
+    Dyn(String),
+    // `(i64, bool)`
+    Tuple(Vec<TypeAnn>),
+}
+
+// Type arguments as written, like the `<i64, bool>` in `Pair<i64, bool>`
+// or `Pair::<i64, bool> { ... }`, and where they were written, which
+// doesn't make them mean anything different
+#[derive(Debug, Clone, Default)]
+pub struct TypeArgList {
+    pub args: Vec<TypeAnn>,
+    pub span: Span,
+}
+
+impl PartialEq for TypeArgList {
+    fn eq(&self, other: &Self) -> bool {
+        self.args == other.args
+    }
+}
+
+impl From<Vec<TypeAnn>> for TypeArgList {
+    fn from(args: Vec<TypeAnn>) -> Self {
+        Self { args, span: Span::default() }
+    }
+}
+
+// Functions every program can call without declaring them. They work on
+// any integer type, which no huck function can, and `print` works on
+// anything that implements `Display`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Builtin {
+    WrappingAdd,
+    WrappingSub,
+    WrappingMul,
+    WrappingRem,
+    Print,
+}
+
+impl Builtin {
+    pub const ALL: [Self; 5] = [Self::WrappingAdd, Self::WrappingSub, Self::WrappingMul, Self::WrappingRem, Self::Print];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|b| b.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::WrappingAdd => "wrapping_add",
+            Self::WrappingSub => "wrapping_sub",
+            Self::WrappingMul => "wrapping_mul",
+            Self::WrappingRem => "wrapping_rem",
+            Self::Print => "print",
+        }
+    }
+}
+
+// The arithmetic in a compound assignment
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AssignOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl AssignOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Self::Add => "+=",
+            Self::Sub => "-=",
+            Self::Mul => "*=",
+            Self::Div => "/=",
+            Self::Rem => "%=",
+        }
+    }
+}
+
+impl<T> HuckAst<T> {
+    pub fn get_metadata(&self) -> &T {
+        match self {
+            Self::Num(_, _, t) => t,
+            Self::BoolLit(_, t) => t,
+            Self::Plus(_, _, t) => t,
+            Self::Minus(_, _, t) => t,
+            Self::Times(_, _, t) => t,
+            Self::Div(_, _, t) => t,
+            Self::Rem(_, _, t) => t,
+            Self::Let(_, _, _, t) => t,
+            Self::VarRef(_, t) => t,
+            Self::Block(_, t) => t,
+            Self::If(_, _, _, t) => t,
+            Self::Equals(_, _, t) => t,
+            Self::NotEquals(_, _, t) => t,
+            Self::Less(_, _, t) => t,
+            Self::LessEq(_, _, t) => t,
+            Self::Greater(_, _, t) => t,
+            Self::GreaterEq(_, _, t) => t,
+            Self::Fn(_, _, _, _, t) => t,
+            Self::Call(_, _, _, t) => t,
+            Self::Neg(_, t) => t,
+            Self::Cast(_, _, t) => t,
+            Self::Float(_, t) => t,
+            Self::Builtin(_, _, t) => t,
+            Self::Struct(_, _, _, t) => t,
+            Self::StructLit(_, _, _, _, t) => t,
+            Self::Field(_, _, t) => t,
+            Self::Tuple(_, t) => t,
+            Self::Enum(_, _, _, t) => t,
+            Self::Variant(_, _, _, _, t) => t,
+            Self::Match(_, _, t) => t,
+            Self::Trait(_, _, t) => t,
+            Self::Impl(_, _, _, t) => t,
+            Self::MethodCall(_, _, _, _, t) => t,
+            Self::Assign(_, _, _, t) => t,
+            Self::While(_, _, _, t) => t,
+            Self::Loop(_, _, t) => t,
+            Self::For(_, _, _, _, _, t) => t,
+            Self::Break(_, _, t) => t,
+            Self::Continue(_, t) => t,
+        }
+    }
+
+    pub fn get_metadata_mut(&mut self) -> &mut T {
+        match self {
+            Self::Num(_, _, t) => t,
+            Self::BoolLit(_, t) => t,
+            Self::Plus(_, _, t) => t,
+            Self::Minus(_, _, t) => t,
+            Self::Times(_, _, t) => t,
+            Self::Div(_, _, t) => t,
+            Self::Rem(_, _, t) => t,
+            Self::Let(_, _, _, t) => t,
+            Self::VarRef(_, t) => t,
+            Self::Block(_, t) => t,
+            Self::If(_, _, _, t) => t,
+            Self::Equals(_, _, t) => t,
+            Self::NotEquals(_, _, t) => t,
+            Self::Less(_, _, t) => t,
+            Self::LessEq(_, _, t) => t,
+            Self::Greater(_, _, t) => t,
+            Self::GreaterEq(_, _, t) => t,
+            Self::Fn(_, _, _, _, t) => t,
+            Self::Call(_, _, _, t) => t,
+            Self::Neg(_, t) => t,
+            Self::Cast(_, _, t) => t,
+            Self::Float(_, t) => t,
+            Self::Builtin(_, _, t) => t,
+            Self::Struct(_, _, _, t) => t,
+            Self::StructLit(_, _, _, _, t) => t,
+            Self::Field(_, _, t) => t,
+            Self::Tuple(_, t) => t,
+            Self::Enum(_, _, _, t) => t,
+            Self::Variant(_, _, _, _, t) => t,
+            Self::Match(_, _, t) => t,
+            Self::Trait(_, _, t) => t,
+            Self::Impl(_, _, _, t) => t,
+            Self::MethodCall(_, _, _, _, t) => t,
+            Self::Assign(_, _, _, t) => t,
+            Self::While(_, _, _, t) => t,
+            Self::Loop(_, _, t) => t,
+            Self::For(_, _, _, _, _, t) => t,
+            Self::Break(_, _, t) => t,
+            Self::Continue(_, t) => t,
+        }
+    }
+
+    /// The expressions directly inside this one, in order. Patterns
+    /// aren't expressions, so they're left out.
+    pub fn children(&self) -> Vec<&HuckAst<T>> {
+        match self {
+            Self::Num(..)
+            | Self::Float(..)
+            | Self::BoolLit(..)
+            | Self::VarRef(..)
+            | Self::Struct(..)
+            | Self::Enum(..)
+            | Self::Trait(..)
+            | Self::Break(_, None, _)
+            | Self::Continue(..) => vec![],
+            Self::Plus(l, r, _)
+            | Self::Minus(l, r, _)
+            | Self::Times(l, r, _)
+            | Self::Div(l, r, _)
+            | Self::Rem(l, r, _)
+            | Self::Equals(l, r, _)
+            | Self::NotEquals(l, r, _)
+            | Self::Less(l, r, _)
+            | Self::LessEq(l, r, _)
+            | Self::Greater(l, r, _)
+            | Self::GreaterEq(l, r, _) => vec![l, r],
+            Self::Neg(operand, _) | Self::Cast(operand, _, _) | Self::Field(operand, _, _) => vec![operand],
+            Self::Let(_, _, init, _) | Self::Assign(_, _, init, _) | Self::Break(_, Some(init), _) => vec![init],
+            Self::Block(exprs, _) | Self::Tuple(exprs, _) => exprs.iter().collect(),
+            Self::While(_, cond, body, _) => vec![cond, body],
+            Self::Loop(_, body, _) => vec![body],
+            Self::For(_, _, from, to, body, _) => vec![from, to, body],
+            Self::If(c, a, b, _) => vec![c, a, b],
+            Self::Fn(_, _, _, body, _) => vec![body],
+            Self::Impl(_, _, methods, _) => methods.iter().map(|(_, method)| method).collect(),
+            Self::MethodCall(receiver, _, args, _, _) => [receiver.as_ref()].into_iter().chain(args).collect(),
+            Self::Call(_, _, args, _) | Self::Builtin(_, args, _) | Self::Variant(_, _, _, args, _) => args.iter().collect(),
+            Self::StructLit(_, _, fields, base, _) => fields.iter().map(|(_, value)| value).chain(base.as_deref()).collect(),
+            Self::Match(scrutinee, arms, _) => {
+                let arms = arms.iter().flat_map(|arm| arm.guard.iter().chain([&arm.body]));
+                [scrutinee.as_ref()].into_iter().chain(arms).collect()
+            },
+        }
+    }
+
+    /// The same tree with `f` applied to every node's metadata.
+    pub fn map_metadata<U>(&self, f: &mut impl FnMut(&T) -> U) -> HuckAst<U> {
+        match self {
+            Self::Num(n, suffix, t) => HuckAst::Num(*n, suffix.clone(), f(t)),
+            Self::BoolLit(b, t) => HuckAst::BoolLit(*b, f(t)),
+            Self::Plus(l, r, t) => HuckAst::Plus(Box::new(l.map_metadata(f)), Box::new(r.map_metadata(f)), f(t)),
+            Self::Minus(l, r, t) => HuckAst::Minus(Box::new(l.map_metadata(f)), Box::new(r.map_metadata(f)), f(t)),
+            Self::Times(l, r, t) => HuckAst::Times(Box::new(l.map_metadata(f)), Box::new(r.map_metadata(f)), f(t)),
+            Self::Div(l, r, t) => HuckAst::Div(Box::new(l.map_metadata(f)), Box::new(r.map_metadata(f)), f(t)),
+            Self::Rem(l, r, t) => HuckAst::Rem(Box::new(l.map_metadata(f)), Box::new(r.map_metadata(f)), f(t)),
+            Self::Let(ident, mutable, e, t) => HuckAst::Let(ident.clone(), *mutable, Box::new(e.map_metadata(f)), f(t)),
+            Self::VarRef(ident, t) => HuckAst::VarRef(ident.clone(), f(t)),
+            Self::Block(exprs, t) => {
+                HuckAst::Block(exprs.iter().map(|e| e.map_metadata(f)).collect(), f(t))
+            },
+            Self::If(c, a, b, t) => HuckAst::If(Box::new(c.map_metadata(f)), Box::new(a.map_metadata(f)), Box::new(b.map_metadata(f)), f(t)),
+            Self::Equals(l, r, t) => HuckAst::Equals(Box::new(l.map_metadata(f)), Box::new(r.map_metadata(f)), f(t)),
+            Self::NotEquals(l, r, t) => HuckAst::NotEquals(Box::new(l.map_metadata(f)), Box::new(r.map_metadata(f)), f(t)),
+            Self::Less(l, r, t) => HuckAst::Less(Box::new(l.map_metadata(f)), Box::new(r.map_metadata(f)), f(t)),
+            Self::LessEq(l, r, t) => HuckAst::LessEq(Box::new(l.map_metadata(f)), Box::new(r.map_metadata(f)), f(t)),
+            Self::Greater(l, r, t) => HuckAst::Greater(Box::new(l.map_metadata(f)), Box::new(r.map_metadata(f)), f(t)),
+            Self::GreaterEq(l, r, t) => HuckAst::GreaterEq(Box::new(l.map_metadata(f)), Box::new(r.map_metadata(f)), f(t)),
+            Self::Fn(type_params, params, ret, body, t) => {
+                HuckAst::Fn(type_params.clone(), params.clone(), ret.clone(), Rc::new(body.map_metadata(f)), f(t))
+            },
+            Self::Call(ident, type_args, args, t) => {
+                HuckAst::Call(ident.clone(), type_args.clone(), args.iter().map(|a| a.map_metadata(f)).collect(), f(t))
+            },
+            Self::Neg(e, t) => HuckAst::Neg(Box::new(e.map_metadata(f)), f(t)),
+            Self::Cast(e, ann, t) => HuckAst::Cast(Box::new(e.map_metadata(f)), ann.clone(), f(t)),
+            Self::Float(x, t) => HuckAst::Float(*x, f(t)),
+            Self::Builtin(builtin, args, t) => {
+                HuckAst::Builtin(*builtin, args.iter().map(|a| a.map_metadata(f)).collect(), f(t))
+            },
+            Self::Struct(name, type_params, fields, t) => HuckAst::Struct(name.clone(), type_params.clone(), fields.clone(), f(t)),
+            Self::StructLit(name, type_args, fields, base, t) => HuckAst::StructLit(
+                name.clone(),
+                type_args.clone(),
+                fields.iter().map(|(field, value)| (field.clone(), value.map_metadata(f))).collect(),
+                base.as_ref().map(|base| Box::new(base.map_metadata(f))),
+                f(t),
+            ),
+            Self::Field(e, field, t) => HuckAst::Field(Box::new(e.map_metadata(f)), field.clone(), f(t)),
+            Self::Tuple(elements, t) => HuckAst::Tuple(elements.iter().map(|e| e.map_metadata(f)).collect(), f(t)),
+            Self::Enum(name, type_params, variants, t) => HuckAst::Enum(name.clone(), type_params.clone(), variants.clone(), f(t)),
+            Self::Variant(name, type_args, variant, args, t) => HuckAst::Variant(
+                name.clone(),
+                type_args.clone(),
+                variant.clone(),
+                args.iter().map(|a| a.map_metadata(f)).collect(),
+                f(t),
+            ),
+            Self::Match(scrutinee, arms, t) => HuckAst::Match(
+                Box::new(scrutinee.map_metadata(f)),
+                arms.iter().map(|arm| MatchArm {
+                    pattern: arm.pattern.map_metadata(f),
+                    guard: arm.guard.as_ref().map(|guard| guard.map_metadata(f)),
+                    body: arm.body.map_metadata(f),
+                }).collect(),
+                f(t),
+            ),
+            Self::Trait(name, methods, t) => HuckAst::Trait(name.clone(), methods.clone(), f(t)),
+            Self::Impl(name, target, methods, t) => HuckAst::Impl(
+                name.clone(),
+                target.clone(),
+                methods.iter().map(|(method, decl)| (method.clone(), decl.map_metadata(f))).collect(),
+                f(t),
+            ),
+            Self::MethodCall(receiver, method, args, trait_name, t) => HuckAst::MethodCall(
+                Box::new(receiver.map_metadata(f)),
+                method.clone(),
+                args.iter().map(|a| a.map_metadata(f)).collect(),
+                trait_name.clone(),
+                f(t),
+            ),
+            Self::Assign(ident, op, value, t) => HuckAst::Assign(ident.clone(), *op, Box::new(value.map_metadata(f)), f(t)),
+            Self::While(label, cond, body, t) => {
+                HuckAst::While(label.clone(), Box::new(cond.map_metadata(f)), Box::new(body.map_metadata(f)), f(t))
+            },
+            Self::Loop(label, body, t) => HuckAst::Loop(label.clone(), Box::new(body.map_metadata(f)), f(t)),
+            Self::For(label, var, start, end, body, t) => HuckAst::For(
+                label.clone(),
+                var.clone(),
+                Box::new(start.map_metadata(f)),
+                Box::new(end.map_metadata(f)),
+                Box::new(body.map_metadata(f)),
+                f(t),
+            ),
+            Self::Break(label, value, t) => HuckAst::Break(label.clone(), value.as_ref().map(|v| Box::new(v.map_metadata(f))), f(t)),
+            Self::Continue(label, t) => HuckAst::Continue(label.clone(), f(t)),
+        }
+    }
 }
 
-// impl<T> HuckAst<T> {
-//     pub fn get_metadata(&self) -> &T {
-//         match self {
-//             Self::Num(_, t) => t,
-//             // Self::BoolLit(_, t) => t,
-//             // Self::Plus(_, _, t) => t,
-//             // Self::Minus(_, _, t) => t,
-//             // Self::Times(_, _, t) => t,
-//             // Self::Div(_, _, t) => t,
-//             // Self::Let(_, _, t) => t,
-//             // Self::VarRef(_, t) => t,
-//             // Self::Block(_, t) => t,
-//             // Self::If(_, _, _, t) => t,
-//         }
-//     }
-// }
+impl<T> Pattern<T> {
+    pub fn get_metadata(&self) -> &T {
+        match self {
+            Self::Wildcard(t) => t,
+            Self::Binding(_, t) => t,
+            Self::Literal(literal) => literal.get_metadata(),
+            Self::Variant(_, _, _, t) => t,
+            Self::Tuple(_, t) => t,
+        }
+    }
+
+    pub fn get_metadata_mut(&mut self) -> &mut T {
+        match self {
+            Self::Wildcard(t) => t,
+            Self::Binding(_, t) => t,
+            Self::Literal(literal) => literal.get_metadata_mut(),
+            Self::Variant(_, _, _, t) => t,
+            Self::Tuple(_, t) => t,
+        }
+    }
+
+    pub fn map_metadata<U>(&self, f: &mut impl FnMut(&T) -> U) -> Pattern<U> {
+        match self {
+            Self::Wildcard(t) => Pattern::Wildcard(f(t)),
+            Self::Binding(name, t) => Pattern::Binding(name.clone(), f(t)),
+            Self::Literal(literal) => Pattern::Literal(literal.map_metadata(f)),
+            Self::Variant(name, variant, fields, t) => Pattern::Variant(
+                name.clone(),
+                variant.clone(),
+                fields.iter().map(|p| p.map_metadata(f)).collect(),
+                f(t),
+            ),
+            Self::Tuple(elements, t) => Pattern::Tuple(elements.iter().map(|p| p.map_metadata(f)).collect(), f(t)),
+        }
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub enum ParseError {
     Eof,
-    Fucked(String),
-    // NotImplemented(String),
+    Fucked(Diagnostic),
+}
+
+impl From<Diagnostic> for ParseError {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Self::Fucked(diagnostic)
+    }
 }
 
 #[derive(Debug, PartialEq, PartialOrd)]
 enum Prec {
-    // Bottom,
-    // Expr,
-    // AddSub,
-    // MultDiv,
-    // Top
+    Bottom,
+    Expr,
+    Compare,
+    AddSub,
+    MultDiv,
+    Cast,
+    Unary,
+    Call,
+    Top
 }
 
 // TODO: better/more idiomatic way of doing this?  possibly look into
 // "custom discriminant values for fieldless enumerations" and
 // #[repr(u8)]
-// impl Prec {
-//     pub fn next(p: Self) -> Self {
-//         match p {
-//             Self::Bottom => Self::Expr,
-//             Self::Expr => Self::Expr,
-//             // Self::Expr => Self::AddSub,
-//             // Self::AddSub => Self::MultDiv,
-//             // Self::MultDiv => Self::Top,
-//             // Self::Top => Self::Top,
-//         }
-//     }
-// }
-
-type TokenStream<'a> = Peekable<Scanner<'a>>;
-
-pub type ParseOutput = HuckAst<()>;
+impl Prec {
+    pub fn next(p: Self) -> Self {
+        match p {
+            Self::Bottom => Self::Expr,
+            Self::Expr => Self::Compare,
+            Self::Compare => Self::AddSub,
+            Self::AddSub => Self::MultDiv,
+            Self::MultDiv => Self::Cast,
+            Self::Cast => Self::Unary,
+            Self::Unary => Self::Call,
+            Self::Call => Self::Top,
+            Self::Top => Self::Top,
+        }
+    }
+}
+
+type TokenStream<'a> = Peekable<Spanned<'a>>;
+
+// Every node knows where it came from, so errors can point at it
+pub type ParseOutput = HuckAst<Span>;
 
 type ParseResult = Result<ParseOutput, ParseError>;
 
-// type PrefixRule<'a> = fn(&mut Parser<'a>, token: Token<'a>) -> ParseResult;
+type PrefixRule<'a> = fn(&mut Parser<'a>, token: Token<'a>) -> ParseResult;
 
-// type InfixRule<'a> = fn(&mut Parser<'a>, token: Token<'a>, lhs: ParseOutput) -> ParseResult;
+type InfixRule<'a> = fn(&mut Parser<'a>, token: Token<'a>, lhs: ParseOutput) -> ParseResult;
 
 pub struct Parser<'a> {
     tokens: TokenStream<'a>,
+    // Span of the last token consumed; nodes end where it does
+    prev_span: Span,
+    // In `if x { ...`, the brace starts the branch, not a struct literal
+    no_struct_literals: bool,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(tokens: TokenStream<'a>) -> Self {
-        Self { tokens }
+    pub fn new(scanner: Scanner<'a>) -> Self {
+        Self { tokens: scanner.spanned().peekable(), prev_span: Span::default(), no_struct_literals: false }
     }
 
     pub fn parse(&mut self) -> ParseResult {
         // Start parsing at lowest precendence
-        // self.parse_prec(Prec::Bottom)
-        let t = self.tokens.next().ok_or(ParseError::Eof)?;
-        self.number(t)
+        self.parse_prec(Prec::Bottom)
+    }
+
+    fn expression(&mut self) -> ParseResult {
+        self.parse_prec(Prec::Expr)
     }
 
-    // fn expression(&mut self) -> ParseResult {
-    //     self.parse_prec(Prec::Expr)
-    // }
+    fn parse_prec(&mut self, prec: Prec) -> ParseResult {
+        // If we're calling parse_prec, we expect there to be another token
+        let t = self.advance()?;
+
+        let prefix_rule = self.get_prefix_rule(t)?;
+        let mut lhs = prefix_rule(self, t)?;
 
-    // fn parse_prec(&mut self, prec: Prec) -> ParseResult {
-    //     // If we're calling parse_prec, we expect there to be another token
-    //     let t = self.tokens.next().ok_or(ParseError::Eof)?;
+        let mut next_prec = self.peek().map(Self::get_prec);
+        // If the next precedence is equal or higher to the current precedence, recur
+        while next_prec.is_some() && prec <= next_prec.unwrap() {
+            let next = self.advance()?;
 
-    //     let prefix_rule = Self::get_prefix_rule(t)?;
-    //     let mut lhs = prefix_rule(self, t)?;
+            let infix_rule = self.get_infix_rule(next)?;
 
-    //     let mut next_prec = self.tokens.peek().map(|next| Self::get_prec(*next));
-    //     // If the next precedence is equal or higher to the current precedence, recur
-    //     while next_prec.is_some() && prec <= next_prec.unwrap() {
-    //         let next = self.tokens.next().unwrap();
+            lhs = infix_rule(self, next, lhs)?;
+            next_prec = self.peek().map(Self::get_prec);
+        }
 
-    //         let infix_rule = Self::get_infix_rule(next)?;
+        Ok(lhs)
+    }
 
-    //         lhs = infix_rule(self, next, lhs)?;
-    //         next_prec = self.tokens.peek().map(|next| Self::get_prec(*next)); // TODO map eta reduce
-    //     }
+    // Run `parse` with struct literals allowed or not, as they were before
+    // afterwards
+    fn struct_literals<T>(&mut self, allowed: bool, parse: impl FnOnce(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        let outer = std::mem::replace(&mut self.no_struct_literals, !allowed);
+        let result = parse(self);
+        self.no_struct_literals = outer;
+        result
+    }
 
-    //     Ok(lhs)
-    // }
+    // From the start of `start` to the end of the last token consumed
+    fn span_from(&self, start: Span) -> Span {
+        Span::new(start.start, self.prev_span.end)
+    }
 
     fn number(&mut self, token: Token) -> ParseResult {
         match token {
             Token::Number(num_str) => {
-                if let Ok(num) = num_str.parse() {
-                    Ok(HuckAst::Num(num, ()))
+                // Whatever follows the digits (and exponent) is a type;
+                // the checker decides whether it's one a number can have
+                let mantissa = num_str.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(num_str.len());
+                let exponent = scanner::exponent_len(&num_str[mantissa..]);
+                let digits = mantissa + exponent;
+                let suffix = match &num_str[digits..] {
+                    "" => None,
+                    name => Some(TypeAnn::Named(name.to_string())),
+                };
+                let is_float = num_str[..mantissa].contains('.') || exponent > 0;
+                let f64_suffix = suffix == Some(TypeAnn::Named("f64".to_string()));
+                if is_float && suffix.is_some() && !f64_suffix {
+                    let has = if exponent > 0 { "an exponent" } else { "a decimal point" };
+                    Err(Diagnostic::syntax(Code::BAD_FLOAT_SUFFIX, format!("`{}` has {}, so it can't be an integer", num_str, has), self.prev_span)
+                        .with_note("floats can only have the suffix `f64`")
+                        .into())
+                } else if is_float || f64_suffix {
+                    // Any string of digits is some float, if only infinity
+                    let value = num_str[..digits].parse().expect("The scanner only produces valid floats");
+                    Ok(HuckAst::Float(value, self.prev_span))
+                } else if let Ok(num) = num_str[..digits].parse() {
+                    Ok(HuckAst::Num(num, suffix, self.prev_span))
                 } else {
-                    Err(ParseError::Fucked(format!("Failed to parse number {}", num_str)))
+                    Err(Diagnostic::syntax(Code::NUMBER_TOO_BIG, format!("Number `{}` is too big", num_str), self.prev_span)
+                        .with_note(format!("the largest number is {}", u64::MAX))
+                        .into())
+                }
+            }
+            _ => Err(Self::unexpected("a number", token, self.prev_span))
+        }
+    }
+
+    fn binary(&mut self,
+              f: fn (Box<ParseOutput>, Box<ParseOutput>, Span) -> ParseOutput,
+              prec: Prec,
+              lhs: ParseOutput
+    ) -> ParseResult {
+        let rhs = self.parse_prec(Prec::next(prec))?;
+        let span = lhs.get_metadata().to(*rhs.get_metadata());
+        Ok(f(Box::new(lhs), Box::new(rhs), span))
+    }
+
+    fn plus(&mut self, _token: Token<'a>, lhs: ParseOutput) -> ParseResult {
+        self.binary(HuckAst::Plus, Prec::AddSub, lhs)
+    }
+
+    fn minus(&mut self, _token: Token<'a>, lhs: ParseOutput) -> ParseResult {
+        self.binary(HuckAst::Minus, Prec::AddSub, lhs)
+    }
+
+    fn times(&mut self, _token: Token<'a>, lhs: ParseOutput) -> ParseResult {
+        self.binary(HuckAst::Times, Prec::MultDiv, lhs)
+    }
+
+    fn div(&mut self, _token: Token<'a>, lhs: ParseOutput) -> ParseResult {
+        self.binary(HuckAst::Div, Prec::MultDiv, lhs)
+    }
+
+    fn rem(&mut self, _token: Token<'a>, lhs: ParseOutput) -> ParseResult {
+        self.binary(HuckAst::Rem, Prec::MultDiv, lhs)
+    }
+
+    fn equals(&mut self, _token: Token<'a>, lhs: ParseOutput) -> ParseResult {
+        self.binary(HuckAst::Equals, Prec::Compare, lhs)
+    }
+
+    fn not_equals(&mut self, _token: Token<'a>, lhs: ParseOutput) -> ParseResult {
+        self.binary(HuckAst::NotEquals, Prec::Compare, lhs)
+    }
+
+    fn less(&mut self, _token: Token<'a>, lhs: ParseOutput) -> ParseResult {
+        self.binary(HuckAst::Less, Prec::Compare, lhs)
+    }
+
+    fn less_eq(&mut self, _token: Token<'a>, lhs: ParseOutput) -> ParseResult {
+        self.binary(HuckAst::LessEq, Prec::Compare, lhs)
+    }
+
+    fn greater(&mut self, _token: Token<'a>, lhs: ParseOutput) -> ParseResult {
+        self.binary(HuckAst::Greater, Prec::Compare, lhs)
+    }
+
+    fn greater_eq(&mut self, _token: Token<'a>, lhs: ParseOutput) -> ParseResult {
+        self.binary(HuckAst::GreaterEq, Prec::Compare, lhs)
+    }
+
+    fn negate(&mut self, _token: Token<'a>) -> ParseResult {
+        let start = self.prev_span;
+        let operand = self.parse_prec(Prec::Unary)?;
+        Ok(HuckAst::Neg(Box::new(operand), self.span_from(start)))
+    }
+
+    // Casts are only to numbers, so in `x as i64 < y` the `<` is a
+    // comparison rather than the start of some type arguments
+    fn cast(&mut self, _token: Token<'a>, lhs: ParseOutput) -> ParseResult {
+        let start = *lhs.get_metadata();
+        let target = self.simple_type_ann()?;
+        Ok(HuckAst::Cast(Box::new(lhs), target, self.span_from(start)))
+    }
+
+    fn call(&mut self, _token: Token<'a>, lhs: ParseOutput) -> ParseResult {
+        let start = *lhs.get_metadata();
+        let ident = match lhs {
+            HuckAst::VarRef(ident, _) => ident,
+            _ => return Err(Diagnostic::syntax(Code::BAD_CALLEE, "Can only call functions by name", start).into()),
+        };
+        self.call_args(ident, TypeArgList::default(), start)
+    }
+
+    // The arguments of a call, after its opening parenthesis
+    fn call_args(&mut self, ident: String, type_args: TypeArgList, start: Span) -> ParseResult {
+        let args = self.struct_literals(true, |parser| {
+            let mut args = vec![];
+            if !parser.next_is(Token::RParen) {
+                args.push(parser.expression()?);
+                while parser.next_is(Token::Comma) {
+                    parser.consume(Token::Comma)?;
+                    args.push(parser.expression()?);
+                }
+            }
+            Ok(args)
+        })?;
+        self.consume(Token::RParen)?;
+
+        Ok(HuckAst::Call(ident, type_args, args, self.span_from(start)))
+    }
+
+    // `p.x`, `t.0` for an element of a tuple, or `p.area()` if it's a
+    // method
+    fn field(&mut self, _token: Token<'a>, lhs: ParseOutput) -> ParseResult {
+        let start = *lhs.get_metadata();
+        if let Some(Token::Number(digits)) = self.peek() {
+            self.advance()?;
+            return self.elements(lhs, digits, start)
+        }
+        let field = self.identifier()?;
+        if self.next_is(Token::LParen) {
+            self.advance()?;
+            let args = self.struct_literals(true, |parser| parser.comma_separated(Token::RParen, Self::expression))?;
+            self.consume(Token::RParen)?;
+            return Ok(HuckAst::MethodCall(Box::new(lhs), field, args, None, self.span_from(start)))
+        }
+        self.unassigned(HuckAst::Field(Box::new(lhs), field, self.span_from(start)))
+    }
+
+    // `t.0`. The scanner sees `t.0.1` as `t` and the float `0.1`, which
+    // is two elements in a row.
+    fn elements(&mut self, mut tuple: ParseOutput, digits: &str, start: Span) -> ParseResult {
+        let span = self.prev_span;
+        let mut end = span.start;
+        for index in digits.split('.') {
+            if index.is_empty() || !index.chars().all(|c| c.is_ascii_digit()) {
+                return Err(Self::unexpected("a field", Token::Number(digits), span))
+            }
+            end += index.len();
+            tuple = HuckAst::Field(Box::new(tuple), index.to_string(), Span::new(start.start, end));
+            end += 1;
+        }
+        self.unassigned(tuple)
+    }
+
+    // Only variables can be assigned to, but `p.x = 5` is the first thing
+    // anyone tries, and otherwise it's an unhelpful "Expected `}`"
+    fn unassigned(&mut self, field: ParseOutput) -> ParseResult {
+        match self.tokens.peek() {
+            Some(&(t, _)) if is_assignment(t) => {
+                let span = *field.get_metadata();
+                Err(Diagnostic::syntax(Code::FIELD_ASSIGNMENT, "Can't assign to a field", span)
+                    .with_label(span, "only variables can be assigned to, not their fields")
+                    .with_help("assign a whole new value to the variable instead; a struct literal ending in `..old` copies the fields it doesn't set")
+                    .into())
+            },
+            _ => Ok(field),
+        }
+    }
+
+    // `(1 + 2)`, or a tuple if there's a comma: `(1, true)`
+    fn grouping(&mut self, _token: Token<'a>) -> ParseResult {
+        let start = self.prev_span;
+        let first = self.struct_literals(true, Self::expression)?;
+        if !self.next_is(Token::Comma) {
+            self.consume(Token::RParen)?;
+            return Ok(first)
+        }
+        self.advance()?;
+        let mut elements = vec![first];
+        elements.extend(self.struct_literals(true, |parser| parser.comma_separated(Token::RParen, Self::expression))?);
+        self.consume(Token::RParen)?;
+        Ok(HuckAst::Tuple(elements, self.span_from(start)))
+    }
+
+    fn block(&mut self, _token: Token<'a>) -> ParseResult {
+        self.struct_literals(true, Self::block_body)
+    }
+
+    fn block_body(&mut self) -> ParseResult {
+        let start = self.prev_span;
+        if self.next_is(Token::RBrace) {
+            self.advance()?;
+            return Err(Diagnostic::syntax(Code::EMPTY_BLOCK, "Empty block", self.span_from(start))
+                .with_note("blocks need at least one expression")
+                .into());
+        }
+
+        let mut exprs = vec![self.expression()?];
+
+        // There's probably a better way to do this pattern
+        while self.next_is(Token::Semicolon) {
+            self.consume(Token::Semicolon)?;
+            exprs.push(self.expression()?);
+        }
+        self.consume(Token::RBrace)?;
+        let res = HuckAst::Block(exprs, self.span_from(start));
+        Ok(res)
+    }
+
+    fn let_decl(&mut self, _token: Token<'a>) -> ParseResult {
+        let start = self.prev_span;
+        let mutable = self.next_is(Token::Mut);
+        if mutable {
+            self.advance()?;
+        }
+        let ident = self.identifier()?;
+
+        self.consume(Token::SingleEq)?;
+
+        let expr = self.expression()?;
+
+        Ok(HuckAst::Let(ident, mutable, Box::new(expr), self.span_from(start)))
+    }
+
+    // `x = 1` or `x += 1`, which like `let` takes everything after it as
+    // the value
+    fn assignment(&mut self, ident: &str, start: Span) -> ParseResult {
+        let op = match self.advance()? {
+            Token::SingleEq => None,
+            Token::PlusEq => Some(AssignOp::Add),
+            Token::MinusEq => Some(AssignOp::Sub),
+            Token::StarEq => Some(AssignOp::Mul),
+            Token::SlashEq => Some(AssignOp::Div),
+            Token::PercentEq => Some(AssignOp::Rem),
+            t => unreachable!("{} isn't an assignment", t),
+        };
+        let value = self.expression()?;
+        Ok(HuckAst::Assign(ident.to_string(), op, Box::new(value), self.span_from(start)))
+    }
+
+    fn var_ref(&mut self, token: Token<'a>) -> ParseResult {
+        match token {
+            Token::Var(ident) if self.peek().is_some_and(is_assignment) => self.assignment(ident, self.prev_span),
+            Token::Var(ident) if self.next_is(Token::ColonColon) => self.path(ident),
+            Token::Var(ident) if self.next_is(Token::LBrace) && !self.no_struct_literals => {
+                self.struct_literal(ident, TypeArgList::default(), self.prev_span)
+            },
+            Token::Var(ident) => Ok(HuckAst::VarRef(ident.to_string(), self.prev_span)),
+            _ => Err(Self::unexpected("a variable", token, self.prev_span))
+        }
+    }
+
+    fn struct_literal(&mut self, name: &str, type_args: TypeArgList, start: Span) -> ParseResult {
+        self.consume(Token::LBrace)?;
+
+        let mut fields = vec![];
+        let mut base = None;
+        self.struct_literals(true, |parser| {
+            while !parser.next_is(Token::RBrace) {
+                // `..base` fills in the rest, so it has to come last
+                if parser.next_is(Token::DotDot) {
+                    parser.advance()?;
+                    base = Some(Box::new(parser.expression()?));
+                    break;
+                }
+                let field = parser.identifier()?;
+                parser.consume(Token::Colon)?;
+                fields.push((field, parser.expression()?));
+                if !parser.next_is(Token::Comma) {
+                    break;
+                }
+                parser.consume(Token::Comma)?;
+            }
+            Ok(())
+        })?;
+        self.consume(Token::RBrace)?;
+
+        Ok(HuckAst::StructLit(name.to_string(), type_args, fields, base, self.span_from(start)))
+    }
+
+    // Whatever comes after `name::`: usually a variant, as in
+    // `Shape::Circle(1.0)`. Type arguments can come first, and then
+    // there's also a call or struct literal they might be for.
+    fn path(&mut self, name: &str) -> ParseResult {
+        let start = self.prev_span;
+        self.consume(Token::ColonColon)?;
+
+        let mut type_args = TypeArgList::default();
+        if self.next_is(Token::Less) {
+            type_args = self.type_args()?;
+            if self.next_is(Token::LParen) {
+                self.advance()?;
+                return self.call_args(name.to_string(), type_args, start);
+            }
+            if self.next_is(Token::LBrace) && !self.no_struct_literals {
+                return self.struct_literal(name, type_args, start);
+            }
+            self.consume(Token::ColonColon)?;
+        }
+        let variant = self.identifier()?;
+
+        let mut args = vec![];
+        if self.next_is(Token::LParen) {
+            self.advance()?;
+            args = self.struct_literals(true, |parser| parser.comma_separated(Token::RParen, Self::expression))?;
+            self.consume(Token::RParen)?;
+        }
+
+        Ok(HuckAst::Variant(name.to_string(), type_args, variant, args, self.span_from(start)))
+    }
+
+    // Zero or more of `item`, up to (but not including) `close`, with an
+    // optional trailing comma
+    fn comma_separated<T>(&mut self, close: Token, item: fn(&mut Self) -> Result<T, ParseError>) -> Result<Vec<T>, ParseError> {
+        let mut items = vec![];
+        while !self.next_is(close) {
+            items.push(item(self)?);
+            if !self.next_is(Token::Comma) {
+                break;
+            }
+            self.consume(Token::Comma)?;
+        }
+        Ok(items)
+    }
+
+    fn enum_decl(&mut self, _token: Token<'a>) -> ParseResult {
+        let start = self.prev_span;
+        let name = self.identifier()?;
+        let type_params = self.type_params()?;
+        self.consume(Token::LBrace)?;
+
+        let mut variants = vec![];
+        while !self.next_is(Token::RBrace) {
+            let variant = self.identifier()?;
+            let mut payload = vec![];
+            if self.next_is(Token::LParen) {
+                self.advance()?;
+                payload = self.comma_separated(Token::RParen, Self::type_ann)?;
+                self.consume(Token::RParen)?;
+            }
+            variants.push((variant, payload));
+            if !self.next_is(Token::Comma) {
+                break;
+            }
+            self.consume(Token::Comma)?;
+        }
+        self.consume(Token::RBrace)?;
+
+        Ok(HuckAst::Enum(name, type_params, variants, self.span_from(start)))
+    }
+
+    fn match_expr(&mut self, _token: Token<'a>) -> ParseResult {
+        let start = self.prev_span;
+        let scrutinee = self.struct_literals(false, Self::expression)?;
+        self.consume(Token::LBrace)?;
+        let brace = self.prev_span;
+
+        let arms = self.struct_literals(true, |parser| {
+            let mut arms = vec![];
+            while !parser.next_is(Token::RBrace) {
+                let pattern = parser.pattern()?;
+                let guard = if parser.next_is(Token::If) {
+                    parser.advance()?;
+                    Some(parser.expression()?)
+                } else {
+                    None
+                };
+                parser.consume(Token::FatArrow)?;
+                let body = parser.expression()?;
+                arms.push(MatchArm { pattern, guard, body });
+                if !parser.next_is(Token::Comma) {
+                    break;
+                }
+                parser.consume(Token::Comma)?;
+            }
+            Ok(arms)
+        })?;
+        self.consume(Token::RBrace)?;
+
+        if arms.is_empty() {
+            return Err(Diagnostic::syntax(Code::EMPTY_MATCH, "Match with no arms", self.span_from(brace))
+                .with_note("a match needs at least one arm")
+                .into());
+        }
+        Ok(HuckAst::Match(Box::new(scrutinee), arms, self.span_from(start)))
+    }
+
+    fn pattern(&mut self) -> Result<Pattern<Span>, ParseError> {
+        let token = self.advance()?;
+        let start = self.prev_span;
+        match token {
+            Token::Var("_") => Ok(Pattern::Wildcard(start)),
+            Token::Var(name) if self.next_is(Token::ColonColon) => {
+                self.advance()?;
+                let variant = self.identifier()?;
+                let mut fields = vec![];
+                if self.next_is(Token::LParen) {
+                    self.advance()?;
+                    fields = self.comma_separated(Token::RParen, Self::pattern)?;
+                    self.consume(Token::RParen)?;
+                }
+                Ok(Pattern::Variant(name.to_string(), variant, fields, self.span_from(start)))
+            },
+            Token::Var(name) => Ok(Pattern::Binding(name.to_string(), start)),
+            Token::LParen => {
+                let first = self.pattern()?;
+                if !self.next_is(Token::Comma) {
+                    self.consume(Token::RParen)?;
+                    return Ok(first)
+                }
+                self.advance()?;
+                let mut elements = vec![first];
+                elements.extend(self.comma_separated(Token::RParen, Self::pattern)?);
+                self.consume(Token::RParen)?;
+                Ok(Pattern::Tuple(elements, self.span_from(start)))
+            },
+            Token::True | Token::False => Ok(Pattern::Literal(self.bool_lit(token)?)),
+            Token::Number(_) => self.literal_pattern(token, start),
+            Token::Minus => {
+                let number = self.advance()?;
+                let literal = self.literal_pattern(number, self.prev_span)?;
+                match literal {
+                    Pattern::Literal(num) => Ok(Pattern::Literal(HuckAst::Neg(Box::new(num), self.span_from(start)))),
+                    _ => unreachable!("Literal patterns are literals"),
+                }
+            },
+            t => Err(Self::unexpected("a pattern", t, start)),
+        }
+    }
+
+    // Floats can't be compared exactly enough to match on
+    fn literal_pattern(&mut self, token: Token<'a>, span: Span) -> Result<Pattern<Span>, ParseError> {
+        match self.number(token)? {
+            HuckAst::Float(..) => Err(Diagnostic::syntax(Code::BAD_PATTERN, "Floats can't be used as patterns", span)
+                .with_label(span, "float literal")
+                .with_help("bind the value and compare it in a guard with `if`")
+                .into()),
+            num => Ok(Pattern::Literal(num)),
+        }
+    }
+
+    fn struct_decl(&mut self, _token: Token<'a>) -> ParseResult {
+        let start = self.prev_span;
+        let name = self.identifier()?;
+        let type_params = self.type_params()?;
+        self.consume(Token::LBrace)?;
+
+        let mut fields = vec![];
+        while !self.next_is(Token::RBrace) {
+            fields.push(self.param()?);
+            if !self.next_is(Token::Comma) {
+                break;
+            }
+            self.consume(Token::Comma)?;
+        }
+        self.consume(Token::RBrace)?;
+
+        Ok(HuckAst::Struct(name, type_params, fields, self.span_from(start)))
+    }
+
+    fn bool_lit(&mut self, token: Token<'a>) -> ParseResult {
+        match token {
+            Token::True => Ok(HuckAst::BoolLit(true, self.prev_span)),
+            Token::False => Ok(HuckAst::BoolLit(false, self.prev_span)),
+            _ => Err(Self::unexpected("a boolean", token, self.prev_span)),
+        }
+    }
+
+    fn conditional(&mut self, token: Token<'a>) -> ParseResult {
+        let start = self.prev_span;
+        let test = self.struct_literals(false, Self::expression)?;
+        self.consume(Token::LBrace)?;
+        let true_branch = self.block(token)?;
+        self.consume(Token::Else)?;
+        self.consume(Token::LBrace)?;
+        let else_branch = self.block(token)?;
+        Ok(HuckAst::If(Box::new(test), Box::new(true_branch), Box::new(else_branch), self.span_from(start)))
+    }
+
+    fn unlabeled_loop(&mut self, token: Token<'a>) -> ParseResult {
+        self.loop_expr(token, None, self.prev_span)
+    }
+
+    // `'outer: loop { ... }`
+    fn labeled_loop(&mut self, token: Token<'a>) -> ParseResult {
+        let start = self.prev_span;
+        let Token::Label(label) = token else { unreachable!("{} isn't a label", token) };
+        self.consume(Token::Colon)?;
+        match self.advance()? {
+            t @ (Token::While | Token::Loop | Token::For) => self.loop_expr(t, Some(label.to_string()), start),
+            t => Err(Self::unexpected("a loop after the label", t, self.prev_span)),
+        }
+    }
+
+    fn loop_expr(&mut self, token: Token<'a>, label: Option<String>, start: Span) -> ParseResult {
+        match token {
+            Token::While => {
+                let cond = self.struct_literals(false, Self::expression)?;
+                let body = self.loop_body()?;
+                Ok(HuckAst::While(label, Box::new(cond), Box::new(body), self.span_from(start)))
+            },
+            Token::Loop => {
+                let body = self.loop_body()?;
+                Ok(HuckAst::Loop(label, Box::new(body), self.span_from(start)))
+            },
+            Token::For => {
+                let var = self.identifier()?;
+                self.consume(Token::In)?;
+                let from = self.struct_literals(false, Self::expression)?;
+                self.consume(Token::DotDot)?;
+                let to = self.struct_literals(false, Self::expression)?;
+                let body = self.loop_body()?;
+                Ok(HuckAst::For(label, var, Box::new(from), Box::new(to), Box::new(body), self.span_from(start)))
+            },
+            _ => unreachable!("{} doesn't start a loop", token),
+        }
+    }
+
+    fn loop_body(&mut self) -> ParseResult {
+        self.consume(Token::LBrace)?;
+        self.block(Token::LBrace)
+    }
+
+    // `break` takes a value if anything but the end of an expression
+    // follows it
+    fn break_expr(&mut self, _token: Token<'a>) -> ParseResult {
+        let start = self.prev_span;
+        let label = self.label()?;
+        let value = match self.peek() {
+            None | Some(Token::Semicolon | Token::RBrace | Token::RParen | Token::Comma) => None,
+            Some(_) => Some(Box::new(self.expression()?)),
+        };
+        Ok(HuckAst::Break(label, value, self.span_from(start)))
+    }
+
+    fn continue_expr(&mut self, _token: Token<'a>) -> ParseResult {
+        let start = self.prev_span;
+        let label = self.label()?;
+        Ok(HuckAst::Continue(label, self.span_from(start)))
+    }
+
+    fn label(&mut self) -> Result<Option<String>, ParseError> {
+        match self.peek() {
+            Some(Token::Label(label)) => {
+                self.advance()?;
+                Ok(Some(label.to_string()))
+            },
+            _ => Ok(None),
+        }
+    }
+
+    fn function(&mut self, _token: Token<'a>) -> ParseResult {
+        let start = self.prev_span;
+        let type_params = self.bounded_type_params()?;
+        self.consume(Token::LParen)?;
+
+        let mut params = vec![];
+        if !self.next_is(Token::RParen) {
+            params.push(self.param()?);
+            while self.next_is(Token::Comma) {
+                self.consume(Token::Comma)?;
+                params.push(self.param()?);
+            }
+        }
+        self.consume(Token::RParen)?;
+
+        self.consume(Token::Colon)?;
+        let return_type = self.type_ann()?;
+        let body = self.expression()?;
+
+        Ok(HuckAst::Fn(type_params, params, return_type, Rc::new(body), self.span_from(start)))
+    }
+
+    // `trait Shape { fn area(self): f64; fn scale(self, by: f64): Self }`
+    fn trait_decl(&mut self, _token: Token<'a>) -> ParseResult {
+        let start = self.prev_span;
+        let name = self.identifier()?;
+        self.consume(Token::LBrace)?;
+        let methods = self.semicolon_separated(|parser| {
+            let (name, params) = parser.method_head()?;
+            parser.consume(Token::Colon)?;
+            Ok(MethodSig { name, params, ret: parser.type_ann()? })
+        })?;
+        self.consume(Token::RBrace)?;
+        Ok(HuckAst::Trait(name, methods, self.span_from(start)))
+    }
+
+    // `impl Shape for Circle { fn area(self): f64 { 3.0 * self.r * self.r } }`
+    fn impl_decl(&mut self, _token: Token<'a>) -> ParseResult {
+        let start = self.prev_span;
+        let name = self.identifier()?;
+        self.consume(Token::For)?;
+        let target = self.type_ann()?;
+        self.consume(Token::LBrace)?;
+        let methods = self.struct_literals(true, |parser| parser.semicolon_separated(|parser| {
+            let method_start = parser.peek_span();
+            let (name, params) = parser.method_head()?;
+            parser.consume(Token::Colon)?;
+            let ret = parser.type_ann()?;
+            let body = parser.expression()?;
+            Ok((name, HuckAst::Fn(vec![], params, ret, Rc::new(body), parser.span_from(method_start))))
+        }))?;
+        self.consume(Token::RBrace)?;
+        Ok(HuckAst::Impl(name, target, methods, self.span_from(start)))
+    }
+
+    // Zero or more of `item` up to a closing brace, separated by
+    // semicolons like the expressions in a block, with an optional
+    // trailing one
+    fn semicolon_separated<T>(&mut self, item: impl Fn(&mut Self) -> Result<T, ParseError>) -> Result<Vec<T>, ParseError> {
+        let mut items = vec![];
+        while !self.next_is(Token::RBrace) {
+            items.push(item(self)?);
+            if !self.next_is(Token::Semicolon) {
+                break;
+            }
+            self.consume(Token::Semicolon)?;
+        }
+        Ok(items)
+    }
+
+    // `fn name(self, x: i64)`: a method's name and parameters, `self`
+    // included
+    fn method_head(&mut self) -> Result<(String, Vec<(String, TypeAnn)>), ParseError> {
+        self.consume(Token::Fn)?;
+        let name = self.identifier()?;
+        self.consume(Token::LParen)?;
+        match self.tokens.peek() {
+            Some((Token::Var("self"), _)) => self.advance()?,
+            Some(&(t, span)) => {
+                return Err(Diagnostic::syntax(Code::MISSING_SELF, format!("Method `{}` has to take `self` first", name), span)
+                    .with_label(span, format!("expected `self`, found `{}`", t))
+                    .with_note("methods are called on a value, as in `x.method()`, which is their `self`")
+                    .into())
+            },
+            None => return Err(ParseError::Eof),
+        };
+        let mut params = vec![(String::from("self"), TypeAnn::Named(String::from("Self")))];
+        while self.next_is(Token::Comma) {
+            self.consume(Token::Comma)?;
+            if self.next_is(Token::RParen) {
+                break;
+            }
+            params.push(self.param()?);
+        }
+        self.consume(Token::RParen)?;
+        Ok((name, params))
+    }
+
+    fn param(&mut self) -> Result<(String, TypeAnn), ParseError> {
+        let ident = self.identifier()?;
+        self.consume(Token::Colon)?;
+        Ok((ident, self.type_ann()?))
+    }
+
+    fn type_ann(&mut self) -> Result<TypeAnn, ParseError> {
+        match self.simple_type_ann()? {
+            TypeAnn::Named(name) if self.next_is(Token::Less) => Ok(TypeAnn::Applied(name, self.type_args()?)),
+            ann => Ok(ann),
+        }
+    }
+
+    // A type without any type arguments
+    fn simple_type_ann(&mut self) -> Result<TypeAnn, ParseError> {
+        match self.advance()? {
+            Token::LParen if self.next_is(Token::RParen) => {
+                self.advance()?;
+                Ok(TypeAnn::Unit)
+            },
+            // `(i64, bool)`, or just a type in parentheses without a comma
+            Token::LParen => {
+                let first = self.type_ann()?;
+                if !self.next_is(Token::Comma) {
+                    self.consume(Token::RParen)?;
+                    return Ok(first)
+                }
+                self.advance()?;
+                let mut elements = vec![first];
+                elements.extend(self.comma_separated(Token::RParen, Self::type_ann)?);
+                self.consume(Token::RParen)?;
+                Ok(TypeAnn::Tuple(elements))
+            },
+            Token::Var(name) => Ok(TypeAnn::Named(name.to_string())),
+            Token::Dyn => Ok(TypeAnn::Dyn(self.identifier()?)),
+            t => Err(Self::unexpected("a type", t, self.prev_span)),
+        }
+    }
+
+    // `<i64, Option<bool>>`
+    fn type_args(&mut self) -> Result<TypeArgList, ParseError> {
+        let start = self.peek_span();
+        self.consume(Token::Less)?;
+        let args = self.comma_separated(Token::Greater, Self::type_ann)?;
+        self.consume(Token::Greater)?;
+        Ok(TypeArgList { args, span: self.span_from(start) })
+    }
+
+    // `<A, B>` after the name of something generic, if it is
+    fn type_params(&mut self) -> Result<Vec<String>, ParseError> {
+        if !self.next_is(Token::Less) {
+            return Ok(vec![]);
+        }
+        self.advance()?;
+        let params = self.comma_separated(Token::Greater, Self::identifier)?;
+        self.consume(Token::Greater)?;
+        Ok(params)
+    }
+
+    // A function's type parameters, which can have bounds: `<T: Eq + Ord, U>`
+    fn bounded_type_params(&mut self) -> Result<Vec<(String, Vec<String>)>, ParseError> {
+        if !self.next_is(Token::Less) {
+            return Ok(vec![]);
+        }
+        self.advance()?;
+        let params = self.comma_separated(Token::Greater, |parser| {
+            let name = parser.identifier()?;
+            let mut bounds = vec![];
+            if parser.next_is(Token::Colon) {
+                parser.advance()?;
+                bounds.push(parser.identifier()?);
+                while parser.next_is(Token::Plus) {
+                    parser.advance()?;
+                    bounds.push(parser.identifier()?);
                 }
             }
-            // _ => Err(ParseError::Fucked(format!("Expected number, found {:?}", token)))
-        }
-    }
-
-    // fn binary(&mut self,
-    //           f: fn (Box<ParseOutput>, Box<ParseOutput>, ()) -> ParseOutput,
-    //           prec: Prec,
-    //           lhs: ParseOutput
-    // ) -> ParseResult {
-    //     let rhs = self.parse_prec(Prec::next(prec))?;
-    //     Ok(f(Box::new(lhs), Box::new(rhs), ()))
-    // }
-
-    // fn plus(&mut self, _token: Token<'a>, lhs: ParseOutput) -> ParseResult {
-    //     self.binary(HuckAst::Plus, Prec::AddSub, lhs)
-    // }
-
-    // fn minus(&mut self, _token: Token<'a>, lhs: ParseOutput) -> ParseResult {
-    //     self.binary(HuckAst::Minus, Prec::AddSub, lhs)
-    // }
-
-    // fn times(&mut self, _token: Token<'a>, lhs: ParseOutput) -> ParseResult {
-    //     self.binary(HuckAst::Times, Prec::MultDiv, lhs)
-    // }
-
-    // fn div(&mut self, _token: Token<'a>, lhs: ParseOutput) -> ParseResult {
-    //     self.binary(HuckAst::Div, Prec::MultDiv, lhs)
-    // }
-
-    // fn grouping(&mut self, _token: Token<'a>) -> ParseResult {
-    //     let grouping = self.expression()?;
-    //     self.consume(Token::RParen)?;
-    //     Ok(grouping)
-    // }
-
-    // fn block(&mut self, _token: Token<'a>) -> ParseResult {
-    //     if self.next_is(Token::RBrace) {
-    //         return Err(ParseError::Fucked("Empty block".to_string()));
-    //     }
-
-    //     let mut exprs = vec![self.expression()?];
-    //     let mut next = self.tokens.peek();
-
-    //     // There's probably a better way to do this pattern
-    //     while next.is_some() && *next.unwrap() == Token::Semicolon {
-    //         self.consume(Token::Semicolon)?;
-    //         exprs.push(self.expression()?);
-
-    //         next = self.tokens.peek();
-    //     }
-    //     self.consume(Token::RBrace)?;
-    //     let res = HuckAst::Block(exprs, ());
-    //     Ok(res)
-    // }
-
-    // fn let_decl(&mut self, _token: Token<'a>) -> ParseResult {
-    //     let ident = match self.tokens.next() {
-    //         Some(Token::Var(ident)) => Ok(ident),
-    //         Some(t) => Err(ParseError::Fucked(format!("Expected identifier, found {:?}", t))),
-    //         None => Err(ParseError::Eof),
-    //     }?;
-
-    //     self.consume(Token::SingleEq)?;
-
-    //     let expr = self.expression()?;
-
-    //     Ok(HuckAst::Let(ident.to_string(), Box::new(expr), ()))
-    // }
-
-    // fn var_ref(&mut self, token: Token<'a>) -> ParseResult {
-    //     match token {
-    //         Token::Var(ident) => Ok(HuckAst::VarRef(ident.to_string(), ())),
-    //         _ => Err(ParseError::Fucked("Expected variable reference".to_string()))
-    //     }
-    // }
-
-    // fn bool_lit(&mut self, token: Token<'a>) -> ParseResult {
-    //     match token {
-    //         Token::True => Ok(HuckAst::BoolLit(true, ())),
-    //         Token::False => Ok(HuckAst::BoolLit(false, ())),
-    //         _ => Err(ParseError::Fucked(format!("Bad boolean literal {:?}", token))),
-    //     }
-    // }
-
-    // fn conditional(&mut self, token: Token<'a>) -> ParseResult {
-    //     let test = self.expression()?;
-    //     self.consume(Token::LBrace)?;
-    //     let true_branch = self.block(token)?;
-    //     self.consume(Token::Else)?;
-    //     self.consume(Token::LBrace)?;
-    //     let else_branch = self.block(token)?;
-    //     Ok(HuckAst::If(Box::new(test), Box::new(true_branch), Box::new(else_branch), ()))
-    // }
-
-    // fn next_is(&mut self, token: Token) -> bool {
-    //     match self.tokens.peek() {
-    //         Some(t) if *t == token => true,
-    //         _ => false
-    //     }
-    // }
-
-    // fn consume(&mut self, token: Token) -> Result<(), ParseError> {
-    //     match self.tokens.peek() {
-    //         Some(t) if *t == token => {
-    //             let _ = self.tokens.next();
-    //             Ok(())
-    //         },
-    //         Some(t) => Err(ParseError::Fucked(format!("Expected token {:?}, found {:?}", token, t))),
-    //         _ => Err(ParseError::Eof),
-    //     }
-    // }
-
-    // fn get_infix_rule(t: Token) -> Result<InfixRule<'a>, ParseError> {
-    //     match t {
-    //         // Token::Plus => Ok(Self::plus),
-    //         // Token::Minus => Ok(Self::minus),
-    //         // Token::Star => Ok(Self::times),
-    //         // Token::Slash => Ok(Self::div),
-    //         _ => Err(ParseError::NotImplemented(format!("No infix rule for token type {:?}", t))),
-    //     }
-    // }
+            Ok((name, bounds))
+        })?;
+        self.consume(Token::Greater)?;
+        Ok(params)
+    }
+
+    fn identifier(&mut self) -> Result<String, ParseError> {
+        match self.advance()? {
+            Token::Var(ident) => Ok(ident.to_string()),
+            t => Err(Self::unexpected("an identifier", t, self.prev_span)),
+        }
+    }
+
+    fn advance(&mut self) -> Result<Token<'a>, ParseError> {
+        let (token, span) = self.tokens.next().ok_or(ParseError::Eof)?;
+        self.prev_span = span;
+        Ok(token)
+    }
+
+    fn peek(&mut self) -> Option<Token<'a>> {
+        self.tokens.peek().map(|(t, _)| *t)
+    }
+
+    // Where the next token is, or the end of the last one if there isn't one
+    fn peek_span(&mut self) -> Span {
+        self.tokens.peek().map_or(Span::new(self.prev_span.end, self.prev_span.end), |(_, span)| *span)
+    }
+
+    fn next_is(&mut self, token: Token) -> bool {
+        self.peek() == Some(token)
+    }
+
+    fn consume(&mut self, token: Token) -> Result<(), ParseError> {
+        match self.tokens.peek() {
+            Some((t, _)) if *t == token => {
+                self.advance()?;
+                Ok(())
+            },
+            Some(&(t, span)) => {
+                let diagnostic = Self::diagnose_unexpected(&format!("`{}`", token), t, span);
+                if !Self::fits_after(token, t) {
+                    return Err(diagnostic.into());
+                }
+                // What we found would make sense right after what's missing,
+                // so offer to add it
+                let at = Span::new(span.start, span.start);
+                Err(diagnostic.with_suggestion(format!("add `{}` here", token), at, format!("{} ", token)).into())
+            },
+            _ => Err(ParseError::Eof),
+        }
+    }
+
+    // Whether inserting `missing` before `found` is a likely fix. A closer
+    // only fits before something that couldn't go on with the expression
+    // instead (a forgotten operator or comma is just as likely there), and
+    // anything else only before what could come next in a program. We
+    // don't guess at all for the rest, or for characters we don't know.
+    fn fits_after(missing: Token, found: Token) -> bool {
+        let starts_expression = |t| matches!(t,
+            Token::True | Token::False | Token::Number(_) | Token::LParen | Token::LBrace | Token::Let | Token::Var(_)
+            | Token::If | Token::Fn | Token::Minus | Token::Struct | Token::Enum | Token::Match | Token::Trait
+            | Token::Impl | Token::While | Token::Loop | Token::For | Token::Label(_) | Token::Break | Token::Continue);
+        match (missing, found) {
+            (_, Token::Unknown(_)) => false,
+            (Token::RParen | Token::RBrace | Token::Greater, found) => !starts_expression(found),
+            (Token::Colon, found) => matches!(found, Token::Var(_) | Token::LParen | Token::Dyn),
+            (Token::SingleEq | Token::FatArrow | Token::In, found) => starts_expression(found),
+            _ => false,
+        }
+    }
+
+    fn unexpected(expected: &str, found: Token, span: Span) -> ParseError {
+        Self::diagnose_unexpected(expected, found, span).into()
+    }
+
+    fn diagnose_unexpected(expected: &str, found: Token, span: Span) -> Diagnostic {
+        match found {
+            Token::Unknown(c) => {
+                Diagnostic::syntax(Code::UNEXPECTED_CHARACTER, format!("Unexpected character `{}`", c), span)
+            },
+            _ => Diagnostic::syntax(Code::UNEXPECTED_TOKEN, format!("Expected {}, found `{}`", expected, found), span)
+                .with_label(span, format!("expected {}", expected)),
+        }
+    }
+
+    fn get_infix_rule(&self, t: Token) -> Result<InfixRule<'a>, ParseError> {
+        match t {
+            Token::Plus => Ok(Self::plus),
+            Token::Minus => Ok(Self::minus),
+            Token::Star => Ok(Self::times),
+            Token::Slash => Ok(Self::div),
+            Token::Percent => Ok(Self::rem),
+            Token::DoubleEq => Ok(Self::equals),
+            Token::BangEq => Ok(Self::not_equals),
+            Token::Less => Ok(Self::less),
+            Token::LessEq => Ok(Self::less_eq),
+            Token::Greater => Ok(Self::greater),
+            Token::GreaterEq => Ok(Self::greater_eq),
+            Token::LParen => Ok(Self::call),
+            Token::As => Ok(Self::cast),
+            Token::Dot => Ok(Self::field),
+            _ => Err(Self::unexpected("an operator", t, self.prev_span)),
+        }
+    }
     
-    // fn get_prefix_rule(t: Token) -> Result<PrefixRule<'a>, ParseError> {
-    //     match t {
-    //         // Token::True => Ok(Self::bool_lit),
-    //         // Token::False => Ok(Self::bool_lit),
-    //         Token::Number(_) => Ok(Self::number),
-    //         // Token::LParen => Ok(Self::grouping),
-    //         // Token::LBrace => Ok(Self::block),
-    //         // Token::Let => Ok(Self::let_decl),
-    //         // Token::Var(_) => Ok(Self::var_ref),
-    //         // Token::If => Ok(Self::conditional),
-    //         // _ => Err(ParseError::NotImplemented(format!("No prefix rule for token type {:?}", t))),
-    //     }
-    // }
-
-    // fn get_prec(t: Token) -> Prec {
-    //     match t {
-    //         Token::Number(_) => Prec::Expr,
-    //         // Token::Plus => Prec::AddSub,
-    //         // Token::Minus => Prec::AddSub,
-    //         // Token::Star => Prec::MultDiv,
-    //         // Token::Slash => Prec::MultDiv,
-    //         // _ => Prec::Bottom,
-    //     }
-    // }
+    fn get_prefix_rule(&self, t: Token) -> Result<PrefixRule<'a>, ParseError> {
+        match t {
+            Token::True => Ok(Self::bool_lit),
+            Token::False => Ok(Self::bool_lit),
+            Token::Number(_) => Ok(Self::number),
+            Token::LParen => Ok(Self::grouping),
+            Token::LBrace => Ok(Self::block),
+            Token::Let => Ok(Self::let_decl),
+            Token::Var(_) => Ok(Self::var_ref),
+            Token::If => Ok(Self::conditional),
+            Token::Fn => Ok(Self::function),
+            Token::Minus => Ok(Self::negate),
+            Token::Struct => Ok(Self::struct_decl),
+            Token::Enum => Ok(Self::enum_decl),
+            Token::Match => Ok(Self::match_expr),
+            Token::Trait => Ok(Self::trait_decl),
+            Token::Impl => Ok(Self::impl_decl),
+            Token::While | Token::Loop | Token::For => Ok(Self::unlabeled_loop),
+            Token::Label(_) => Ok(Self::labeled_loop),
+            Token::Break => Ok(Self::break_expr),
+            Token::Continue => Ok(Self::continue_expr),
+            _ => Err(Self::unexpected("an expression", t, self.prev_span)),
+        }
+    }
+
+    fn get_prec(t: Token) -> Prec {
+        match t {
+            Token::Number(_) => Prec::Expr,
+            Token::Plus => Prec::AddSub,
+            Token::Minus => Prec::AddSub,
+            Token::Star => Prec::MultDiv,
+            Token::Slash => Prec::MultDiv,
+            Token::Percent => Prec::MultDiv,
+            Token::DoubleEq => Prec::Compare,
+            Token::BangEq => Prec::Compare,
+            Token::Less => Prec::Compare,
+            Token::LessEq => Prec::Compare,
+            Token::Greater => Prec::Compare,
+            Token::GreaterEq => Prec::Compare,
+            Token::As => Prec::Cast,
+            Token::LParen => Prec::Call,
+            Token::Dot => Prec::Call,
+            _ => Prec::Bottom,
+        }
+    }
+}
+
+fn is_assignment(token: Token) -> bool {
+    matches!(token, Token::SingleEq | Token::PlusEq | Token::MinusEq | Token::StarEq | Token::SlashEq | Token::PercentEq)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::diagnostic::Note;
     use crate::scanner::Scanner;
 
     use crate::parser::HuckAst::*;
 
-    fn make_scanner(s: &str) -> Peekable<Scanner> {
-        Scanner::new(s).peekable()
+    fn make_scanner(s: &str) -> Scanner<'_> {
+        Scanner::new(s)
+    }
+
+    // Most tests don't care where things are
+    fn parse(scanner: Scanner) -> Result<HuckAst<()>, ParseError> {
+        Parser::new(scanner).parse().map(|ast| ast.map_metadata(&mut |_| ()))
     }
 
     #[test]
     fn empty() {
         let scanner = make_scanner("");
-        let parsed = Parser::new(scanner).parse();
+        let parsed = parse(scanner);
         assert_eq!(parsed, Err(ParseError::Eof));
     }
 
     #[test]
     fn number() {
         let scanner = make_scanner("42");
-        let parsed = Parser::new(scanner).parse();
-        assert_eq!(parsed, Ok(Num(42, ())));
-    }
-
-    // #[test]
-    // fn let_decl() {
-    //     let scanner = make_scanner("let var_name = 5");
-    //     let parsed = Parser::new(scanner).parse();
-    //     assert_eq!(parsed, Ok(Let("var_name".to_string(), Box::new(Num(5, ())), ())));
-    // }
-
-    // #[test]
-    // fn block() {
-    //     let scanner = make_scanner("{let x = 42; x + 1}");
-    //     let parsed = Parser::new(scanner).parse();
-    //     assert_eq!(parsed, Ok(
-    //         Block(vec![
-    //             Let("x".to_string(), Box::new(Num(42, ())), ()),
-    //             Plus(
-    //                 Box::new(VarRef("x".to_string(), ())),
-    //                 Box::new(Num(1, ())),
-    //                 ()
-    //             ),
-    //         ], ())
-    //     ));
-    // }
-
-    // #[test]
-    // fn simple_block() {
-    //     let scanner = make_scanner("{1}");
-    //     let parsed = Parser::new(scanner).parse();
-    //     assert_eq!(parsed, Ok(
-    //         Block(vec![Num(1, ())], ())
-    //     ));
-    // }
-
-    // #[test]
-    // fn empty_block() {
-    //     let scanner = make_scanner("{}");
-    //     let parsed = Parser::new(scanner).parse();
-    //     assert!(parsed.is_err());
-    // }
-
-    // #[test]
-    // fn arithmetic() {
-    //     let scanner = make_scanner("1 - 2 * 3");
-    //     let parsed = Parser::new(scanner).parse();
-
-    //     assert_eq!(parsed, Ok(
-    //         Minus(
-    //             Box::new(Num(1, ())),
-    //             Box::new(Times(
-    //                 Box::new(Num(2, ())),
-    //                 Box::new(Num(3, ())),
-    //                 ()
-    //             )),
-    //             ()
-    //         )
-    //     ));
-    // }
-
-    // #[test]
-    // fn grouping() {
-    //     let scanner = make_scanner("(1 + 2) / 3");
-    //     let parsed = Parser::new(scanner).parse();
-
-    //     assert_eq!(parsed, Ok(
-    //         Div(
-    //             Box::new(Plus(
-    //                 Box::new(Num(1, ())),
-    //                 Box::new(Num(2, ())),
-    //                 ()
-    //             )),
-    //             Box::new(Num(3, ())),
-    //             ()
-    //         )
-    //     ));
-    // }
-
-    // #[test]
-    // fn nested_grouping() {
-    //     let scanner = make_scanner("(((420)))");
-    //     assert_eq!(
-    //         Parser::new(scanner).parse(),
-    //         Ok(Num(420, ()))
-    //     )
-    // }
-
-    // #[test]
-    // fn bad_grouping() {
-    //     let scanner = make_scanner("(2580");
-    //     assert!(Parser::new(scanner).parse().is_err())
-    // }
-
-    // #[test]
-    // fn bool() {
-    //     let scanner = make_scanner("false");
-    //     assert_eq!(Parser::new(scanner).parse(), Ok(BoolLit(false, ())));
-    //     let scanner = make_scanner("true");
-    //     assert_eq!(Parser::new(scanner).parse(), Ok(BoolLit(true, ())));
-    // }
-
-    // #[test]
-    // fn conditional() {
-    //     let scanner = make_scanner("if true { 1 } else { 3 + 2 }");
-    //     assert_eq!(
-    //         Parser::new(scanner).parse(), Ok(
-    //             If(
-    //                 Box::new(BoolLit(true, ())),
-    //                 Box::new(Block(vec![Num(1, ())], ())),
-    //                 Box::new(
-    //                     Block(vec![
-    //                         Plus(
-    //                             Box::new(Num(3, ())),
-    //                             Box::new(Num(2, ())),
-    //                             ()
-    //                         )
-    //                     ], ()
-    //                     )
-    //                 ),
-    //                 ()
-    //             )
-    //         )
-    //     );
-    // }
+        let parsed = parse(scanner);
+        assert_eq!(parsed, Ok(Num(42, None, ())));
+    }
+
+    #[test]
+    fn floats() {
+        assert_eq!(parse(make_scanner("2.5")), Ok(Float(2.5, ())));
+        assert_eq!(parse(make_scanner("2f64")), Ok(Float(2.0, ())));
+        assert!(parse(make_scanner("2.5u8")).is_err());
+        assert_eq!(parse(make_scanner("1e308")), Ok(Float(1e308, ())));
+        assert_eq!(parse(make_scanner("2.5e-3")), Ok(Float(2.5e-3, ())));
+        assert_eq!(parse(make_scanner("2E+3f64")), Ok(Float(2e3, ())));
+        assert!(parse(make_scanner("1e3u8")).is_err());
+    }
+
+    #[test]
+    fn let_decl() {
+        let scanner = make_scanner("let var_name = 5");
+        let parsed = parse(scanner);
+        assert_eq!(parsed, Ok(Let("var_name".to_string(), false, Box::new(Num(5, None, ())), ())));
+    }
+
+    #[test]
+    fn assignment() {
+        let parsed = parse(make_scanner("{let mut x = 1; x = x + 1; x *= 2}"));
+        assert_eq!(parsed, Ok(Block(vec![
+            Let("x".to_string(), true, Box::new(Num(1, None, ())), ()),
+            Assign("x".to_string(), None, Box::new(Plus(
+                Box::new(VarRef("x".to_string(), ())),
+                Box::new(Num(1, None, ())),
+                ()
+            )), ()),
+            Assign("x".to_string(), Some(AssignOp::Mul), Box::new(Num(2, None, ())), ()),
+        ], ())));
+        assert!(parse(make_scanner("let mut = 1")).is_err());
+    }
+
+    #[test]
+    fn loops() {
+        let var = |x: &str| Box::new(VarRef(x.to_string(), ()));
+        let parsed = parse(make_scanner("while x < n { x += 1 }"));
+        assert_eq!(parsed, Ok(While(
+            None,
+            Box::new(Less(var("x"), var("n"), ())),
+            Box::new(Block(vec![Assign("x".to_string(), Some(AssignOp::Add), Box::new(Num(1, None, ())), ())], ())),
+            (),
+        )));
+        let parsed = parse(make_scanner("'outer: for i in 0..n { loop { if i == 2 { break 'outer } else { continue } } }"));
+        let Ok(For(Some(label), i, from, to, body, ())) = parsed else { panic!("{:?}", parsed) };
+        assert_eq!((label.as_str(), i.as_str(), *from, to), ("outer", "i", Num(0, None, ()), var("n")));
+        let Block(loops, ()) = *body else { panic!() };
+        let Loop(None, body, ()) = &loops[0] else { panic!() };
+        let Block(exprs, ()) = body.as_ref() else { panic!() };
+        let If(_, then_branch, else_branch, ()) = &exprs[0] else { panic!() };
+        assert_eq!(**then_branch, Block(vec![Break(Some("outer".to_string()), None, ())], ()));
+        assert_eq!(**else_branch, Block(vec![Continue(None, ())], ()));
+
+        // Values go after the label
+        assert_eq!(parse(make_scanner("break 'a x + 1")), Ok(Break(Some("a".to_string()), Some(Box::new(Plus(var("x"), Box::new(Num(1, None, ())), ()))), ())));
+        assert_eq!(parse(make_scanner("{break; 1}")), Ok(Block(vec![Break(None, None, ()), Num(1, None, ())], ())));
+        assert!(parse(make_scanner("'a: 1")).is_err());
+        assert!(parse(make_scanner("for i in 10 { i }")).is_err());
+    }
+
+    #[test]
+    fn block() {
+        let scanner = make_scanner("{let x = 42; x + 1}");
+        let parsed = parse(scanner);
+        assert_eq!(parsed, Ok(
+            Block(vec![
+                Let("x".to_string(), false, Box::new(Num(42, None, ())), ()),
+                Plus(
+                    Box::new(VarRef("x".to_string(), ())),
+                    Box::new(Num(1, None, ())),
+                    ()
+                ),
+            ], ())
+        ));
+    }
+
+    #[test]
+    fn simple_block() {
+        let scanner = make_scanner("{1}");
+        let parsed = parse(scanner);
+        assert_eq!(parsed, Ok(
+            Block(vec![Num(1, None, ())], ())
+        ));
+    }
+
+    #[test]
+    fn empty_block() {
+        let scanner = make_scanner("{}");
+        let parsed = parse(scanner);
+        assert!(parsed.is_err());
+    }
+
+    #[test]
+    fn arithmetic() {
+        let scanner = make_scanner("1 - 2 * 3");
+        let parsed = parse(scanner);
+
+        assert_eq!(parsed, Ok(
+            Minus(
+                Box::new(Num(1, None, ())),
+                Box::new(Times(
+                    Box::new(Num(2, None, ())),
+                    Box::new(Num(3, None, ())),
+                    ()
+                )),
+                ()
+            )
+        ));
+
+        let scanner = make_scanner("1 - 2 % 3 * 4");
+        let parsed = parse(scanner);
+
+        assert_eq!(parsed, Ok(
+            Minus(
+                Box::new(Num(1, None, ())),
+                Box::new(Times(
+                    Box::new(Rem(
+                        Box::new(Num(2, None, ())),
+                        Box::new(Num(3, None, ())),
+                        ()
+                    )),
+                    Box::new(Num(4, None, ())),
+                    ()
+                )),
+                ()
+            )
+        ));
+    }
+
+    #[test]
+    fn negation_and_casts() {
+        let scanner = make_scanner("1 + -2u8 as i64 * 3");
+        let parsed = parse(scanner);
+        let u8_ann = Some(TypeAnn::Named("u8".to_string()));
+
+        assert_eq!(parsed, Ok(
+            Plus(
+                Box::new(Num(1, None, ())),
+                Box::new(Times(
+                    Box::new(Cast(
+                        Box::new(Neg(Box::new(Num(2, u8_ann, ())), ())),
+                        TypeAnn::Named("i64".to_string()),
+                        ()
+                    )),
+                    Box::new(Num(3, None, ())),
+                    ()
+                )),
+                ()
+            )
+        ));
+    }
+
+    #[test]
+    fn grouping() {
+        let scanner = make_scanner("(1 + 2) / 3");
+        let parsed = parse(scanner);
+
+        assert_eq!(parsed, Ok(
+            Div(
+                Box::new(Plus(
+                    Box::new(Num(1, None, ())),
+                    Box::new(Num(2, None, ())),
+                    ()
+                )),
+                Box::new(Num(3, None, ())),
+                ()
+            )
+        ));
+    }
+
+    #[test]
+    fn nested_grouping() {
+        let scanner = make_scanner("(((420)))");
+        assert_eq!(
+            parse(scanner),
+            Ok(Num(420, None, ()))
+        )
+    }
+
+    #[test]
+    fn bad_grouping() {
+        let scanner = make_scanner("(2580");
+        assert!(parse(scanner).is_err())
+    }
+
+    #[test]
+    fn bool() {
+        let scanner = make_scanner("false");
+        assert_eq!(parse(scanner), Ok(BoolLit(false, ())));
+        let scanner = make_scanner("true");
+        assert_eq!(parse(scanner), Ok(BoolLit(true, ())));
+    }
+
+    #[test]
+    fn conditional() {
+        let scanner = make_scanner("if true { 1 } else { 3 + 2 }");
+        assert_eq!(
+            parse(scanner), Ok(
+                If(
+                    Box::new(BoolLit(true, ())),
+                    Box::new(Block(vec![Num(1, None, ())], ())),
+                    Box::new(
+                        Block(vec![
+                            Plus(
+                                Box::new(Num(3, None, ())),
+                                Box::new(Num(2, None, ())),
+                                ()
+                            )
+                        ], ()
+                        )
+                    ),
+                    ()
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn comparison() {
+        let scanner = make_scanner("1 + 2 == 3");
+        assert_eq!(
+            parse(scanner),
+            Ok(Equals(
+                Box::new(Plus(Box::new(Num(1, None, ())), Box::new(Num(2, None, ())), ())),
+                Box::new(Num(3, None, ())),
+                ()
+            ))
+        );
+    }
+
+    #[test]
+    fn function() {
+        let scanner = make_scanner("fn (x: i64, y: bool): () { x }");
+        assert_eq!(
+            parse(scanner),
+            Ok(Fn(
+                vec![],
+                vec![
+                    ("x".to_string(), TypeAnn::Named("i64".to_string())),
+                    ("y".to_string(), TypeAnn::Named("bool".to_string())),
+                ],
+                TypeAnn::Unit,
+                Rc::new(Block(vec![VarRef("x".to_string(), ())], ())),
+                ()
+            ))
+        );
+    }
+
+    #[test]
+    fn call() {
+        let scanner = make_scanner("f(1, g()) * 2");
+        assert_eq!(
+            parse(scanner),
+            Ok(Times(
+                Box::new(Call("f".to_string(), TypeArgList::default(), vec![Num(1, None, ()), Call("g".to_string(), TypeArgList::default(), vec![], ())], ())),
+                Box::new(Num(2, None, ())),
+                ()
+            ))
+        );
+    }
+
+    #[test]
+    fn bad_call() {
+        let scanner = make_scanner("(1 + 2)(3)");
+        assert!(parse(scanner).is_err());
+    }
+
+    #[test]
+    fn structs() {
+        let parsed = parse(make_scanner("{struct P { x: i64, y: bool, }; P { y: true, ..p }.x}"));
+        assert_eq!(parsed, Ok(Block(vec![
+            Struct("P".to_string(), vec![], vec![
+                ("x".to_string(), TypeAnn::Named("i64".to_string())),
+                ("y".to_string(), TypeAnn::Named("bool".to_string())),
+            ], ()),
+            Field(
+                Box::new(StructLit(
+                    "P".to_string(),
+                    TypeArgList::default(),
+                    vec![("y".to_string(), BoolLit(true, ()))],
+                    Some(Box::new(VarRef("p".to_string(), ()))),
+                    ()
+                )),
+                "x".to_string(),
+                ()
+            ),
+        ], ())));
+        assert_eq!(parse(make_scanner("E {}")), Ok(StructLit("E".to_string(), TypeArgList::default(), vec![], None, ())));
+        assert!(parse(make_scanner("P { ..p, x: 1 }")).is_err());
+        // Only variables can be assigned to
+        assert!(matches!(parse(make_scanner("{ p.x = 5 }")), Err(ParseError::Fucked(d)) if d.code == Some(Code::FIELD_ASSIGNMENT)));
+        assert!(matches!(parse(make_scanner("{ t.0.1 += 5 }")), Err(ParseError::Fucked(d)) if d.code == Some(Code::FIELD_ASSIGNMENT)));
+    }
+
+    #[test]
+    fn struct_literals_in_conditions() {
+        // The brace after `x` starts the branch
+        let parsed = parse(make_scanner("if x { 1 } else { 2 }"));
+        assert!(matches!(parsed, Ok(If(test, _, _, ())) if *test == VarRef("x".to_string(), ())));
+        // but parentheses bring struct literals back
+        let parsed = parse(make_scanner("if (P { b: true }).b { 1 } else { 2 }"));
+        assert!(matches!(parsed, Ok(If(test, _, _, ())) if matches!(*test, Field(..))));
+    }
+
+    #[test]
+    fn enums() {
+        let parsed = parse(make_scanner("{enum E { A(i64, bool), B, }; E::A(1, true)}"));
+        assert_eq!(parsed, Ok(Block(vec![
+            Enum("E".to_string(), vec![], vec![
+                ("A".to_string(), vec![TypeAnn::Named("i64".to_string()), TypeAnn::Named("bool".to_string())]),
+                ("B".to_string(), vec![]),
+            ], ()),
+            Variant("E".to_string(), TypeArgList::default(), "A".to_string(), vec![Num(1, None, ()), BoolLit(true, ())], ()),
+        ], ())));
+        assert_eq!(parse(make_scanner("E::B")), Ok(Variant("E".to_string(), TypeArgList::default(), "B".to_string(), vec![], ())));
+    }
+
+    #[test]
+    fn generics() {
+        let named = |name: &str| TypeAnn::Named(name.to_string());
+        let parsed = parse(make_scanner("{enum O<T> { S(T), N }; let f = fn <T>(x: O<T>): O<O<T>> { O::S(x) }; f::<i64>(O::<i64>::N)}"));
+        let Ok(Block(exprs, ())) = parsed else { panic!("{:?}", parsed) };
+        assert_eq!(exprs[0], Enum("O".to_string(), vec!["T".to_string()], vec![
+            ("S".to_string(), vec![named("T")]),
+            ("N".to_string(), vec![]),
+        ], ()));
+        let Let(_, _, f, ()) = &exprs[1] else { panic!("{:?}", exprs[1]) };
+        let Fn(type_params, params, ret, _, ()) = f.as_ref() else { panic!("{:?}", f) };
+        assert_eq!(type_params, &vec![("T".to_string(), vec![])]);
+        assert_eq!(params[0].1, TypeAnn::Applied("O".to_string(), vec![named("T")].into()));
+        assert_eq!(*ret, TypeAnn::Applied("O".to_string(), vec![TypeAnn::Applied("O".to_string(), vec![named("T")].into())].into()));
+        assert_eq!(exprs[2], Call("f".to_string(), vec![named("i64")].into(), vec![
+            Variant("O".to_string(), vec![named("i64")].into(), "N".to_string(), vec![], ()),
+        ], ()));
+
+        let parsed = parse(make_scanner("P::<bool> { x: true }"));
+        assert!(matches!(parsed, Ok(StructLit(_, type_args, ..)) if type_args.args == vec![named("bool")] && type_args.span == Span::new(3, 9)));
+        // Casts are only to numbers, so this is a comparison
+        let parsed = parse(make_scanner("x as i64 < y"));
+        assert!(matches!(parsed, Ok(Less(..))), "{:?}", parsed);
+    }
+
+    #[test]
+    fn match_expr() {
+        let parsed = parse(make_scanner("match x { E::A(-1, _) => 1, E::A(n, true) if n > 0 => n, _ => 0, }"));
+        let Ok(Match(scrutinee, arms, ())) = parsed else { panic!("{:?}", parsed) };
+        assert_eq!(*scrutinee, VarRef("x".to_string(), ()));
+        assert_eq!(arms.len(), 3);
+        assert_eq!(arms[0].pattern, Pattern::Variant("E".to_string(), "A".to_string(), vec![
+            Pattern::Literal(Neg(Box::new(Num(1, None, ())), ())),
+            Pattern::Wildcard(()),
+        ], ()));
+        assert_eq!(arms[1].pattern, Pattern::Variant("E".to_string(), "A".to_string(), vec![
+            Pattern::Binding("n".to_string(), ()),
+            Pattern::Literal(BoolLit(true, ())),
+        ], ()));
+        assert!(matches!(arms[1].guard, Some(Greater(..))));
+        assert_eq!(arms[2].pattern, Pattern::Wildcard(()));
+        assert_eq!(arms[2].body, Num(0, None, ()));
+
+        assert!(parse(make_scanner("match x { 1.5 => 1 }")).is_err());
+        assert!(parse(make_scanner("match x { }")).is_err());
+    }
+
+    #[test]
+    fn tuples() {
+        let num = |n| Num(n, None, ());
+        assert_eq!(parse(make_scanner("(1, (2,), 3,)")), Ok(Tuple(vec![num(1), Tuple(vec![num(2)], ()), num(3)], ())));
+        assert_eq!(parse(make_scanner("t.0.1")), Ok(Field(
+            Box::new(Field(Box::new(VarRef("t".to_string(), ())), "0".to_string(), ())),
+            "1".to_string(),
+            (),
+        )));
+        assert!(parse(make_scanner("t.0u8")).is_err());
+
+        let parsed = parse(make_scanner("fn (p: (i64, (bool,))): () { match p { (0, (b,)) => b, ((x), _) => x } }"));
+        let Ok(Fn(_, params, _, body, ())) = parsed else { panic!("{:?}", parsed) };
+        let named = |name: &str| TypeAnn::Named(name.to_string());
+        assert_eq!(params[0].1, TypeAnn::Tuple(vec![named("i64"), TypeAnn::Tuple(vec![named("bool")])]));
+        let Block(exprs, ()) = body.as_ref() else { panic!("{:?}", body) };
+        let Match(_, arms, ()) = &exprs[0] else { panic!("{:?}", exprs) };
+        assert_eq!(arms[0].pattern, Pattern::Tuple(vec![
+            Pattern::Literal(num(0)),
+            Pattern::Tuple(vec![Pattern::Binding("b".to_string(), ())], ()),
+        ], ()));
+        assert_eq!(arms[1].pattern, Pattern::Tuple(vec![Pattern::Binding("x".to_string(), ()), Pattern::Wildcard(())], ()));
+    }
+
+    #[test]
+    fn traits() {
+        let named = |name: &str| TypeAnn::Named(name.to_string());
+        let parsed = parse(make_scanner("{trait S { fn area(self): f64; fn grow(self, by: f64,): Self; }; impl S for Sq { fn area(self): f64 { 1.0 } }; s.area() }"));
+        let Ok(Block(exprs, ())) = parsed else { panic!("{:?}", parsed) };
+        let self_param = ("self".to_string(), named("Self"));
+        assert_eq!(exprs[0], Trait("S".to_string(), vec![
+            MethodSig { name: "area".to_string(), params: vec![self_param.clone()], ret: named("f64") },
+            MethodSig { name: "grow".to_string(), params: vec![self_param.clone(), ("by".to_string(), named("f64"))], ret: named("Self") },
+        ], ()));
+        let Impl(name, target, methods, ()) = &exprs[1] else { panic!("{:?}", exprs[1]) };
+        assert_eq!((name.as_str(), target), ("S", &named("Sq")));
+        assert!(matches!(&methods[..], [(area, Fn(type_params, params, ..))] if area == "area" && type_params.is_empty() && params == &vec![self_param]));
+        assert_eq!(exprs[2], MethodCall(Box::new(VarRef("s".to_string(), ())), "area".to_string(), vec![], None, ()));
+
+        let parsed = parse(make_scanner("fn <T: Eq + Ord, U>(x: dyn S): T { x }"));
+        let Ok(Fn(type_params, params, ..)) = parsed else { panic!("{:?}", parsed) };
+        assert_eq!(type_params, vec![
+            ("T".to_string(), vec!["Eq".to_string(), "Ord".to_string()]),
+            ("U".to_string(), vec![]),
+        ]);
+        assert_eq!(params[0].1, TypeAnn::Dyn("S".to_string()));
+        assert!(matches!(parse(make_scanner("trait S { fn f(x: i64): i64 }")), Err(ParseError::Fucked(d)) if d.code == Some(Code::MISSING_SELF)));
+    }
+
+    #[test]
+    fn spans() {
+        let parsed = Parser::new(Scanner::new("{let x = f(1);\n if x { 2 } else { 3 }}")).parse().unwrap();
+        let Block(exprs, span) = parsed else { panic!() };
+        assert_eq!(span, Span::new(0, 38));
+        let Let(_, _, init, span) = &exprs[0] else { panic!() };
+        assert_eq!(*span, Span::new(1, 13));
+        assert_eq!(*init.get_metadata(), Span::new(9, 13));
+        assert_eq!(*exprs[1].get_metadata(), Span::new(16, 37));
+    }
+
+    #[test]
+    fn errors() {
+        let err = |s| match Parser::new(Scanner::new(s)).parse() {
+            Err(ParseError::Fucked(d)) => Some((d.code.unwrap(), d.message, d.primary.unwrap().span)),
+            Err(ParseError::Eof) => None,
+            Ok(ast) => panic!("{:?} parsed", ast),
+        };
+        assert_eq!(err("{1; 2 3}"), Some((Code::UNEXPECTED_TOKEN, "Expected an operator, found `3`".to_string(), Span::new(6, 7))));
+        assert_eq!(err("let 1 = 2"), Some((Code::UNEXPECTED_TOKEN, "Expected an identifier, found `1`".to_string(), Span::new(4, 5))));
+        assert_eq!(err("(1 + 2"), None);
+        assert_eq!(err("1 + $"), Some((Code::UNEXPECTED_CHARACTER, "Unexpected character `$`".to_string(), Span::new(4, 5))));
+
+        let Err(ParseError::Fucked(missing_colon)) = Parser::new(Scanner::new("fn (x: i64) i64 { x }")).parse() else {
+            panic!()
+        };
+        assert_eq!(missing_colon.notes, vec![Note::Suggestion {
+            message: "add `:` here".to_string(),
+            span: Span::new(12, 12),
+            replacement: ": ".to_string(),
+        }]);
+
+        let suggestions = |s| match Parser::new(Scanner::new(s)).parse() {
+            Err(ParseError::Fucked(d)) => d.notes.into_iter().filter_map(|note| match note {
+                Note::Suggestion { message, span, .. } => Some((message, span)),
+                _ => None,
+            }).collect::<Vec<_>>(),
+            result => panic!("{:?}", result),
+        };
+        assert_eq!(suggestions("f(1, 2;"), vec![("add `)` here".to_string(), Span::new(6, 6))]);
+        assert_eq!(suggestions("if x { 1 else { 2 }"), vec![("add `}` here".to_string(), Span::new(9, 9))]);
+        assert_eq!(suggestions("match x { y 1 }"), vec![("add `=>` here".to_string(), Span::new(12, 12))]);
+        // Nothing we can tell is missing: a character we don't know, something
+        // that can't follow the missing token, or something that could be
+        // the rest of an expression with an operator missing instead
+        assert_eq!(suggestions("{ let x = 1; x $ 2 }"), vec![]);
+        assert_eq!(suggestions("let x: i32 = 1"), vec![]);
+        assert_eq!(suggestions("let sum = fn(n: i64) -> i64 { n }"), vec![]);
+        assert_eq!(suggestions("f(1 2)"), vec![]);
+    }
 }
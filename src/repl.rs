@@ -0,0 +1,218 @@
+// Interactive read-eval-print loop.
+//
+// Each input is parsed, checked and interpreted on its own, but the
+// checker and interpreter live for the whole session, so top-level
+// `let`s (including functions) stay visible to later inputs. An input
+// that fails at any stage leaves the session as it was before.
+
+use crate::interp::{Interpreter, DEFAULT_STACK_LIMIT};
+use crate::parser::{HuckAst, ParseOutput};
+use crate::scanner::{Scanner, Token};
+use crate::typecheck::Checker;
+use crate::{parse_str, Diagnostic};
+
+use std::io::{self, BufRead, Write};
+
+const PROMPT: &str = "huck> ";
+const CONTINUE_PROMPT: &str = "  ... ";
+
+pub struct Repl {
+    checker: Checker,
+    interpreter: Interpreter,
+    stack_limit: usize,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self {
+            checker: Checker::new(),
+            interpreter: Interpreter::new(),
+            stack_limit: DEFAULT_STACK_LIMIT,
+        }
+    }
+
+    // See `Interpreter::with_stack_limit`
+    pub fn with_stack_limit(mut self, bytes: usize) -> Self {
+        self.interpreter = self.interpreter.with_stack_limit(bytes);
+        self.stack_limit = bytes;
+        self
+    }
+
+    fn parse(input: &str) -> Result<ParseOutput, String> {
+        parse_str(input).map_err(|err| Self::report(&err, input))
+    }
+
+    // Errors point into the input they came from
+    fn report(diagnostic: &Diagnostic, input: &str) -> String {
+        diagnostic.render("<repl>", input, false).trim_end().to_string()
+    }
+
+    // Evaluate one complete input, returning the line to print
+    pub fn eval(&mut self, input: &str) -> Result<String, String> {
+        let input = input.trim();
+        if let Some(command) = input.strip_prefix(':') {
+            return self.command(command);
+        }
+
+        let ast = Self::parse(input)?;
+        let saved_checker = self.checker.clone();
+        let checked = self.checker.check(&ast).map_err(|err| {
+            self.checker = saved_checker.clone();
+            Self::report(&err, input)
+        })?;
+        let warnings = self.checker.take_warnings().iter()
+            .map(|warning| Self::report(warning, input) + "\n")
+            .collect::<String>();
+
+        // Functions have no value worth printing, so show the signature
+        if let HuckAst::Let(ident, _, init_expr, _) = &checked {
+            if let HuckAst::Fn(.., fn_type) = init_expr.as_ref() {
+                self.interpreter.eval(&checked).map_err(|err| format!("Runtime error: {}", err))?;
+                return Ok(format!("{}{} : {}", warnings, ident, fn_type.ty));
+            }
+        }
+
+        match self.interpreter.eval(&checked) {
+            Ok(value) => Ok(format!("{}{} : {}", warnings, value, checked.ty())),
+            Err(err) => {
+                self.checker = saved_checker;
+                Err(format!("Runtime error: {}", err))
+            },
+        }
+    }
+
+    fn command(&mut self, command: &str) -> Result<String, String> {
+        let (name, rest) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+        match name {
+            "type" => {
+                let ast = Self::parse(rest)?;
+                // Checking a `let` would bind it, so use a scratch checker
+                let checked = self.checker.clone().check(&ast)
+                    .map_err(|err| Self::report(&err, rest))?;
+                Ok(checked.ty().to_string())
+            },
+            "ast" => Ok(format!("{:?}", Self::parse(rest)?.map_metadata(&mut |_| ()))),
+            "reset" => {
+                *self = Self::new().with_stack_limit(self.stack_limit);
+                Ok(String::from("Session reset"))
+            },
+            _ => Err(format!("Unknown command :{}; try :type, :ast, :reset or :quit", name)),
+        }
+    }
+}
+
+// More lines are needed while there are unclosed braces
+fn is_incomplete(input: &str) -> bool {
+    let depth = Scanner::new(input).fold(0, |depth, token| match token {
+        Token::LBrace => depth + 1,
+        Token::RBrace => depth - 1,
+        _ => depth,
+    });
+    depth > 0
+}
+
+pub fn run<R, W>(mut repl: Repl, input: R, output: &mut W) -> io::Result<()>
+where R: BufRead, W: Write
+{
+    let mut lines = input.lines();
+    let mut buffer = String::new();
+
+    loop {
+        write!(output, "{}", if buffer.is_empty() { PROMPT } else { CONTINUE_PROMPT })?;
+        output.flush()?;
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        buffer.push_str(&line);
+        buffer.push('\n');
+        if is_incomplete(&buffer) {
+            continue;
+        }
+
+        let entry = std::mem::take(&mut buffer);
+        match entry.trim() {
+            "" => continue,
+            ":quit" | ":q" => return Ok(()),
+            _ => (),
+        }
+        match repl.eval(&entry) {
+            Ok(result) => writeln!(output, "{}", result)?,
+            Err(err) => writeln!(output, "{}", err)?,
+        }
+    }
+    writeln!(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn persistent_state() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval("let x = 20"), Ok("20 : i64".to_string()));
+        assert_eq!(
+            repl.eval("let double = fn (n: i64): i64 { n * 2 }"),
+            Ok("double : fn (i64): i64".to_string())
+        );
+        assert_eq!(repl.eval("double(x) + 2"), Ok("42 : i64".to_string()));
+        assert_eq!(repl.eval("x == 20"), Ok("true : bool".to_string()));
+    }
+
+    #[test]
+    fn errors_leave_state_alone() {
+        let mut repl = Repl::new();
+        assert!(repl.eval("let y = 1 / 0").is_err());
+        assert!(repl.eval("y").is_err());
+        assert!(repl.eval("{ let z = 1; true + z }").is_err());
+        assert_eq!(repl.eval("let z = true"), Ok("true : bool".to_string()));
+    }
+
+    #[test]
+    fn commands() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval(":type let x = 1 < 2"), Ok("bool".to_string()));
+        assert!(repl.eval("x").is_err());
+        assert_eq!(repl.eval(":ast 1"), Ok("Num(1, None, ())".to_string()));
+        repl.eval("let x = 1").unwrap();
+        repl.eval(":reset").unwrap();
+        assert!(repl.eval("x").is_err());
+        assert!(repl.eval(":bogus").is_err());
+    }
+
+    #[test]
+    fn error_snippets() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval("1 + true"), Err("\
+error[E0105]: Arithmetic needs two numbers of the same type, not i64 and bool
+ --> <repl>:1:5
+  |
+1 | 1 + true
+  | -   ^^^^ this has type bool
+  | |
+  | this has type i64".to_string()));
+
+        let warned = repl.eval("match 1 { _ => 1, 2 => 2 }").unwrap();
+        assert!(warned.starts_with("warning[E0130]: Unreachable match arm\n"), "{}", warned);
+        assert!(warned.ends_with("\n1 : i64"), "{}", warned);
+    }
+
+    #[test]
+    fn multi_line_input() {
+        let input = "let f = fn (n: i64): i64 {\n  n + 1\n}\nf(1)\n";
+        let mut output = vec![];
+        run(Repl::new(), input.as_bytes(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "huck>   ...   ... f : fn (i64): i64\nhuck> 2 : i64\nhuck> \n"
+        );
+    }
+}
@@ -1,29 +1,147 @@
+use crate::diagnostic::Span;
+
+use std::fmt;
+
+// How long the exponent at the start of `rest` is, like the `e-5` in
+// `1e-5`, or 0 if there isn't one. An `e` without digits after it starts
+// a type suffix instead.
+pub fn exponent_len(rest: &str) -> usize {
+    let Some(after_e) = rest.strip_prefix(['e', 'E']) else {
+        return 0;
+    };
+    let digits = after_e.strip_prefix(['+', '-']).unwrap_or(after_e);
+    match digits.find(|c: char| !c.is_ascii_digit()).unwrap_or(digits.len()) {
+        0 => 0,
+        n => rest.len() - digits.len() + n,
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Token<'a> {
-    // Plus,
-    // Minus,
-    // Star,
-    // Slash,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
     Number(&'a str),
-    // True,
-    // False,
-    // RParen,
-    // LParen,
-    // RBrace,
-    // LBrace,
-    // Let,
-    // SingleEq,
-    // Semicolon,
-    // Var(&'a str),
-    // If,
-    // Else,
+    True,
+    False,
+    RParen,
+    LParen,
+    RBrace,
+    LBrace,
+    Let,
+    SingleEq,
+    Semicolon,
+    Var(&'a str),
+    If,
+    Else,
+    Fn,
+    Colon,
+    Comma,
+    DoubleEq,
+    BangEq,
+    Less,
+    LessEq,
+    Greater,
+    GreaterEq,
+    As,
+    Struct,
+    Dot,
+    DotDot,
+    Enum,
+    ColonColon,
+    Match,
+    FatArrow,
+    Trait,
+    Impl,
+    For,
+    Dyn,
+    Mut,
+    PlusEq,
+    MinusEq,
+    StarEq,
+    SlashEq,
+    PercentEq,
+    While,
+    Loop,
+    In,
+    Break,
+    Continue,
+    // `'outer`, without the quote
+    Label(&'a str),
+    // Anything we don't recognize; the parser reports it
+    Unknown(&'a str),
 }
 use Token::*;
 
+// How tokens are written, for error messages
+impl fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Plus => "+",
+            Minus => "-",
+            Star => "*",
+            Slash => "/",
+            Percent => "%",
+            Number(n) => n,
+            True => "true",
+            False => "false",
+            RParen => ")",
+            LParen => "(",
+            RBrace => "}",
+            LBrace => "{",
+            Let => "let",
+            SingleEq => "=",
+            Semicolon => ";",
+            Var(v) => v,
+            If => "if",
+            Else => "else",
+            Fn => "fn",
+            Colon => ":",
+            Comma => ",",
+            DoubleEq => "==",
+            BangEq => "!=",
+            Less => "<",
+            LessEq => "<=",
+            Greater => ">",
+            GreaterEq => ">=",
+            As => "as",
+            Struct => "struct",
+            Dot => ".",
+            DotDot => "..",
+            Enum => "enum",
+            ColonColon => "::",
+            Match => "match",
+            FatArrow => "=>",
+            Trait => "trait",
+            Impl => "impl",
+            For => "for",
+            Dyn => "dyn",
+            Mut => "mut",
+            PlusEq => "+=",
+            MinusEq => "-=",
+            StarEq => "*=",
+            SlashEq => "/=",
+            PercentEq => "%=",
+            While => "while",
+            Loop => "loop",
+            In => "in",
+            Break => "break",
+            Continue => "continue",
+            Label(label) => return write!(f, "'{}", label),
+            Unknown(c) => c,
+        };
+        write!(f, "{}", text)
+    }
+}
+
 #[derive(Debug)]
 pub struct Scanner<'a> {
     source: &'a str,
     position: usize,
+    // Where the most recently scanned token starts
+    token_start: usize,
 }
 
 impl<'a> Scanner<'a> {
@@ -31,10 +149,15 @@ impl<'a> Scanner<'a> {
         Scanner{
             source,
             position: 0,
+            token_start: 0,
         }
     }
 
-    // TODO: handle non-integers
+    // Also yield where each token is in the source
+    pub fn spanned(self) -> Spanned<'a> {
+        Spanned { scanner: self }
+    }
+
     fn number(&mut self) -> Option<Token<'a>> {
          // We already advanced the position during Iterator::next(),
          // so decrement by one
@@ -44,63 +167,94 @@ impl<'a> Scanner<'a> {
         while Self::is_digit(self.peek().unwrap_or("_") /* "_" isn't a number */) {
             self.position += 1;
         }
+        // A decimal point only counts if there are digits after it
+        let after_point = self.source.get(self.position + 1..self.position + 2);
+        if self.peek() == Some(".") && after_point.is_some_and(Self::is_digit) {
+            self.position += 1;
+            while self.peek().is_some_and(Self::is_digit) {
+                self.position += 1;
+            }
+        }
+        self.position += exponent_len(&self.source[self.position..]);
+        // A type suffix, like `8u8`, is part of the number
+        while self.peek().is_some_and(|c| Self::is_digit(c) || Self::is_alpha(c)) {
+            self.position += 1;
+        }
 
         Some(Number(self.source.get(start_index..self.position)?))
     }
 
-    // fn identifier(&mut self) -> Option<Token<'a>> {
-    //     let start_index = self.position - 1;
+    fn identifier(&mut self) -> Option<Token<'a>> {
+        let start_index = self.position - 1;
 
-    //     while let Some(next_char) = self.peek() {
-    //         if Self::is_digit(next_char) || Self::is_alpha(next_char) {
-    //             self.position += 1;
-    //         }
-    //         else { break; }
-    //     }
+        while let Some(next_char) = self.peek() {
+            if Self::is_digit(next_char) || Self::is_alpha(next_char) {
+                self.position += 1;
+            }
+            else { break; }
+        }
 
-    //     let ident = self.source.get(start_index..self.position)?;
+        let ident = self.source.get(start_index..self.position)?;
 
-    //     Some(match ident {
-    //         "let" => Let,
-    //         "true" => True,
-    //         "false" => False,
-    //         "if" => If,
-    //         "else" => Else,
-    //         _ => Var(ident)
-    //     })
-    // }
+        Some(match ident {
+            "let" => Let,
+            "true" => True,
+            "false" => False,
+            "if" => If,
+            "else" => Else,
+            "fn" => Fn,
+            "as" => As,
+            "struct" => Struct,
+            "enum" => Enum,
+            "match" => Match,
+            "trait" => Trait,
+            "impl" => Impl,
+            "for" => For,
+            "dyn" => Dyn,
+            "mut" => Mut,
+            "while" => While,
+            "loop" => Loop,
+            "in" => In,
+            "break" => Break,
+            "continue" => Continue,
+            _ => Var(ident)
+        })
+    }
 
-    // Get the next character, if it exists, and advance the scanner
-    fn next_char(&mut self) -> Option<&'a str> {
-        // We're at the end
-        if self.position >= self.source.len() {
-            None
-        } else {
-            // This always increments the position to the next character
+    // Pick between a one- and two-character operator depending on whether
+    // the next character is `second`
+    fn either(&mut self, second: &str, double: Token<'a>, single: Token<'a>) -> Option<Token<'a>> {
+        if self.peek() == Some(second) {
             self.position += 1;
-            self.source.get(self.position - 1..self.position)
+            Some(double)
+        } else {
+            Some(single)
         }
     }
 
+    // Get the next character, if it exists, and advance the scanner
+    fn next_char(&mut self) -> Option<&'a str> {
+        let c = self.peek()?;
+        self.position += c.len();
+        Some(c)
+    }
+
     // Get the next character, if it exists, without incrementing
     fn peek(&self) -> Option<&'a str> {
-        if self.position >= self.source.len() {
-            None
-        } else {
-            self.source.get(self.position..self.position + 1)
-        }
+        let c = self.source.get(self.position..)?.chars().next()?;
+        self.source.get(self.position..self.position + c.len_utf8())
     }
 
     pub fn is_digit(s: &'a str) -> bool {
         "0123456789".contains(s)
     }
 
-    // pub fn is_alpha(s: &'a str) -> bool {
-    //     "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz_".contains(s)
-    // }
+    pub fn is_alpha(s: &'a str) -> bool {
+        "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz_".contains(s)
+    }
 
     pub fn is_whitespace(s: &'a str) -> bool {
-        " \t\n".contains(s)
+        " \t\r\n".contains(s)
     }
 }
 
@@ -109,29 +263,71 @@ impl<'a> Iterator for Scanner<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
+            self.token_start = self.position;
             let c = self.next_char()?;
             match c {
                 c if Self::is_whitespace(c) => { continue; } // Munch whitespace
                 c if Self::is_digit(c) => {
                     return self.number()
                 },
-                // "+" => return Some(Plus),
-                // "-" => return Some(Minus),
-                // "*" => return Some(Star),
-                // "/" => return Some(Slash),
-                // "(" => return Some(LParen),
-                // ")" => return Some(RParen),
-                // "{" => return Some(LBrace),
-                // "}" => return Some(RBrace),
-                // "=" => return Some(SingleEq),
-                // ";" => return Some(Semicolon),
-                // _ => return self.identifier(),
-                _ => return None,
+                "+" => return self.either("=", PlusEq, Plus),
+                "-" => return self.either("=", MinusEq, Minus),
+                "*" => return self.either("=", StarEq, Star),
+                // Line comments run to the end of the line
+                "/" if self.peek() == Some("/") => {
+                    while self.peek().is_some_and(|c| c != "\n") {
+                        self.next_char();
+                    }
+                },
+                "/" => return self.either("=", SlashEq, Slash),
+                "%" => return self.either("=", PercentEq, Percent),
+                "(" => return Some(LParen),
+                ")" => return Some(RParen),
+                "{" => return Some(LBrace),
+                "}" => return Some(RBrace),
+                "=" if self.peek() == Some(">") => {
+                    self.position += 1;
+                    return Some(FatArrow)
+                },
+                "=" => return self.either("=", DoubleEq, SingleEq),
+                "<" => return self.either("=", LessEq, Less),
+                ">" => return self.either("=", GreaterEq, Greater),
+                "!" if self.peek() == Some("=") => {
+                    self.position += 1;
+                    return Some(BangEq)
+                },
+                ";" => return Some(Semicolon),
+                ":" => return self.either(":", ColonColon, Colon),
+                "," => return Some(Comma),
+                "." => return self.either(".", DotDot, Dot),
+                // Labels can be keywords too, as in `'loop`
+                "'" if self.peek().is_some_and(Self::is_alpha) => {
+                    let start = self.position;
+                    while self.peek().is_some_and(|c| Self::is_digit(c) || Self::is_alpha(c)) {
+                        self.position += 1;
+                    }
+                    return Some(Label(&self.source[start..self.position]))
+                },
+                c if Self::is_alpha(c) => return self.identifier(),
+                c => return Some(Unknown(c)),
             };
         }
     }
 }
 
+pub struct Spanned<'a> {
+    scanner: Scanner<'a>,
+}
+
+impl<'a> Iterator for Spanned<'a> {
+    type Item = (Token<'a>, Span);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.scanner.next()?;
+        Some((token, Span::new(self.scanner.token_start, self.scanner.position)))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -146,6 +342,18 @@ mod test {
     fn numbers() {
         let tokens = Scanner::new("1124\n").collect::<Vec<_>>();
         assert_eq!(tokens, vec![Number("1124")]);
+        let tokens = Scanner::new("255u8 as i64").collect::<Vec<_>>();
+        assert_eq!(tokens, vec![Number("255u8"), As, Var("i64")]);
+        let tokens = Scanner::new("2.5 + 1.5f64").collect::<Vec<_>>();
+        assert_eq!(tokens, vec![Number("2.5"), Plus, Number("1.5f64")]);
+        // A point without digits after it is field access
+        let tokens = Scanner::new("1.x").collect::<Vec<_>>();
+        assert_eq!(tokens, vec![Number("1"), Dot, Var("x")]);
+        let tokens = Scanner::new("1e308 2.5E-3f64 1e+2").collect::<Vec<_>>();
+        assert_eq!(tokens, vec![Number("1e308"), Number("2.5E-3f64"), Number("1e+2")]);
+        // Without digits it's a suffix, which the checker will reject
+        let tokens = Scanner::new("1e- 2").collect::<Vec<_>>();
+        assert_eq!(tokens, vec![Number("1e"), Minus, Number("2")]);
     }
 
     #[test]
@@ -154,9 +362,92 @@ mod test {
         assert_eq!(tokens, vec![SingleEq, Semicolon, Star, Minus, Plus, Slash, LParen, RParen, LBrace, RBrace]);
     }
 
+    #[test]
+    fn comparisons() {
+        let tokens = Scanner::new("== != < <= > >= =").collect::<Vec<_>>();
+        assert_eq!(tokens, vec![DoubleEq, BangEq, Less, LessEq, Greater, GreaterEq, SingleEq]);
+    }
+
+    #[test]
+    fn function() {
+        let tokens = Scanner::new("fn (x: i64, y: bool): bool").collect::<Vec<_>>();
+        assert_eq!(tokens, vec![
+            Fn, LParen, Var("x"), Colon, Var("i64"), Comma, Var("y"), Colon, Var("bool"), RParen, Colon, Var("bool")
+        ]);
+    }
+
     #[test]
     fn identifiers() {
         let tokens = Scanner::new("true if ident let else false ").collect::<Vec<_>>();
         assert_eq!(tokens, vec![True, If, Var("ident"), Let, Else, False]);
     }
+
+    #[test]
+    fn structs() {
+        let tokens = Scanner::new("struct P { x: i64 } P { ..p }.x").collect::<Vec<_>>();
+        assert_eq!(tokens, vec![
+            Struct, Var("P"), LBrace, Var("x"), Colon, Var("i64"), RBrace,
+            Var("P"), LBrace, DotDot, Var("p"), RBrace, Dot, Var("x"),
+        ]);
+    }
+
+    #[test]
+    fn enums() {
+        let tokens = Scanner::new("enum E { A(i64) } match E::A(1) { E::A(_) => 1 }").collect::<Vec<_>>();
+        assert_eq!(tokens, vec![
+            Enum, Var("E"), LBrace, Var("A"), LParen, Var("i64"), RParen, RBrace,
+            Match, Var("E"), ColonColon, Var("A"), LParen, Number("1"), RParen,
+            LBrace, Var("E"), ColonColon, Var("A"), LParen, Var("_"), RParen, FatArrow, Number("1"), RBrace,
+        ]);
+    }
+
+    #[test]
+    fn traits() {
+        let tokens = Scanner::new("trait T {} impl T for i64 {} x as dyn T").collect::<Vec<_>>();
+        assert_eq!(tokens, vec![
+            Trait, Var("T"), LBrace, RBrace, Impl, Var("T"), For, Var("i64"), LBrace, RBrace, Var("x"), As, Dyn, Var("T"),
+        ]);
+    }
+
+    #[test]
+    fn assignment() {
+        let tokens = Scanner::new("let mut x = 1; x += 2; x -= -3; x *= 4; x /= 5; x %= 6 % 7").collect::<Vec<_>>();
+        assert_eq!(tokens, vec![
+            Let, Mut, Var("x"), SingleEq, Number("1"), Semicolon,
+            Var("x"), PlusEq, Number("2"), Semicolon,
+            Var("x"), MinusEq, Minus, Number("3"), Semicolon,
+            Var("x"), StarEq, Number("4"), Semicolon,
+            Var("x"), SlashEq, Number("5"), Semicolon,
+            Var("x"), PercentEq, Number("6"), Percent, Number("7"),
+        ]);
+    }
+
+    #[test]
+    fn loops() {
+        let tokens = Scanner::new("'outer: while x { for i in 0..n { break 'outer 1 }; loop { continue } }").collect::<Vec<_>>();
+        assert_eq!(tokens, vec![
+            Label("outer"), Colon, While, Var("x"), LBrace,
+            For, Var("i"), In, Number("0"), DotDot, Var("n"), LBrace, Break, Label("outer"), Number("1"), RBrace, Semicolon,
+            Loop, LBrace, Continue, RBrace, RBrace,
+        ]);
+        let tokens = Scanner::new("'a 'loop '").collect::<Vec<_>>();
+        assert_eq!(tokens, vec![Label("a"), Label("loop"), Unknown("'")]);
+    }
+
+    #[test]
+    fn comments() {
+        let tokens = Scanner::new("1 // one / two\n/ 2 //").collect::<Vec<_>>();
+        assert_eq!(tokens, vec![Number("1"), Slash, Number("2")]);
+    }
+
+    #[test]
+    fn spans() {
+        let tokens = Scanner::new(" let xy\t<= é").spanned().collect::<Vec<_>>();
+        assert_eq!(tokens, vec![
+            (Let, Span::new(1, 4)),
+            (Var("xy"), Span::new(5, 7)),
+            (LessEq, Span::new(8, 10)),
+            (Unknown("é"), Span::new(11, 13)),
+        ]);
+    }
 }
@@ -1,133 +1,2469 @@
-use crate::parser::{HuckAst, ParseOutput};
+use crate::diagnostic::{Code, Diagnostic, Span};
+use crate::exhaustiveness;
+use crate::parser::{AssignOp, Builtin, HuckAst, MatchArm, MethodSig, ParseOutput, Pattern, TypeAnn, TypeArgList};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
 
-#[derive(PartialEq, Clone, Copy, Debug)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum IntType {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+// Integers of every type are kept in an i64, sign-extended if the type is
+// signed and zero-extended if it isn't; a u64 is just its bit pattern.
+// The backends keep the same representation in their 64-bit registers.
+impl IntType {
+    pub const ALL: [Self; 8] = [Self::I8, Self::I16, Self::I32, Self::I64, Self::U8, Self::U16, Self::U32, Self::U64];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::I8 => "i8",
+            Self::I16 => "i16",
+            Self::I32 => "i32",
+            Self::I64 => "i64",
+            Self::U8 => "u8",
+            Self::U16 => "u16",
+            Self::U32 => "u32",
+            Self::U64 => "u64",
+        }
+    }
+
+    pub fn bits(self) -> u32 {
+        match self {
+            Self::I8 | Self::U8 => 8,
+            Self::I16 | Self::U16 => 16,
+            Self::I32 | Self::U32 => 32,
+            Self::I64 | Self::U64 => 64,
+        }
+    }
+
+    pub fn is_signed(self) -> bool {
+        matches!(self, Self::I8 | Self::I16 | Self::I32 | Self::I64)
+    }
+
+    pub fn min(self) -> i128 {
+        if self.is_signed() { -(1 << (self.bits() - 1)) } else { 0 }
+    }
+
+    pub fn max(self) -> i128 {
+        if self.is_signed() { (1 << (self.bits() - 1)) - 1 } else { (1 << self.bits()) - 1 }
+    }
+
+    /// Truncate a 64-bit result to this type, extending it back out the
+    /// way values of this type are stored.
+    pub fn wrap(self, n: i64) -> i64 {
+        let shift = 64 - self.bits();
+        if self.is_signed() {
+            (n << shift) >> shift
+        } else {
+            (((n as u64) << shift) >> shift) as i64
+        }
+    }
+
+    /// The number a stored value stands for.
+    pub fn value(self, n: i64) -> i128 {
+        if self.is_signed() { n as i128 } else { n as u64 as i128 }
+    }
+
+    /// Store a number as this type, or None if it's out of range.
+    pub fn fit(self, n: i128) -> Option<i64> {
+        (self.min()..=self.max()).contains(&n).then_some(n as i64)
+    }
+
+    /// Add two stored values, or None if the sum doesn't fit.
+    pub fn add(self, a: i64, b: i64) -> Option<i64> {
+        self.fit(self.value(a) + self.value(b))
+    }
+
+    pub fn sub(self, a: i64, b: i64) -> Option<i64> {
+        self.fit(self.value(a) - self.value(b))
+    }
+
+    // Two u64s can multiply past what an i128 holds
+    pub fn mul(self, a: i64, b: i64) -> Option<i64> {
+        self.value(a).checked_mul(self.value(b)).and_then(|n| self.fit(n))
+    }
+
+    /// Divide two stored values, or None on a zero divisor or a quotient
+    /// that doesn't fit (`MIN / -1` for the signed types).
+    pub fn divide(self, a: i64, b: i64) -> Option<i64> {
+        if b == 0 {
+            return None;
+        }
+        self.fit(self.value(a) / self.value(b))
+    }
+
+    /// The remainder of dividing two stored values, with the sign of the
+    /// dividend. It always fits, but `MIN % -1` fails anyway, as it does
+    /// in Rust, since the division behind it overflows.
+    pub fn remainder(self, a: i64, b: i64) -> Option<i64> {
+        self.divide(a, b)?;
+        Some((self.value(a) % self.value(b)) as i64)
+    }
+
+    pub fn compare(self, a: i64, b: i64) -> std::cmp::Ordering {
+        self.value(a).cmp(&self.value(b))
+    }
+
+    /// Convert a float the way `as` does: toward zero, saturating at the
+    /// ends of the type, with NaN becoming 0.
+    pub fn from_f64(self, x: f64) -> i64 {
+        (x as i128).clamp(self.min(), self.max()) as i64
+    }
+
+    pub fn to_f64(self, n: i64) -> f64 {
+        self.value(n) as f64
+    }
+}
+
+impl fmt::Display for IntType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub enum TypeInfo {
-    // Unit,
-    // Bool,
-    Int64,
+    Unit,
+    Bool,
+    Int(IntType),
+    F64,
+    // Functions aren't first-class, but their names still need a type
+    Fn(Vec<TypeInfo>, Box<TypeInfo>),
+    Struct(Rc<StructType>),
+    Enum(Rc<EnumType>),
+    // A type parameter, inside whatever generic thing declares it
+    Param(String),
+    // A generic function's `Fn` type, with its type parameters and the
+    // traits each has to implement. Each call instantiates them.
+    Generic(Vec<(String, Vec<Rc<TraitType>>)>, Box<TypeInfo>),
+    // `dyn Trait`: a value of any type implementing the trait
+    Dyn(Rc<TraitType>),
+    // What `break`, `continue` and a `loop` nothing breaks out of are:
+    // they never finish, so they can stand in for any type
+    Never,
+}
+
+impl TypeInfo {
+    /// The type with each type parameter in `args` replaced by its
+    /// argument.
+    pub fn subst(&self, args: &HashMap<String, TypeInfo>) -> TypeInfo {
+        let all = |types: &[TypeInfo]| types.iter().map(|t| t.subst(args)).collect::<Vec<_>>();
+        match self {
+            Self::Param(name) => args.get(name).cloned().unwrap_or_else(|| self.clone()),
+            Self::Fn(params, ret) => Self::Fn(all(params), Box::new(ret.subst(args))),
+            // Anything generic has type arguments, so the rest can't have
+            // parameters in it
+            Self::Struct(s) if !s.args.is_empty() => Self::Struct(Rc::new(StructType {
+                name: s.name.clone(),
+                args: all(&s.args),
+                fields: s.fields.iter().map(|(name, t)| (name.clone(), t.subst(args))).collect(),
+                id: s.id,
+            })),
+            Self::Enum(e) if !e.args.is_empty() => Self::Enum(Rc::new(EnumType {
+                name: e.name.clone(),
+                args: all(&e.args),
+                variants: e.variants.iter().map(|(name, payload)| (name.clone(), all(payload))).collect(),
+                id: e.id,
+            })),
+            _ => self.clone(),
+        }
+    }
+
+    /// Match `self`, which can mention the type parameters `params`,
+    /// against `actual`, adding what that says the parameters are to
+    /// `bound`. False if they can't be made the same.
+    pub fn unify(&self, actual: &TypeInfo, params: &[String], bound: &mut HashMap<String, TypeInfo>) -> bool {
+        let all = |ours: &[TypeInfo], theirs: &[TypeInfo], bound: &mut HashMap<String, TypeInfo>| {
+            ours.len() == theirs.len() && ours.iter().zip(theirs).all(|(a, b)| a.unify(b, params, bound))
+        };
+        match (self, actual) {
+            (_, Self::Never) => true,
+            (Self::Param(name), _) if params.contains(name) => match bound.get(name) {
+                Some(t) => t == actual,
+                None => {
+                    bound.insert(name.clone(), actual.clone());
+                    true
+                },
+            },
+            (Self::Struct(a), Self::Struct(b)) if a.id == b.id => all(&a.args, &b.args, bound),
+            (Self::Enum(a), Self::Enum(b)) if a.id == b.id => all(&a.args, &b.args, bound),
+            (Self::Fn(a, a_ret), Self::Fn(b, b_ret)) => all(a, b, bound) && a_ret.unify(b_ret, params, bound),
+            _ => self == actual,
+        }
+    }
+
+    /// Whether a value of this type can go where `expected` is needed.
+    pub fn fits(&self, expected: &TypeInfo) -> bool {
+        *self == Self::Never || self == expected
+    }
+
+    /// The type of something that's either `self` or `other`, like an
+    /// `if`, or None if they can't be the same.
+    pub fn join(&self, other: &TypeInfo) -> Option<TypeInfo> {
+        match (self, other) {
+            (Self::Never, _) => Some(other.clone()),
+            (_, Self::Never) => Some(self.clone()),
+            _ => (self == other).then(|| self.clone()),
+        }
+    }
+
+    // What to expect of something that has to be the same type as a
+    // value of this one; nothing, if it never has a value
+    fn expectation(&self) -> Option<&TypeInfo> {
+        (*self != Self::Never).then_some(self)
+    }
+
+    /// A generic function's `Fn` type, without its type parameters.
+    /// Anything else is left alone.
+    pub fn signature(&self) -> &TypeInfo {
+        match self {
+            Self::Generic(_, signature) => signature,
+            _ => self,
+        }
+    }
+
+    /// Whether the type parameter `name` appears anywhere in the type.
+    pub fn mentions(&self, name: &str) -> bool {
+        match self {
+            Self::Param(param) => param == name,
+            Self::Fn(params, ret) => params.iter().any(|t| t.mentions(name)) || ret.mentions(name),
+            Self::Struct(s) => s.args.iter().any(|t| t.mentions(name)),
+            Self::Enum(e) => e.args.iter().any(|t| t.mentions(name)),
+            _ => false,
+        }
+    }
+
+    // Whether a `dyn` appears anywhere in the type
+    fn has_dyn(&self) -> bool {
+        match self {
+            Self::Dyn(_) => true,
+            Self::Fn(params, ret) => params.iter().any(Self::has_dyn) || ret.has_dyn(),
+            Self::Struct(s) => s.args.iter().any(Self::has_dyn),
+            Self::Enum(e) => e.args.iter().any(Self::has_dyn),
+            _ => false,
+        }
+    }
+
+    // A declared struct or enum's own type parameters
+    fn declared_params(&self) -> Vec<String> {
+        let args = match self {
+            Self::Struct(s) if !s.is_tuple() => &s.args,
+            Self::Enum(e) => &e.args,
+            _ => return vec![],
+        };
+        args.iter().map(|arg| match arg {
+            Self::Param(name) => name.clone(),
+            _ => unreachable!("Declarations are generic over parameters"),
+        }).collect()
+    }
+}
+
+/// A declared struct. Structs are nominal: two declarations are
+/// different types even if they have the same name and fields, so each
+/// gets its own `id`. Generic structs have type arguments, which are just
+/// its own parameters in the declaration itself; `fields` are already
+/// instantiated with them.
+#[derive(PartialEq, Eq, Hash, Debug)]
+pub struct StructType {
+    pub name: String,
+    pub args: Vec<TypeInfo>,
+    pub fields: Vec<(String, TypeInfo)>,
+    pub id: usize,
+}
+
+impl StructType {
+    /// `(A, B)`, which is a struct with a field for each element named by
+    /// its position. Tuples aren't declared, so their ids count down from
+    /// the top, one for each number of elements.
+    pub fn tuple(elements: Vec<TypeInfo>) -> Self {
+        let fields = elements.iter().enumerate().map(|(i, t)| (i.to_string(), t.clone())).collect();
+        Self { name: String::new(), id: usize::MAX - elements.len(), args: elements, fields }
+    }
+
+    pub fn is_tuple(&self) -> bool {
+        self.name.is_empty()
+    }
+
+    pub fn field(&self, name: &str) -> Option<(usize, &TypeInfo)> {
+        self.fields.iter().enumerate().find(|(_, (field, _))| field == name).map(|(i, (_, ty))| (i, ty))
+    }
+
+    // For notes listing what's there instead
+    fn field_names(&self) -> String {
+        if self.is_tuple() {
+            return format!("{} has elements `0` to `{}`", TypeInfo::Struct(Rc::new(StructType::tuple(self.args.clone()))), self.args.len() - 1);
+        }
+        if self.fields.is_empty() {
+            return format!("`{}` has no fields", self.name);
+        }
+        let names = self.fields.iter().map(|(name, _)| format!("`{}`", name)).collect::<Vec<_>>();
+        format!("`{}` has fields {}", self.name, names.join(", "))
+    }
+}
+
+/// A declared enum: each value is one of its variants, carrying that
+/// variant's payload. Nominal like structs, and numbered and made generic
+/// the same way.
+#[derive(PartialEq, Eq, Hash, Debug)]
+pub struct EnumType {
+    pub name: String,
+    pub args: Vec<TypeInfo>,
+    pub variants: Vec<(String, Vec<TypeInfo>)>,
+    pub id: usize,
+}
+
+impl EnumType {
+    pub fn variant(&self, name: &str) -> Option<(usize, &[TypeInfo])> {
+        self.variants.iter().enumerate()
+            .find(|(_, (variant, _))| variant == name)
+            .map(|(i, (_, payload))| (i, payload.as_slice()))
+    }
+
+    fn variant_names(&self) -> String {
+        if self.variants.is_empty() {
+            return format!("`{}` has no variants", self.name);
+        }
+        let names = self.variants.iter().map(|(name, _)| format!("`{}`", name)).collect::<Vec<_>>();
+        format!("`{}` has variants {}", self.name, names.join(", "))
+    }
+}
+
+/// A declared trait: the methods a type needs to implement it. Each
+/// method's type takes `self` first, and calls the implementing type
+/// `Self`. Numbered from the same sequence as structs and enums.
+#[derive(PartialEq, Eq, Hash, Debug)]
+pub struct TraitType {
+    pub name: String,
+    pub methods: Vec<(String, TypeInfo)>,
+    pub id: usize,
+}
+
+impl TraitType {
+    /// The type of `method` when `self_type` implements the trait.
+    pub fn method(&self, name: &str, self_type: &TypeInfo) -> Option<TypeInfo> {
+        let args = HashMap::from([(String::from("Self"), self_type.clone())]);
+        self.methods.iter().find(|(method, _)| method == name).map(|(_, ty)| ty.subst(&args))
+    }
+
+    // A method that can't be called through a `dyn`, because it takes or
+    // returns another `Self`, whose type wouldn't be known
+    fn unsafe_method(&self) -> Option<&str> {
+        self.methods.iter().find(|(_, ty)| {
+            let TypeInfo::Fn(params, ret) = ty else { unreachable!("Methods are functions") };
+            params[1..].iter().any(|t| t.mentions("Self")) || ret.mentions("Self")
+        }).map(|(name, _)| name.as_str())
+    }
+
+    fn method_names(&self) -> String {
+        let names = self.methods.iter().map(|(name, _)| format!("`{}`", name)).collect::<Vec<_>>();
+        match names.is_empty() {
+            true => format!("`{}` has no methods", self.name),
+            false => format!("`{}` has methods {}", self.name, names.join(", ")),
+        }
+    }
+}
+
+/// Whether a built-in type implements one of the built-in traits, which
+/// it does by doing what `==`, `<` or `print` already do for it.
+pub fn builtin_impl(trait_name: &str, ty: &TypeInfo) -> bool {
+    matches!(
+        (trait_name, ty),
+        ("Eq" | "Display", TypeInfo::Int(_) | TypeInfo::F64 | TypeInfo::Bool) | ("Ord", TypeInfo::Int(_) | TypeInfo::F64)
+    )
+}
+
+// Types are shown the way they're written in huck source
+impl fmt::Display for TypeInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unit => write!(f, "()"),
+            Self::Bool => write!(f, "bool"),
+            Self::Int(t) => write!(f, "{}", t),
+            Self::F64 => write!(f, "f64"),
+            Self::Fn(params, ret) => {
+                let params = params.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+                write!(f, "fn ({}): {}", params.join(", "), ret)
+            },
+            Self::Struct(s) if s.is_tuple() => match &s.args[..] {
+                [element] => write!(f, "({},)", element),
+                elements => write!(f, "({})", elements.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(", ")),
+            },
+            Self::Struct(s) => write!(f, "{}{}", s.name, TypeArgs(&s.args)),
+            Self::Enum(e) => write!(f, "{}{}", e.name, TypeArgs(&e.args)),
+            Self::Param(name) => write!(f, "{}", name),
+            Self::Generic(params, fn_type) => {
+                let Self::Fn(param_types, ret) = fn_type.as_ref() else { unreachable!("Only functions are generic") };
+                let params = params.iter().map(|(name, bounds)| match bounds.is_empty() {
+                    true => name.clone(),
+                    false => format!("{}: {}", name, bounds.iter().map(|b| b.name.as_str()).collect::<Vec<_>>().join(" + ")),
+                }).collect::<Vec<_>>();
+                let param_types = param_types.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+                write!(f, "fn <{}>({}): {}", params.join(", "), param_types.join(", "), ret)
+            },
+            Self::Dyn(trait_type) => write!(f, "dyn {}", trait_type.name),
+            Self::Never => write!(f, "!"),
+        }
+    }
+}
+
+// `<i64, bool>`, or nothing for a type that isn't generic
+struct TypeArgs<'a>(&'a [TypeInfo]);
+
+impl fmt::Display for TypeArgs<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        let args = self.0.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        write!(f, "<{}>", args.join(", "))
+    }
 }
 
 type CheckInput = ParseOutput;
 
-pub type CheckOutput = HuckAst<TypeInfo>;
+pub type CheckOutput = HuckAst<Typed>;
+
+// What the checker knows about a node: its type, and where it came from
+// so later stages can still point at the source
+#[derive(Debug, PartialEq, Clone)]
+pub struct Typed {
+    pub ty: TypeInfo,
+    pub span: Span,
+}
+
+impl HuckAst<Typed> {
+    pub fn ty(&self) -> &TypeInfo {
+        &self.get_metadata().ty
+    }
+
+    pub fn span(&self) -> Span {
+        self.get_metadata().span
+    }
+}
+
+type CheckResult = Result<CheckOutput, Diagnostic>;
 
-type CheckResult = Result<CheckOutput, String>;
+#[derive(Clone, Copy, PartialEq)]
+enum LoopKind {
+    While,
+    Loop,
+    For,
+}
+
+impl LoopKind {
+    fn keyword(self) -> &'static str {
+        match self {
+            Self::While => "while",
+            Self::Loop => "loop",
+            Self::For => "for",
+        }
+    }
+}
+
+// A loop being checked, for the `break`s and `continue`s inside it
+#[derive(Clone)]
+struct LoopContext {
+    label: Option<String>,
+    // Only a `loop` can break with a value
+    kind: LoopKind,
+    expected: Option<TypeInfo>,
+    // What the first `break` gave the loop, and where it was
+    first_break: Option<(TypeInfo, Span)>,
+}
 
+#[derive(Clone)]
 pub struct Checker {
-    _env: Vec<HashMap<String, TypeInfo>>
+    env: Vec<HashMap<String, TypeInfo>>,
+    // Structs and enums declared in each scope of `env`
+    types: Vec<HashMap<String, TypeInfo>>,
+    // Where each `let` in each scope of `env` is, and whether it's
+    // `mut`, for assignments to check against
+    lets: Vec<HashMap<String, (Span, bool)>>,
+    // The loops around what's being checked, innermost last
+    loops: Vec<LoopContext>,
+    // Index of the first loop inside the function being checked. A
+    // function can't break out of a loop it's declared in.
+    loop_base: usize,
+    next_type_id: usize,
+    // Index of the first scope belonging to the function being checked;
+    // anything below it is a local of some enclosing function
+    frame_base: usize,
+    // Type parameters of the generic function or type being checked, with
+    // their bounds. Only its own are visible, not those of anything around
+    // it.
+    type_params: Vec<(String, Vec<Rc<TraitType>>)>,
+    // What `Self` is, inside a trait or impl
+    self_type: Option<TypeInfo>,
+    // Traits are only declared at the top level, so there's one set of
+    // them, along with which types implement each (by trait id)
+    traits: HashMap<String, Rc<TraitType>>,
+    impls: HashSet<(usize, TypeInfo)>,
+    warnings: Vec<Diagnostic>,
+    // Whether to carry on past errors in a block's expressions, keeping
+    // them in `errors`, as an editor wants. `poisoned` has the variables
+    // whose `let` had one, which anything using them would too.
+    recovering: bool,
+    errors: Vec<Diagnostic>,
+    poisoned: HashSet<String>,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Checker {
     pub fn new() -> Self {
-        Self {
-            _env: vec![HashMap::new()]
+        let mut checker = Self {
+            env: vec![HashMap::new()],
+            types: vec![HashMap::new()],
+            lets: vec![HashMap::new()],
+            loops: vec![],
+            loop_base: 0,
+            next_type_id: 0,
+            frame_base: 0,
+            type_params: vec![],
+            self_type: None,
+            traits: HashMap::new(),
+            impls: HashSet::new(),
+            warnings: vec![],
+            recovering: false,
+            errors: vec![],
+            poisoned: HashSet::new(),
+        };
+        // The traits behind `==`, `<` and `print`. `cmp` is negative,
+        // zero or positive as `self` is less than, equal to or greater
+        // than `other`.
+        let this = || TypeInfo::Param(String::from("Self"));
+        let builtins = [
+            ("Eq", "eq", vec![this(), this()], TypeInfo::Bool),
+            ("Ord", "cmp", vec![this(), this()], TypeInfo::Int(IntType::I64)),
+            ("Display", "print", vec![this()], TypeInfo::Unit),
+        ];
+        for (name, method, params, ret) in builtins {
+            let methods = vec![(method.to_string(), TypeInfo::Fn(params, Box::new(ret)))];
+            let trait_type = TraitType { name: name.to_string(), methods, id: checker.next_type_id };
+            checker.next_type_id += 1;
+            checker.traits.insert(name.to_string(), Rc::new(trait_type));
+        }
+        checker
+    }
+
+    /// Problems found so far that don't stop the program from compiling,
+    /// like match arms that can never match. Each is only returned once.
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Check a program the way an editor wants it checked: carrying on past
+    /// errors where it can, so it can report all of them. Whatever can
+    /// still be checked comes back too, with each expression in a block
+    /// that had an error replaced by an empty block of type `!` (or of
+    /// its function's type, for a function that's declared up front).
+    pub fn check_all(&mut self, ast: &CheckInput) -> (Option<CheckOutput>, Vec<Diagnostic>) {
+        self.recovering = true;
+        let checked = self.check(ast);
+        self.recovering = false;
+        self.poisoned.clear();
+        let mut errors = std::mem::take(&mut self.errors);
+        let checked = checked.map_err(|diagnostic| errors.push(diagnostic)).ok();
+        errors.sort_by_key(|error| error.primary.as_ref().map(|label| label.span.start));
+        (checked, errors)
+    }
+
+    // Carry on with a block after `expr` in it had an error, which is
+    // kept unless it's only because of an earlier one. Anything it was
+    // in the middle of is forgotten.
+    fn recover(&mut self, expr: &CheckInput, diagnostic: Diagnostic, scopes: (usize, usize, usize, usize)) -> CheckOutput {
+        let (env, types, lets, loops) = scopes;
+        self.env.truncate(env);
+        self.types.truncate(types);
+        self.lets.truncate(lets);
+        self.loops.truncate(loops);
+        if !self.uses_poisoned(expr) {
+            self.errors.push(diagnostic);
+        }
+
+        let stand_in = |ty, span| HuckAst::Block(vec![], Typed { ty, span });
+        let span = *expr.get_metadata();
+        match expr {
+            HuckAst::Let(ident, mutable, init, _) => {
+                let ty = match init.as_ref() {
+                    HuckAst::Fn(..) => self.env.last().unwrap().get(ident).cloned().unwrap_or(TypeInfo::Never),
+                    _ => {
+                        self.poisoned.insert(ident.clone());
+                        TypeInfo::Never
+                    },
+                };
+                let init = stand_in(ty.clone(), *init.get_metadata());
+                HuckAst::Let(ident.clone(), *mutable, Box::new(init), Typed { ty, span })
+            },
+            _ => stand_in(TypeInfo::Never, span),
+        }
+    }
+
+    fn uses_poisoned(&self, ast: &CheckInput) -> bool {
+        let uses = match ast {
+            HuckAst::VarRef(ident, _) | HuckAst::Call(ident, ..) | HuckAst::Assign(ident, ..) => self.poisoned.contains(ident),
+            _ => false,
+        };
+        uses || ast.children().into_iter().any(|child| self.uses_poisoned(child))
+    }
+
+    fn begin_scope(&mut self) {
+        self.env.push(HashMap::new());
+        self.types.push(HashMap::new());
+        self.lets.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.env.pop();
+        self.types.pop();
+        self.lets.pop();
+    }
+
+    fn add_var(&mut self, ident: String, info: TypeInfo) {
+        // Anything but a `let` shadowing a `let` can't be assigned to
+        self.lets.last_mut().unwrap().remove(&ident);
+        let map = self.env.last_mut().unwrap();
+        map.insert(ident, info);
+    }
+
+    // Only a `let mut` can be assigned to. `ident` is known to be bound.
+    fn assignable(&self, ident: &str, span: Span) -> Result<(), Diagnostic> {
+        let depth = self.env.iter().rposition(|map| map.contains_key(ident)).unwrap();
+        match self.lets[depth].get(ident) {
+            Some((_, true)) => Ok(()),
+            Some((declared, false)) => {
+                Err(Diagnostic::type_error(Code::IMMUTABLE_ASSIGN, format!("Can't assign to immutable variable `{}`", ident), span)
+                    .with_label(span, "assigned here")
+                    .with_secondary(*declared, format!("`{}` is declared here", ident))
+                    .with_suggestion("make it mutable", Span::new(declared.start, declared.start + "let".len()), "let mut"))
+            },
+            None => Err(Diagnostic::type_error(Code::IMMUTABLE_ASSIGN, format!("Can't assign to `{}`", ident), span)
+                .with_label(span, "assigned here")
+                .with_note("only variables declared with `let mut` can be assigned to")),
+        }
+    }
+
+    fn get_var(&mut self, ident: &str, span: Span) -> Result<TypeInfo, Diagnostic> {
+        for (depth, map) in self.env.iter().enumerate().rev() {
+            match map.get(ident) {
+                // Functions end up global, so they're callable from anywhere
+                Some(info @ (TypeInfo::Fn(..) | TypeInfo::Generic(..))) => return Ok(info.clone()),
+                Some(_) if depth < self.frame_base => {
+                    return Err(Diagnostic::type_error(
+                        Code::CAPTURED_LOCAL,
+                        format!("Function can't capture local variable `{}` of an enclosing function", ident),
+                        span,
+                    )
+                    .with_label(span, "captured here")
+                    .with_note("functions can only see their parameters, their own locals and other functions")
+                    .with_help(format!("pass `{}` in as a parameter", ident)))
+                },
+                Some(info) => return Ok(info.clone()),
+                None => continue,
+            }
+        }
+        Err(Diagnostic::type_error(Code::UNBOUND_VARIABLE, format!("Unbound variable `{}`", ident), span)
+            .with_label(span, "not found in this scope"))
+    }
+
+    // Builtins can be shadowed like anything else
+    fn is_bound(&self, ident: &str) -> bool {
+        self.env.iter().any(|map| map.contains_key(ident))
+    }
+
+    /// Declare a function that's defined outside of huck, like the host
+    /// functions of an embedding `Engine`.
+    pub fn declare_fn(&mut self, ident: &str, fn_type: TypeInfo) {
+        self.env[0].insert(ident.to_string(), fn_type);
+    }
+
+    // Generic structs and enums need exactly as many type arguments as
+    // they have parameters, and nothing else takes any
+    fn resolve_type(&self, ann: &TypeAnn, span: Span) -> Result<TypeInfo, Diagnostic> {
+        // Miscounted type arguments are pointed at, if there are any
+        let (name, args, args_span) = match ann {
+            TypeAnn::Unit => return Ok(TypeInfo::Unit),
+            TypeAnn::Named(name) => (name, &[][..], span),
+            TypeAnn::Applied(name, args) => (name, &args.args[..], args.span),
+            TypeAnn::Tuple(elements) => {
+                let elements = elements.iter()
+                    .map(|element| self.resolve_type(element, span))
+                    .collect::<Result<Vec<_>, _>>()?;
+                return Ok(TypeInfo::Struct(Rc::new(StructType::tuple(elements))))
+            },
+            TypeAnn::Dyn(name) => {
+                let trait_type = self.get_trait(name, span)?;
+                if let Some(method) = trait_type.unsafe_method() {
+                    return Err(Diagnostic::type_error(Code::NOT_OBJECT_SAFE, format!("Can't make a `dyn {}`", name), span)
+                        .with_label(span, format!("`{}` takes or returns another `Self`", method))
+                        .with_note("a `dyn` only knows what type its own value is, so its methods can only use `Self` for `self`"))
+                }
+                return Ok(TypeInfo::Dyn(trait_type))
+            },
+        };
+        let args = args.iter()
+            .map(|arg| self.resolve_type(arg, span))
+            .collect::<Result<Vec<_>, _>>()?;
+        let ty = match (name.as_str(), IntType::from_name(name)) {
+            _ if self.type_params.iter().any(|(param, _)| param == name) => TypeInfo::Param(name.clone()),
+            ("Self", _) if self.self_type.is_some() => self.self_type.clone().unwrap(),
+            (_, Some(t)) => TypeInfo::Int(t),
+            ("f64", _) => TypeInfo::F64,
+            ("bool", _) => TypeInfo::Bool,
+            _ => {
+                let decl = self.get_type(name).ok_or_else(|| {
+                    Diagnostic::type_error(Code::UNKNOWN_TYPE, format!("Unknown type `{}`", name), span)
+                        .with_note("the built-in types are `i8`, `i16`, `i32`, `i64`, `u8`, `u16`, `u32`, `u64`, `f64`, `bool` and `()`")
+                        .with_help("structs and enums have to be declared before they're used")
+                })?;
+                let params = decl.declared_params();
+                if args.len() != params.len() {
+                    return Err(Self::type_argument_count(name, params.len(), args.len(), args_span))
+                }
+                return Ok(decl.subst(&params.into_iter().zip(args).collect()))
+            },
+        };
+        if !args.is_empty() {
+            return Err(Self::type_argument_count(name, 0, args.len(), args_span))
+        }
+        Ok(ty)
+    }
+
+    // The type arguments given explicitly to `name`, by the parameter
+    // they're for. None at all means they're left to be worked out.
+    fn type_arguments(&self, name: &str, params: &[String], type_args: &TypeArgList, span: Span) -> Result<HashMap<String, TypeInfo>, Diagnostic> {
+        let anns = &type_args.args;
+        if anns.is_empty() {
+            return Ok(HashMap::new())
+        }
+        if anns.len() != params.len() {
+            return Err(Self::type_argument_count(name, params.len(), anns.len(), type_args.span))
+        }
+        params.iter()
+            .zip(anns)
+            .map(|(param, ann)| Ok((param.clone(), self.resolve_type(ann, span)?)))
+            .collect()
+    }
+
+    fn type_argument_count(name: &str, expected: usize, found: usize, span: Span) -> Diagnostic {
+        let message = match expected {
+            0 => format!("`{}` doesn't take type arguments", name),
+            1 => format!("`{}` takes 1 type argument but was given {}", name, found),
+            _ => format!("`{}` takes {} type arguments but was given {}", name, expected, found),
+        };
+        Diagnostic::type_error(Code::TYPE_ARGUMENT_COUNT, message, span)
+            .with_label(span, format!("expected {}, found {}", expected, found))
+    }
+
+    fn check_type_params(params: &[String], span: Span) -> Result<(), Diagnostic> {
+        for (i, param) in params.iter().enumerate() {
+            let reason = match () {
+                _ if params[..i].contains(param) => "declared twice",
+                _ if Self::is_builtin_type(param) => "a built-in type",
+                _ if param == "Self" => "what traits call the type implementing them",
+                _ => continue,
+            };
+            return Err(Diagnostic::type_error(Code::DUPLICATE_TYPE, format!("Can't declare a type parameter called `{}`", param), span)
+                .with_label(span, format!("`{}` is {}", param, reason)))
+        }
+        Ok(())
+    }
+
+    fn with_type_params<R>(&mut self, params: Vec<(String, Vec<Rc<TraitType>>)>, f: impl FnOnce(&mut Self) -> R) -> R {
+        let outer = std::mem::replace(&mut self.type_params, params);
+        let result = f(self);
+        self.type_params = outer;
+        result
+    }
+
+    fn with_self_type<R>(&mut self, self_type: TypeInfo, f: impl FnOnce(&mut Self) -> R) -> R {
+        let outer = self.self_type.replace(self_type);
+        let result = f(self);
+        self.self_type = outer;
+        result
+    }
+
+    // What expecting `expected` from something generic, whose type is
+    // `result` before it's instantiated, says its type parameters are, on
+    // top of those already `bound`. This is only a guess for giving
+    // literals a type; an argument saying otherwise wins.
+    fn hints(result: &TypeInfo, expected: Option<&TypeInfo>, params: &[String], bound: &HashMap<String, TypeInfo>) -> HashMap<String, TypeInfo> {
+        let mut hints = bound.clone();
+        Self::infer_rest(result, expected, params, &mut hints);
+        hints
+    }
+
+    // The type an argument of declared type `ty` should have, if the
+    // hints say what all of it is
+    fn hint(ty: &TypeInfo, params: &[String], hints: &HashMap<String, TypeInfo>) -> Option<TypeInfo> {
+        let known = params.iter().all(|param| !ty.mentions(param) || hints.contains_key(param));
+        known.then(|| ty.subst(hints))
+    }
+
+    // Once the arguments are done, parameters they don't mention can still
+    // come from what the result is expected to be
+    fn infer_rest(result: &TypeInfo, expected: Option<&TypeInfo>, params: &[String], bound: &mut HashMap<String, TypeInfo>) {
+        let mut inferred = bound.clone();
+        if expected.is_some_and(|expected| result.unify(expected, params, &mut inferred)) {
+            *bound = inferred;
         }
     }
 
-    // fn begin_scope(&mut self) {
-    //     self.env.push(HashMap::new());
-    // }
+    // Every type parameter has to be known by the end. `example` is how
+    // to give them explicitly.
+    fn all_bound(params: &[String], bound: &HashMap<String, TypeInfo>, name: &str, example: String, span: Span) -> Result<(), Diagnostic> {
+        match params.iter().find(|param| !bound.contains_key(*param)) {
+            Some(param) => Err(Diagnostic::type_error(
+                Code::CANNOT_INFER,
+                format!("Can't work out type parameter `{}` of `{}`", param, name),
+                span,
+            )
+            .with_label(span, format!("`{}` isn't known here", param))
+            .with_help(format!("give the type arguments explicitly, as in `{}`", example))),
+            None => Ok(()),
+        }
+    }
+
+    fn get_type(&self, name: &str) -> Option<TypeInfo> {
+        self.types.iter().rev().find_map(|map| map.get(name)).cloned()
+    }
+
+    fn get_trait(&self, name: &str, span: Span) -> Result<Rc<TraitType>, Diagnostic> {
+        self.traits.get(name).cloned().ok_or_else(|| {
+            Diagnostic::type_error(Code::UNKNOWN_TRAIT, format!("Unknown trait `{}`", name), span)
+                .with_label(span, "not found")
+                .with_note("the built-in traits are `Eq`, `Ord` and `Display`")
+        })
+    }
 
-    // fn end_scope(&mut self) {
-    //     self.env.pop();
-    // }
+    // Whether `ty` implements `trait_type`. Type parameters implement
+    // whatever their bounds say, and a `dyn` only its own trait.
+    fn implements(&self, ty: &TypeInfo, trait_type: &TraitType) -> bool {
+        match ty {
+            TypeInfo::Param(name) => self.type_params.iter()
+                .any(|(param, bounds)| param == name && bounds.iter().any(|bound| bound.id == trait_type.id)),
+            TypeInfo::Dyn(own) => own.id == trait_type.id,
+            _ => builtin_impl(&trait_type.name, ty) || self.impls.contains(&(trait_type.id, ty.clone())),
+        }
+    }
 
-    // fn add_var(&mut self, ident: String, info: TypeInfo) {
-    //     let map = self.env.last_mut().unwrap();
-    //     map.insert(ident, info);
-    // }
+    // Something needed `ty` to implement `trait_type`, and it doesn't
+    fn not_implemented(ty: &TypeInfo, trait_type: &TraitType, message: String, span: Span) -> Diagnostic {
+        let diagnostic = Diagnostic::type_error(Code::NOT_IMPLEMENTED, message, span);
+        match ty {
+            TypeInfo::Param(name) => diagnostic.with_help(format!("add a bound, as in `<{}: {}>`", name, trait_type.name)),
+            TypeInfo::Struct(tuple) if tuple.is_tuple() => diagnostic,
+            TypeInfo::Struct(_) | TypeInfo::Enum(_) if ty.declared_params().is_empty() => {
+                diagnostic.with_help(format!("implement it with `impl {} for {} {{ ... }}`", trait_type.name, ty))
+            },
+            _ => diagnostic,
+        }
+    }
 
-    // fn get_var(&mut self, ident: String) -> Option<TypeInfo> {
-    //     for map in self.env.iter().rev() {
-    //         if let Some(&info) = map.get(&ident) {
-    //             return Some(info)
-    //         } else {
-    //             continue;
-    //         }
-    //     }
-    //     None
-    // }
+    // Traits and impls are visible to the whole program
+    fn top_level(&self, what: &str, span: Span) -> Result<(), Diagnostic> {
+        if self.env.len() == 1 {
+            return Ok(())
+        }
+        Err(Diagnostic::type_error(Code::NOT_TOP_LEVEL, format!("{} can only be declared at the top level", what), span)
+            .with_label(span, "inside a block")
+            .with_note("traits and impls apply to the whole program, so they can't be declared inside a block or function"))
+    }
+
+    fn get_enum(&self, name: &str, span: Span) -> Result<Rc<EnumType>, Diagnostic> {
+        match self.get_type(name) {
+            Some(TypeInfo::Enum(enum_type)) => Ok(enum_type),
+            Some(_) => Err(Diagnostic::type_error(Code::NOT_AN_ENUM, format!("`{}` isn't an enum", name), span)
+                .with_label(span, "not an enum")
+                .with_note("only enums have variants")),
+            None if Self::is_builtin_type(name) => {
+                Err(Diagnostic::type_error(Code::NOT_AN_ENUM, format!("`{}` isn't an enum", name), span)
+                    .with_label(span, format!("`{}` is a built-in type", name)))
+            },
+            None => Err(Diagnostic::type_error(Code::UNKNOWN_TYPE, format!("Unknown enum `{}`", name), span)
+                .with_label(span, "not found in this scope")),
+        }
+    }
+
+    // The index and payload of `name::variant`, checking it has `count`
+    // values in it
+    fn get_variant(enum_type: &EnumType, variant: &str, count: usize, span: Span) -> Result<(usize, Vec<TypeInfo>), Diagnostic> {
+        let name = &enum_type.name;
+        let Some((index, payload)) = enum_type.variant(variant) else {
+            return Err(Diagnostic::type_error(
+                Code::UNKNOWN_VARIANT,
+                format!("Enum `{}` has no variant `{}`", name, variant),
+                span,
+            )
+            .with_label(span, "unknown variant")
+            .with_note(enum_type.variant_names()))
+        };
+        if count != payload.len() {
+            let types = payload.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+            let carries = match payload.len() {
+                0 => String::from("nothing"),
+                _ => format!("({})", types.join(", ")),
+            };
+            return Err(Diagnostic::type_error(
+                Code::PAYLOAD_COUNT,
+                format!("`{}::{}` carries {} values but {} were given", name, variant, payload.len(), count),
+                span,
+            )
+            .with_label(span, format!("expected {}, found {}", payload.len(), count))
+            .with_note(format!("`{}::{}` carries {}", name, variant, carries)))
+        }
+        Ok((index, payload.to_vec()))
+    }
+
+    fn is_builtin_type(name: &str) -> bool {
+        IntType::from_name(name).is_some() || name == "f64" || name == "bool"
+    }
+
+    // Fields can only use types declared before the struct, so a struct
+    // can't end up containing itself except by naming itself directly
+    fn declare_struct(&mut self, name: &str, type_params: &[String], fields: &[(String, TypeAnn)], span: Span) -> Result<(), Diagnostic> {
+        if Self::is_builtin_type(name) {
+            return Err(Diagnostic::type_error(Code::DUPLICATE_TYPE, format!("Can't declare a struct called `{}`", name), span)
+                .with_label(span, format!("`{}` is a built-in type", name)))
+        }
+        Self::check_type_params(type_params, span)?;
+        let mut field_types = vec![];
+        for (field, ann) in fields {
+            if field_types.iter().any(|(seen, _)| seen == field) {
+                return Err(Diagnostic::type_error(Code::DUPLICATE_FIELD, format!("Struct `{}` has two fields called `{}`", name, field), span)
+                    .with_label(span, format!("`{}` is declared twice", field)))
+            }
+            if names(ann, name) {
+                return Err(Diagnostic::type_error(Code::RECURSIVE_TYPE, format!("Struct `{}` can't contain itself", name), span)
+                    .with_label(span, format!("field `{}` holds a {}", field, name))
+                    .with_note("struct fields are stored inline, so this struct would be infinitely big"))
+            }
+            let field_type = self.with_type_params(unbounded(type_params), |checker| checker.resolve_type(ann, span))?;
+            field_types.push((field.clone(), field_type));
+        }
+
+        let id = self.next_type_id;
+        self.next_type_id += 1;
+        let args = type_params.iter().cloned().map(TypeInfo::Param).collect();
+        let struct_type = StructType { name: name.to_string(), args, fields: field_types, id };
+        self.types.last_mut().unwrap().insert(name.to_string(), TypeInfo::Struct(Rc::new(struct_type)));
+        Ok(())
+    }
+
+    // Payloads are stored inline like struct fields, so the same goes for
+    // enums containing themselves
+    fn declare_enum(&mut self, name: &str, type_params: &[String], variants: &[(String, Vec<TypeAnn>)], span: Span) -> Result<(), Diagnostic> {
+        if Self::is_builtin_type(name) {
+            return Err(Diagnostic::type_error(Code::DUPLICATE_TYPE, format!("Can't declare an enum called `{}`", name), span)
+                .with_label(span, format!("`{}` is a built-in type", name)))
+        }
+        Self::check_type_params(type_params, span)?;
+        let mut variant_types: Vec<(String, Vec<TypeInfo>)> = vec![];
+        for (variant, payload) in variants {
+            if variant_types.iter().any(|(seen, _)| seen == variant) {
+                return Err(Diagnostic::type_error(Code::DUPLICATE_VARIANT, format!("Enum `{}` has two variants called `{}`", name, variant), span)
+                    .with_label(span, format!("`{}` is declared twice", variant)))
+            }
+            if payload.iter().any(|ann| names(ann, name)) {
+                return Err(Diagnostic::type_error(Code::RECURSIVE_TYPE, format!("Enum `{}` can't contain itself", name), span)
+                    .with_label(span, format!("variant `{}` carries a {}", variant, name))
+                    .with_note("payloads are stored inline, so this enum would be infinitely big"))
+            }
+            let types = self.with_type_params(unbounded(type_params), |checker| {
+                payload.iter()
+                    .map(|ann| checker.resolve_type(ann, span))
+                    .collect::<Result<Vec<_>, _>>()
+            })?;
+            variant_types.push((variant.clone(), types));
+        }
+
+        let id = self.next_type_id;
+        self.next_type_id += 1;
+        let args = type_params.iter().cloned().map(TypeInfo::Param).collect();
+        let enum_type = EnumType { name: name.to_string(), args, variants: variant_types, id };
+        self.types.last_mut().unwrap().insert(name.to_string(), TypeInfo::Enum(Rc::new(enum_type)));
+        Ok(())
+    }
+
+    // A trait's methods can mention `Self`, which stays a type parameter
+    // until an impl says what it is
+    fn declare_trait(&mut self, name: &str, methods: &[MethodSig], span: Span) -> Result<(), Diagnostic> {
+        if self.traits.contains_key(name) {
+            let reason = match name {
+                "Eq" | "Ord" | "Display" => "a built-in trait",
+                _ => "declared twice",
+            };
+            return Err(Diagnostic::type_error(Code::DUPLICATE_TYPE, format!("Can't declare a trait called `{}`", name), span)
+                .with_label(span, format!("`{}` is {}", name, reason)))
+        }
+        let mut method_types: Vec<(String, TypeInfo)> = vec![];
+        for method in methods {
+            if method_types.iter().any(|(seen, _)| *seen == method.name) {
+                return Err(Diagnostic::type_error(
+                    Code::DUPLICATE_FIELD,
+                    format!("Trait `{}` has two methods called `{}`", name, method.name),
+                    span,
+                )
+                .with_label(span, format!("`{}` is declared twice", method.name)))
+            }
+            let ty = self.with_self_type(TypeInfo::Param(String::from("Self")), |checker| {
+                checker.function_type(&[], &method.params, &method.ret, span)
+            })?;
+            method_types.push((method.name.clone(), ty));
+        }
+
+        let id = self.next_type_id;
+        self.next_type_id += 1;
+        let trait_type = TraitType { name: name.to_string(), methods: method_types, id };
+        self.traits.insert(name.to_string(), Rc::new(trait_type));
+        Ok(())
+    }
+
+    // Impls are for built-in types and non-generic structs and enums, and
+    // there's only ever one of each trait for each type
+    fn declare_impl(&mut self, name: &str, target: &TypeAnn, span: Span) -> Result<(), Diagnostic> {
+        let trait_type = self.get_trait(name, span)?;
+        let ty = self.resolve_type(target, span)?;
+        let allowed = match &ty {
+            TypeInfo::Int(_) | TypeInfo::F64 | TypeInfo::Bool => true,
+            TypeInfo::Struct(tuple) if tuple.is_tuple() => false,
+            TypeInfo::Struct(_) | TypeInfo::Enum(_) => ty.declared_params().is_empty(),
+            _ => false,
+        };
+        if !allowed {
+            return Err(Diagnostic::type_error(Code::BAD_IMPL, format!("Can't implement traits for {}", ty), span)
+                .with_label(span, format!("impl for {}", ty))
+                .with_note("traits can be implemented for built-in types, and structs and enums that aren't generic"))
+        }
+        if builtin_impl(name, &ty) || !self.impls.insert((trait_type.id, ty.clone())) {
+            return Err(Diagnostic::type_error(Code::DUPLICATE_IMPL, format!("{} already implements `{}`", ty, name), span)
+                .with_label(span, "implemented again here"))
+        }
+        Ok(())
+    }
+
+    // An impl has to have exactly the trait's methods, with `Self` being
+    // the type it's for. Each is checked like a function.
+    fn check_impl(&mut self, name: &str, target: &TypeAnn, methods: &[(String, CheckInput)], span: Span) -> CheckResult {
+        let trait_type = self.get_trait(name, span)?;
+        let ty = self.resolve_type(target, span)?;
+        let mismatch = |message: String, span: Span| Diagnostic::type_error(Code::IMPL_MISMATCH, message, span);
+
+        let mut checked_methods: Vec<(String, CheckOutput)> = vec![];
+        for (method, decl) in methods {
+            let HuckAst::Fn(_, params, ret, body, fn_span) = decl else { unreachable!("The parser makes methods functions") };
+            if checked_methods.iter().any(|(seen, _)| seen == method) {
+                return Err(mismatch(format!("`{}` is implemented twice", method), *fn_span)
+                    .with_label(*fn_span, "implemented again here"))
+            }
+            let Some(expected) = trait_type.method(method, &ty) else {
+                return Err(mismatch(format!("`{}` isn't a method of `{}`", method, name), *fn_span)
+                    .with_label(*fn_span, "not in the trait")
+                    .with_note(trait_type.method_names()))
+            };
+            let fn_type = self.with_self_type(ty.clone(), |checker| checker.function_type(&[], params, ret, *fn_span))?;
+            if fn_type != expected {
+                return Err(mismatch(format!("Method `{}` should have type {} to implement `{}`", method, expected, name), *fn_span)
+                    .with_label(*fn_span, format!("this has type {}", fn_type)))
+            }
+            let checked_body = self.with_self_type(ty.clone(), |checker| checker.check_fn_body(method, &fn_type, params, body, *fn_span))?;
+            let checked = HuckAst::Fn(vec![], params.clone(), ret.clone(), Rc::new(checked_body), Typed { ty: fn_type, span: *fn_span });
+            checked_methods.push((method.clone(), checked));
+        }
+
+        let missing = trait_type.methods.iter()
+            .filter(|(method, _)| !checked_methods.iter().any(|(given, _)| given == method))
+            .map(|(method, _)| format!("`{}`", method))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(mismatch(
+                format!("Missing {} {} in impl of `{}` for {}", if missing.len() == 1 { "method" } else { "methods" }, missing.join(", "), name, ty),
+                span,
+            )
+            .with_label(span, "every method of the trait needs a body"))
+        }
+        Ok(HuckAst::Impl(name.to_string(), target.clone(), checked_methods, Typed { ty: TypeInfo::Unit, span }))
+    }
+
+    // Each call to a generic function works out its type arguments from
+    // its arguments and result, so they all have to appear in the signature
+    fn function_type(&mut self, type_params: &[(String, Vec<String>)], params: &[(String, TypeAnn)], ret: &TypeAnn, span: Span) -> Result<TypeInfo, Diagnostic> {
+        let names = type_params.iter().map(|(name, _)| name.clone()).collect::<Vec<_>>();
+        Self::check_type_params(&names, span)?;
+        let type_params = type_params.iter()
+            .map(|(name, bounds)| {
+                let bounds = bounds.iter().map(|bound| self.get_trait(bound, span)).collect::<Result<Vec<_>, _>>()?;
+                Ok((name.clone(), bounds))
+            })
+            .collect::<Result<Vec<_>, Diagnostic>>()?;
+        let signature = self.with_type_params(type_params.clone(), |checker| {
+            let param_types = params.iter()
+                .map(|(_, ann)| checker.resolve_type(ann, span))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(TypeInfo::Fn(param_types, Box::new(checker.resolve_type(ret, span)?)))
+        })?;
+        if type_params.is_empty() {
+            return Ok(signature)
+        }
+        if let Some(unused) = names.iter().find(|param| !signature.mentions(param)) {
+            return Err(Diagnostic::type_error(
+                Code::UNUSED_TYPE_PARAMETER,
+                format!("Type parameter `{}` isn't used by the function's parameters or return type", unused),
+                span,
+            )
+            .with_label(span, format!("`{}` is declared here", unused))
+            .with_note("calls work out what a type parameter is from the types of their arguments and result"))
+        }
+        Ok(TypeInfo::Generic(type_params, Box::new(signature)))
+    }
 
     pub fn check(&mut self, ast: &CheckInput) -> CheckResult {
+        self.check_expecting(ast, None)
+    }
+
+    // Integer literals without a suffix take their type from where
+    // they're used, so `expected` is the type the surrounding code wants,
+    // if it knows. Nothing else needs it: a mismatch is still reported
+    // wherever it would have been.
+    fn check_expecting(&mut self, ast: &CheckInput, expected: Option<&TypeInfo>) -> CheckResult {
+        let typed = |ty| Typed { ty, span: *ast.get_metadata() };
         match ast {
-            HuckAst::Num(n, _) => Ok(HuckAst::Num(*n, TypeInfo::Int64)),
-            // HuckAst::BoolLit(b, _) => Ok(HuckAst::BoolLit(*b, TypeInfo::Bool)),
-            // HuckAst::Plus(lhs, rhs, _) => self.check_binary(lhs, rhs, HuckAst::Plus),
-            // HuckAst::Minus(lhs, rhs, _) => self.check_binary(lhs, rhs, HuckAst::Minus),
-            // HuckAst::Times(lhs, rhs, _) => self.check_binary(lhs, rhs, HuckAst::Times),
-            // HuckAst::Div(lhs, rhs, _) => self.check_binary(lhs, rhs, HuckAst::Div),
-            // HuckAst::Let(ident, init_expr, _) => {
-            //     let checked_expr = self.check(init_expr)?;
-            //     let &type_info = checked_expr.get_metadata();
-            //     self.add_var(ident.to_string(), type_info);
-            //     Ok(HuckAst::Let(String::from(ident), Box::new(checked_expr), type_info))
-            // }
-            // HuckAst::Block(exprs, _) => {
-            //     self.begin_scope();
-            //     let mut last_expr_type = TypeInfo::Unit;
-            //     let mut checked_exprs: Vec<CheckOutput> = vec![];
-            //     checked_exprs.reserve_exact(exprs.len());
-
-            //     for expr in exprs {
-            //         let checked_expr = self.check(expr)?;
-            //         let &type_info = checked_expr.get_metadata();
-            //         checked_exprs.push(checked_expr);
-            //         last_expr_type = type_info;
-            //     }
-
-            //     self.end_scope();
-            //     Ok(HuckAst::Block(checked_exprs, last_expr_type))
-            // },
-            // HuckAst::If(test_expr, then_expr, else_expr, _) => {
-            //     let checked_test = self.check(test_expr)?;
-            //     if *checked_test.get_metadata() != TypeInfo::Bool {
-            //         Err(String::from("Require boolean condition for if expression"))
-            //     } else {
-            //         let checked_then = self.check(then_expr)?;
-            //         let checked_else = self.check(else_expr)?;
-            //         let &then_type = checked_then.get_metadata();
-            //         let &else_type = checked_else.get_metadata();
-            //         if then_type == else_type {
-            //             Ok(
-            //                 HuckAst::If(
-            //                     Box::new(checked_test),
-            //                     Box::new(checked_then),
-            //                     Box::new(checked_else),
-            //                     then_type
-            //                 )
-            //             )
-            //         } else {
-            //             Err(format!(
-            //                 "Conditional branch types {:?} and {:?} do not match",
-            //                 then_type,
-            //                 else_type
-            //             ))
-            //         }
-            //     }
-            // },
-            // HuckAst::VarRef(ident, _) => {
-            //     if let Some(type_info) = self.get_var(ident.to_string()) {
-            //         Ok(HuckAst::VarRef(String::from(ident), type_info))
-            //     } else {
-            //         Err(format!("Unbound variable {:?}", ident))
-            //     }
-            // },
-        }
-    }
-    
-    // fn check_binary(&mut self, lhs: &CheckInput, rhs: &CheckInput, f: BinaryExpr) -> CheckResult {
-    //     let checked_lhs = self.check(lhs)?;
-    //     let checked_rhs = self.check(rhs)?;
-    //     let &l_type = checked_lhs.get_metadata();
-    //     let &r_type = checked_rhs.get_metadata();
-    //     if l_type == r_type {
-    //         Ok(f(Box::new(checked_lhs), Box::new(checked_rhs), l_type))
-    //     } else {
-    //         Err(format!("Cannot typecheck expressions {:?} and {:?}", lhs, rhs))
-    //     }
-    // }
-}
-
-// type BinaryExpr = fn (Box<CheckOutput>, Box<CheckOutput>, TypeInfo) -> CheckOutput;
+            HuckAst::Num(n, suffix, span) => {
+                let t = self.literal_type(suffix, expected, *span)?;
+                Self::check_literal(*n as i128, t, *span)?;
+                Ok(HuckAst::Num(*n, suffix.clone(), typed(TypeInfo::Int(t))))
+            },
+            // The most negative number of each type is only in range
+            // once it's negated
+            HuckAst::Neg(operand, span) if matches!(operand.as_ref(), HuckAst::Num(..)) => {
+                let HuckAst::Num(n, suffix, num_span) = operand.as_ref() else { unreachable!() };
+                let t = self.literal_type(suffix, expected, *num_span)?;
+                if !t.is_signed() {
+                    return Err(Self::bad_negation(&TypeInfo::Int(t), *span, *num_span));
+                }
+                Self::check_literal(-(*n as i128), t, *span)?;
+                let literal = HuckAst::Num(*n, suffix.clone(), Typed { ty: TypeInfo::Int(t), span: *num_span });
+                Ok(HuckAst::Neg(Box::new(literal), typed(TypeInfo::Int(t))))
+            },
+            HuckAst::Neg(operand, span) => {
+                let checked = self.check_expecting(operand, expected)?;
+                match checked.ty().clone() {
+                    t @ TypeInfo::Int(int_type) if int_type.is_signed() => Ok(HuckAst::Neg(Box::new(checked), typed(t))),
+                    TypeInfo::F64 => Ok(HuckAst::Neg(Box::new(checked), typed(TypeInfo::F64))),
+                    t => Err(Self::bad_negation(&t, *span, *operand.get_metadata())),
+                }
+            },
+            HuckAst::Cast(operand, target, span) => self.check_cast(operand, target, *span),
+            HuckAst::BoolLit(b, _) => Ok(HuckAst::BoolLit(*b, typed(TypeInfo::Bool))),
+            HuckAst::Float(x, _) => Ok(HuckAst::Float(*x, typed(TypeInfo::F64))),
+            HuckAst::Plus(lhs, rhs, span) => self.check_binary(lhs, rhs, *span, expected, HuckAst::Plus),
+            HuckAst::Minus(lhs, rhs, span) => self.check_binary(lhs, rhs, *span, expected, HuckAst::Minus),
+            HuckAst::Times(lhs, rhs, span) => self.check_binary(lhs, rhs, *span, expected, HuckAst::Times),
+            HuckAst::Div(lhs, rhs, span) => self.check_binary(lhs, rhs, *span, expected, HuckAst::Div),
+            HuckAst::Rem(lhs, rhs, span) => self.check_remainder(lhs, rhs, *span, expected),
+            HuckAst::Let(ident, mutable, init_expr, span) => self.check_let(ident, *mutable, init_expr, *span),
+            HuckAst::Assign(ident, op, value, span) => self.check_assign(ident, *op, value, *span),
+            HuckAst::Block(exprs, span) => self.check_block(exprs, *span, expected),
+            HuckAst::If(test_expr, then_expr, else_expr, span) => self.check_if(test_expr, then_expr, else_expr, *span, expected),
+            HuckAst::VarRef(ident, span) => {
+                let type_info = self.get_var(ident, *span)?;
+                match type_info.signature() {
+                    TypeInfo::Fn(params, _) => {
+                        let args = vec!["_"; params.len()].join(", ");
+                        Err(Diagnostic::type_error(
+                            Code::FUNCTION_AS_VALUE,
+                            format!("Function `{}` can only be called, not used as a value", ident),
+                            *span,
+                        )
+                        // Only a help: the `_`s need filling in, so it's not a
+                        // replacement anyone could apply as is
+                        .with_help(format!("call it, as in `{}({})`", ident, args)))
+                    },
+                    _ => Ok(HuckAst::VarRef(String::from(ident), typed(type_info))),
+                }
+            },
+            HuckAst::Equals(lhs, rhs, span) => self.check_equality(lhs, rhs, *span, HuckAst::Equals),
+            HuckAst::NotEquals(lhs, rhs, span) => self.check_equality(lhs, rhs, *span, HuckAst::NotEquals),
+            HuckAst::Less(lhs, rhs, span) => self.check_comparison(lhs, rhs, *span, HuckAst::Less),
+            HuckAst::LessEq(lhs, rhs, span) => self.check_comparison(lhs, rhs, *span, HuckAst::LessEq),
+            HuckAst::Greater(lhs, rhs, span) => self.check_comparison(lhs, rhs, *span, HuckAst::Greater),
+            HuckAst::GreaterEq(lhs, rhs, span) => self.check_comparison(lhs, rhs, *span, HuckAst::GreaterEq),
+            HuckAst::Fn(_, _, _, _, span) => {
+                Err(Diagnostic::type_error(Code::ANONYMOUS_FUNCTION, "Functions must be declared with let", *span)
+                    .with_suggestion("give it a name", Span::new(span.start, span.start), "let f = "))
+            },
+            HuckAst::Call(ident, type_args, args, span) if !self.is_bound(ident) && Builtin::from_name(ident).is_some() => {
+                self.type_arguments(ident, &[], type_args, *span)?;
+                let builtin = Builtin::from_name(ident).unwrap();
+                self.check_builtin(builtin, args, *span, expected)
+            },
+            HuckAst::Call(ident, type_args, args, span) => self.check_call(ident, type_args, args, *span, expected),
+            HuckAst::Builtin(..) => unreachable!("The parser doesn't make builtin calls"),
+            HuckAst::Struct(name, type_params, fields, span) => {
+                self.declare_struct(name, type_params, fields, *span)?;
+                Ok(HuckAst::Struct(name.clone(), type_params.clone(), fields.clone(), typed(TypeInfo::Unit)))
+            },
+            HuckAst::StructLit(name, type_args, fields, base, span) => {
+                self.check_struct_literal(name, type_args, fields, base.as_deref(), *span, expected)
+            },
+            HuckAst::Field(operand, field, span) => self.check_field(operand, field, *span),
+            HuckAst::Tuple(elements, span) => self.check_tuple(elements, *span, expected),
+            HuckAst::Enum(name, type_params, variants, span) => {
+                self.declare_enum(name, type_params, variants, *span)?;
+                Ok(HuckAst::Enum(name.clone(), type_params.clone(), variants.clone(), typed(TypeInfo::Unit)))
+            },
+            HuckAst::Variant(name, type_args, variant, args, span) => {
+                self.check_variant(name, type_args, variant, args, *span, expected)
+            },
+            HuckAst::Match(scrutinee, arms, span) => self.check_match(scrutinee, arms, *span, expected),
+            HuckAst::Trait(name, methods, span) => {
+                self.top_level("Traits", *span)?;
+                self.declare_trait(name, methods, *span)?;
+                Ok(HuckAst::Trait(name.clone(), methods.clone(), typed(TypeInfo::Unit)))
+            },
+            HuckAst::Impl(name, target, methods, span) => {
+                self.top_level("Impls", *span)?;
+                self.declare_impl(name, target, *span)?;
+                self.check_impl(name, target, methods, *span)
+            },
+            HuckAst::MethodCall(receiver, method, args, _, span) => self.check_method_call(receiver, method, args, *span),
+            HuckAst::While(label, cond, body, _) => {
+                let checked_cond = self.check(cond)?;
+                let cond_type = checked_cond.ty();
+                if *cond_type != TypeInfo::Bool {
+                    let span = *cond.get_metadata();
+                    return Err(Diagnostic::type_error(Code::NON_BOOL_CONDITION, "Require boolean condition for while loop", span)
+                        .with_label(span, format!("expected bool, found {}", cond_type)))
+                }
+                let (checked_body, _) = self.check_loop_body(label, LoopKind::While, None, body)?;
+                Ok(HuckAst::While(label.clone(), Box::new(checked_cond), Box::new(checked_body), typed(TypeInfo::Unit)))
+            },
+            HuckAst::Loop(label, body, _) => {
+                let (checked_body, break_type) = self.check_loop_body(label, LoopKind::Loop, expected, body)?;
+                Ok(HuckAst::Loop(label.clone(), Box::new(checked_body), typed(break_type.unwrap_or(TypeInfo::Never))))
+            },
+            HuckAst::For(label, var, from, to, body, span) => self.check_for(label, var, from, to, body, *span),
+            HuckAst::Break(label, value, span) => self.check_break(label, value.as_deref(), *span),
+            HuckAst::Continue(label, span) => {
+                self.enclosing_loop(label.as_deref(), "continue", *span)?;
+                Ok(HuckAst::Continue(label.clone(), typed(TypeInfo::Never)))
+            },
+        }
+    }
+
+    // The body's value is thrown away each time round; what a `loop` is
+    // comes from its `break`s, and with none it never finishes
+    fn check_loop_body(&mut self,
+                       label: &Option<String>,
+                       kind: LoopKind,
+                       expected: Option<&TypeInfo>,
+                       body: &CheckInput
+    ) -> Result<(CheckOutput, Option<TypeInfo>), Diagnostic> {
+        self.loops.push(LoopContext { label: label.clone(), kind, expected: expected.cloned(), first_break: None });
+        let checked_body = self.check(body);
+        let context = self.loops.pop().unwrap();
+        Ok((checked_body?, context.first_break.map(|(ty, _)| ty)))
+    }
+
+    // Both ends of the range are integers of the same type, which the
+    // loop variable has too
+    fn check_for(&mut self,
+                 label: &Option<String>,
+                 var: &str,
+                 from: &CheckInput,
+                 to: &CheckInput,
+                 body: &CheckInput,
+                 span: Span
+    ) -> CheckResult {
+        let (checked_from, checked_to) = self.check_pair(from, to, None)?;
+        let (from_type, to_type) = (checked_from.ty().clone(), checked_to.ty().clone());
+        let TypeInfo::Int(_) = from_type else {
+            let span = *from.get_metadata();
+            return Err(Diagnostic::type_error(Code::BAD_RANGE, format!("Can't loop over a range of {}", from_type), span)
+                .with_label(span, format!("expected an integer, found {}", from_type))
+                .with_note("`for` loops count through integers"))
+        };
+        if to_type != from_type {
+            let span = *to.get_metadata();
+            return Err(Diagnostic::type_error(Code::BAD_RANGE, format!("Range from {} to {}", from_type, to_type), span)
+                .with_label(span, format!("expected {}, found {}", from_type, to_type))
+                .with_secondary(*from.get_metadata(), format!("the range starts at a {}", from_type)))
+        }
+
+        self.begin_scope();
+        self.add_var(var.to_string(), from_type);
+        let checked_body = self.check_loop_body(label, LoopKind::For, None, body);
+        self.end_scope();
+        let (checked_body, _) = checked_body?;
+        Ok(HuckAst::For(
+            label.clone(),
+            var.to_string(),
+            Box::new(checked_from),
+            Box::new(checked_to),
+            Box::new(checked_body),
+            Typed { ty: TypeInfo::Unit, span },
+        ))
+    }
+
+    // The innermost loop, or the one with the label. Loops outside the
+    // function being checked don't count, but they're worth a note.
+    fn enclosing_loop(&self, label: Option<&str>, keyword: &str, span: Span) -> Result<usize, Diagnostic> {
+        let (outer, inner) = self.loops.split_at(self.loop_base);
+        let boundary = "functions can't break out of loops they're declared in";
+        match label {
+            None => match inner.len().checked_sub(1) {
+                Some(i) => Ok(self.loop_base + i),
+                None => {
+                    let diagnostic = Diagnostic::type_error(Code::OUTSIDE_LOOP, format!("`{}` outside of a loop", keyword), span)
+                        .with_label(span, "not inside any loop");
+                    Err(if outer.is_empty() { diagnostic } else { diagnostic.with_note(boundary) })
+                },
+            },
+            Some(label) => match inner.iter().rposition(|l| l.label.as_deref() == Some(label)) {
+                Some(i) => Ok(self.loop_base + i),
+                None => {
+                    let diagnostic = Diagnostic::type_error(Code::UNKNOWN_LABEL, format!("Unknown label `'{}`", label), span)
+                        .with_label(span, "no loop around this has that label");
+                    match outer.iter().any(|l| l.label.as_deref() == Some(label)) {
+                        true => Err(diagnostic.with_note(boundary)),
+                        false => Err(diagnostic),
+                    }
+                },
+            },
+        }
+    }
+
+    // `break` never finishes, whatever it gives its loop. Every `break`
+    // out of a loop has to agree on what that is.
+    fn check_break(&mut self, label: &Option<String>, value: Option<&CheckInput>, span: Span) -> CheckResult {
+        let index = self.enclosing_loop(label.as_deref(), "break", span)?;
+        let kind = self.loops[index].kind;
+        if let (Some(value), true) = (value, kind != LoopKind::Loop) {
+            let value_span = *value.get_metadata();
+            return Err(Diagnostic::type_error(Code::BREAK_VALUE, format!("Can't break out of a `{}` loop with a value", kind.keyword()), value_span)
+                .with_label(value_span, "this value has nowhere to go")
+                .with_note(format!("a `{}` loop is always (); only `loop` can break with a value", kind.keyword())))
+        }
+
+        let context = &self.loops[index];
+        let expected = context.first_break.as_ref()
+            .and_then(|(ty, _)| ty.expectation())
+            .or(context.expected.as_ref())
+            .cloned();
+        let checked_value = value.map(|value| self.check_expecting(value, expected.as_ref())).transpose()?;
+        let ty = checked_value.as_ref().map_or(TypeInfo::Unit, |value| value.ty().clone());
+        let context = &mut self.loops[index];
+        match &context.first_break {
+            Some((first, first_span)) => match first.join(&ty) {
+                // One that never finishes doesn't say what the loop is
+                Some(joined) => if *first == TypeInfo::Never {
+                    context.first_break = Some((joined, span));
+                },
+                None => {
+                    let value_span = value.map_or(span, |value| *value.get_metadata());
+                    return Err(Diagnostic::type_error(
+                        Code::BREAK_MISMATCH,
+                        format!("Loop breaks with both {} and {}", first, ty),
+                        value_span,
+                    )
+                    .with_label(value_span, format!("expected {}, found {}", first, ty))
+                    .with_secondary(*first_span, format!("this breaks with {}", first))
+                    .with_note("every `break` out of a loop needs the same type"))
+                },
+            },
+            None => context.first_break = Some((ty, span)),
+        }
+        Ok(HuckAst::Break(label.clone(), checked_value.map(Box::new), Typed { ty: TypeInfo::Never, span }))
+    }
+
+    fn check_let(&mut self, ident: &str, mutable: bool, init_expr: &CheckInput, span: Span) -> CheckResult {
+        let typed = |ty| Typed { ty, span };
+        if let HuckAst::Fn(type_params, params, ret, body, fn_span) = init_expr {
+            if mutable {
+                return Err(Diagnostic::type_error(Code::MUTABLE_FUNCTION, format!("Function `{}` can't be mutable", ident), span)
+                    .with_label(span, "declared with `let mut`")
+                    .with_note("functions are declared once and can't be assigned to"))
+            }
+            let checked_fn = self.check_fn(ident, type_params, params, ret, body, *fn_span)?;
+            return Ok(HuckAst::Let(String::from(ident), false, Box::new(checked_fn), typed(TypeInfo::Unit)))
+        }
+
+        let checked_expr = self.check(init_expr)?;
+        let type_info = checked_expr.ty().clone();
+        if let TypeInfo::Fn(..) = type_info {
+            let span = *init_expr.get_metadata();
+            return Err(Diagnostic::type_error(
+                Code::FUNCTION_AS_VALUE,
+                format!("Functions are not first-class values; can't bind `{}` to one", ident),
+                span,
+            )
+            .with_label(span, format!("this has type {}", type_info)))
+        }
+        self.add_var(ident.to_string(), type_info.clone());
+        self.lets.last_mut().unwrap().insert(ident.to_string(), (span, mutable));
+        Ok(HuckAst::Let(String::from(ident), mutable, Box::new(checked_expr), typed(type_info)))
+    }
+
+    // Assignments are (). Compound ones are checked as the arithmetic
+    // they stand for, which is what they become.
+    fn check_assign(&mut self, ident: &str, op: Option<AssignOp>, value: &CheckInput, span: Span) -> CheckResult {
+        let typed = |ty| Typed { ty, span };
+        let name_span = Span::new(span.start, span.start + ident.len());
+        let target_type = self.get_var(ident, name_span)?;
+        self.assignable(ident, name_span)?;
+        let value = match op {
+            None => value.clone(),
+            Some(op) => {
+                let lhs = Box::new(HuckAst::VarRef(ident.to_string(), name_span));
+                let rhs = Box::new(value.clone());
+                match op {
+                    AssignOp::Add => HuckAst::Plus(lhs, rhs, span),
+                    AssignOp::Sub => HuckAst::Minus(lhs, rhs, span),
+                    AssignOp::Mul => HuckAst::Times(lhs, rhs, span),
+                    AssignOp::Div => HuckAst::Div(lhs, rhs, span),
+                    AssignOp::Rem => HuckAst::Rem(lhs, rhs, span),
+                }
+            },
+        };
+        let checked_value = self.check_expecting(&value, Some(&target_type))?;
+        let value_type = checked_value.ty().clone();
+        if !value_type.fits(&target_type) {
+            let value_span = *value.get_metadata();
+            return Err(Diagnostic::type_error(
+                Code::ASSIGN_MISMATCH,
+                format!("Can't assign {} to `{}`, which has type {}", value_type, ident, target_type),
+                value_span,
+            )
+            .with_label(value_span, format!("expected {}, found {}", target_type, value_type)))
+        }
+        Ok(HuckAst::Assign(ident.to_string(), None, Box::new(checked_value), typed(TypeInfo::Unit)))
+    }
+
+    fn check_block(&mut self, exprs: &[CheckInput], span: Span, expected: Option<&TypeInfo>) -> CheckResult {
+        let typed = |ty| Typed { ty, span };
+        let top_level = self.env.len() == 1;
+        self.begin_scope();
+        let mut last_expr_type = TypeInfo::Unit;
+        let mut checked_exprs: Vec<CheckOutput> = vec![];
+        checked_exprs.reserve_exact(exprs.len());
+
+        // Structs, enums and traits are visible throughout their block
+        // too, in the order they're declared
+        for expr in exprs {
+            let (name, span) = match expr {
+                HuckAst::Struct(name, _, _, span) | HuckAst::Enum(name, _, _, span) => (name, span),
+                HuckAst::Trait(name, methods, span) if top_level => {
+                    self.declare_trait(name, methods, *span)?;
+                    continue;
+                },
+                _ => continue,
+            };
+            if self.types.last().unwrap().contains_key(name) {
+                return Err(Diagnostic::type_error(
+                    Code::DUPLICATE_TYPE,
+                    format!("Type `{}` is declared twice in the same block", name),
+                    *span,
+                )
+                .with_label(*span, "declared again here"))
+            }
+            match expr {
+                HuckAst::Struct(_, type_params, fields, _) => self.declare_struct(name, type_params, fields, *span)?,
+                HuckAst::Enum(_, type_params, variants, _) => self.declare_enum(name, type_params, variants, *span)?,
+                _ => unreachable!(),
+            }
+        }
+
+        // So is which types implement which traits
+        for expr in exprs {
+            match expr {
+                HuckAst::Impl(name, target, _, span) if top_level => self.declare_impl(name, target, *span)?,
+                _ => continue,
+            }
+        }
+
+        // Functions are visible throughout the block they're
+        // declared in, so they can be (mutually) recursive
+        for expr in exprs {
+            if let HuckAst::Let(ident, _, init_expr, _) = expr {
+                if let HuckAst::Fn(type_params, params, ret, _, span) = init_expr.as_ref() {
+                    let fn_type = self.function_type(type_params, params, ret, *span)?;
+                    self.add_var(ident.to_string(), fn_type);
+                }
+            }
+        }
+
+        for (i, expr) in exprs.iter().enumerate() {
+            let expected = if i + 1 == exprs.len() { expected } else { None };
+            let checked_expr = match expr {
+                // Already declared above
+                HuckAst::Struct(name, type_params, fields, span) => {
+                    HuckAst::Struct(name.clone(), type_params.clone(), fields.clone(), Typed { ty: TypeInfo::Unit, span: *span })
+                },
+                HuckAst::Enum(name, type_params, variants, span) => {
+                    HuckAst::Enum(name.clone(), type_params.clone(), variants.clone(), Typed { ty: TypeInfo::Unit, span: *span })
+                },
+                HuckAst::Trait(name, methods, span) if top_level => {
+                    HuckAst::Trait(name.clone(), methods.clone(), Typed { ty: TypeInfo::Unit, span: *span })
+                },
+                HuckAst::Impl(name, target, methods, span) if top_level => self.check_impl(name, target, methods, *span)?,
+                _ if self.recovering => {
+                    let scopes = (self.env.len(), self.types.len(), self.lets.len(), self.loops.len());
+                    match self.check_expecting(expr, expected) {
+                        Ok(checked) => {
+                            if let HuckAst::Let(ident, ..) = expr {
+                                self.poisoned.remove(ident);
+                            }
+                            checked
+                        },
+                        Err(diagnostic) => self.recover(expr, diagnostic, scopes),
+                    }
+                },
+                _ => self.check_expecting(expr, expected)?,
+            };
+            let type_info = checked_expr.ty().clone();
+            checked_exprs.push(checked_expr);
+            last_expr_type = type_info;
+        }
+
+        self.end_scope();
+        Ok(HuckAst::Block(checked_exprs, typed(last_expr_type)))
+    }
+
+    fn check_if(&mut self,
+                test_expr: &CheckInput,
+                then_expr: &CheckInput,
+                else_expr: &CheckInput,
+                span: Span,
+                expected: Option<&TypeInfo>
+    ) -> CheckResult {
+        let typed = |ty| Typed { ty, span };
+        let checked_test = self.check(test_expr)?;
+        let test_type = checked_test.ty();
+        if *test_type != TypeInfo::Bool {
+            let span = *test_expr.get_metadata();
+            Err(Diagnostic::type_error(Code::NON_BOOL_CONDITION, "Require boolean condition for if expression", span)
+                .with_label(span, format!("expected bool, found {}", test_type)))
+        } else {
+            let (checked_then, checked_else) = self.check_pair(then_expr, else_expr, expected)?;
+            let then_type = checked_then.ty().clone();
+            let else_type = checked_else.ty().clone();
+            if let Some(ty) = then_type.join(&else_type) {
+                Ok(
+                    HuckAst::If(
+                        Box::new(checked_test),
+                        Box::new(checked_then),
+                        Box::new(checked_else),
+                        typed(ty)
+                    )
+                )
+            } else {
+                let span = tail_span(else_expr);
+                Err(Diagnostic::type_error(
+                    Code::BRANCH_MISMATCH,
+                    format!("Conditional branch types {} and {} do not match", then_type, else_type),
+                    span,
+                )
+                .with_label(span, format!("expected {}, found {}", then_type, else_type))
+                .with_secondary(tail_span(then_expr), format!("this branch has type {}", then_type))
+                .with_note("both branches of an if need the same type"))
+            }
+        }
+    }
+
+    fn check_cast(&mut self, operand: &CheckInput, target: &TypeAnn, span: Span) -> CheckResult {
+        let typed = |ty| Typed { ty, span };
+        let checked = self.check(operand)?;
+        let from = checked.ty().clone();
+        let to = self.resolve_type(target, span)?;
+        match (&from, &to) {
+            (TypeInfo::Int(_) | TypeInfo::Bool | TypeInfo::F64, TypeInfo::Int(_))
+            | (TypeInfo::Int(_) | TypeInfo::F64, TypeInfo::F64) => {
+                Ok(HuckAst::Cast(Box::new(checked), target.clone(), typed(to)))
+            },
+            (_, TypeInfo::Dyn(_)) if from.has_dyn() && from != to => {
+                Err(Diagnostic::type_error(Code::BAD_CAST, format!("Can't cast {} to {}", from, to), span)
+                    .with_label(span, format!("{} as {}", from, to))
+                    .with_note("a `dyn` can't hold another `dyn`"))
+            },
+            // Anything implementing a trait can become a `dyn` of it
+            (_, TypeInfo::Dyn(trait_type)) if self.implements(&from, trait_type) => {
+                Ok(HuckAst::Cast(Box::new(checked), target.clone(), typed(to)))
+            },
+            (_, TypeInfo::Dyn(trait_type)) => {
+                let operand_span = *operand.get_metadata();
+                Err(Self::not_implemented(&from, trait_type, format!("Can't cast {} to {}", from, to), span)
+                    .with_label(operand_span, format!("{} doesn't implement `{}`", from, trait_type.name)))
+            },
+            _ => {
+                let operand_span = *operand.get_metadata();
+                Err(Diagnostic::type_error(Code::BAD_CAST, format!("Can't cast {} to {}", from, to), span)
+                    .with_label(span, format!("{} as {}", from, to))
+                    .with_secondary(operand_span, format!("this has type {}", from))
+                    .with_note("`as` converts between numbers, and from bools to integers"))
+            },
+        }
+    }
+
+    // Like a struct literal, each element gets its type from the one
+    // expected of it, if there is one
+    fn check_tuple(&mut self, elements: &[CheckInput], span: Span, expected: Option<&TypeInfo>) -> CheckResult {
+        let hints = match expected {
+            Some(TypeInfo::Struct(tuple)) if tuple.is_tuple() && tuple.args.len() == elements.len() => tuple.args.iter().map(Some).collect(),
+            _ => vec![None; elements.len()],
+        };
+        let checked = elements.iter()
+            .zip(hints)
+            .map(|(element, hint)| self.check_expecting(element, hint))
+            .collect::<Result<Vec<_>, _>>()?;
+        let ty = TypeInfo::Struct(Rc::new(StructType::tuple(checked.iter().map(|element| element.ty().clone()).collect())));
+        Ok(HuckAst::Tuple(checked, Typed { ty, span }))
+    }
+
+    fn check_field(&mut self, operand: &CheckInput, field: &str, span: Span) -> CheckResult {
+        let typed = |ty| Typed { ty, span };
+        let checked = self.check(operand)?;
+        let TypeInfo::Struct(struct_type) = checked.ty().clone() else {
+            let operand_span = *operand.get_metadata();
+            return Err(Diagnostic::type_error(
+                Code::NOT_A_STRUCT,
+                format!("Can't get field `{}` of {}", field, checked.ty()),
+                span,
+            )
+            .with_label(operand_span, format!("this has type {}", checked.ty()))
+            .with_note("only structs have fields"))
+        };
+        match struct_type.field(field) {
+            Some((_, ty)) => {
+                let ty = ty.clone();
+                Ok(HuckAst::Field(Box::new(checked), field.to_string(), typed(ty)))
+            },
+            None => Err(Diagnostic::type_error(
+                Code::UNKNOWN_FIELD,
+                match struct_type.is_tuple() {
+                    true => format!("Tuple {} has no element `{}`", checked.ty(), field),
+                    false => format!("Struct `{}` has no field `{}`", struct_type.name, field),
+                },
+                span,
+            )
+            .with_label(span, "unknown field")
+            .with_note(struct_type.field_names())),
+        }
+    }
+    // Calls to a generic function instantiate it with whatever the
+    // arguments say its type parameters are, unless they're given
+    fn check_call(&mut self,
+                  ident: &str,
+                  type_args: &TypeArgList,
+                  args: &[CheckInput],
+                  span: Span,
+                  expected: Option<&TypeInfo>
+    ) -> CheckResult {
+        let name_span = Span::new(span.start, span.start + ident.len());
+        let fn_type = self.get_var(ident, name_span)?;
+        let (type_params, bounds): (Vec<String>, Vec<Vec<Rc<TraitType>>>) = match &fn_type {
+            TypeInfo::Generic(type_params, _) => type_params.iter().cloned().unzip(),
+            _ => (vec![], vec![]),
+        };
+        let TypeInfo::Fn(param_types, ret) = fn_type.signature() else {
+            return Err(Diagnostic::type_error(Code::NOT_CALLABLE, format!("`{}` has type {} and can't be called", ident, fn_type), name_span)
+                .with_label(name_span, "not a function"))
+        };
+        if args.len() != param_types.len() {
+            let params = param_types.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+            return Err(Diagnostic::type_error(
+                Code::WRONG_ARGUMENT_COUNT,
+                format!(
+                    "Function `{}` takes {} arguments but was given {}",
+                    ident,
+                    param_types.len(),
+                    args.len()
+                ),
+                span,
+            )
+            .with_note(format!("`{}` has parameters ({})", ident, params.join(", "))))
+        }
+
+        let mut bound = self.type_arguments(ident, &type_params, type_args, span)?;
+        let hints = Self::hints(ret, expected, &type_params, &bound);
+        let checked_args = self.check_generic_args(args, param_types, &type_params, &hints, &mut bound, |i, param_type, arg_type| {
+            let arg_span = *args[i].get_metadata();
+            Diagnostic::type_error(
+                Code::ARGUMENT_MISMATCH,
+                format!("Argument to `{}` has type {} but {} was expected", ident, arg_type, param_type),
+                arg_span,
+            )
+            .with_label(arg_span, format!("expected {}, found {}", param_type, arg_type))
+        })?;
+        Self::infer_rest(ret, expected, &type_params, &mut bound);
+        Self::all_bound(&type_params, &bound, ident, format!("{}::<{}>(..)", ident, type_params.join(", ")), span)?;
+        for (param, bounds) in type_params.iter().zip(&bounds) {
+            let ty = &bound[param];
+            if let Some(missing) = bounds.iter().find(|bound| !self.implements(ty, bound)) {
+                return Err(Self::not_implemented(ty, missing, format!("{} doesn't implement `{}`", ty, missing.name), span)
+                    .with_label(span, format!("`{}` needs `{}: {}`, and `{}` is {}", ident, param, missing.name, param, ty)))
+            }
+        }
+        let ty = ret.subst(&bound);
+        Ok(HuckAst::Call(String::from(ident), type_args.clone(), checked_args, Typed { ty, span }))
+    }
+
+    fn check_variant(&mut self,
+                     name: &str,
+                     type_args: &TypeArgList,
+                     variant: &str,
+                     args: &[CheckInput],
+                     span: Span,
+                     expected: Option<&TypeInfo>
+    ) -> CheckResult {
+        let name_span = Span::new(span.start, span.start + name.len());
+        let enum_type = self.get_enum(name, name_span)?;
+        let (_, payload) = Self::get_variant(&enum_type, variant, args.len(), span)?;
+        let decl = TypeInfo::Enum(enum_type);
+        let params = decl.declared_params();
+        let mut bound = self.type_arguments(name, &params, type_args, name_span)?;
+        let hints = Self::hints(&decl, expected, &params, &bound);
+
+        let checked_args = self.check_generic_args(args, &payload, &params, &hints, &mut bound, |i, ty, given| {
+            let arg_span = *args[i].get_metadata();
+            Diagnostic::type_error(
+                Code::ARGUMENT_MISMATCH,
+                format!("`{}::{}` carries {} but was given {}", name, variant, ty, given),
+                arg_span,
+            )
+            .with_label(arg_span, format!("expected {}, found {}", ty, given))
+        })?;
+        Self::infer_rest(&decl, expected, &params, &mut bound);
+        Self::all_bound(&params, &bound, name, format!("{}::<{}>::{}", name, params.join(", "), variant), span)?;
+        let ty = decl.subst(&bound);
+        Ok(HuckAst::Variant(name.to_string(), type_args.clone(), variant.to_string(), checked_args, Typed { ty, span }))
+    }
+
+    // Check the arguments of something generic against their declared
+    // types, working out its type parameters as it goes. Arguments that
+    // need to know their type before they can be checked, like literals or
+    // `Option::None`, wait until the others have said what it is.
+    fn check_generic_args(&mut self,
+                          args: &[CheckInput],
+                          types: &[TypeInfo],
+                          params: &[String],
+                          hints: &HashMap<String, TypeInfo>,
+                          bound: &mut HashMap<String, TypeInfo>,
+                          mismatch: impl Fn(usize, &TypeInfo, &TypeInfo) -> Diagnostic
+    ) -> Result<Vec<CheckOutput>, Diagnostic> {
+        let mut checked = vec![None; args.len()];
+        for last_chance in [false, true] {
+            for (i, (arg, ty)) in args.iter().zip(types).enumerate() {
+                if checked[i].is_some() {
+                    continue;
+                }
+                let known = hints.iter().chain(bound.iter()).map(|(k, v)| (k.clone(), v.clone())).collect();
+                let result = match Self::hint(ty, params, &known) {
+                    Some(hint) => self.check_expecting(arg, Some(&hint))?,
+                    None if last_chance => self.check(arg)?,
+                    None if is_literal(arg) => continue,
+                    None => match self.try_check(arg)? {
+                        Some(result) => result,
+                        None => continue,
+                    },
+                };
+                if !ty.unify(result.ty(), params, bound) {
+                    return Err(mismatch(i, &ty.subst(bound), result.ty()))
+                }
+                checked[i] = Some(result);
+            }
+        }
+        Ok(checked.into_iter().map(Option::unwrap).collect())
+    }
+
+    // Check `ast` with nothing expected of it, unless it can't be checked
+    // without knowing what type it should be. Then it's as if it was never
+    // checked at all.
+    fn try_check(&mut self, ast: &CheckInput) -> Result<Option<CheckOutput>, Diagnostic> {
+        let (env, types, warnings) = (self.env.len(), self.types.len(), self.warnings.len());
+        // Whatever goes wrong has to come out here, to tell whether it's
+        // just for want of an expected type
+        let recovering = std::mem::replace(&mut self.recovering, false);
+        let checked = self.check(ast);
+        self.recovering = recovering;
+        match checked {
+            Err(diagnostic) if diagnostic.code == Some(Code::CANNOT_INFER) => {
+                self.env.truncate(env);
+                self.types.truncate(types);
+                self.warnings.truncate(warnings);
+                Ok(None)
+            },
+            result => result.map(Some),
+        }
+    }
+
+    // Every arm has to have the type of the first one, apart from any that
+    // never finish
+    fn check_match(&mut self, scrutinee: &CheckInput, arms: &[MatchArm<Span>], span: Span, expected: Option<&TypeInfo>) -> CheckResult {
+        let checked_scrutinee = self.check(scrutinee)?;
+        let scrutinee_type = checked_scrutinee.ty().clone();
+
+        let mut checked_arms: Vec<MatchArm<Typed>> = vec![];
+        // The type so far, and the arm that said what it is
+        let mut arm_type: Option<(TypeInfo, usize)> = None;
+        for (i, arm) in arms.iter().enumerate() {
+            self.begin_scope();
+            let hint = arm_type.as_ref().and_then(|(ty, _)| ty.expectation());
+            let checked = self.check_arm(arm, &scrutinee_type, hint.or(expected));
+            self.end_scope();
+            let checked = checked?;
+
+            let body_type = checked.body.ty().clone();
+            arm_type = match arm_type {
+                None => Some((body_type, i)),
+                Some((ty, first)) => match ty.join(&body_type) {
+                    Some(joined) if ty == TypeInfo::Never => Some((joined, i)),
+                    Some(_) => Some((ty, first)),
+                    None => {
+                        let body_span = tail_span(&arm.body);
+                        return Err(Diagnostic::type_error(
+                            Code::ARM_MISMATCH,
+                            format!("Match arm has type {} but the first arm has type {}", body_type, ty),
+                            body_span,
+                        )
+                        .with_label(body_span, format!("expected {}, found {}", ty, body_type))
+                        .with_secondary(tail_span(&arms[first].body), format!("this arm has type {}", ty))
+                        .with_note("every arm of a match needs the same type"))
+                    },
+                },
+            };
+            checked_arms.push(checked);
+        }
+
+        let report = exhaustiveness::check(&checked_arms, &scrutinee_type);
+        if let Some(missing) = report.missing {
+            let span = *scrutinee.get_metadata();
+            let mut diagnostic = Diagnostic::type_error(
+                Code::NON_EXHAUSTIVE,
+                format!("Match doesn't cover every value: `{}` isn't matched", missing),
+                span,
+            )
+            .with_label(span, format!("pattern `{}` not covered", missing))
+            .with_help(format!("add an arm for `{}`, or a `_` arm for everything else", missing));
+            if arms.iter().any(|arm| arm.guard.is_some()) {
+                diagnostic = diagnostic.with_note("arms with guards don't count, since the guard might be false");
+            }
+            return Err(diagnostic)
+        }
+        for i in report.unreachable {
+            let span = checked_arms[i].pattern.get_metadata().span;
+            self.warnings.push(Diagnostic::warning(Code::UNREACHABLE_ARM, "Unreachable match arm", span)
+                .with_label(span, "this arm never matches")
+                .with_note("the arms above it already match everything it does"));
+        }
+
+        let (ty, _) = arm_type.unwrap();
+        Ok(HuckAst::Match(Box::new(checked_scrutinee), checked_arms, Typed { ty, span }))
+    }
+
+    // Check an arm in a scope of its own, where its bindings live
+    fn check_arm(&mut self, arm: &MatchArm<Span>, scrutinee_type: &TypeInfo, expected: Option<&TypeInfo>) -> Result<MatchArm<Typed>, Diagnostic> {
+        let mut bindings = vec![];
+        let pattern = self.check_pattern(&arm.pattern, scrutinee_type, &mut bindings)?;
+        for (name, ty) in bindings {
+            self.add_var(name, ty);
+        }
+
+        let guard = match &arm.guard {
+            Some(guard) => {
+                let checked = self.check(guard)?;
+                if *checked.ty() != TypeInfo::Bool {
+                    let span = *guard.get_metadata();
+                    return Err(Diagnostic::type_error(Code::NON_BOOL_CONDITION, "Require boolean condition for match guard", span)
+                        .with_label(span, format!("expected bool, found {}", checked.ty())))
+                }
+                Some(checked)
+            },
+            None => None,
+        };
+        let body = self.check_expecting(&arm.body, expected)?;
+        Ok(MatchArm { pattern, guard, body })
+    }
+
+    // Check that `pattern` can match a value of type `ty`, adding the
+    // names it binds to `bindings`
+    fn check_pattern(&mut self,
+                     pattern: &Pattern<Span>,
+                     ty: &TypeInfo,
+                     bindings: &mut Vec<(String, TypeInfo)>
+    ) -> Result<Pattern<Typed>, Diagnostic> {
+        let typed = |span: &Span| Typed { ty: ty.clone(), span: *span };
+        match pattern {
+            Pattern::Wildcard(span) => Ok(Pattern::Wildcard(typed(span))),
+            Pattern::Binding(name, span) => {
+                if bindings.iter().any(|(bound, _)| bound == name) {
+                    return Err(Diagnostic::type_error(
+                        Code::DUPLICATE_BINDING,
+                        format!("`{}` is bound more than once in the same pattern", name),
+                        *span,
+                    )
+                    .with_label(*span, "bound again here"))
+                }
+                bindings.push((name.clone(), ty.clone()));
+                Ok(Pattern::Binding(name.clone(), typed(span)))
+            },
+            Pattern::Literal(literal) => {
+                let checked = self.check_expecting(literal, Some(ty))?;
+                if checked.ty() != ty {
+                    return Err(Self::pattern_mismatch(checked.ty(), ty, *literal.get_metadata()))
+                }
+                Ok(Pattern::Literal(checked))
+            },
+            Pattern::Variant(name, variant, fields, span) => {
+                let name_span = Span::new(span.start, span.start + name.len());
+                let decl = self.get_enum(name, name_span)?;
+                // The payload of a generic enum is whatever the value's
+                // type says it is
+                let enum_type = match ty {
+                    TypeInfo::Enum(enum_type) if enum_type.id == decl.id => enum_type.clone(),
+                    _ => return Err(Self::pattern_mismatch(&TypeInfo::Enum(decl), ty, *span)),
+                };
+                let (_, payload) = Self::get_variant(&enum_type, variant, fields.len(), *span)?;
+                let checked_fields = fields.iter()
+                    .zip(&payload)
+                    .map(|(field, field_type)| self.check_pattern(field, field_type, bindings))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Pattern::Variant(name.clone(), variant.clone(), checked_fields, typed(span)))
+            },
+            Pattern::Tuple(elements, span) => {
+                let element_types = match ty {
+                    TypeInfo::Struct(tuple) if tuple.is_tuple() && tuple.args.len() == elements.len() => &tuple.args,
+                    _ => {
+                        let found = elements.iter().map(|_| String::from("_")).collect::<Vec<_>>();
+                        let found = if found.len() == 1 { String::from("(_,)") } else { format!("({})", found.join(", ")) };
+                        return Err(Diagnostic::type_error(
+                            Code::PATTERN_MISMATCH,
+                            format!("Pattern `{}` can't match a value of type {}", found, ty),
+                            *span,
+                        )
+                        .with_label(*span, format!("expected {}, found a tuple of {}", ty, elements.len())))
+                    },
+                };
+                let checked_elements = elements.iter()
+                    .zip(element_types)
+                    .map(|(element, element_type)| self.check_pattern(element, element_type, bindings))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Pattern::Tuple(checked_elements, typed(span)))
+            },
+        }
+    }
+
+    fn pattern_mismatch(pattern_type: &TypeInfo, ty: &TypeInfo, span: Span) -> Diagnostic {
+        Diagnostic::type_error(
+            Code::PATTERN_MISMATCH,
+            format!("Pattern of type {} can't match a value of type {}", pattern_type, ty),
+            span,
+        )
+        .with_label(span, format!("expected {}, found {}", ty, pattern_type))
+    }
+
+    fn check_struct_literal(&mut self,
+                            name: &str,
+                            type_args: &TypeArgList,
+                            fields: &[(String, CheckInput)],
+                            base: Option<&CheckInput>,
+                            span: Span,
+                            expected: Option<&TypeInfo>
+    ) -> CheckResult {
+        let name_span = Span::new(span.start, span.start + name.len());
+        let struct_type = match self.get_type(name) {
+            Some(TypeInfo::Struct(struct_type)) => struct_type,
+            _ if Self::is_builtin_type(name) => {
+                return Err(Diagnostic::type_error(Code::NOT_A_STRUCT, format!("`{}` isn't a struct", name), name_span)
+                    .with_label(name_span, "not a struct"))
+            },
+            _ => {
+                return Err(Diagnostic::type_error(Code::UNKNOWN_TYPE, format!("Unknown struct `{}`", name), name_span)
+                    .with_label(name_span, "not found in this scope"))
+            },
+        };
+        let decl = TypeInfo::Struct(struct_type.clone());
+        let params = decl.declared_params();
+        let mut bound = self.type_arguments(name, &params, type_args, name_span)?;
+        let hints = Self::hints(&decl, expected, &params, &bound);
+
+        let mut field_types = vec![];
+        for (i, (field, value)) in fields.iter().enumerate() {
+            let value_span = *value.get_metadata();
+            if fields[..i].iter().any(|(seen, _)| seen == field) {
+                return Err(Diagnostic::type_error(Code::DUPLICATE_FIELD, format!("Field `{}` is given twice", field), value_span)
+                    .with_label(value_span, "given again here"))
+            }
+            let Some((_, field_type)) = struct_type.field(field) else {
+                return Err(Diagnostic::type_error(
+                    Code::UNKNOWN_FIELD,
+                    format!("Struct `{}` has no field `{}`", name, field),
+                    value_span,
+                )
+                .with_label(value_span, "unknown field")
+                .with_note(struct_type.field_names()))
+            };
+            field_types.push(field_type.clone());
+        }
+        let values = fields.iter().map(|(_, value)| value.clone()).collect::<Vec<_>>();
+        let checked_values = self.check_generic_args(&values, &field_types, &params, &hints, &mut bound, |i, field_type, given| {
+            let (field, value) = &fields[i];
+            let value_span = *value.get_metadata();
+            Diagnostic::type_error(
+                Code::FIELD_MISMATCH,
+                format!("Field `{}` of `{}` has type {} but was given {}", field, name, field_type, given),
+                value_span,
+            )
+            .with_label(value_span, format!("expected {}, found {}", field_type, given))
+        })?;
+        let checked_fields = fields.iter().map(|(field, _)| field.clone()).zip(checked_values).collect();
+
+        let checked_base = match base {
+            Some(base) => {
+                let checked = self.check(base)?;
+                if !decl.unify(checked.ty(), &params, &mut bound) {
+                    let base_span = *base.get_metadata();
+                    return Err(Diagnostic::type_error(
+                        Code::FIELD_MISMATCH,
+                        format!("The rest of `{}`'s fields have to come from another {}, not {}", name, name, checked.ty()),
+                        base_span,
+                    )
+                    .with_label(base_span, format!("expected {}, found {}", name, checked.ty())))
+                }
+                Some(Box::new(checked))
+            },
+            None => {
+                let missing = struct_type.fields.iter()
+                    .filter(|(field, _)| !fields.iter().any(|(given, _)| given == field))
+                    .map(|(field, _)| format!("`{}`", field))
+                    .collect::<Vec<_>>();
+                if !missing.is_empty() {
+                    return Err(Diagnostic::type_error(
+                        Code::MISSING_FIELDS,
+                        format!("Missing {} {} in `{}`", if missing.len() == 1 { "field" } else { "fields" }, missing.join(", "), name),
+                        span,
+                    )
+                    .with_label(span, "every field needs a value")
+                    .with_help(format!("use `..` to take the rest from another {}", name)))
+                }
+                None
+            },
+        };
+        Self::infer_rest(&decl, expected, &params, &mut bound);
+        Self::all_bound(&params, &bound, name, format!("{}::<{}> {{ .. }}", name, params.join(", ")), span)?;
+        let ty = decl.subst(&bound);
+        Ok(HuckAst::StructLit(name.to_string(), type_args.clone(), checked_fields, checked_base, Typed { ty, span }))
+    }
+
+    fn check_fn(&mut self,
+                ident: &str,
+                type_params: &[(String, Vec<String>)],
+                params: &[(String, TypeAnn)],
+                ret: &TypeAnn,
+                body: &CheckInput,
+                span: Span
+    ) -> CheckResult {
+        let fn_type = self.function_type(type_params, params, ret, span)?;
+        // Already there if it was hoisted by the enclosing block
+        self.add_var(ident.to_string(), fn_type.clone());
+        let checked_body = self.check_fn_body(ident, &fn_type, params, body, span)?;
+        Ok(HuckAst::Fn(type_params.to_vec(), params.to_vec(), ret.clone(), Rc::new(checked_body), Typed { ty: fn_type, span }))
+    }
+
+    // The body of a function or method, which has to return what its type
+    // says
+    fn check_fn_body(&mut self,
+                     ident: &str,
+                     fn_type: &TypeInfo,
+                     params: &[(String, TypeAnn)],
+                     body: &CheckInput,
+                     span: Span
+    ) -> CheckResult {
+        let TypeInfo::Fn(param_types, ret_type) = fn_type.signature().clone() else {
+            unreachable!()
+        };
+        let type_params = match fn_type {
+            TypeInfo::Generic(type_params, _) => type_params.clone(),
+            _ => vec![],
+        };
+
+        let outer_frame_base = self.frame_base;
+        self.frame_base = self.env.len();
+        let outer_loop_base = std::mem::replace(&mut self.loop_base, self.loops.len());
+        self.begin_scope();
+        for ((param, _), param_type) in params.iter().zip(param_types) {
+            self.add_var(param.to_string(), param_type);
+        }
+        let checked_body = self.with_type_params(type_params, |checker| checker.check_expecting(body, Some(&ret_type)));
+        self.end_scope();
+        self.frame_base = outer_frame_base;
+        self.loop_base = outer_loop_base;
+
+        let checked_body = checked_body?;
+        let body_type = checked_body.ty();
+        if !body_type.fits(&ret_type) {
+            let body_span = tail_span(body);
+            return Err(Diagnostic::type_error(
+                Code::RETURN_MISMATCH,
+                format!("Function `{}` should return {} but its body has type {}", ident, ret_type, body_type),
+                body_span,
+            )
+            .with_label(body_span, format!("expected {}, found {}", ret_type, body_type))
+            .with_secondary(Span::new(span.start, body.get_metadata().start), format!("`{}` returns {}", ident, ret_type)))
+        }
+        Ok(checked_body)
+    }
+
+    // Which trait a method comes from is worked out from the receiver's
+    // type: it has to implement exactly one trait with a method by that
+    // name
+    fn check_method_call(&mut self, receiver: &CheckInput, method: &str, args: &[CheckInput], span: Span) -> CheckResult {
+        let checked_receiver = self.check(receiver)?;
+        let ty = checked_receiver.ty().clone();
+        let receiver_span = *receiver.get_metadata();
+        let mut declaring = self.traits.values()
+            .filter(|t| t.method(method, &ty).is_some())
+            .cloned()
+            .collect::<Vec<_>>();
+        declaring.sort_by_key(|t| t.id);
+        let candidates = declaring.iter().filter(|t| self.implements(&ty, t)).collect::<Vec<_>>();
+        let trait_type = match candidates.as_slice() {
+            [trait_type] => Rc::clone(trait_type),
+            [] => {
+                let diagnostic = Diagnostic::type_error(Code::UNKNOWN_METHOD, format!("No method `{}` on {}", method, ty), span)
+                    .with_label(receiver_span, format!("this has type {}", ty));
+                return Err(match declaring.first() {
+                    Some(t) => Self::not_implemented(&ty, t, diagnostic.message.clone(), span)
+                        .with_label(receiver_span, format!("this has type {}", ty))
+                        .with_note(format!("`{}` is a method of `{}`, which {} doesn't implement", method, t.name, ty)),
+                    None => diagnostic,
+                })
+            },
+            _ => {
+                let names = candidates.iter().map(|t| format!("`{}`", t.name)).collect::<Vec<_>>();
+                return Err(Diagnostic::type_error(
+                    Code::AMBIGUOUS_METHOD,
+                    format!("Method `{}` could come from more than one trait", method),
+                    span,
+                )
+                .with_label(receiver_span, format!("{} implements {}", ty, names.join(" and ")))
+                .with_note("traits implemented by the same type can't share method names"))
+            },
+        };
+
+        let Some(TypeInfo::Fn(params, ret)) = trait_type.method(method, &ty) else { unreachable!("Methods are functions") };
+        if args.len() + 1 != params.len() {
+            return Err(Diagnostic::type_error(
+                Code::WRONG_ARGUMENT_COUNT,
+                format!("Method `{}` takes {} arguments but was given {}", method, params.len() - 1, args.len()),
+                span,
+            )
+            .with_note(format!("`{}` is a method of `{}`", method, trait_type.name)))
+        }
+        let mut checked_args = vec![];
+        for (arg, param_type) in args.iter().zip(&params[1..]) {
+            let checked = self.check_expecting(arg, Some(param_type))?;
+            if !checked.ty().fits(param_type) {
+                let arg_span = *arg.get_metadata();
+                return Err(Diagnostic::type_error(
+                    Code::ARGUMENT_MISMATCH,
+                    format!("Argument to `{}` has type {} but {} was expected", method, checked.ty(), param_type),
+                    arg_span,
+                )
+                .with_label(arg_span, format!("expected {}, found {}", param_type, checked.ty())))
+            }
+            checked_args.push(checked);
+        }
+        Ok(HuckAst::MethodCall(
+            Box::new(checked_receiver),
+            method.to_string(),
+            checked_args,
+            Some(trait_type.name.clone()),
+            Typed { ty: *ret, span },
+        ))
+    }
+
+    // Check both sides of an operator. `problem` says what's wrong with
+    // their types, if anything, and whether it's the left side's fault
+    fn check_operands(&mut self,
+                      lhs: &CheckInput,
+                      rhs: &CheckInput,
+                      span: Span,
+                      expected: Option<&TypeInfo>,
+                      problem: impl FnOnce(&TypeInfo, &TypeInfo) -> Option<(String, bool)>
+    ) -> Result<(CheckOutput, CheckOutput), Diagnostic> {
+        let (checked_lhs, checked_rhs) = self.check_pair(lhs, rhs, expected)?;
+        let l_type = checked_lhs.ty();
+        let r_type = checked_rhs.ty();
+        let Some((message, blame_lhs)) = problem(l_type, r_type) else {
+            return Ok((checked_lhs, checked_rhs))
+        };
+
+        let (l_label, r_label) = (format!("this has type {}", l_type), format!("this has type {}", r_type));
+        let diagnostic = Diagnostic::type_error(Code::BAD_OPERANDS, message, span);
+        Err(if blame_lhs {
+            diagnostic.with_label(*lhs.get_metadata(), l_label).with_secondary(*rhs.get_metadata(), r_label)
+        } else {
+            diagnostic.with_label(*rhs.get_metadata(), r_label).with_secondary(*lhs.get_metadata(), l_label)
+        })
+    }
+
+    // Check two expressions that need the same type. A literal on its own
+    // takes its type from the other one, so that goes second.
+    fn check_pair(&mut self,
+                  first: &CheckInput,
+                  second: &CheckInput,
+                  expected: Option<&TypeInfo>
+    ) -> Result<(CheckOutput, CheckOutput), Diagnostic> {
+        if is_literal(first) && !is_literal(second) {
+            let checked_second = self.check_expecting(second, expected)?;
+            let checked_first = self.check_expecting(first, checked_second.ty().expectation().or(expected))?;
+            Ok((checked_first, checked_second))
+        } else {
+            let checked_first = self.check_expecting(first, expected)?;
+            let checked_second = self.check_expecting(second, checked_first.ty().expectation().or(expected))?;
+            Ok((checked_first, checked_second))
+        }
+    }
+
+    fn check_binary(&mut self,
+                    lhs: &CheckInput,
+                    rhs: &CheckInput,
+                    span: Span,
+                    expected: Option<&TypeInfo>,
+                    f: BinaryExpr
+    ) -> CheckResult {
+        let (checked_lhs, checked_rhs) = self.check_operands(lhs, rhs, span, expected, |l, r| {
+            Self::number_operands(l, r).map(|blame_lhs| {
+                (format!("Arithmetic needs two numbers of the same type, not {} and {}", l, r), blame_lhs)
+            })
+        })?;
+        let ty = checked_lhs.ty().clone();
+        Ok(f(Box::new(checked_lhs), Box::new(checked_rhs), Typed { ty, span }))
+    }
+
+    // There's no `%` for floats, since the native backend would need
+    // `fmod` for it
+    fn check_remainder(&mut self, lhs: &CheckInput, rhs: &CheckInput, span: Span, expected: Option<&TypeInfo>) -> CheckResult {
+        let (checked_lhs, checked_rhs) = self.check_operands(lhs, rhs, span, expected, |l, r| {
+            Self::integer_operands(l, r).map(|blame_lhs| {
+                (format!("`%` needs two integers of the same type, not {} and {}", l, r), blame_lhs)
+            })
+        })?;
+        let ty = checked_lhs.ty().clone();
+        Ok(HuckAst::Rem(Box::new(checked_lhs), Box::new(checked_rhs), Typed { ty, span }))
+    }
+
+    // The wrapping builtins are arithmetic that's allowed to overflow, so
+    // they're checked like it, minus the floats
+    fn check_builtin(&mut self, builtin: Builtin, args: &[CheckInput], span: Span, expected: Option<&TypeInfo>) -> CheckResult {
+        if builtin == Builtin::Print {
+            return self.check_print(args, span)
+        }
+        let [lhs, rhs] = args else {
+            return Err(Diagnostic::type_error(
+                Code::WRONG_ARGUMENT_COUNT,
+                format!("Function `{}` takes 2 arguments but was given {}", builtin.name(), args.len()),
+                span,
+            )
+            .with_note(format!("`{}` takes two integers of the same type", builtin.name())))
+        };
+        let (checked_lhs, checked_rhs) = self.check_operands(lhs, rhs, span, expected, |l, r| {
+            Self::integer_operands(l, r).map(|blame_lhs| {
+                (format!("`{}` needs two integers of the same type, not {} and {}", builtin.name(), l, r), blame_lhs)
+            })
+        })?;
+        let ty = checked_lhs.ty().clone();
+        Ok(HuckAst::Builtin(builtin, vec![checked_lhs, checked_rhs], Typed { ty, span }))
+    }
+
+    // `print` shows anything that implements `Display`. Built-in types
+    // have their own way of doing it; anything else calls its `print`.
+    fn check_print(&mut self, args: &[CheckInput], span: Span) -> CheckResult {
+        let typed = |ty| Typed { ty, span };
+        let [arg] = args else {
+            return Err(Diagnostic::type_error(
+                Code::WRONG_ARGUMENT_COUNT,
+                format!("Function `print` takes 1 argument but was given {}", args.len()),
+                span,
+            )
+            .with_note("`print` takes anything that implements `Display`"))
+        };
+        let checked = self.check(arg)?;
+        let ty = checked.ty().clone();
+        let display = Rc::clone(&self.traits["Display"]);
+        if !self.implements(&ty, &display) {
+            let arg_span = *arg.get_metadata();
+            return Err(Self::not_implemented(&ty, &display, format!("Can't print {}", ty), span)
+                .with_label(arg_span, format!("{} doesn't implement `Display`", ty)))
+        }
+        if builtin_impl("Display", &ty) {
+            return Ok(HuckAst::Builtin(Builtin::Print, vec![checked], typed(TypeInfo::Unit)))
+        }
+        Ok(HuckAst::MethodCall(Box::new(checked), String::from("print"), vec![], Some(String::from("Display")), typed(TypeInfo::Unit)))
+    }
+
+    // Built-in types have their own `==`. Anything else has to implement
+    // `Eq`, and `a == b` becomes `a.eq(b) == true`.
+    fn check_equality(&mut self, lhs: &CheckInput, rhs: &CheckInput, span: Span, f: BinaryExpr) -> CheckResult {
+        let (checked_lhs, checked_rhs) = self.check_operands(lhs, rhs, span, None, |l, r| {
+            (l != r).then(|| (format!("Cannot compare {} with {}", l, r), false))
+        })?;
+        let typed = |ty| Typed { ty, span };
+        let ty = checked_lhs.ty().clone();
+        if !needs_trait(&ty) {
+            return Ok(f(Box::new(checked_lhs), Box::new(checked_rhs), typed(TypeInfo::Bool)))
+        }
+        let eq = Rc::clone(&self.traits["Eq"]);
+        if !self.implements(&ty, &eq) {
+            let message = match ty {
+                TypeInfo::Struct(ref tuple) if tuple.is_tuple() => format!("Cannot compare tuples with `==`; compare the elements of the {}s instead", ty),
+                TypeInfo::Struct(_) => format!("Cannot compare structs with `==`; compare the fields of the {}s instead", ty),
+                TypeInfo::Enum(_) => format!("Cannot compare enums with `==`; use `match` on the {} instead", ty),
+                _ => format!("Cannot compare values of type {}", ty),
+            };
+            return Err(Self::not_implemented(&ty, &eq, message, span)
+                .with_label(*lhs.get_metadata(), format!("{} doesn't implement `Eq`", ty)))
+        }
+        let call = HuckAst::MethodCall(Box::new(checked_lhs), String::from("eq"), vec![checked_rhs], Some(String::from("Eq")), typed(TypeInfo::Bool));
+        Ok(f(Box::new(call), Box::new(HuckAst::BoolLit(true, typed(TypeInfo::Bool))), typed(TypeInfo::Bool)))
+    }
+
+    // The same goes for ordering and `Ord`: `a < b` is `a.cmp(b) < 0`
+    fn check_comparison(&mut self, lhs: &CheckInput, rhs: &CheckInput, span: Span, f: BinaryExpr) -> CheckResult {
+        let (checked_lhs, checked_rhs) = self.check_operands(lhs, rhs, span, None, |l, r| match l {
+            _ if l == r && needs_trait(l) => None,
+            _ => Self::number_operands(l, r).map(|blame_lhs| (format!("Cannot order {} and {}", l, r), blame_lhs)),
+        })?;
+        let typed = |ty| Typed { ty, span };
+        let ty = checked_lhs.ty().clone();
+        if !needs_trait(&ty) {
+            return Ok(f(Box::new(checked_lhs), Box::new(checked_rhs), typed(TypeInfo::Bool)))
+        }
+        let ord = Rc::clone(&self.traits["Ord"]);
+        if !self.implements(&ty, &ord) {
+            return Err(Self::not_implemented(&ty, &ord, format!("Cannot order values of type {}", ty), span)
+                .with_label(*lhs.get_metadata(), format!("{} doesn't implement `Ord`", ty)))
+        }
+        let i64_type = TypeInfo::Int(IntType::I64);
+        let call = HuckAst::MethodCall(Box::new(checked_lhs), String::from("cmp"), vec![checked_rhs], Some(String::from("Ord")), typed(i64_type.clone()));
+        Ok(f(Box::new(call), Box::new(HuckAst::Num(0, None, typed(i64_type))), typed(TypeInfo::Bool)))
+    }
+
+    // `None` if both sides are numbers of the same type, otherwise
+    // whether it's the left one that's wrong
+    fn number_operands(l: &TypeInfo, r: &TypeInfo) -> Option<bool> {
+        match (l, r) {
+            (TypeInfo::Int(a), TypeInfo::Int(b)) if a == b => None,
+            (TypeInfo::F64, TypeInfo::F64) => None,
+            (TypeInfo::Int(_) | TypeInfo::F64, _) => Some(false),
+            _ => Some(true),
+        }
+    }
+
+    // The same, but only for integers
+    fn integer_operands(l: &TypeInfo, r: &TypeInfo) -> Option<bool> {
+        match (l, r) {
+            (TypeInfo::Int(a), TypeInfo::Int(b)) if a == b => None,
+            (TypeInfo::Int(_), _) => Some(false),
+            _ => Some(true),
+        }
+    }
+
+    // A suffix says what type a literal is; otherwise it's whatever
+    // integer type is expected, or i64
+    fn literal_type(&self, suffix: &Option<TypeAnn>, expected: Option<&TypeInfo>, span: Span) -> Result<IntType, Diagnostic> {
+        match (suffix, expected) {
+            (Some(ann), _) => match self.resolve_type(ann, span)? {
+                TypeInfo::Int(t) => Ok(t),
+                t => Err(Diagnostic::type_error(Code::UNKNOWN_TYPE, format!("Numbers can't have type {}", t), span)
+                    .with_label(span, "this suffix isn't an integer type")),
+            },
+            (None, Some(TypeInfo::Int(t))) => Ok(*t),
+            (None, _) => Ok(IntType::I64),
+        }
+    }
+
+    fn check_literal(n: i128, t: IntType, span: Span) -> Result<(), Diagnostic> {
+        if (t.min()..=t.max()).contains(&n) {
+            return Ok(());
+        }
+        Err(Diagnostic::type_error(Code::LITERAL_OUT_OF_RANGE, format!("{} doesn't fit in {}", n, t), span)
+            .with_label(span, format!("this has type {}", t))
+            .with_note(format!("{} goes from {} to {}", t, t.min(), t.max()))
+            .with_help("use a wider type, or `as` to convert a number on purpose"))
+    }
+
+    fn bad_negation(t: &TypeInfo, span: Span, operand_span: Span) -> Diagnostic {
+        Diagnostic::type_error(Code::BAD_OPERANDS, format!("Can't negate {}", t), span)
+            .with_label(operand_span, format!("this has type {}", t))
+            .with_note("only signed integers and floats can be negated")
+    }
+}
+
+// Types whose operators come from traits rather than being built in
+fn needs_trait(ty: &TypeInfo) -> bool {
+    matches!(ty, TypeInfo::Struct(_) | TypeInfo::Enum(_) | TypeInfo::Param(_) | TypeInfo::Dyn(_))
+}
+
+// Type parameters of a struct or enum, which can't have bounds
+fn unbounded(params: &[String]) -> Vec<(String, Vec<Rc<TraitType>>)> {
+    params.iter().map(|param| (param.clone(), vec![])).collect()
+}
+
+// The value of `-n` for an integer literal `n`. This has to be worked out
+// as a whole rather than by negating `n`, which overflows for the most
+// negative number of each type.
+pub fn negated_literal(operand: &CheckOutput) -> Option<(i64, IntType)> {
+    match operand {
+        HuckAst::Num(n, _, Typed { ty: TypeInfo::Int(t), .. }) => Some((t.wrap((*n as i64).wrapping_neg()), *t)),
+        _ => None,
+    }
+}
+
+// Whether a type annotation is the type `name`, with or without type
+// arguments, or a tuple holding it
+fn names(ann: &TypeAnn, name: &str) -> bool {
+    match ann {
+        TypeAnn::Tuple(elements) => elements.iter().any(|element| names(element, name)),
+        _ => matches!(ann, TypeAnn::Named(named) | TypeAnn::Applied(named, _) if named == name),
+    }
+}
+
+// Literals with no suffix, whose type comes from their surroundings
+fn is_literal(ast: &CheckInput) -> bool {
+    match ast {
+        HuckAst::Num(_, None, _) => true,
+        HuckAst::Neg(operand, _) => is_literal(operand),
+        HuckAst::Block(exprs, _) => exprs.last().is_some_and(is_literal),
+        _ => false,
+    }
+}
+
+// Where the value of an expression comes from: the last expression of a
+// block, rather than the whole (possibly many-line) thing
+fn tail_span(ast: &CheckInput) -> Span {
+    match ast {
+        HuckAst::Block(exprs, _) => tail_span(exprs.last().expect("Blocks aren't empty")),
+        _ => *ast.get_metadata(),
+    }
+}
+
+type BinaryExpr = fn (Box<CheckOutput>, Box<CheckOutput>, Typed) -> CheckOutput;
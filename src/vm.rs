@@ -0,0 +1,372 @@
+// Stack VM for the bytecode in `bytecode`.
+//
+// A call frame's locals live on the value stack, starting with the
+// arguments the caller pushed, and the operand stack for the frame sits
+// on top of them. Programs can come from `.hbc` files, so anything the
+// compiler would never produce (bad slots, stack underflow, running off
+// the end of a function) is a runtime error rather than a panic.
+
+use crate::bytecode::{Op, Program};
+use crate::interp::{self, EvalResult, FloatOp, IntOp, Value};
+use crate::ir::NO_MATCH;
+use crate::typecheck::IntType;
+
+use std::cmp::Ordering;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+struct Frame {
+    func: usize,
+    ip: usize,
+    base: usize,
+}
+
+pub struct Vm<'a> {
+    program: &'a Program,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    // Where `print` writes
+    out: Box<dyn Write>,
+}
+
+pub fn run(program: &Program) -> EvalResult {
+    Vm::new(program).run()
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        Self { program, stack: vec![], frames: vec![], out: Box::new(io::stdout()) }
+    }
+
+    /// Send what the program prints somewhere other than stdout.
+    pub fn with_output(mut self, out: impl Write + 'static) -> Self {
+        self.out = Box::new(out);
+        self
+    }
+
+    fn pop(&mut self) -> EvalResult {
+        self.stack.pop().ok_or_else(|| String::from("Stack underflow"))
+    }
+
+    fn enter(&mut self, func: u16) -> Result<Frame, String> {
+        let callee = self.program.functions.get(func as usize)
+            .ok_or_else(|| format!("Call to unknown function {}", func))?;
+        let arity = callee.arity as usize;
+        if self.stack.len() < arity || (callee.locals as usize) < arity {
+            return Err(format!("Bad call to {}", callee.name));
+        }
+        let base = self.stack.len() - arity;
+        self.stack.resize(base + callee.locals as usize, Value::Unit);
+        Ok(Frame { func: func as usize, ip: 0, base })
+    }
+
+    pub fn run(&mut self) -> EvalResult {
+        if self.program.functions.is_empty() {
+            return Err(String::from("Program has no entry point"));
+        }
+        let program = self.program;
+        let mut frame = self.enter(0)?;
+
+        loop {
+            let function = &program.functions[frame.func];
+            let op = *function.code.get(frame.ip)
+                .ok_or_else(|| format!("Fell off the end of {}", function.name))?;
+            let locals = function.locals as usize;
+            frame.ip += 1;
+
+            match op {
+                Op::Unit => self.stack.push(Value::Unit),
+                Op::Bool(b) => self.stack.push(Value::Bool(b)),
+                Op::Int(n, t) => self.stack.push(Value::Int(n, t)),
+                Op::Load(slot) if (slot as usize) < locals => {
+                    self.stack.push(self.stack[frame.base + slot as usize].clone());
+                },
+                Op::Store(slot) if (slot as usize) < locals => {
+                    let value = self.stack.last().cloned().ok_or_else(|| String::from("Stack underflow"))?;
+                    self.stack[frame.base + slot as usize] = value;
+                },
+                Op::Load(slot) | Op::Store(slot) => {
+                    return Err(format!("Local slot {} out of range in {}", slot, function.name))
+                },
+                Op::Pop => {
+                    self.pop()?;
+                },
+                Op::Float(x) => self.stack.push(Value::Float(x)),
+                Op::Add => self.arithmetic(interp::add, |a, b| a + b)?,
+                Op::Sub => self.arithmetic(interp::sub, |a, b| a - b)?,
+                Op::Mul => self.arithmetic(interp::mul, |a, b| a * b)?,
+                Op::Div => self.arithmetic(interp::div, |a, b| a / b)?,
+                Op::Rem => self.arithmetic(interp::rem, |a, b| a % b)?,
+                Op::WrappingAdd => self.wrapping(interp::wrapping_add)?,
+                Op::WrappingSub => self.wrapping(interp::wrapping_sub)?,
+                Op::WrappingMul => self.wrapping(interp::wrapping_mul)?,
+                Op::WrappingRem => self.wrapping(interp::wrapping_rem)?,
+                Op::Cast(t) => {
+                    let value = self.pop()?;
+                    self.stack.push(interp::cast(value, t)?);
+                },
+                Op::CastFloat => {
+                    let value = self.pop()?;
+                    self.stack.push(interp::cast_to_float(value)?);
+                },
+                Op::Eq | Op::Ne => {
+                    let rhs = self.pop()?;
+                    let lhs = self.pop()?;
+                    self.stack.push(Value::Bool((lhs == rhs) == (op == Op::Eq)));
+                },
+                Op::Lt => self.comparison(Ordering::is_lt)?,
+                Op::Le => self.comparison(Ordering::is_le)?,
+                Op::Gt => self.comparison(Ordering::is_gt)?,
+                Op::Ge => self.comparison(Ordering::is_ge)?,
+                Op::Jump(target) => frame.ip = target as usize,
+                Op::JumpIfFalse(target) => match self.pop()? {
+                    Value::Bool(false) => frame.ip = target as usize,
+                    Value::Bool(true) => (),
+                    v => return Err(format!("Expected a boolean condition but found {}", v)),
+                },
+                Op::Call(func) => {
+                    let callee = self.enter(func)?;
+                    self.frames.push(std::mem::replace(&mut frame, callee));
+                },
+                Op::TailCall(func) => {
+                    // Slide the arguments down over the current frame
+                    let arity = program.functions.get(func as usize).map_or(0, |f| f.arity as usize);
+                    if self.stack.len() < frame.base + arity {
+                        return Err(String::from("Stack underflow"));
+                    }
+                    let args = self.stack.len() - arity;
+                    self.stack.drain(frame.base..args);
+                    frame = self.enter(func)?;
+                },
+                Op::Struct(index) => {
+                    let shape = program.structs.get(index as usize)
+                        .ok_or_else(|| format!("Unknown struct {}", index))?;
+                    let fields = self.stack.len().checked_sub(shape.fields.len())
+                        .filter(|&start| start >= frame.base + locals)
+                        .ok_or_else(|| String::from("Stack underflow"))?;
+                    let values = self.stack.split_off(fields);
+                    self.stack.push(Value::Struct(shape.clone(), values.into()));
+                },
+                Op::Field(index) => match self.pop()? {
+                    Value::Struct(shape, values) => {
+                        let value = values.get(index as usize)
+                            .ok_or_else(|| format!("{} has no field {}", shape.name, index))?;
+                        self.stack.push(value.clone());
+                    },
+                    v => return Err(format!("Cannot get a field of {}", v)),
+                },
+                Op::Variant(index, variant) => {
+                    let shape = program.enums.get(index as usize)
+                        .ok_or_else(|| format!("Unknown enum {}", index))?;
+                    let (_, payload) = shape.variants.get(variant as usize)
+                        .ok_or_else(|| format!("{} has no variant {}", shape.name, variant))?;
+                    let fields = self.stack.len().checked_sub(*payload)
+                        .filter(|&start| start >= frame.base + locals)
+                        .ok_or_else(|| String::from("Stack underflow"))?;
+                    let values = self.stack.split_off(fields);
+                    self.stack.push(Value::Enum(shape.clone(), variant as usize, values.into()));
+                },
+                Op::Tag => match self.pop()? {
+                    Value::Enum(_, variant, _) => self.stack.push(Value::Int(variant as i64, IntType::U32)),
+                    v => return Err(format!("Cannot get the variant of {}", v)),
+                },
+                Op::Payload(index) => match self.pop()? {
+                    Value::Enum(shape, variant, values) => {
+                        let value = values.get(index as usize)
+                            .ok_or_else(|| format!("{}::{} has no payload value {}", shape.name, shape.variants[variant].0, index))?;
+                        self.stack.push(value.clone());
+                    },
+                    v => return Err(format!("Cannot get the payload of {}", v)),
+                },
+                Op::NoMatch => return Err(String::from(NO_MATCH)),
+                Op::Dyn(index) => {
+                    if index as usize >= program.vtables.len() {
+                        return Err(format!("Unknown vtable {}", index));
+                    }
+                    let value = self.pop()?;
+                    self.stack.push(Value::Dyn(index as usize, Rc::new(value)));
+                },
+                Op::DynCall(method, args) => {
+                    let receiver = self.stack.len().checked_sub(args as usize + 1)
+                        .filter(|&receiver| receiver >= frame.base + locals)
+                        .ok_or_else(|| String::from("Stack underflow"))?;
+                    let (vtable, value) = match &self.stack[receiver] {
+                        Value::Dyn(vtable, value) => (*vtable, Value::clone(value)),
+                        v => return Err(format!("Cannot call a method on {}", v)),
+                    };
+                    let func = *program.vtables[vtable].get(method as usize)
+                        .ok_or_else(|| format!("Vtable {} has no method {}", vtable, method))?;
+                    self.stack[receiver] = value;
+                    let callee = self.enter(func)?;
+                    self.frames.push(std::mem::replace(&mut frame, callee));
+                },
+                Op::Print => {
+                    let value = self.pop()?;
+                    self.stack.push(interp::print(self.out.as_mut(), &value)?);
+                },
+                Op::Return => {
+                    let value = self.pop()?;
+                    self.stack.truncate(frame.base);
+                    match self.frames.pop() {
+                        Some(caller) => {
+                            frame = caller;
+                            self.stack.push(value);
+                        },
+                        None => return Ok(value),
+                    }
+                },
+            }
+        }
+    }
+
+    fn arithmetic(&mut self, int_op: IntOp, float_op: FloatOp) -> Result<(), String> {
+        let rhs = self.pop()?;
+        let lhs = self.pop()?;
+        self.stack.push(interp::arithmetic(lhs, rhs, int_op, float_op)?);
+        Ok(())
+    }
+
+    fn wrapping(&mut self, int_op: IntOp) -> Result<(), String> {
+        let rhs = self.pop()?;
+        let lhs = self.pop()?;
+        self.stack.push(interp::int_arithmetic(lhs, rhs, int_op)?);
+        Ok(())
+    }
+
+    fn comparison(&mut self, f: fn(Ordering) -> bool) -> Result<(), String> {
+        let rhs = self.pop()?;
+        let lhs = self.pop()?;
+        self.stack.push(interp::comparison(lhs, rhs, f)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bcgen::compile;
+    use crate::bytecode::Function;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use crate::typecheck::{Checker, IntType};
+
+    fn run_str(s: &str) -> EvalResult {
+        let ast = Parser::new(Scanner::new(s)).parse().unwrap();
+        let checked = crate::mono::monomorphize(&Checker::new().check(&ast).unwrap()).unwrap();
+        let program = compile(&checked);
+        // Everything should survive a trip through the file format
+        let program = Program::decode(&program.encode()).unwrap();
+        run(&program)
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(run_str("{let x = 1; let y = 2; 50 + y * x / 2 - 1}"), Ok(Value::Int(50, IntType::I64)));
+        assert!(run_str("{let x = 0; 1 / x}").is_err());
+        assert_eq!(run_str("{let x = 7u8; -(x as i16) * 300 / 2u8 as i16}"), Ok(Value::Int(-1050, IntType::I16)));
+        assert_eq!(run_str("4000000000u32 / 3u32 > 1000000000"), Ok(Value::Bool(true)));
+        assert_eq!(run_str("{let x = 255u8; x + 1u8}"), Err(String::from("Overflow in `+`")));
+        assert_eq!(run_str("{let x = 255u8; wrapping_add(x, 1u8) + wrapping_mul(x, x) + wrapping_sub(0u8, x)}"), Ok(Value::Int(2, IntType::U8)));
+    }
+
+    #[test]
+    fn floats() {
+        assert_eq!(run_str("{let x = 1.5; -x * 3 as f64 / 0.5}"), Ok(Value::Float(-9.0)));
+        assert_eq!(run_str("{let zero = 0.0; let nan = zero / zero; if nan < 1.0 { true } else { nan == nan }}"), Ok(Value::Bool(false)));
+        assert_eq!(run_str("-300.7 as u8"), Ok(Value::Int(0, IntType::U8)));
+    }
+
+    #[test]
+    fn conditional() {
+        assert_eq!(run_str("{let test = false; 1 + if test { 1 } else { 2 }}"), Ok(Value::Int(3, IntType::I64)));
+        assert_eq!(run_str("if 1 < 2 { true != false } else { false }"), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn calls() {
+        let source = "{
+            let fact = fn (n: i64): i64 { if n == 0 { 1 } else { n * fact(n - 1) } };
+            let x = fact(10);
+            x + fact(3)
+        }";
+        assert_eq!(run_str(source), Ok(Value::Int(3628806, IntType::I64)));
+    }
+
+    #[test]
+    fn structs() {
+        let source = "{
+            struct Inner { a: bool, b: f64 };
+            struct Outer { i: Inner, n: u8 };
+            let o = Outer { n: 7, i: Inner { b: 0.5, a: true } };
+            let p = Outer { n: 8, ..o };
+            if p.i.a { p.i.b + p.n as f64 } else { 0.0 }
+        }";
+        assert_eq!(run_str(source), Ok(Value::Float(8.5)));
+    }
+
+    #[test]
+    fn matches() {
+        let source = "{
+            enum Maybe { Some(i64), None };
+            enum Pair { Two(Maybe, bool) };
+            let get = fn (p: Pair): i64 {
+                match p { Pair::Two(Maybe::Some(n), true) if n > 0 => n, Pair::Two(Maybe::Some(n), _) => 0 - n, _ => 100 }
+            };
+            get(Pair::Two(Maybe::Some(3), true)) + get(Pair::Two(Maybe::Some(-4), true)) + get(Pair::Two(Maybe::None, false))
+        }";
+        assert_eq!(run_str(source), Ok(Value::Int(107, IntType::I64)));
+        assert_eq!(run_str("match true { false => 1, true => 2 }"), Ok(Value::Int(2, IntType::I64)));
+    }
+
+    #[test]
+    fn dyn_calls() {
+        let source = "{
+            trait Shape { fn area(self): f64; fn scaled(self, by: f64): f64 };
+            struct Square { side: f64 };
+            impl Shape for Square { fn area(self): f64 { self.side * self.side }; fn scaled(self, by: f64): f64 { self.area() * by } };
+            impl Shape for i64 { fn area(self): f64 { self as f64 }; fn scaled(self, by: f64): f64 { by } };
+            let shapes = (Square { side: 3.0 } as dyn Shape, 4 as dyn Shape);
+            shapes.0.scaled(2.0) + shapes.1.area()
+        }";
+        assert_eq!(run_str(source), Ok(Value::Float(22.0)));
+    }
+
+    #[test]
+    fn deep_tail_recursion() {
+        let source = "{
+            let odd = fn (x: i64): bool { if (x == 0) { false } else { even(x - 1) } };
+            let even = fn (x: i64): bool { if (x == 0) { true } else { odd(x - 1) } };
+            even(1000001)
+        }";
+        assert_eq!(run_str(source), Ok(Value::Bool(false)));
+    }
+
+    #[test]
+    fn malformed_bytecode() {
+        let program = Program {
+            structs: vec![],
+            enums: vec![],
+            vtables: vec![],
+            functions: vec![Function {
+                name: "main".to_string(),
+                arity: 0,
+                locals: 0,
+                code: vec![Op::Load(3), Op::Return],
+            }],
+        };
+        assert!(run(&program).is_err());
+        // A method call on something that isn't a `dyn`
+        let program = Program {
+            structs: vec![],
+            enums: vec![],
+            vtables: vec![vec![0]],
+            functions: vec![Function {
+                name: "main".to_string(),
+                arity: 0,
+                locals: 0,
+                code: vec![Op::Unit, Op::DynCall(0, 0), Op::Return],
+            }],
+        };
+        assert_eq!(run(&program), Err(String::from("Cannot call a method on ()")));
+    }
+}
@@ -0,0 +1,528 @@
+// Golden tests over the sample programs in `huck-src/`. Each program says
+// what it should do in comments at the top:
+//
+//     // expect: 42
+//     // expect-error: E0104 at 3:5
+//     // expect-runtime-error: Division by zero
+//     // expect-warning: E0130 at 4:5
+//     // expect-output: 3.5
+//
+// `expect` is the value the program prints with `huck run`;
+// `expect-error` is the code and line:column of the diagnostic that
+// rejects it; and `expect-runtime-error` is (part of) the message it
+// fails with. There's one `expect-warning` for each warning the checker
+// should give, and it mustn't give any others. Likewise there's one
+// `expect-output` for each line the program writes with `print`, in
+// order. Programs run on every backend unless a `// backends:` line
+// lists the ones to use: interp, vm, native and llvm. Native executables
+// and LLVM modules (run with `lli`) only have their exit code, so they're
+// compared against the low byte of the expected value, except that floats
+// are printed and structs and enums just exit with 0. Both exit with
+// status 101 when a check fails, and the message also says where, which
+// is why runtime errors only have to contain the expected message.
+//
+// `cargo test --test golden -- --bless` rewrites the expectations (all
+// of them: the outcome, warnings and output) to whatever the interpreter
+// does now. Any other arguments pick out the programs whose names
+// contain them.
+
+use huck::link::Linker;
+use huck::opt::OptLevel;
+use huck::{bcgen, diagnostic, interp, vm, Diagnostic, Session};
+
+use std::cell::RefCell;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{exit, Command, Output};
+use std::rc::Rc;
+
+#[derive(Debug, PartialEq, Clone)]
+enum Outcome {
+    Value(String),
+    Error { code: String, line: usize, col: usize },
+    RuntimeError(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Value(value) => write!(f, "expect: {}", value),
+            Self::Error { code, line, col } => write!(f, "expect-error: {} at {}:{}", code, line, col),
+            Self::RuntimeError(message) => write!(f, "expect-runtime-error: {}", message),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Backend {
+    Interp,
+    Vm,
+    Native(OptLevel),
+    Llvm,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Interp => write!(f, "interp"),
+            Self::Vm => write!(f, "vm"),
+            Self::Native(OptLevel::O0) => write!(f, "native -O0"),
+            Self::Native(OptLevel::O1) => write!(f, "native -O1"),
+            Self::Native(OptLevel::O2) => write!(f, "native -O2"),
+            Self::Llvm => write!(f, "llvm"),
+        }
+    }
+}
+
+struct Header {
+    expect: Option<Outcome>,
+    // The code and line:column of each warning
+    warnings: Vec<(String, usize, usize)>,
+    // Each line the program prints
+    output: Vec<String>,
+    backends: Vec<Backend>,
+    // The lines of the file holding expectations of any kind, for blessing
+    expectation_lines: Vec<usize>,
+}
+
+const ALL_BACKENDS: [Backend; 5] = [Backend::Interp, Backend::Vm, Backend::Native(OptLevel::O0), Backend::Native(OptLevel::O2), Backend::Llvm];
+
+// The header is the comments before the first line of code
+fn parse_header(source: &str) -> Result<Header, String> {
+    let mut header = Header { expect: None, warnings: vec![], output: vec![], backends: ALL_BACKENDS.to_vec(), expectation_lines: vec![] };
+    for (i, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let Some(comment) = line.strip_prefix("//") else {
+            break;
+        };
+        let Some((key, value)) = comment.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        let expect = match key.trim() {
+            "expect" => Outcome::Value(value.to_string()),
+            "expect-runtime-error" => Outcome::RuntimeError(value.to_string()),
+            "expect-error" => {
+                let (code, line, col) = parse_location(value)
+                    .ok_or_else(|| format!("line {}: expected `CODE at LINE:COL`, not {:?}", i + 1, value))?;
+                Outcome::Error { code, line, col }
+            },
+            "expect-warning" => {
+                let warning = parse_location(value)
+                    .ok_or_else(|| format!("line {}: expected `CODE at LINE:COL`, not {:?}", i + 1, value))?;
+                header.warnings.push(warning);
+                header.expectation_lines.push(i);
+                continue;
+            },
+            "expect-output" => {
+                header.output.push(value.to_string());
+                header.expectation_lines.push(i);
+                continue;
+            },
+            "backends" => {
+                header.backends = value.split([',', ' ']).filter(|name| !name.is_empty())
+                    .map(|name| match name {
+                        "interp" => Ok(vec![Backend::Interp]),
+                        "vm" => Ok(vec![Backend::Vm]),
+                        "native" => Ok(vec![Backend::Native(OptLevel::O0), Backend::Native(OptLevel::O2)]),
+                        "llvm" => Ok(vec![Backend::Llvm]),
+                        _ => Err(format!("line {}: unknown backend {:?}", i + 1, name)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+                    .concat();
+                continue;
+            },
+            _ => continue,
+        };
+        if header.expect.is_some() {
+            return Err(format!("line {}: more than one expectation", i + 1));
+        }
+        header.expect = Some(expect);
+        header.expectation_lines.push(i);
+    }
+    Ok(header)
+}
+
+fn parse_location(value: &str) -> Option<(String, usize, usize)> {
+    let (code, position) = value.split_once(" at ")?;
+    let (line, col) = position.trim().split_once(':')?;
+    Some((code.trim().to_string(), line.parse().ok()?, col.parse().ok()?))
+}
+
+// A diagnostic's code and where its primary label starts
+fn locate(diagnostic: &Diagnostic, source: &str) -> (String, usize, usize) {
+    let (line, col) = diagnostic.primary.as_ref()
+        .map_or((0, 0), |label| diagnostic::line_col(source, label.span.start));
+    let code = diagnostic.code.map_or_else(|| String::from("none"), |code| code.to_string());
+    (code, line, col)
+}
+
+// Every warning the checker gives about the program
+fn warnings(source: &str) -> Vec<(String, usize, usize)> {
+    let mut session = Session::default();
+    session.check(source);
+    session.diagnostics().iter()
+        .filter(|diagnostic| !diagnostic.is_error())
+        .map(|warning| locate(warning, source))
+        .collect()
+}
+
+// What a backend did with a program: the checked program's outcome for
+// the interpreter and VM. Native and LLVM can only report an exit code
+// unless they printed a float.
+enum Run {
+    Outcome(Outcome),
+    Exit(i32),
+    Skipped(String),
+}
+
+// Where the interpreter and VM print to, so it can be checked afterwards
+#[derive(Clone, Default)]
+struct Sink(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// What happened, along with the lines the program printed. Native and
+// LLVM programs print their value after their output if it's a float, so
+// `printed` says how many lines of stdout are output.
+fn run(name: &str, source: &str, backend: Backend, printed: usize) -> (Run, Vec<String>) {
+    let checked = match huck::parse_str(source).and_then(|ast| huck::check(&ast)) {
+        Ok(checked) => checked,
+        Err(diagnostic) => {
+            let (code, line, col) = locate(&diagnostic, source);
+            return (Run::Outcome(Outcome::Error { code, line, col }), vec![]);
+        },
+    };
+    let sink = Sink::default();
+    let result = match backend {
+        Backend::Interp => interp::Interpreter::new().with_output(sink.clone()).eval(&checked),
+        Backend::Vm => vm::Vm::new(&bcgen::compile(&checked)).with_output(sink.clone()).run(),
+        Backend::Native(level) => return run_native(name, &huck::compile_to_asm(&checked, source, level), printed),
+        Backend::Llvm => return run_llvm(name, &huck::compile_to_llvm(&checked, source, OptLevel::O2), printed),
+    };
+    let output = String::from_utf8_lossy(&sink.0.borrow()).lines().map(String::from).collect();
+    let run = Run::Outcome(match result {
+        Ok(value) => Outcome::Value(value.to_string()),
+        Err(message) => Outcome::RuntimeError(message),
+    });
+    (run, output)
+}
+
+fn run_native(name: &str, asm: &str, printed: usize) -> (Run, Vec<String>) {
+    let dir = env::temp_dir().join(format!("huck-golden-{}-{}", name, std::process::id()));
+    if let Err(err) = fs::create_dir_all(&dir) {
+        return (Run::Skipped(format!("can't create {}: {}", dir.display(), err)), vec![]);
+    }
+    let exe = dir.join(name);
+    let result = Linker::new(None, false).link(asm.as_bytes(), &exe).and_then(|_| {
+        Command::new(&exe).output().map_err(|err| format!("can't run {}: {}", exe.display(), err))
+    });
+    let _ = fs::remove_dir_all(&dir);
+    match result {
+        // No C compiler means no native backend, which isn't the
+        // program's fault
+        Err(message) if message.contains("Can't find a C compiler") => (Run::Skipped(message), vec![]),
+        Err(message) => (Run::Outcome(Outcome::RuntimeError(message)), vec![]),
+        Ok(output) => finished(&output, printed),
+    }
+}
+
+fn run_llvm(name: &str, llvm: &str, printed: usize) -> (Run, Vec<String>) {
+    let path = env::temp_dir().join(format!("huck-golden-{}-{}.ll", name, std::process::id()));
+    if let Err(err) = fs::write(&path, llvm) {
+        return (Run::Skipped(format!("can't write {}: {}", path.display(), err)), vec![]);
+    }
+    let result = Command::new("lli").arg(&path).output();
+    let _ = fs::remove_file(&path);
+    match result {
+        // Likewise for LLVM
+        Err(err) if err.kind() == io::ErrorKind::NotFound => (Run::Skipped(String::from("can't find lli")), vec![]),
+        Err(err) => (Run::Outcome(Outcome::RuntimeError(format!("can't run lli: {}", err))), vec![]),
+        Ok(output) => finished(&output, printed),
+    }
+}
+
+// How a native or LLVM program exited, and what it printed
+fn finished(output: &Output, printed: usize) -> (Run, Vec<String>) {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines: Vec<String> = stdout.lines().map(String::from).collect();
+    let value = lines.split_off(printed.min(lines.len())).join("\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let run = match (output.status.code(), stderr.trim().strip_prefix("Runtime error: ")) {
+        (Some(101), Some(message)) => Run::Outcome(Outcome::RuntimeError(message.to_string())),
+        (Some(0), _) if !value.is_empty() => Run::Outcome(Outcome::Value(value.trim().to_string())),
+        (Some(code), _) => Run::Exit(code),
+        (None, _) => Run::Outcome(Outcome::RuntimeError(format!("killed ({})", output.status))),
+    };
+    (run, lines)
+}
+
+// Exit codes are the low byte of the program's value
+fn exit_code(value: &str) -> Option<i32> {
+    match value {
+        "()" | "false" => Some(0),
+        "true" => Some(1),
+        // Native programs whose value is a struct or enum just exit cleanly
+        s if s.ends_with('}') || s.contains("::") => Some(0),
+        n => n.parse::<i64>().ok().map(|n| (n & 0xff) as i32),
+    }
+}
+
+fn matches(expected: &Outcome, run: &Run) -> bool {
+    match (expected, run) {
+        (Outcome::RuntimeError(expected), Run::Outcome(Outcome::RuntimeError(actual))) => actual.contains(expected.as_str()),
+        (expected, Run::Outcome(actual)) => expected == actual,
+        (Outcome::Value(value), Run::Exit(code)) => exit_code(value) == Some(*code),
+        (_, Run::Exit(_)) => false,
+        (_, Run::Skipped(_)) => true,
+    }
+}
+
+fn describe(run: &Run) -> String {
+    match run {
+        Run::Outcome(outcome) => format!("// {}", outcome),
+        Run::Exit(code) => format!("exit code {}", code),
+        Run::Skipped(why) => format!("skipped: {}", why),
+    }
+}
+
+// What blessing writes into a program's header
+struct Blessing {
+    outcome: Outcome,
+    warnings: Vec<(String, usize, usize)>,
+    output: Vec<String>,
+}
+
+// Replace all the expectations in the header with new ones, where the
+// first of the old ones was or else at the top
+fn bless(path: &Path, source: &str, header: &Header, blessing: &Blessing) -> Result<(), String> {
+    let old = &header.expectation_lines;
+    // Every line of code moves up or down by however many more lines of
+    // expectations there are now (code always comes after them)
+    let new = 1 + blessing.warnings.len() + blessing.output.len();
+    let moved = |line: usize| line + new - old.len();
+    let outcome = match &blessing.outcome {
+        Outcome::Error { code, line, col } => Outcome::Error { code: code.clone(), line: moved(*line), col: *col },
+        outcome => outcome.clone(),
+    };
+    let mut expectations = vec![format!("// {}", outcome)];
+    expectations.extend(blessing.warnings.iter()
+        .map(|(code, line, col)| format!("// expect-warning: {} at {}:{}", code, moved(*line), col)));
+    expectations.extend(blessing.output.iter().map(|line| format!("// expect-output: {}", line)));
+
+    let at = old.first().copied().unwrap_or(0);
+    let mut lines: Vec<String> = source.lines().enumerate()
+        .filter(|(i, _)| !old.contains(i))
+        .map(|(_, line)| line.to_string())
+        .collect();
+    lines.splice(at..at, expectations);
+    fs::write(path, lines.join("\n") + "\n").map_err(|err| format!("can't write {}: {}", path.display(), err))
+}
+
+// Returns a description of what went wrong, if anything did
+fn test_program(path: &Path, blessing: bool) -> Result<Vec<String>, String> {
+    let name = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("program");
+    let source = fs::read_to_string(path).map_err(|err| format!("can't read it: {}", err))?;
+    let header = parse_header(&source)?;
+
+    let runs: Vec<(Backend, Run, Vec<String>)> = header.backends.iter()
+        .map(|&backend| {
+            let (run, output) = run(name, &source, backend, header.output.len());
+            (backend, run, output)
+        })
+        .collect();
+    let notes = runs.iter()
+        .filter_map(|(backend, run, _)| match run {
+            Run::Skipped(why) => Some(format!("{} skipped: {}", backend, why)),
+            _ => None,
+        })
+        .collect();
+
+    let expected = match (&header.expect, blessing) {
+        (Some(expected), false) => expected.clone(),
+        (None, false) => return Err(String::from("no `// expect` header; run with --bless to add one")),
+        // The first backend that has a full answer is the reference
+        (_, true) => {
+            let actual = runs.iter().find_map(|(_, run, output)| match run {
+                Run::Outcome(outcome) => Some((outcome.clone(), output.clone())),
+                _ => None,
+            });
+            let (outcome, output) = actual.ok_or("no backend can produce an expectation to bless")?;
+            let blessing = Blessing { outcome, warnings: warnings(&source), output };
+            if header.expect.as_ref() != Some(&blessing.outcome) || header.warnings != blessing.warnings || header.output != blessing.output {
+                bless(path, &source, &header, &blessing)?;
+                return test_program(path, false);
+            }
+            blessing.outcome
+        },
+    };
+
+    let mut mismatches: Vec<String> = runs.iter()
+        .filter(|(_, run, _)| !matches(&expected, run))
+        .map(|(backend, run, _)| format!("{}:\n    - // {}\n    + {}", backend, expected, describe(run)))
+        .collect();
+    let output_mismatches = runs.iter()
+        .filter(|(_, run, output)| !matches!(run, Run::Skipped(_)) && *output != header.output)
+        .map(|(backend, _, output)| format!("{} output:\n    - {:?}\n    + {:?}", backend, header.output, output));
+    mismatches.extend(output_mismatches);
+    let show = |warnings: &[(String, usize, usize)]| {
+        warnings.iter().map(|(code, line, col)| format!("{} at {}:{}", code, line, col)).collect::<Vec<_>>().join(", ")
+    };
+    let actual = warnings(&source);
+    if actual != header.warnings {
+        mismatches.push(format!("warnings:\n    - {}\n    + {}", show(&header.warnings), show(&actual)));
+    }
+    if mismatches.is_empty() {
+        Ok(notes)
+    } else {
+        Err(mismatches.join("\n  "))
+    }
+}
+
+// Programs with stale expectations, and what blessing should turn them into
+const BLESSINGS: [(&str, &str); 2] = [
+    (
+        "// Every kind of expectation gets rewritten\n\
+         // expect: 1\n\
+         // backends: interp, vm\n\
+         // expect-output: 2\n\
+         {\n\
+         \x20 let f = fn (n: i64): i64 { n };\n\
+         \x20 print(f(3));\n\
+         \x20 match 4 {\n\
+         \x20   n => n,\n\
+         \x20   5 => 0,\n\
+         \x20 }\n\
+         }\n",
+        "// Every kind of expectation gets rewritten\n\
+         // expect: 4\n\
+         // expect-warning: E0130 at 11:5\n\
+         // expect-output: 3\n\
+         // backends: interp, vm\n\
+         {\n\
+         \x20 let f = fn (n: i64): i64 { n };\n\
+         \x20 print(f(3));\n\
+         \x20 match 4 {\n\
+         \x20   n => n,\n\
+         \x20   5 => 0,\n\
+         \x20 }\n\
+         }\n",
+    ),
+    (
+        "// expect-error: E0104 at 1:1\n\
+         // expect-warning: E0130 at 1:1\n\
+         // backends: interp, vm\n\
+         {\n\
+         \x20 let zero = fn (): i64 { 0 };\n\
+         \x20 print(1);\n\
+         \x20 10 / zero()\n\
+         }\n",
+        "// expect-runtime-error: Division by zero\n\
+         // expect-output: 1\n\
+         // backends: interp, vm\n\
+         {\n\
+         \x20 let zero = fn (): i64 { 0 };\n\
+         \x20 print(1);\n\
+         \x20 10 / zero()\n\
+         }\n",
+    ),
+];
+
+// Blessing each of `BLESSINGS` gives what it should, which then passes
+fn test_blessing() -> Result<(), String> {
+    let dir = env::temp_dir().join(format!("huck-golden-bless-{}", std::process::id()));
+    fs::create_dir_all(&dir).map_err(|err| format!("can't create {}: {}", dir.display(), err))?;
+    let result = BLESSINGS.iter().enumerate().try_for_each(|(i, (stale, blessed))| {
+        let path = dir.join(format!("bless-{}.huck", i));
+        fs::write(&path, stale).map_err(|err| format!("can't write {}: {}", path.display(), err))?;
+        test_program(&path, true)?;
+        let actual = fs::read_to_string(&path).map_err(|err| format!("can't read {}: {}", path.display(), err))?;
+        if actual != *blessed {
+            return Err(format!("blessing program {}:\n    - {:?}\n    + {:?}", i, blessed, actual));
+        }
+        test_program(&path, false).map(|_| ())
+    });
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+fn programs(dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap_or_else(|err| panic!("Can't read {}: {}", dir.display(), err))
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "huck"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let blessing = args.iter().any(|arg| arg == "--bless");
+    // Flags are cargo's business (or ours, for --bless)
+    let filters: Vec<&String> = args.iter().filter(|arg| !arg.starts_with('-')).collect();
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("huck-src");
+    let paths: Vec<PathBuf> = programs(&dir).into_iter()
+        .filter(|path| filters.is_empty() || filters.iter().any(|filter| path.to_string_lossy().contains(filter.as_str())))
+        .collect();
+
+    println!("\nrunning {} golden tests", paths.len());
+    let mut failures = vec![];
+    for path in &paths {
+        let name = path.strip_prefix(env!("CARGO_MANIFEST_DIR")).unwrap_or(path).display();
+        match test_program(path, blessing) {
+            Ok(notes) => {
+                println!("golden {} ... ok", name);
+                for note in notes {
+                    println!("  ({})", note);
+                }
+            },
+            Err(why) => {
+                println!("golden {} ... FAILED", name);
+                failures.push(format!("{}:\n  {}", name, why));
+            },
+        }
+    }
+
+    // Blessing is checked on programs of its own, so it only needs to run
+    // when all the programs do
+    let mut total = paths.len();
+    if filters.is_empty() {
+        total += 1;
+        match test_blessing() {
+            Ok(()) => println!("golden (blessing) ... ok"),
+            Err(why) => {
+                println!("golden (blessing) ... FAILED");
+                failures.push(format!("(blessing):\n  {}", why));
+            },
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:\n");
+        for failure in &failures {
+            println!("{}\n", failure);
+        }
+    }
+    let result = if failures.is_empty() { "ok" } else { "FAILED" };
+    println!("\ngolden test result: {}. {} passed; {} failed\n", result, total - failures.len(), failures.len());
+    if !failures.is_empty() {
+        exit(1);
+    }
+}